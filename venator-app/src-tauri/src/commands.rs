@@ -1,20 +1,28 @@
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use tauri::ipc::{Channel, Invoke};
 use tauri::{Runtime, State};
 use venator_engine::engine::AsyncEngine;
 use venator_engine::filter::{
-    validate_event_filter, validate_span_filter, FilterPredicate, Order, Query,
+    validate_event_filter, validate_span_filter, FilterPredicate, Order, Query, SpanEventQuery,
+};
+use venator_engine::storage::FileStorage;
+use venator_engine::{
+    AggregateQuery, AnnotationTarget, DeleteFilter, DeleteMode, LatencyGroup, NewAnnotation, Pin,
+    SpanEventType, SubscriptionId, SubscriptionResponse, Timestamp, TraceRoot,
 };
-use venator_engine::{DeleteFilter, SubscriptionId, SubscriptionResponse, Timestamp};
 
 use crate::views::{
-    DatasetStatsView, DeleteMetricsView, EventView, FilterPredicateResultView, InputView, Session,
-    SpanView, StatusView, SubscriptionResponseView,
+    AggregateRowView, AnnotationView, AttributeSummaryView, AttributeValueCountView,
+    CacheStatsView, CompactMetricsView, CriticalPathSpanView, DatasetStatsView, DeleteMetricsView,
+    EventView, FilterPredicateResultView, FlameGraphView, HistogramBucketView, InputView,
+    IntegrityReportView, LatencyStatsView, MergeMetricsView, ServiceDependencyView, Session,
+    SpanEventView, SpanView, StatusView, SubscriptionResponseView, TraceView,
 };
-use crate::{DatasetConfig, IngressState, SessionPersistence};
+use crate::{DatasetAccess, DatasetConfig, IngressState, SessionPersistence};
 
 #[tauri::command]
 async fn get_events(
@@ -62,6 +70,43 @@ async fn get_event_count(
     Ok(events)
 }
 
+#[tauri::command]
+async fn get_event_histogram(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+    buckets: usize,
+) -> Result<Vec<HistogramBucketView>, String> {
+    let histogram = engine
+        .query_event_histogram(filter, start, end, buckets)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(histogram
+        .into_iter()
+        .map(HistogramBucketView::from)
+        .collect())
+}
+
+#[tauri::command]
+async fn get_event_aggregate(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+    group_by: Vec<String>,
+    aggregates: Vec<String>,
+) -> Result<Vec<AggregateRowView>, String> {
+    let query = parse_aggregate_query(filter, start, end, &group_by, &aggregates)?;
+    let rows = engine
+        .query_event_aggregate(query)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows.into_iter().map(AggregateRowView::from).collect())
+}
+
 #[tauri::command]
 async fn parse_event_filter(
     _engine: State<'_, AsyncEngine>,
@@ -130,6 +175,223 @@ async fn get_span_count(
     Ok(spans)
 }
 
+#[tauri::command]
+async fn get_span_histogram(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+    buckets: usize,
+) -> Result<Vec<HistogramBucketView>, String> {
+    let histogram = engine
+        .query_span_histogram(filter, start, end, buckets)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(histogram
+        .into_iter()
+        .map(HistogramBucketView::from)
+        .collect())
+}
+
+#[tauri::command]
+async fn get_span_aggregate(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+    group_by: Vec<String>,
+    aggregates: Vec<String>,
+) -> Result<Vec<AggregateRowView>, String> {
+    let query = parse_aggregate_query(filter, start, end, &group_by, &aggregates)?;
+    let rows = engine
+        .query_span_aggregate(query)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows.into_iter().map(AggregateRowView::from).collect())
+}
+
+#[tauri::command]
+async fn get_span_latency(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+    group_by: Option<LatencyGroup>,
+) -> Result<Vec<LatencyStatsView>, String> {
+    let stats = engine
+        .query_span_latency(filter, start, end, group_by)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(stats.into_iter().map(LatencyStatsView::from).collect())
+}
+
+#[tauri::command]
+async fn get_trace(
+    engine: State<'_, AsyncEngine>,
+    trace: String,
+) -> Result<Option<TraceView>, String> {
+    let root: TraceRoot = trace.parse().map_err(|_| "invalid trace root".to_owned())?;
+
+    let trace = engine.query_trace(root).await.map_err(|e| e.to_string())?;
+
+    Ok(trace.map(TraceView::from))
+}
+
+#[tauri::command]
+async fn get_critical_path(
+    engine: State<'_, AsyncEngine>,
+    trace: String,
+) -> Result<Option<Vec<CriticalPathSpanView>>, String> {
+    let root: TraceRoot = trace.parse().map_err(|_| "invalid trace root".to_owned())?;
+
+    let path = engine
+        .query_critical_path(root)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(path.map(|path| path.into_iter().map(CriticalPathSpanView::from).collect()))
+}
+
+#[tauri::command]
+async fn get_flame_graph(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+) -> Result<FlameGraphView, String> {
+    let flame_graph = engine
+        .query_flame_graph(filter, start, end)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(flame_graph.into())
+}
+
+#[tauri::command]
+async fn export_flame_graph(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+    path: PathBuf,
+) -> Result<(), String> {
+    let flame_graph = engine
+        .query_flame_graph(filter, start, end)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    std::fs::write(&path, flame_graph.to_folded()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_service_dependencies(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+) -> Result<Vec<ServiceDependencyView>, String> {
+    let dependencies = engine
+        .query_service_dependencies(filter, start, end)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(dependencies
+        .into_iter()
+        .map(ServiceDependencyView::from)
+        .collect())
+}
+
+#[tauri::command]
+async fn get_span_timeline(
+    engine: State<'_, AsyncEngine>,
+    span: Timestamp,
+    kinds: Option<Vec<SpanEventType>>,
+) -> Result<Vec<SpanEventView>, String> {
+    let span_events = engine
+        .query_span_event(SpanEventQuery {
+            span: Some(span),
+            kinds,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            previous: None,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(span_events.into_iter().map(SpanEventView::from).collect())
+}
+
+#[tauri::command]
+async fn get_event_attribute_names(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+) -> Result<Vec<AttributeSummaryView>, String> {
+    let names = engine
+        .query_event_attribute_names(filter, start, end)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(names.into_iter().map(AttributeSummaryView::from).collect())
+}
+
+#[tauri::command]
+async fn get_span_attribute_names(
+    engine: State<'_, AsyncEngine>,
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+) -> Result<Vec<AttributeSummaryView>, String> {
+    let names = engine
+        .query_span_attribute_names(filter, start, end)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(names.into_iter().map(AttributeSummaryView::from).collect())
+}
+
+#[tauri::command]
+async fn get_event_attribute_values(
+    engine: State<'_, AsyncEngine>,
+    name: String,
+    prefix: String,
+    limit: usize,
+) -> Result<Vec<AttributeValueCountView>, String> {
+    let values = engine
+        .query_event_attribute_values(name, prefix, limit)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(values
+        .into_iter()
+        .map(AttributeValueCountView::from)
+        .collect())
+}
+
+#[tauri::command]
+async fn get_span_attribute_values(
+    engine: State<'_, AsyncEngine>,
+    name: String,
+    prefix: String,
+    limit: usize,
+) -> Result<Vec<AttributeValueCountView>, String> {
+    let values = engine
+        .query_span_attribute_values(name, prefix, limit)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(values
+        .into_iter()
+        .map(AttributeValueCountView::from)
+        .collect())
+}
+
 #[tauri::command]
 async fn parse_span_filter(
     _engine: State<'_, AsyncEngine>,
@@ -159,12 +421,24 @@ async fn delete_entities(
     end: Option<Timestamp>,
     inside: bool,
     dry_run: bool,
+    span_filter: Option<Vec<FilterPredicate>>,
+    event_filter: Option<Vec<FilterPredicate>>,
+    whole_traces: Option<bool>,
 ) -> Result<DeleteMetricsView, String> {
+    let mode = if whole_traces.unwrap_or(true) {
+        DeleteMode::Traces
+    } else {
+        DeleteMode::Matching
+    };
+
     let metrics = engine
         .delete(DeleteFilter {
             start: start.unwrap_or(Timestamp::MIN),
             end: end.unwrap_or(Timestamp::MAX),
             inside,
+            span_filter,
+            event_filter,
+            mode,
             dry_run,
         })
         .await
@@ -173,6 +447,143 @@ async fn delete_entities(
     Ok(metrics.into())
 }
 
+#[tauri::command]
+async fn set_span_pinned(
+    engine: State<'_, AsyncEngine>,
+    span: Timestamp,
+    pinned: bool,
+) -> Result<(), String> {
+    if pinned {
+        engine.pin(Pin::Span(span)).await
+    } else {
+        engine.unpin(Pin::Span(span)).await
+    }
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_event_pinned(
+    engine: State<'_, AsyncEngine>,
+    event: Timestamp,
+    pinned: bool,
+) -> Result<(), String> {
+    if pinned {
+        engine.pin(Pin::Event(event)).await
+    } else {
+        engine.unpin(Pin::Event(event)).await
+    }
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_annotations(engine: State<'_, AsyncEngine>) -> Result<Vec<AnnotationView>, String> {
+    let annotations = engine
+        .query_annotations()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(annotations.into_iter().map(AnnotationView::from).collect())
+}
+
+#[tauri::command]
+async fn add_annotation(
+    engine: State<'_, AsyncEngine>,
+    span: Option<Timestamp>,
+    event: Option<Timestamp>,
+    start: Option<Timestamp>,
+    end: Option<Timestamp>,
+    text: String,
+) -> Result<Timestamp, String> {
+    let target = match (span, event, start, end) {
+        (Some(span), None, None, None) => AnnotationTarget::Span(span),
+        (None, Some(event), None, None) => AnnotationTarget::Event(event),
+        (None, None, Some(start), Some(end)) => AnnotationTarget::Timespan(start, end),
+        _ => return Err("annotation must target a span, an event, or a timespan".to_owned()),
+    };
+
+    engine
+        .add_annotation(NewAnnotation { target, text })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_annotation(
+    engine: State<'_, AsyncEngine>,
+    annotation: Timestamp,
+    text: String,
+) -> Result<(), String> {
+    engine
+        .update_annotation(annotation, text)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_annotation(
+    engine: State<'_, AsyncEngine>,
+    annotation: Timestamp,
+) -> Result<(), String> {
+    engine
+        .remove_annotation(annotation)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn undo_delete(
+    engine: State<'_, AsyncEngine>,
+    tombstone: Timestamp,
+) -> Result<DeleteMetricsView, String> {
+    let metrics = engine
+        .undo_delete(tombstone)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(metrics.into())
+}
+
+#[tauri::command]
+async fn compact_dataset(engine: State<'_, AsyncEngine>) -> Result<CompactMetricsView, String> {
+    let metrics = engine.compact().await.map_err(|e| e.to_string())?;
+
+    Ok(metrics.into())
+}
+
+#[tauri::command]
+async fn merge_dataset(
+    engine: State<'_, AsyncEngine>,
+    path: PathBuf,
+) -> Result<MergeMetricsView, String> {
+    if !std::fs::exists(&path).map_err(|e| e.to_string())? {
+        return Err(format!("{} does not exist", path.display()));
+    }
+
+    // the other dataset is only read from, so it can be merged even if it
+    // is currently open in another window
+    let storage = FileStorage::new_read_only(&path).map_err(|e| e.to_string())?;
+    let metrics = engine
+        .merge_dataset(Box::new(storage))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(metrics.into())
+}
+
+#[tauri::command]
+async fn verify_dataset(engine: State<'_, AsyncEngine>) -> Result<IntegrityReportView, String> {
+    let report = engine.verify().await.map_err(|e| e.to_string())?;
+
+    Ok(report.into())
+}
+
+#[tauri::command]
+async fn repair_dataset(engine: State<'_, AsyncEngine>) -> Result<IntegrityReportView, String> {
+    let report = engine.repair().await.map_err(|e| e.to_string())?;
+
+    Ok(report.into())
+}
+
 #[tauri::command]
 async fn get_stats(engine: State<'_, AsyncEngine>) -> Result<DatasetStatsView, String> {
     engine
@@ -295,6 +706,7 @@ async fn save_session(
 async fn get_status(
     engine: State<'_, AsyncEngine>,
     dataset: State<'_, DatasetConfig>,
+    access: State<'_, DatasetAccess>,
     ingress: State<'_, Option<Arc<IngressState>>>,
 ) -> Result<StatusView, String> {
     let ((ingress_message, ingress_error), bytes_per_second) = match &*ingress {
@@ -307,12 +719,21 @@ async fn get_status(
         None => (("not listening".into(), None), 0.0),
     };
 
-    let dataset_name = match &*dataset {
+    let mut dataset_name = match &*dataset {
         DatasetConfig::Default(_) => "default dataset".to_owned(),
-        DatasetConfig::File(path) => format!("{}", path.display()),
+        DatasetConfig::Partitioned {
+            is_default: true, ..
+        } => "default dataset".to_owned(),
+        DatasetConfig::File(path) | DatasetConfig::Partitioned { path, .. } => {
+            format!("{}", path.display())
+        }
         DatasetConfig::Memory => ":memory:".to_owned(),
     };
 
+    if access.read_only {
+        dataset_name.push_str(" (read-only)");
+    }
+
     let engine_status = engine.get_status().await.map_err(|e| e.to_string())?;
 
     Ok(StatusView {
@@ -321,6 +742,32 @@ async fn get_status(
         dataset_name,
         ingress_bytes_per_second: bytes_per_second,
         engine_load: engine_status.load,
+        cache: engine_status.cache.map(CacheStatsView::from),
+    })
+}
+
+fn parse_aggregate_query(
+    filter: Vec<FilterPredicate>,
+    start: Timestamp,
+    end: Timestamp,
+    group_by: &[String],
+    aggregates: &[String],
+) -> Result<AggregateQuery, String> {
+    let group_by = group_by
+        .iter()
+        .map(|key| key.parse().map_err(|e| format!("{e}: '{key}'")))
+        .collect::<Result<_, String>>()?;
+    let aggregates = aggregates
+        .iter()
+        .map(|aggregate| aggregate.parse().map_err(|e| format!("{e}: '{aggregate}'")))
+        .collect::<Result<_, String>>()?;
+
+    Ok(AggregateQuery {
+        filter,
+        start,
+        end,
+        group_by,
+        aggregates,
     })
 }
 
@@ -328,11 +775,37 @@ pub(crate) fn handler<R: Runtime>() -> impl Fn(Invoke<R>) -> bool + Send + Sync
     tauri::generate_handler![
         get_events,
         get_event_count,
+        get_event_histogram,
+        get_event_aggregate,
         parse_event_filter,
         get_spans,
         get_span_count,
+        get_span_histogram,
+        get_span_aggregate,
+        get_span_latency,
+        get_trace,
+        get_critical_path,
+        get_flame_graph,
+        export_flame_graph,
+        get_service_dependencies,
+        get_span_timeline,
+        get_event_attribute_names,
+        get_span_attribute_names,
+        get_event_attribute_values,
+        get_span_attribute_values,
         parse_span_filter,
         delete_entities,
+        set_span_pinned,
+        set_event_pinned,
+        get_annotations,
+        add_annotation,
+        update_annotation,
+        remove_annotation,
+        undo_delete,
+        compact_dataset,
+        merge_dataset,
+        verify_dataset,
+        repair_dataset,
         get_stats,
         subscribe_to_spans,
         unsubscribe_from_spans,
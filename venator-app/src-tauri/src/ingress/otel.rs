@@ -24,13 +24,14 @@ use opentelemetry_proto::tonic::collector::trace::v1::{
     ExportTraceServiceRequest, ExportTraceServiceResponse,
 };
 use opentelemetry_proto::tonic::common::v1::{AnyValue, KeyValue};
+use opentelemetry_proto::tonic::trace::v1::status::StatusCode as OtelStatusCode;
 use prost::bytes::{Bytes, BytesMut};
 use tonic::{async_trait, Request, Response, Status};
 
 use venator_engine::engine::AsyncEngine;
 use venator_engine::{
     FullSpanId, Level, NewCloseSpanEvent, NewCreateSpanEvent, NewEvent, NewResource, NewSpanEvent,
-    NewSpanEventKind, SourceKind, SpanId, Timestamp, TraceId, Value,
+    NewSpanEventKind, SourceKind, SpanId, TraceId, Value,
 };
 
 use super::IngressState;
@@ -187,14 +188,14 @@ async fn process_logs_request(
 
             for log_record in &scope_log.log_records {
                 let timestamp = if log_record.time_unix_nano != 0 {
-                    log_record.time_unix_nano / 1000
+                    log_record.time_unix_nano
                 } else if log_record.observed_time_unix_nano != 0 {
-                    log_record.observed_time_unix_nano / 1000
+                    log_record.observed_time_unix_nano
                 } else {
                     SystemTime::now()
                         .duration_since(UNIX_EPOCH)
                         .expect("now should not be before the UNIX epoch")
-                        .as_micros() as u64
+                        .as_nanos() as u64
                 };
 
                 let trace_id = parse_trace_id(&log_record.trace_id);
@@ -216,8 +217,7 @@ async fn process_logs_request(
                 let event = NewEvent {
                     kind: SourceKind::Opentelemetry,
                     resource_key,
-                    timestamp: Timestamp::new(timestamp)
-                        .expect("now should not be at the UNIX epoch"),
+                    timestamp,
                     span_id: trace_id.and_then(|trace_id| {
                         span_id.map(|span_id| FullSpanId::Opentelemetry(trace_id, span_id))
                     }),
@@ -301,8 +301,8 @@ async fn process_trace_request(
             let scope_file_column = extract_file_column(&mut instrumentation_attributes);
 
             for span in &scope_span.spans {
-                let created_timestamp = span.start_time_unix_nano / 1000;
-                let closed_timestamp = span.end_time_unix_nano / 1000;
+                let created_timestamp = span.start_time_unix_nano;
+                let closed_timestamp = span.end_time_unix_nano;
 
                 let Some(trace_id) = parse_trace_id(&span.trace_id) else {
                     tracing::warn!("failed to parse trace id from span");
@@ -317,15 +317,24 @@ async fn process_trace_request(
                 let parent_span_id = parse_span_id(&span.parent_span_id);
                 let mut attributes = conv_value_map(&span.attributes);
 
+                // spans don't have levels so just set to @level if it has
+                // it or just fallback to INFO, but a span with an error
+                // status is raised to at least ERROR
                 let level = extract_level(&mut attributes).or(scope_level);
-                let Ok(level) = Level::from_otel_severity(level.unwrap_or(9)) else {
+                let Ok(mut level) = Level::from_otel_severity(level.unwrap_or(9)) else {
                     tracing::warn!("failed to interpret level from span");
                     continue;
                 };
 
-                // spans don't have levels so just set to @level if it has
-                // it or just fallback to INFO (todo: there is a "status"
-                // which could be "error")
+                let is_error = span
+                    .status
+                    .as_ref()
+                    .is_some_and(|status| status.code() == OtelStatusCode::Error);
+
+                if is_error && (level as i32) < (Level::Error as i32) {
+                    level = Level::Error;
+                }
+
                 let busy = extract_busy(&mut attributes);
                 let namespace =
                     extract_namespace(&mut attributes).or_else(|| scope_namespace.clone());
@@ -336,8 +345,7 @@ async fn process_trace_request(
                 let file_column = extract_file_column(&mut attributes).or(scope_file_column);
 
                 let create_span_event = NewSpanEvent {
-                    timestamp: Timestamp::new(created_timestamp)
-                        .expect("now should not be at the UNIX epoch"),
+                    timestamp: created_timestamp,
                     span_id: FullSpanId::Opentelemetry(trace_id, span_id),
                     kind: NewSpanEventKind::Create(NewCreateSpanEvent {
                         kind: SourceKind::Opentelemetry,
@@ -362,8 +370,7 @@ async fn process_trace_request(
                 let _ = engine.insert_span_event(create_span_event).await;
 
                 let close_span_event = NewSpanEvent {
-                    timestamp: Timestamp::new(closed_timestamp)
-                        .expect("now should not be at the UNIX epoch"),
+                    timestamp: closed_timestamp,
                     span_id: FullSpanId::Opentelemetry(trace_id, span_id),
                     kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy }),
                 };
@@ -374,13 +381,12 @@ async fn process_trace_request(
                 let _ = engine.insert_span_event(close_span_event).await;
 
                 for event in &span.events {
-                    let timestamp = event.time_unix_nano / 1000;
+                    let timestamp = event.time_unix_nano;
 
                     let mut attributes = conv_value_map(&event.attributes);
 
-                    // spans events don't have levels so just set to @level
-                    // if it has it or just fallback to INFO (todo: there is
-                    // a "status" which could be "error")
+                    // spans events don't have levels or a status so just
+                    // set to @level if it has it or just fallback to INFO
                     let level = extract_level(&mut attributes).or(scope_level);
                     let Ok(level) = Level::from_otel_severity(level.unwrap_or(9)) else {
                         tracing::warn!("failed to interpret level from log record");
@@ -399,8 +405,7 @@ async fn process_trace_request(
                     let event = NewEvent {
                         kind: SourceKind::Opentelemetry,
                         resource_key,
-                        timestamp: Timestamp::new(timestamp)
-                            .expect("now should not be at the UNIX epoch"),
+                        timestamp,
                         span_id: Some(FullSpanId::Opentelemetry(trace_id, span_id)),
                         content: Value::Str(event.name.to_owned()),
                         namespace,
@@ -540,7 +545,7 @@ fn extract_busy(attributes: &mut BTreeMap<String, Value>) -> Option<u64> {
     let _ = attributes.remove_entry("idle_ns");
 
     match val {
-        Some((_, Value::I64(busy))) => Some(busy as u64 / 1000),
+        Some((_, Value::I64(busy))) => Some(busy as u64),
         Some((key, val)) => {
             attributes.insert(key, val);
             None
@@ -572,3 +577,84 @@ fn extract_level(attributes: &mut BTreeMap<String, Value>) -> Option<i32> {
         None => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry_proto::tonic::trace::v1::{ResourceSpans, ScopeSpans, Span, Status};
+    use venator_engine::filter::{Order, Query};
+    use venator_engine::storage::TransientStorage;
+    use venator_engine::Timestamp;
+
+    use super::*;
+
+    fn otel_span(span_id: u8, name: &str, status: Option<OtelStatusCode>) -> Span {
+        Span {
+            trace_id: vec![1; 16],
+            span_id: vec![span_id; 8],
+            name: name.to_owned(),
+            start_time_unix_nano: 1_000_000_000,
+            end_time_unix_nano: 2_000_000_000,
+            status: status.map(|code| Status {
+                message: String::new(),
+                code: code as i32,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn spans_with_error_status_are_ingested_as_errors() {
+        let engine = AsyncEngine::new(TransientStorage::new()).unwrap();
+
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                scope_spans: vec![ScopeSpans {
+                    spans: vec![
+                        otel_span(1, "failed", Some(OtelStatusCode::Error)),
+                        otel_span(2, "succeeded", Some(OtelStatusCode::Ok)),
+                        otel_span(3, "unset", None),
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        process_trace_request(&engine, &request).await;
+
+        // the span events are not awaited and queries take priority, so wait
+        // on another insert to know the spans have been processed
+        let _ = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .await
+            .await;
+
+        let spans = engine
+            .query_span(Query {
+                filter: vec![],
+                order: Order::Asc,
+                limit: 10,
+                start: Timestamp::MIN,
+                end: Timestamp::MAX,
+                previous: None,
+            })
+            .await
+            .unwrap();
+
+        let levels: Vec<_> = spans
+            .iter()
+            .map(|span| (span.name.as_str(), span.level.to_string()))
+            .collect();
+
+        assert_eq!(
+            levels,
+            [
+                ("failed", "ERROR".to_owned()),
+                ("succeeded", "INFO".to_owned()),
+                ("unset", "INFO".to_owned()),
+            ]
+        );
+    }
+}
@@ -162,7 +162,7 @@ async fn handle_tracing_stream<S: AsyncRead + Unpin>(
                 };
 
                 let span_event = NewSpanEvent {
-                    timestamp: msg.timestamp,
+                    timestamp: msg.timestamp.get() * 1000,
                     span_id: FullSpanId::Tracing(instance_id, span_id),
                     kind: NewSpanEventKind::Create(NewCreateSpanEvent {
                         kind: SourceKind::Tracing,
@@ -194,7 +194,7 @@ async fn handle_tracing_stream<S: AsyncRead + Unpin>(
                 };
 
                 let span_event = NewSpanEvent {
-                    timestamp: msg.timestamp,
+                    timestamp: msg.timestamp.get() * 1000,
                     span_id: FullSpanId::Tracing(instance_id, span_id),
                     kind: NewSpanEventKind::Update(NewUpdateSpanEvent {
                         attributes: conv_value_map(update_data.attributes),
@@ -213,7 +213,7 @@ async fn handle_tracing_stream<S: AsyncRead + Unpin>(
                 };
 
                 let span_event = NewSpanEvent {
-                    timestamp: msg.timestamp,
+                    timestamp: msg.timestamp.get() * 1000,
                     span_id: FullSpanId::Tracing(instance_id, span_id),
                     kind: NewSpanEventKind::Follows(NewFollowsSpanEvent {
                         follows: follows_data.follows,
@@ -232,7 +232,7 @@ async fn handle_tracing_stream<S: AsyncRead + Unpin>(
                 };
 
                 let span_event = NewSpanEvent {
-                    timestamp: msg.timestamp,
+                    timestamp: msg.timestamp.get() * 1000,
                     span_id: FullSpanId::Tracing(instance_id, span_id),
                     kind: NewSpanEventKind::Enter(NewEnterSpanEvent {
                         thread_id: enter_data.thread_id,
@@ -251,7 +251,7 @@ async fn handle_tracing_stream<S: AsyncRead + Unpin>(
                 };
 
                 let span_event = NewSpanEvent {
-                    timestamp: msg.timestamp,
+                    timestamp: msg.timestamp.get() * 1000,
                     span_id: FullSpanId::Tracing(instance_id, span_id),
                     kind: NewSpanEventKind::Exit,
                 };
@@ -268,7 +268,7 @@ async fn handle_tracing_stream<S: AsyncRead + Unpin>(
                 };
 
                 let span_event = NewSpanEvent {
-                    timestamp: msg.timestamp,
+                    timestamp: msg.timestamp.get() * 1000,
                     span_id: FullSpanId::Tracing(instance_id, span_id),
                     kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
                 };
@@ -292,7 +292,7 @@ async fn handle_tracing_stream<S: AsyncRead + Unpin>(
                 let event = NewEvent {
                     kind: SourceKind::Tracing,
                     resource_key,
-                    timestamp: msg.timestamp,
+                    timestamp: msg.timestamp.get() * 1000,
                     span_id: msg
                         .span_id
                         .map(|span_id| FullSpanId::Tracing(instance_id, span_id)),
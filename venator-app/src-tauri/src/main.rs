@@ -1,8 +1,10 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Error as AnyError;
 use clap::{ArgAction, Parser};
@@ -10,7 +12,9 @@ use tauri::menu::{Menu, MenuBuilder, MenuEvent, MenuItem, PredefinedMenuItem, Su
 use tauri::{AppHandle, Emitter, Manager, WindowEvent, Wry};
 use tauri_plugin_dialog::DialogExt;
 use venator_engine::engine::AsyncEngine;
-use venator_engine::storage::{CachedStorage, FileStorage, TransientStorage};
+use venator_engine::storage::{
+    CacheCapacities, CachedStorage, FileStorage, PartitionedStorage, TransientStorage,
+};
 
 mod commands;
 mod ingress;
@@ -21,20 +25,30 @@ use ingress::{launch_ingress_thread, IngressState};
 enum DatasetConfig {
     Default(PathBuf),
     File(PathBuf),
+    Partitioned {
+        path: PathBuf,
+        duration: Duration,
+        is_default: bool,
+    },
     Memory,
 }
 
 impl DatasetConfig {
-    fn prepare(&self) {
+    fn prepare(&self) -> Result<(), AnyError> {
         match self {
             DatasetConfig::Memory => { /* nothing to do */ }
             DatasetConfig::File(_) => { /* nothing to do, path should already exist */ }
+            DatasetConfig::Partitioned { .. } => { /* nothing to do, created when opened */ }
             DatasetConfig::Default(path) => {
                 if let Some(dir) = path.parent() {
-                    std::fs::create_dir_all(dir).expect("could not create default directory");
+                    std::fs::create_dir_all(dir).map_err(|err| {
+                        anyhow::anyhow!("could not create default directory: {err}")
+                    })?;
                 }
             }
         }
+
+        Ok(())
     }
 }
 
@@ -54,26 +68,75 @@ struct Args {
     persist_session: bool,
     #[arg(long, action = ArgAction::SetTrue, default_value_t = false, conflicts_with = "persist_session", hide = true)]
     no_persist_session: bool,
+
+    /// The number of resources to keep cached from a file dataset (0 disables
+    /// caching)
+    #[arg(long, default_value_t = 10000)]
+    cache_resources: usize,
+
+    /// The number of spans to keep cached from a file dataset (0 disables
+    /// caching)
+    #[arg(long, default_value_t = 10000)]
+    cache_spans: usize,
+
+    /// The number of span events to keep cached from a file dataset (0 disables
+    /// caching)
+    #[arg(long, default_value_t = 10000)]
+    cache_span_events: usize,
+
+    /// The number of events to keep cached from a file dataset (0 disables
+    /// caching)
+    #[arg(long, default_value_t = 10000)]
+    cache_events: usize,
+
+    /// Opens the dataset without modifying it (implied if another instance
+    /// already has it open for writing)
+    #[arg(long, action = ArgAction::SetTrue, default_value_t = false)]
+    read_only: bool,
+
+    /// Splits the dataset into a directory of files that each hold this many
+    /// hours of data, so old data can be deleted quickly
+    #[arg(long, conflicts_with = "read_only")]
+    partition_hours: Option<u64>,
+
+    /// The number of minutes that deleted data can be restored before it is
+    /// permanently removed
+    #[arg(long, default_value_t = 60)]
+    delete_grace_minutes: u64,
+
+    /// The file to save an in-memory dataset to when closing, so the session
+    /// can be recovered later (only used with `--dataset :memory:`)
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+
+    /// Also saves the in-memory dataset to the snapshot file at this interval
+    #[arg(long, requires = "snapshot")]
+    snapshot_minutes: Option<u64>,
 }
 
 impl Args {
     fn dataset(&self) -> DatasetConfig {
-        if let Some(dataset) = &self.dataset {
-            if dataset == ":memory:" {
-                return DatasetConfig::Memory;
-            } else {
-                return DatasetConfig::File(PathBuf::from(dataset));
-            }
+        if self.dataset.as_deref() == Some(":memory:") {
+            return DatasetConfig::Memory;
         }
 
-        if cfg!(debug_assertions) {
-            DatasetConfig::Default(PathBuf::from("local.vena.db"))
-        } else {
-            DatasetConfig::Default(
-                directories::ProjectDirs::from("", "", "Venator")
-                    .map(|dirs| dirs.data_dir().to_path_buf().join("default.vena.db"))
-                    .unwrap_or(PathBuf::from("default.vena.db")),
-            )
+        let path = self.dataset.as_ref().map(PathBuf::from);
+
+        if let Some(duration) = self.partition_duration() {
+            // a partitioned dataset is a directory, so it gets its own default
+            // rather than colliding with the default dataset file
+            return DatasetConfig::Partitioned {
+                is_default: path.is_none(),
+                path: path.unwrap_or_else(|| default_dataset_path("local.vena", "default.vena")),
+                duration,
+            };
+        }
+
+        match path {
+            Some(path) => DatasetConfig::File(path),
+            None => {
+                DatasetConfig::Default(default_dataset_path("local.vena.db", "default.vena.db"))
+            }
         }
     }
 
@@ -92,10 +155,64 @@ impl Args {
         }
     }
 
+    fn read_only(&self, dataset: &DatasetConfig) -> bool {
+        match dataset {
+            DatasetConfig::Default(path) | DatasetConfig::File(path) => {
+                self.read_only || FileStorage::is_locked(path)
+            }
+            // there is no read-only mode for partitioned datasets, so
+            // `--read-only` is rejected and opening it while locked fails
+            DatasetConfig::Partitioned { .. } => false,
+            DatasetConfig::Memory => false,
+        }
+    }
+
+    fn partition_duration(&self) -> Option<Duration> {
+        self.partition_hours
+            .map(|hours| Duration::from_secs(hours.max(1) * 60 * 60))
+    }
+
+    fn delete_grace_period(&self) -> Duration {
+        Duration::from_secs(self.delete_grace_minutes * 60)
+    }
+
+    fn snapshot(&self) -> Option<DatasetSnapshot> {
+        let path = self.snapshot.clone()?;
+
+        match self.dataset() {
+            DatasetConfig::Memory => {}
+            DatasetConfig::Default(_)
+            | DatasetConfig::File(_)
+            | DatasetConfig::Partitioned { .. } => {
+                tracing::warn!("ignoring snapshot since the dataset is already a file");
+                return None;
+            }
+        }
+
+        Some(DatasetSnapshot {
+            path,
+            interval: self
+                .snapshot_minutes
+                .map(|minutes| Duration::from_secs(minutes.max(1) * 60)),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn cache_capacities(&self) -> CacheCapacities {
+        CacheCapacities {
+            resources: self.cache_resources,
+            spans: self.cache_spans,
+            span_events: self.cache_span_events,
+            events: self.cache_events,
+        }
+    }
+
     fn persist_session(&self) -> Option<PathBuf> {
         if self.persist_session {
             return match self.dataset() {
-                DatasetConfig::Default(mut path) | DatasetConfig::File(mut path) => {
+                DatasetConfig::Default(mut path)
+                | DatasetConfig::File(mut path)
+                | DatasetConfig::Partitioned { mut path, .. } => {
                     path.set_extension("user");
                     Some(path)
                 }
@@ -109,11 +226,21 @@ impl Args {
         }
 
         match self.dataset() {
-            DatasetConfig::Default(mut path) => {
+            DatasetConfig::Default(mut path)
+            | DatasetConfig::Partitioned {
+                mut path,
+                is_default: true,
+                ..
+            } => {
                 path.set_extension("user");
                 Some(path)
             }
-            DatasetConfig::File(mut path) => {
+            DatasetConfig::File(mut path)
+            | DatasetConfig::Partitioned {
+                mut path,
+                is_default: false,
+                ..
+            } => {
                 path.set_extension("user");
                 if let Ok(true) = std::fs::exists(&path) {
                     Some(path)
@@ -135,22 +262,55 @@ fn main() -> Result<(), AnyError> {
 
     let args = Args::parse();
     let dataset = args.dataset();
-    let bind = args.bind();
+    let read_only = args.read_only(&dataset);
+    let bind = args.bind().filter(|_| !read_only);
     let persist_session = args.persist_session();
+    let cache_capacities = args.cache_capacities();
+    let delete_grace_period = args.delete_grace_period();
+    let snapshot = args.snapshot().map(Arc::new);
+
+    if args.partition_hours.is_some() && matches!(dataset, DatasetConfig::Memory) {
+        return Err(anyhow::anyhow!(
+            "an in-memory dataset cannot be partitioned"
+        ));
+    }
 
-    dataset.prepare();
+    dataset.prepare()?;
     let engine = match &dataset {
-        DatasetConfig::Default(path) => {
-            AsyncEngine::new(CachedStorage::new(10000, FileStorage::new(path)))?
-        }
-        DatasetConfig::File(path) => {
-            AsyncEngine::new(CachedStorage::new(10000, FileStorage::new(path)))?
+        DatasetConfig::Default(path) | DatasetConfig::File(path) if read_only => AsyncEngine::new(
+            CachedStorage::with_capacities(cache_capacities, FileStorage::new_read_only(path)?),
+        )?,
+        DatasetConfig::Partitioned { path, duration, .. } => {
+            AsyncEngine::new(CachedStorage::with_capacities(
+                cache_capacities,
+                PartitionedStorage::new(path, *duration)?,
+            ))?
         }
+        DatasetConfig::Default(path) => AsyncEngine::new(CachedStorage::with_capacities(
+            cache_capacities,
+            FileStorage::new(path)?,
+        ))?,
+        DatasetConfig::File(path) => AsyncEngine::new(CachedStorage::with_capacities(
+            cache_capacities,
+            FileStorage::new(path)?,
+        ))?,
         DatasetConfig::Memory => AsyncEngine::new(TransientStorage::new())?,
     };
 
+    set_delete_grace_period(&engine, delete_grace_period);
+
     let ingress = bind.map(|bind| launch_ingress_thread(engine.clone(), bind.to_string()));
 
+    if let Some(snapshot) = snapshot.clone() {
+        if let Some(interval) = snapshot.interval {
+            let engine = engine.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(interval);
+                snapshot_dataset(&engine, &snapshot);
+            });
+        }
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_dialog::init())
@@ -165,13 +325,19 @@ fn main() -> Result<(), AnyError> {
         .on_window_event(|window, event| {
             if let WindowEvent::CloseRequested { .. } = event {
                 let engine = window.state::<AsyncEngine>();
+                let snapshot = window.state::<Option<Arc<DatasetSnapshot>>>();
+                if let Some(snapshot) = snapshot.inner() {
+                    snapshot_dataset(&engine, snapshot);
+                }
                 shutdown(&engine);
             }
         })
         .manage(engine.clone())
         .manage(dataset)
+        .manage(snapshot)
         .manage(ingress)
         .manage(SessionPersistence(persist_session))
+        .manage(DatasetAccess { read_only })
         .invoke_handler(crate::commands::handler())
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -193,6 +359,13 @@ fn build_menu(handle: &AppHandle) -> Result<Menu<Wry>, Box<dyn std::error::Error
                     true,
                     None::<&str>,
                 )?,
+                &MenuItem::with_id(
+                    handle,
+                    "merge-dataset",
+                    "Merge dataset into this one",
+                    true,
+                    None::<&str>,
+                )?,
                 &PredefinedMenuItem::separator(handle)?,
                 &MenuItem::with_id(handle, "save-dataset-as", "Save as", true, None::<&str>)?,
                 &MenuItem::with_id(
@@ -289,6 +462,35 @@ fn build_menu(handle: &AppHandle) -> Result<Menu<Wry>, Box<dyn std::error::Error
                     true,
                     None::<&str>,
                 )?,
+                &MenuItem::with_id(
+                    handle,
+                    "delete-matching",
+                    "Delete matching filter",
+                    true,
+                    None::<&str>,
+                )?,
+                &MenuItem::with_id(
+                    handle,
+                    "undo-delete",
+                    "Undo last delete",
+                    true,
+                    None::<&str>,
+                )?,
+                &PredefinedMenuItem::separator(handle)?,
+                &MenuItem::with_id(
+                    handle,
+                    "compact-dataset",
+                    "Compact dataset",
+                    true,
+                    None::<&str>,
+                )?,
+                &MenuItem::with_id(
+                    handle,
+                    "verify-dataset",
+                    "Verify dataset",
+                    true,
+                    None::<&str>,
+                )?,
             ],
         )?)
         .item(&Submenu::with_items(
@@ -333,6 +535,9 @@ fn handle_menu_event(app: &AppHandle<Wry>, event: MenuEvent) {
                     .expect("could not spawn new process");
             });
         }
+        "merge-dataset" => {
+            let _ = app.emit("merge-dataset-clicked", ());
+        }
         "save-dataset-as" => {
             let engine = app.state::<AsyncEngine>().inner().clone();
 
@@ -340,7 +545,13 @@ fn handle_menu_event(app: &AppHandle<Wry>, event: MenuEvent) {
                 let Some(path) = file_path else { return };
                 let Some(path) = path.as_path() else { return };
 
-                let new_storage = FileStorage::new(path);
+                let new_storage = match FileStorage::new(path) {
+                    Ok(storage) => storage,
+                    Err(err) => {
+                        tracing::error!(?err, "failed to create dataset");
+                        return;
+                    }
+                };
 
                 copy_dataset(&engine, new_storage);
             });
@@ -396,6 +607,18 @@ fn handle_menu_event(app: &AppHandle<Wry>, event: MenuEvent) {
         "delete-outside" => {
             let _ = app.emit("delete-outside-clicked", ());
         }
+        "delete-matching" => {
+            let _ = app.emit("delete-matching-clicked", ());
+        }
+        "undo-delete" => {
+            let _ = app.emit("undo-delete-clicked", ());
+        }
+        "compact-dataset" => {
+            let _ = app.emit("compact-dataset-clicked", ());
+        }
+        "verify-dataset" => {
+            let _ = app.emit("verify-dataset-clicked", ());
+        }
         "help-about" => {
             let _ = open::that("https://github.com/kmdreko/venator");
         }
@@ -416,9 +639,73 @@ async fn shutdown(engine: &AsyncEngine) {
     }
 }
 
+#[tokio::main(flavor = "current_thread")]
+async fn set_delete_grace_period(engine: &AsyncEngine, grace_period: Duration) {
+    if let Err(err) = engine.set_delete_grace_period(grace_period).await {
+        tracing::error!(?err, "failed to set delete grace period");
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn copy_dataset(engine: &AsyncEngine, new_storage: FileStorage) {
     let _ = engine.copy_dataset(Box::new(new_storage)).await;
 }
 
+/// Replaces the snapshot file with the current contents of the dataset. This
+/// writes to a temporary file first so that a failed or interrupted snapshot
+/// does not clobber the previous one.
+#[tokio::main(flavor = "current_thread")]
+async fn snapshot_dataset(engine: &AsyncEngine, snapshot: &DatasetSnapshot) {
+    let _guard = snapshot.lock.lock().unwrap_or_else(|err| err.into_inner());
+
+    let temp_path = with_suffix(&snapshot.path, ".tmp");
+    let _ = std::fs::remove_file(&temp_path);
+
+    let storage = match FileStorage::new(&temp_path) {
+        Ok(storage) => storage,
+        Err(err) => {
+            tracing::error!(?err, "failed to create snapshot file");
+            return;
+        }
+    };
+
+    let result = engine
+        .copy_dataset(Box::new(storage))
+        .await
+        .and_then(|_| std::fs::rename(&temp_path, &snapshot.path).map_err(AnyError::from));
+
+    match result {
+        Ok(()) => tracing::info!(path = ?snapshot.path, "saved snapshot"),
+        Err(err) => tracing::error!(?err, "failed to save snapshot"),
+    }
+}
+
+fn default_dataset_path(local_name: &str, default_name: &str) -> PathBuf {
+    if cfg!(debug_assertions) {
+        PathBuf::from(local_name)
+    } else {
+        directories::ProjectDirs::from("", "", "Venator")
+            .map(|dirs| dirs.data_dir().to_path_buf().join(default_name))
+            .unwrap_or(PathBuf::from(default_name))
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
 struct SessionPersistence(Option<PathBuf>);
+
+struct DatasetSnapshot {
+    path: PathBuf,
+    interval: Option<Duration>,
+    // held while a snapshot is being written so that a periodic snapshot and
+    // the final one do not write to the temporary file at the same time
+    lock: Mutex<()>,
+}
+
+struct DatasetAccess {
+    read_only: bool,
+}
@@ -4,9 +4,14 @@ use serde::{Deserialize, Serialize};
 use venator_engine::filter::{
     FallibleFilterPredicate, FilterPredicateSingle, FilterPropertyKind, InputError, ValuePredicate,
 };
+use venator_engine::storage::CacheStats;
 use venator_engine::{
-    Ancestor, Attribute, AttributeSource, ComposedEvent, ComposedSpan, DatasetStats, DeleteMetrics,
-    FullSpanId, Timestamp, Value,
+    to_micros, AggregateRow, Ancestor, Annotation, AnnotationTarget, Attribute, AttributeSource,
+    AttributeSummary, AttributeValueCount, CompactMetrics, ComposedEvent, ComposedSpan,
+    CriticalPathSpan, DatasetStats, DeleteMetrics, FlameGraph, FlameNode, FullSpanId,
+    HistogramBucket, IntegrityReport, LatencyBucket, LatencyStats, MergeMetrics, ResourceKey,
+    ServiceDependency, SpanEvent, SpanEventKind, SpanEventType, Timestamp, Trace, TraceEvent,
+    TraceSpan, Value, ValueKind,
 };
 
 pub type FullSpanIdView = String;
@@ -90,7 +95,9 @@ impl From<FilterPredicateSingle> for FilterPredicateSingleView {
 pub struct EventView {
     pub kind: SourceKindView,
     pub ancestors: Vec<AncestorView>, // in root-first order
+    pub key: Timestamp,
     pub timestamp: Timestamp,
+    pub precise_timestamp: String, // in nanoseconds, too large for a JS number
     pub content: String,
     pub namespace: Option<String>,
     pub function: Option<String>,
@@ -108,7 +115,9 @@ impl From<ComposedEvent> for EventView {
                 .into_iter()
                 .map(AncestorView::from)
                 .collect(),
-            timestamp: event.timestamp,
+            key: event.key,
+            timestamp: to_micros(event.timestamp),
+            precise_timestamp: event.timestamp.to_string(),
             content: event.content.to_string(),
             namespace: event.namespace,
             function: event.function,
@@ -128,9 +137,12 @@ pub struct SpanView {
     pub kind: SourceKindView,
     pub id: FullSpanIdView,
     pub ancestors: Vec<AncestorView>, // in root-first order
+    pub key: Timestamp,
     pub created_at: Timestamp,
     pub closed_at: Option<Timestamp>,
-    pub busy: Option<u64>,
+    pub precise_created_at: String, // in nanoseconds, too large for a JS number
+    pub precise_closed_at: Option<String>,
+    pub busy: Option<u64>, // in microseconds
     pub name: String,
     pub namespace: Option<String>,
     pub function: Option<String>,
@@ -146,9 +158,12 @@ impl From<ComposedSpan> for SpanView {
             kind: span.kind.to_string(),
             id: span.id.to_string(),
             ancestors: span.ancestors.into_iter().map(AncestorView::from).collect(),
-            created_at: span.created_at,
-            closed_at: span.closed_at,
-            busy: span.busy,
+            key: span.key,
+            created_at: to_micros(span.created_at),
+            closed_at: span.closed_at.map(to_micros),
+            precise_created_at: span.created_at.to_string(),
+            precise_closed_at: span.closed_at.map(|closed_at| closed_at.to_string()),
+            busy: span.busy.map(|busy| busy / 1000),
             name: span.name,
             namespace: span.namespace,
             function: span.function,
@@ -316,6 +331,318 @@ pub(crate) struct StatusView {
     pub ingress_bytes_per_second: f64,
     pub dataset_name: String,
     pub engine_load: f64,
+    pub cache: Option<CacheStatsView>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct HistogramBucketView {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub counts: [usize; 6],
+}
+
+impl From<HistogramBucket> for HistogramBucketView {
+    fn from(bucket: HistogramBucket) -> Self {
+        HistogramBucketView {
+            start: bucket.start,
+            end: bucket.end,
+            counts: bucket.counts,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct AggregateRowView {
+    pub group: Vec<Option<String>>,
+    pub values: Vec<Option<f64>>,
+}
+
+impl From<AggregateRow> for AggregateRowView {
+    fn from(row: AggregateRow) -> Self {
+        AggregateRowView {
+            group: row.group,
+            values: row.values,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct LatencyStatsView {
+    pub group: Option<String>,
+    pub count: usize,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+    pub histogram: Vec<LatencyBucketView>,
+}
+
+impl From<LatencyStats> for LatencyStatsView {
+    fn from(stats: LatencyStats) -> Self {
+        LatencyStatsView {
+            group: stats.group,
+            count: stats.count,
+            p50: stats.p50,
+            p90: stats.p90,
+            p99: stats.p99,
+            max: stats.max,
+            histogram: stats
+                .histogram
+                .into_iter()
+                .map(LatencyBucketView::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct LatencyBucketView {
+    pub start: u64,
+    pub end: Option<u64>, // unbounded for the last bucket
+    pub count: usize,
+}
+
+impl From<LatencyBucket> for LatencyBucketView {
+    fn from(bucket: LatencyBucket) -> Self {
+        LatencyBucketView {
+            start: bucket.start,
+            end: (bucket.end != u64::MAX).then_some(bucket.end),
+            count: bucket.count,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct TraceView {
+    pub spans: Vec<TraceSpanView>,
+    pub events: Vec<TraceEventView>,
+}
+
+impl From<Trace> for TraceView {
+    fn from(trace: Trace) -> Self {
+        TraceView {
+            spans: trace.spans.into_iter().map(TraceSpanView::from).collect(),
+            events: trace.events.into_iter().map(TraceEventView::from).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct TraceSpanView {
+    #[serde(flatten)]
+    pub span: SpanView,
+    pub parent_key: Option<Timestamp>,
+    pub depth: usize,
+    pub orphaned: bool,
+}
+
+impl From<TraceSpan> for TraceSpanView {
+    fn from(trace_span: TraceSpan) -> Self {
+        TraceSpanView {
+            span: trace_span.span.into(),
+            parent_key: trace_span.parent_key,
+            depth: trace_span.depth,
+            orphaned: trace_span.orphaned,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct TraceEventView {
+    #[serde(flatten)]
+    pub event: EventView,
+    pub parent_key: Option<Timestamp>,
+    pub depth: usize,
+    pub orphaned: bool,
+}
+
+impl From<TraceEvent> for TraceEventView {
+    fn from(trace_event: TraceEvent) -> Self {
+        TraceEventView {
+            event: trace_event.event.into(),
+            parent_key: trace_event.parent_key,
+            depth: trace_event.depth,
+            orphaned: trace_event.orphaned,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct CriticalPathSpanView {
+    pub key: Timestamp,
+    pub name: String,
+    pub self_time: u64,    // in microseconds
+    pub busy: Option<u64>, // in microseconds
+}
+
+impl From<CriticalPathSpan> for CriticalPathSpanView {
+    fn from(span: CriticalPathSpan) -> Self {
+        CriticalPathSpanView {
+            key: span.key,
+            name: span.name,
+            self_time: span.self_time,
+            busy: span.busy.map(|busy| busy / 1000),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ServiceDependencyView {
+    pub caller: ResourceKey,
+    pub callee: ResourceKey,
+    pub count: usize,
+    pub errors: usize,
+    pub latency: Option<LatencyStatsView>,
+}
+
+impl From<ServiceDependency> for ServiceDependencyView {
+    fn from(dependency: ServiceDependency) -> Self {
+        ServiceDependencyView {
+            caller: dependency.caller,
+            callee: dependency.callee,
+            count: dependency.count,
+            errors: dependency.errors,
+            latency: dependency.latency.map(LatencyStatsView::from),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct FlameGraphView {
+    pub roots: Vec<FlameNodeView>,
+}
+
+impl From<FlameGraph> for FlameGraphView {
+    fn from(flame_graph: FlameGraph) -> Self {
+        FlameGraphView {
+            roots: flame_graph
+                .roots
+                .into_iter()
+                .map(FlameNodeView::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct FlameNodeView {
+    pub name: String,
+    pub count: usize,
+    pub total_time: u64, // in microseconds
+    pub self_time: u64,  // in microseconds
+    pub children: Vec<FlameNodeView>,
+}
+
+impl From<FlameNode> for FlameNodeView {
+    fn from(node: FlameNode) -> Self {
+        FlameNodeView {
+            name: node.name,
+            count: node.count,
+            total_time: node.total_time,
+            self_time: node.self_time,
+            children: node.children.into_iter().map(FlameNodeView::from).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct SpanEventView {
+    pub kind: SpanEventType,
+    pub timestamp: Timestamp,
+    pub precise_timestamp: String, // in nanoseconds, too large for a JS number
+    pub attributes: Vec<LinkAttributeView>, // for create and update events
+    pub thread_id: Option<u64>,    // for enter events
+    pub follows: Option<Timestamp>, // for follows events
+    pub busy: Option<u64>,         // for close events, in microseconds
+}
+
+impl From<SpanEvent> for SpanEventView {
+    fn from(span_event: SpanEvent) -> Self {
+        let mut view = SpanEventView {
+            kind: span_event.kind.event_type(),
+            timestamp: to_micros(span_event.timestamp),
+            precise_timestamp: span_event.timestamp.to_string(),
+            attributes: Vec::new(),
+            thread_id: None,
+            follows: None,
+            busy: None,
+        };
+
+        match span_event.kind {
+            SpanEventKind::Create(create) => {
+                view.attributes = create
+                    .instrumentation_attributes
+                    .into_iter()
+                    .chain(create.attributes)
+                    .map(LinkAttributeView::from)
+                    .collect();
+            }
+            SpanEventKind::Update(update) => {
+                view.attributes = update
+                    .attributes
+                    .into_iter()
+                    .map(LinkAttributeView::from)
+                    .collect();
+            }
+            SpanEventKind::Follows(follows) => view.follows = Some(follows.follows),
+            SpanEventKind::Enter(enter) => view.thread_id = Some(enter.thread_id),
+            SpanEventKind::Exit => {}
+            SpanEventKind::Close(close) => view.busy = close.busy.map(|busy| busy / 1000),
+        }
+
+        view
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct AttributeSummaryView {
+    pub name: String,
+    pub count: usize,
+    pub kinds: Vec<ValueKind>,
+}
+
+impl From<AttributeSummary> for AttributeSummaryView {
+    fn from(summary: AttributeSummary) -> Self {
+        AttributeSummaryView {
+            name: summary.name,
+            count: summary.count,
+            kinds: summary.kinds,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct AttributeValueCountView {
+    pub value: String,
+    pub count: usize,
+}
+
+impl From<AttributeValueCount> for AttributeValueCountView {
+    fn from(value: AttributeValueCount) -> Self {
+        AttributeValueCountView {
+            value: value.value,
+            count: value.count,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct CacheStatsView {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub memory_estimate: usize,
+}
+
+impl From<CacheStats> for CacheStatsView {
+    fn from(stats: CacheStats) -> Self {
+        CacheStatsView {
+            hits: stats.hits,
+            misses: stats.misses,
+            evictions: stats.evictions,
+            memory_estimate: stats.memory_estimate,
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -323,6 +650,7 @@ pub(crate) struct DeleteMetricsView {
     pub spans: usize,
     pub span_events: usize,
     pub events: usize,
+    pub tombstone: Option<Timestamp>,
 }
 
 impl From<DeleteMetrics> for DeleteMetricsView {
@@ -331,10 +659,105 @@ impl From<DeleteMetrics> for DeleteMetricsView {
             spans: metrics.spans,
             span_events: metrics.span_events,
             events: metrics.events,
+            tombstone: metrics.tombstone,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct CompactMetricsView {
+    pub bytes_reclaimed: u64,
+}
+
+impl From<CompactMetrics> for CompactMetricsView {
+    fn from(metrics: CompactMetrics) -> Self {
+        CompactMetricsView {
+            bytes_reclaimed: metrics.bytes_reclaimed,
         }
     }
 }
 
+#[derive(Serialize)]
+pub(crate) struct MergeMetricsView {
+    pub resources: usize,
+    pub spans: usize,
+    pub span_events: usize,
+    pub events: usize,
+}
+
+impl From<MergeMetrics> for MergeMetricsView {
+    fn from(metrics: MergeMetrics) -> Self {
+        MergeMetricsView {
+            resources: metrics.resources,
+            spans: metrics.spans,
+            span_events: metrics.span_events,
+            events: metrics.events,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct IntegrityReportView {
+    pub ok: bool,
+    pub dangling_parents: usize,
+    pub dangling_span_events: usize,
+    pub orphans: usize,
+    pub duplicate_span_ids: usize,
+    pub unclosed_spans: usize,
+    pub index_mismatches: usize,
+}
+
+impl From<IntegrityReport> for IntegrityReportView {
+    fn from(report: IntegrityReport) -> Self {
+        IntegrityReportView {
+            ok: report.is_ok(),
+            dangling_parents: report.dangling_span_parents.len()
+                + report.dangling_event_parents.len(),
+            dangling_span_events: report.dangling_span_events.len(),
+            orphans: report.orphaned_spans.len() + report.orphaned_events.len(),
+            duplicate_span_ids: report.duplicate_span_ids.len(),
+            unclosed_spans: report.unclosed_spans.len(),
+            index_mismatches: report.mismatched_spans.len()
+                + report.mismatched_span_events.len()
+                + report.mismatched_events.len(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct AnnotationView {
+    pub key: Timestamp,
+    pub span: Option<Timestamp>,
+    pub event: Option<Timestamp>,
+    pub start: Option<Timestamp>,
+    pub end: Option<Timestamp>,
+    pub text: String,
+}
+
+impl From<Annotation> for AnnotationView {
+    fn from(annotation: Annotation) -> Self {
+        let mut view = AnnotationView {
+            key: annotation.key,
+            span: None,
+            event: None,
+            start: None,
+            end: None,
+            text: annotation.text,
+        };
+
+        match annotation.target {
+            AnnotationTarget::Span(key) => view.span = Some(key),
+            AnnotationTarget::Event(key) => view.event = Some(key),
+            AnnotationTarget::Timespan(start, end) => {
+                view.start = Some(start);
+                view.end = Some(end);
+            }
+        }
+
+        view
+    }
+}
+
 #[derive(Clone, Serialize)]
 #[serde(tag = "kind", content = "entity", rename_all = "snake_case")]
 pub(crate) enum SubscriptionResponseView<T> {
@@ -1,8 +1,8 @@
 import { listen } from '@tauri-apps/api/event';
-import { ask, message, save } from '@tauri-apps/plugin-dialog';
+import { ask, message, open, save } from '@tauri-apps/plugin-dialog';
 import { writeTextFile } from '@tauri-apps/plugin-fs';
 import { EventsScreen } from "./screens/events-screen";
-import { AppStatus, deleteEntities, Event, getEvents, getSpans, getStats, getStatus, Input, loadSession, parseEventFilter, parseSpanFilter, saveSession, Session, SessionTab, Span, Timestamp, ValidFilterPredicate } from "./invoke";
+import { AppStatus, CacheStats, compactDataset, deleteEntities, Event, getEvents, getSpans, getStats, getStatus, Input, loadSession, mergeDataset, parseEventFilter, parseSpanFilter, repairDataset, saveSession, Session, SessionTab, Span, Timestamp, undoDelete, ValidFilterPredicate, verifyDataset } from "./invoke";
 import { batch, createSignal, Match, onMount, Show, Switch } from "solid-js";
 import { Counts, PaginationFilter, PartialCountFilter, PartialFilter, PositionedSpan, Timespan } from "./models";
 import { SpansScreen } from "./screens/spans-screen";
@@ -208,6 +208,7 @@ function App() {
 
     let [selectedScreen, setSelectedScreen] = createSignal<number | undefined>();
     let [status, setStatus] = createSignal<AppStatus | null>(null);
+    let [lastTombstone, setLastTombstone] = createSignal<Timestamp | null>(null);
 
     let undoHistories: UndoHistory[] = [];
     let root_element = document.querySelector('#root')!;
@@ -373,7 +374,7 @@ function App() {
                         break;
                     }
 
-                    previous = events[events.length - 1].timestamp;
+                    previous = events[events.length - 1].key;
                 }
             } else if (current_screen.kind == 'spans') {
                 let previous: number | undefined;
@@ -395,7 +396,7 @@ function App() {
                         break;
                     }
 
-                    previous = spans[spans.length - 1].created_at;
+                    previous = spans[spans.length - 1].key;
                 }
             }
 
@@ -472,7 +473,8 @@ function App() {
             });
 
             if (answer) {
-                await deleteEntities(null, null, true, false);
+                let deleted = await deleteEntities(null, null, true, false);
+                setLastTombstone(deleted.tombstone);
 
                 forceResetScreenFilters();
             }
@@ -490,7 +492,8 @@ function App() {
             });
 
             if (answer) {
-                await deleteEntities(timespan[0], timespan[1], true, false);
+                let deleted = await deleteEntities(timespan[0], timespan[1], true, false);
+                setLastTombstone(deleted.tombstone);
 
                 forceResetScreenFilters();
             }
@@ -508,12 +511,115 @@ function App() {
             });
 
             if (answer) {
-                await deleteEntities(timespan[0], timespan[1], false, false);
+                let deleted = await deleteEntities(timespan[0], timespan[1], false, false);
+                setLastTombstone(deleted.tombstone);
 
                 forceResetScreenFilters();
             }
         });
 
+        await listen('delete-matching-clicked', async () => {
+            let screen = screens()[selectedScreen()!];
+            if (screen.kind == 'trace') {
+                await message(`Deleting by filter is only available from the events and spans screens.`, {
+                    title: `Delete from ${status()?.dataset_name}`,
+                    kind: 'info',
+                });
+                return;
+            }
+
+            let timespan = screen.timespan!;
+            let filter = screen.filter.filter(f => f.input == 'valid') as ValidFilterPredicate[];
+            let spanFilter = screen.kind == 'spans' ? filter : null;
+            let eventFilter = screen.kind == 'events' ? filter : null;
+
+            let wholeTraces = await ask(`Also delete the rest of the traces that matching ${screen.kind} belong to?`, {
+                title: `Delete from ${status()?.dataset_name}`,
+                kind: 'info',
+            });
+
+            let metrics = await deleteEntities(timespan[0], timespan[1], true, true, spanFilter, eventFilter, wholeTraces);
+
+            let answer = await ask(`This will delete ${metrics.spans} spans and ${metrics.events} events. \n\n Proceed?`, {
+                title: `Delete from ${status()?.dataset_name}`,
+                kind: 'warning',
+            });
+
+            if (answer) {
+                let deleted = await deleteEntities(timespan[0], timespan[1], true, false, spanFilter, eventFilter, wholeTraces);
+                setLastTombstone(deleted.tombstone);
+
+                forceResetScreenFilters();
+            }
+        });
+
+        await listen('undo-delete-clicked', async () => {
+            let tombstone = lastTombstone();
+            if (tombstone == null) {
+                await message(`There is no delete to undo.`, {
+                    title: `Undo delete in ${status()?.dataset_name}`,
+                    kind: 'info',
+                });
+                return;
+            }
+
+            let metrics = await undoDelete(tombstone);
+            setLastTombstone(null);
+
+            await message(`Restored ${metrics.spans} spans and ${metrics.events} events.`, {
+                title: `Undo delete in ${status()?.dataset_name}`,
+                kind: 'info',
+            });
+
+            forceResetScreenFilters();
+        });
+
+        await listen('compact-dataset-clicked', async () => {
+            let metrics = await compactDataset();
+
+            await message(`Reclaimed ${(metrics.bytes_reclaimed / 1048576).toFixed(2)} MB.`, {
+                title: `Compact ${status()?.dataset_name}`,
+                kind: 'info',
+            });
+        });
+
+        await listen('verify-dataset-clicked', async () => {
+            let report = await verifyDataset();
+
+            if (report.ok) {
+                await message(`No problems were found.`, {
+                    title: `Verify ${status()?.dataset_name}`,
+                    kind: 'info',
+                });
+                return;
+            }
+
+            let answer = await ask(`Found ${report.dangling_parents} dangling parents, ${report.dangling_span_events} dangling span events, ${report.orphans} orphans, ${report.duplicate_span_ids} duplicate span ids, ${report.unclosed_spans} unclosed spans, and ${report.index_mismatches} index mismatches. \n\n Repair?`, {
+                title: `Verify ${status()?.dataset_name}`,
+                kind: 'warning',
+            });
+
+            if (answer) {
+                await repairDataset();
+
+                forceResetScreenFilters();
+            }
+        });
+
+        await listen('merge-dataset-clicked', async () => {
+            let path = await open({ multiple: false, directory: false });
+            if (path == null) {
+                return;
+            }
+
+            let metrics = await mergeDataset(path);
+
+            await message(`Merged ${metrics.spans} spans and ${metrics.events} events.`, {
+                title: `Merge into ${status()?.dataset_name}`,
+                kind: 'info',
+            });
+        });
+
         document.onkeydown = function (e) {
             if (e.ctrlKey && !e.altKey && !e.shiftKey && e.key == 'z') {
                 e.preventDefault();
@@ -585,7 +691,7 @@ function App() {
         return await screen.store.getEvents(filter, wait);
     }
 
-    async function getAndCacheEventCounts(screen: EventsScreenData, filter: PartialCountFilter, wait?: boolean, cache?: boolean): Promise<Counts | null> {
+    async function getAndCacheEventCounts(screen: EventsScreenData, filter: PartialCountFilter, wait?: boolean, cache?: boolean): Promise<Counts[] | null> {
         return await screen.store.getEventCounts(filter, wait, cache);
     }
 
@@ -593,7 +699,7 @@ function App() {
         return await screen.store.getSpans(filter, wait);
     }
 
-    async function getAndCacheSpanCounts(screen: SpansScreenData, filter: PartialCountFilter, wait?: boolean, cache?: boolean): Promise<Counts | null> {
+    async function getAndCacheSpanCounts(screen: SpansScreenData, filter: PartialCountFilter, wait?: boolean, cache?: boolean): Promise<Counts[] | null> {
         return await screen.store.getSpanCounts(filter, wait, cache);
     }
 
@@ -1604,6 +1710,12 @@ function App() {
                         <span style="display: inline-block; padding: 0 8px; width: 75px; text-align: right;">{formatBytesPerSecond(s().ingress_bytes_per_second)}</span>
                         -
                         <span style="display: inline-block; padding: 0 8px; width: 75px;">load: {s().engine_load.toFixed(1)}%</span>
+                        <Show when={s().cache}>
+                            {c => <>
+                                -
+                                <span style="display: inline-block; padding: 0 8px; width: 90px;" title={formatCacheStats(c())}>cache: {formatCacheHitRate(c())}</span>
+                            </>}
+                        </Show>
                     </span>
                 </>}
             </Show>
@@ -1611,6 +1723,19 @@ function App() {
     </>);
 }
 
+function formatCacheHitRate(stats: CacheStats): string {
+    let total = stats.hits + stats.misses;
+    if (total == 0) {
+        return '--';
+    }
+
+    return (stats.hits / total * 100).toFixed(1) + '%';
+}
+
+function formatCacheStats(stats: CacheStats): string {
+    return `hits: ${stats.hits}\nmisses: ${stats.misses}\nevictions: ${stats.evictions}\nmemory: ~${(stats.memory_estimate / 1000000).toFixed(1)} MB`;
+}
+
 function formatBytesPerSecond(bytes: number): string {
     if (bytes > 1000000) {
         return (bytes / 1000000).toFixed(1) + ' MB/s';
@@ -2,7 +2,7 @@ import { createEffect, createMemo, createSignal, For, Match, Show, Switch, useCo
 import { writeText } from '@tauri-apps/plugin-clipboard-manager';
 import { Menu } from '@tauri-apps/api/menu';
 import { LogicalPosition } from '@tauri-apps/api/dpi';
-import { Ancestor, Attribute, Event, FilterPredicate, FullSpanId, getEventCount, getSpanCount, Input, Span, TraceRoot } from '../invoke'
+import { addAnnotation, Ancestor, Attribute, Event, FilterPredicate, FullSpanId, getEventCount, getSpans, Input, setEventPinned, setSpanPinned, Span, TraceRoot } from '../invoke'
 import { Timespan } from '../models';
 import { NavigationContext } from '../context/navigation';
 import { ColumnData, ScreenData } from '../App';
@@ -44,8 +44,8 @@ export function EventDetailPane(props: EventDetailPaneProps) {
     createEffect(async () => {
         let countAtTimestamp = await getEventCount({
             filter: props.filter.filter(f => f.input == 'valid'),
-            start: props.event.timestamp,
-            end: props.event.timestamp,
+            start: props.event.key,
+            end: props.event.key,
         });
         setInFilter(countAtTimestamp == 1);
     })
@@ -63,6 +63,18 @@ export function EventDetailPane(props: EventDetailPaneProps) {
         await menu.popup(new LogicalPosition(e.clientX, e.clientY));
     }
 
+    async function showHeaderContextMenu(e: MouseEvent) {
+        e.preventDefault();
+        let menu = await Menu.new({
+            items: [
+                { text: "pin event", action: () => setEventPinned(props.event.key, true) },
+                { text: "unpin event", action: () => setEventPinned(props.event.key, false) },
+                { text: "bookmark event", action: () => addAnnotation({ event: props.event.key }, "") },
+            ]
+        });
+        await menu.popup(new LogicalPosition(e.clientX, e.clientY));
+    }
+
     let startingX = 0;
     let startingWidth = 0;
     function ongrab(e: MouseEvent) {
@@ -102,7 +114,7 @@ export function EventDetailPane(props: EventDetailPaneProps) {
     return (<>
         <div id="detail-pane-grabber" onmousedown={ongrab} oncontextmenu={showGrabberContextMenu}></div>
         <div id="detail-pane" style={`width: ${width()}px; min-width: ${width()}px;`}>
-            <div id="detail-header" onauxclick={onClickHeader} onclick={onClickHeader}>
+            <div id="detail-header" onauxclick={onClickHeader} onclick={onClickHeader} oncontextmenu={showHeaderContextMenu}>
                 <span>event details</span>
                 <button onclick={() => props.updateSelectedRow(null)}>X</button>
             </div>
@@ -182,7 +194,9 @@ export function SpanDetailPane(props: SpanDetailPaneProps) {
     }
 
     createEffect(async () => {
-        let countAtTimestamp = await getSpanCount({
+        // other spans may be created in the same microsecond, so this checks
+        // that this exact span is returned
+        let spansAtTimestamp = await getSpans({
             filter: props.filter.filter(f => f.input == 'valid').map(f => f as FilterPredicate).concat({
                 predicate_kind: 'single',
                 predicate: {
@@ -192,10 +206,11 @@ export function SpanDetailPane(props: SpanDetailPaneProps) {
                     value: ['Eq', `${props.span.created_at}`],
                 },
             } as FilterPredicate),
+            order: 'asc',
             start: props.span.created_at,
-            end: props.span.created_at,
+            end: props.span.key,
         });
-        setInFilter(countAtTimestamp == 1);
+        setInFilter(spansAtTimestamp.some(span => span.key == props.span.key));
     })
 
     function onClickHeader(e: MouseEvent) {
@@ -211,6 +226,18 @@ export function SpanDetailPane(props: SpanDetailPaneProps) {
         await menu.popup(new LogicalPosition(e.clientX, e.clientY));
     }
 
+    async function showHeaderContextMenu(e: MouseEvent) {
+        e.preventDefault();
+        let menu = await Menu.new({
+            items: [
+                { text: "pin trace", action: () => setSpanPinned(props.span.key, true) },
+                { text: "unpin trace", action: () => setSpanPinned(props.span.key, false) },
+                { text: "bookmark span", action: () => addAnnotation({ span: props.span.key }, "") },
+            ]
+        });
+        await menu.popup(new LogicalPosition(e.clientX, e.clientY));
+    }
+
     let startingX = 0;
     let startingWidth = 0;
     function ongrab(e: MouseEvent) {
@@ -250,7 +277,7 @@ export function SpanDetailPane(props: SpanDetailPaneProps) {
     return (<>
         <div id="detail-pane-grabber" onmousedown={ongrab} oncontextmenu={showGrabberContextMenu}></div>
         <div id="detail-pane" style={`width: ${width()}px; min-width: ${width()}px;`}>
-            <div id="detail-header" onauxclick={onClickHeader} onclick={onClickHeader}>
+            <div id="detail-header" onauxclick={onClickHeader} onclick={onClickHeader} oncontextmenu={showHeaderContextMenu}>
                 span details
                 <button onclick={() => props.updateSelectedRow(null)}>X</button>
             </div>
@@ -16,7 +16,7 @@ export type SpanGraphProps = {
     setCount: (count: [number, boolean]) => void,
 
     getPositionedSpans: (filter: PartialFilter, wait?: boolean) => Promise<PositionedSpan[] | null>,
-    getSpanCounts: (filter: PartialCountFilter, wait?: boolean, cache?: boolean) => Promise<Counts | null>,
+    getSpanCounts: (filter: PartialCountFilter, wait?: boolean, cache?: boolean) => Promise<Counts[] | null>,
 };
 
 export function SpanGraph(props: SpanGraphProps) {
@@ -132,7 +132,7 @@ export type SpanCountGraphProps = {
     updateTimespan: (new_timespan: Timespan) => void,
     hoveredRow: Span | null,
 
-    getSpanCounts: (filter: PartialCountFilter, wait?: boolean, cache?: boolean) => Promise<Counts | null>,
+    getSpanCounts: (filter: PartialCountFilter, wait?: boolean, cache?: boolean) => Promise<Counts[] | null>,
 
     setCount: (count: [number, boolean]) => void,
 };
@@ -145,65 +145,55 @@ export function SpanCountGraph(props: SpanCountGraphProps) {
         let current_timespan = props.timespan;
 
         const buckets = calcBucketSizes(current_timespan[0], current_timespan[1]);
+        if (buckets.length == 0) {
+            batch(() => {
+                props.setCount([0, true]);
+                setBars([]);
+            });
+            return;
+        }
 
-        let initial_height = 1;
-        let initial_bars = buckets.map(span => [span, null] as [Timespan, Counts | null]);
-        let initial_count = 0;
-        for (let i in buckets) {
-            let [start, end] = buckets[i];
-            let current_bar = await props.getSpanCounts({ start, end }, false);
-            let current_overlaps = await props.getSpanCounts({ start, end: start }, false);
-
-            if (current_bar != null && current_overlaps != null) {
-                initial_bars[i] = [initial_bars[i][0], current_bar];
-
-                let current_overlap = current_overlaps[0] + current_overlaps[1] + current_overlaps[2] + current_overlaps[3] + current_overlaps[4] + current_overlaps[5];
-                let current_total = current_bar[0] + current_bar[1] + current_bar[2] + current_bar[3] + current_bar[4] + current_bar[5];
-                if (current_total > initial_height) {
-                    initial_height = current_total;
-                }
+        // all the bars are fetched at once, but the end of the last bucket is
+        // excluded since it is the start of the next one
+        let histogram_filter = {
+            start: buckets[0][0],
+            end: buckets[buckets.length - 1][1] - 1,
+            buckets: buckets.length,
+        };
 
-                initial_count += current_total - current_overlap;
-            }
+        // the count only covers the timespan, which may only partially cover
+        // the first and last buckets, and a single bucket counts each span once
+        let count_filter = {
+            start: current_timespan[0],
+            end: current_timespan[1],
+            buckets: 1,
+        };
+
+        function isStale(): boolean {
+            return current_timespan != props.timespan || current_filter != props.filter || props.mode != 'count';
         }
 
+        let initial_counts = await props.getSpanCounts(histogram_filter, false);
+        let initial_count = await props.getSpanCounts(count_filter, false);
+
         batch(() => {
-            props.setCount([initial_count, false]);
-            setBars(initial_bars);
+            props.setCount([initial_count?.[0] != undefined ? countTotal(initial_count[0]) : 0, false]);
+            setBars(buckets.map((span, i) => [span, initial_counts?.[i] ?? null]));
         });
 
-        let count = 0;
-        for (let i in buckets) {
-            // await new Promise(resolve => setTimeout(resolve, 5));
-
-            let [start, end] = buckets[i];
-            let new_bar = (await props.getSpanCounts({ start, end }))!;
-            if (start >= current_timespan[0] && end <= current_timespan[1]) {
-                let new_overlaps = (await props.getSpanCounts({ start, end: start }))!;
-                let new_overlap = new_overlaps[0] + new_overlaps[1] + new_overlaps[2] + new_overlaps[3] + new_overlaps[4] + new_overlaps[5];
-                count += new_bar[0] + new_bar[1] + new_bar[2] + new_bar[3] + new_bar[4] - new_overlap;
-            } else {
-                let p_start = Math.max(start, current_timespan[0]);
-                let p_end = Math.min(end, current_timespan[1]);
-                let partial_bar = (await props.getSpanCounts({ start: p_start, end: p_end }, true, false))!;
-                let new_overlaps = end > current_timespan[1] ? (await props.getSpanCounts({ start, end: start }))! : [0, 0, 0, 0, 0, 0];
-                let new_overlap = new_overlaps[0] + new_overlaps[1] + new_overlaps[2] + new_overlaps[3] + new_overlaps[4] + new_overlaps[5];
-                count += partial_bar[0] + partial_bar[1] + partial_bar[2] + partial_bar[3] + partial_bar[4] - new_overlap;
-            }
+        let counts = (await props.getSpanCounts(histogram_filter))!;
+        if (isStale()) {
+            return;
+        }
 
-            if (current_timespan != props.timespan || current_filter != props.filter || props.mode != 'count') {
-                return;
-            }
+        setBars(buckets.map((span, i) => [span, counts[i] ?? null]));
 
-            let current_bars = untrack(bars);
-            if (current_bars[i][1] == new_bar) continue;
-            let updated_bars = [...current_bars];
-            updated_bars[i] = [updated_bars[i][0], new_bar];
-            setBars(updated_bars);
-            props.setCount([count, false]);
+        let count = (await props.getSpanCounts(count_filter))!;
+        if (isStale()) {
+            return;
         }
 
-        props.setCount([count, true]);
+        props.setCount([count[0] != undefined ? countTotal(count[0]) : 0, true]);
     });
 
     function barHeightMax() {
@@ -319,6 +309,10 @@ const BUCKET_SIZES = [
     864000000000,// 10d
 ];
 
+function countTotal(counts: Counts): number {
+    return counts[0] + counts[1] + counts[2] + counts[3] + counts[4] + counts[5];
+}
+
 function indexOfSmallest<T>(a: T[], f: (a: T) => number) {
     let lowest_id = 0;
     let lowest_value = f(a[0]);
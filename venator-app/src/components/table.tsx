@@ -912,7 +912,7 @@ export function Table<T extends Event | Span>(props: TableProps<T>) {
         let current_order = order();
         let current_entries = entries();
         let last_entry = current_entries[current_entries.length - 1];
-        let new_events = (await props.getEntries({ order: current_order, start, end, previous: getTimestamp(last_entry) }))!;
+        let new_events = (await props.getEntries({ order: current_order, start, end, previous: getKey(last_entry) }))!;
         setEntries(current_entries.concat(new_events));
 
         let new_status = (new_events.length == 50) ? 'partial' as const : 'done' as const;
@@ -926,8 +926,8 @@ export function Table<T extends Event | Span>(props: TableProps<T>) {
         }
     }
 
-    function getTimestamp(e: T): Timestamp {
-        return (e as any).timestamp || (e as any).created_at || (e as any).connected_at;
+    function getKey(e: T): Timestamp {
+        return (e as any).key || (e as any).connected_at;
     }
 
     function toggleOrder() {
@@ -964,7 +964,7 @@ export function Table<T extends Event | Span>(props: TableProps<T>) {
             return false;
         }
 
-        return getTimestamp(selected) == getTimestamp(row);
+        return getKey(selected) == getKey(row);
     }
 
     function isHovered(row: T): boolean {
@@ -973,7 +973,7 @@ export function Table<T extends Event | Span>(props: TableProps<T>) {
             return false;
         }
 
-        return getTimestamp(hovered) == getTimestamp(row);
+        return getKey(hovered) == getKey(row);
     }
 
     function getGridTemplateColumns(): string {
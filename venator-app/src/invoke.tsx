@@ -54,10 +54,142 @@ export type CountFilter = {
     end: Timestamp;
 };
 
+export type HistogramFilter = {
+    filter: FilterPredicate[];
+    start: Timestamp;
+    end: Timestamp;
+    buckets: number;
+};
+
+export type HistogramBucket = {
+    start: Timestamp;
+    end: Timestamp;
+    counts: [number, number, number, number, number, number];
+};
+
+export type AggregateFilter = {
+    filter: FilterPredicate[];
+    start: Timestamp;
+    end: Timestamp;
+    groupBy: string[];
+    aggregates: string[];
+};
+
+export type AggregateRow = {
+    group: (string | null)[];
+    values: (number | null)[];
+};
+
+export type LatencyFilter = {
+    filter: FilterPredicate[];
+    start: Timestamp;
+    end: Timestamp;
+    groupBy: 'name' | 'function' | null;
+};
+
+export type LatencyStats = {
+    group: string | null;
+    count: number;
+    p50: number;
+    p90: number;
+    p99: number;
+    max: number;
+    histogram: LatencyBucket[];
+};
+
+export type LatencyBucket = {
+    start: number;
+    end: number | null;
+    count: number;
+};
+
+export type Trace = {
+    spans: (Span & TraceNode)[];
+    events: (Event & TraceNode)[];
+};
+
+export type TraceNode = {
+    parent_key: Timestamp | null;
+    depth: number;
+    orphaned: boolean;
+};
+
+export type CriticalPathSpan = {
+    key: Timestamp;
+    name: string;
+    self_time: number;
+    busy: number | null;
+};
+
+export type ServiceDependency = {
+    caller: number;
+    callee: number;
+    count: number;
+    errors: number;
+    latency: LatencyStats | null;
+};
+
+export type FlameGraph = {
+    roots: FlameNode[];
+};
+
+export type FlameNode = {
+    name: string;
+    count: number;
+    total_time: number;
+    self_time: number;
+    children: FlameNode[];
+};
+
+export type SpanEventType = 'create' | 'update' | 'follows' | 'enter' | 'exit' | 'close';
+
+export type SpanEvent = {
+    kind: SpanEventType;
+    timestamp: Timestamp;
+    precise_timestamp: string;
+    attributes: SpanEventAttribute[];
+    thread_id: number | null;
+    follows: Timestamp | null;
+    busy: number | null;
+};
+
+export type SpanEventAttribute = {
+    name: string;
+    value: string;
+    type: Attribute['type'];
+};
+
+export type AttributeNamesFilter = {
+    filter: FilterPredicate[];
+    start: Timestamp;
+    end: Timestamp;
+};
+
+export type AttributeValuesFilter = {
+    name: string;
+    prefix: string;
+    limit: number;
+};
+
+export type ValueKind = 'null' | 'f64' | 'i64' | 'u64' | 'i128' | 'u128' | 'bool' | 'str' | 'bytes' | 'array' | 'object';
+
+export type AttributeSummary = {
+    name: string;
+    count: number;
+    kinds: ValueKind[];
+};
+
+export type AttributeValueCount = {
+    value: string;
+    count: number;
+};
+
 export type Event = {
     kind: SourceKind,
     ancestors: Ancestor[];
+    key: Timestamp;
     timestamp: Timestamp;
+    precise_timestamp: string; // in nanoseconds
     content: string;
     namespace: string | null;
     function: string | null;
@@ -79,8 +211,11 @@ export type Span = {
     kind: SourceKind,
     id: FullSpanId,
     ancestors: Ancestor[];
+    key: Timestamp;
     created_at: Timestamp;
     closed_at: Timestamp | null;
+    precise_created_at: string; // in nanoseconds
+    precise_closed_at: string | null;
     busy: number | null;
     name: string;
     namespace: string | null;
@@ -109,12 +244,56 @@ export type AppStatus = {
     ingress_bytes_per_second: number;
     dataset_name: string;
     engine_load: number;
+    cache: CacheStats | null;
+};
+
+export type CacheStats = {
+    hits: number;
+    misses: number;
+    evictions: number;
+    memory_estimate: number;
 };
 
 export type DeleteMetrics = {
     spans: number;
     span_events: number;
     events: number;
+    tombstone: Timestamp | null;
+};
+
+export type Annotation = {
+    key: Timestamp;
+    span: Timestamp | null;
+    event: Timestamp | null;
+    start: Timestamp | null;
+    end: Timestamp | null;
+    text: string;
+};
+
+export type AnnotationTarget =
+    | { span: Timestamp }
+    | { event: Timestamp }
+    | { start: Timestamp; end: Timestamp };
+
+export type CompactMetrics = {
+    bytes_reclaimed: number;
+};
+
+export type MergeMetrics = {
+    resources: number;
+    spans: number;
+    span_events: number;
+    events: number;
+};
+
+export type IntegrityReport = {
+    ok: boolean;
+    dangling_parents: number;
+    dangling_span_events: number;
+    orphans: number;
+    duplicate_span_ids: number;
+    unclosed_spans: number;
+    index_mismatches: number;
 };
 
 export type Session = {
@@ -152,6 +331,16 @@ export async function getEventCount(filter: CountFilter): Promise<number> {
     return await invoke<number>("get_event_count", filter);
 }
 
+export async function getEventHistogram(filter: HistogramFilter): Promise<HistogramBucket[]> {
+    console.debug("invoking 'get_event_histogram'");
+    return await invoke<HistogramBucket[]>("get_event_histogram", filter);
+}
+
+export async function getEventAggregate(filter: AggregateFilter): Promise<AggregateRow[]> {
+    console.debug("invoking 'get_event_aggregate'");
+    return await invoke<AggregateRow[]>("get_event_aggregate", filter);
+}
+
 export async function parseEventFilter(filter: string): Promise<Input[]> {
     console.debug("invoking 'parse_event_filter'");
     return await invoke<Input[]>("parse_event_filter", { filter });
@@ -167,14 +356,134 @@ export async function getSpanCount(filter: CountFilter): Promise<number> {
     return await invoke<number>("get_span_count", filter);
 }
 
+export async function getSpanHistogram(filter: HistogramFilter): Promise<HistogramBucket[]> {
+    console.debug("invoking 'get_span_histogram'");
+    return await invoke<HistogramBucket[]>("get_span_histogram", filter);
+}
+
+export async function getSpanAggregate(filter: AggregateFilter): Promise<AggregateRow[]> {
+    console.debug("invoking 'get_span_aggregate'");
+    return await invoke<AggregateRow[]>("get_span_aggregate", filter);
+}
+
+export async function getSpanLatency(filter: LatencyFilter): Promise<LatencyStats[]> {
+    console.debug("invoking 'get_span_latency'");
+    return await invoke<LatencyStats[]>("get_span_latency", filter);
+}
+
+export async function getTrace(trace: TraceRoot): Promise<Trace | null> {
+    console.debug("invoking 'get_trace'");
+    return await invoke<Trace | null>("get_trace", { trace });
+}
+
+export async function getCriticalPath(trace: TraceRoot): Promise<CriticalPathSpan[] | null> {
+    console.debug("invoking 'get_critical_path'");
+    return await invoke<CriticalPathSpan[] | null>("get_critical_path", { trace });
+}
+
+export async function getFlameGraph(filter: CountFilter): Promise<FlameGraph> {
+    console.debug("invoking 'get_flame_graph'");
+    return await invoke<FlameGraph>("get_flame_graph", filter);
+}
+
+export async function exportFlameGraph(filter: CountFilter, path: string): Promise<void> {
+    console.debug("invoking 'export_flame_graph'");
+    return await invoke<void>("export_flame_graph", { ...filter, path });
+}
+
+export async function getServiceDependencies(filter: CountFilter): Promise<ServiceDependency[]> {
+    console.debug("invoking 'get_service_dependencies'");
+    return await invoke<ServiceDependency[]>("get_service_dependencies", filter);
+}
+
+export async function getSpanTimeline(span: Timestamp, kinds: SpanEventType[] | null = null): Promise<SpanEvent[]> {
+    console.debug("invoking 'get_span_timeline'");
+    return await invoke<SpanEvent[]>("get_span_timeline", { span, kinds });
+}
+
+export async function getEventAttributeNames(filter: AttributeNamesFilter): Promise<AttributeSummary[]> {
+    console.debug("invoking 'get_event_attribute_names'");
+    return await invoke<AttributeSummary[]>("get_event_attribute_names", filter);
+}
+
+export async function getSpanAttributeNames(filter: AttributeNamesFilter): Promise<AttributeSummary[]> {
+    console.debug("invoking 'get_span_attribute_names'");
+    return await invoke<AttributeSummary[]>("get_span_attribute_names", filter);
+}
+
+export async function getEventAttributeValues(filter: AttributeValuesFilter): Promise<AttributeValueCount[]> {
+    console.debug("invoking 'get_event_attribute_values'");
+    return await invoke<AttributeValueCount[]>("get_event_attribute_values", filter);
+}
+
+export async function getSpanAttributeValues(filter: AttributeValuesFilter): Promise<AttributeValueCount[]> {
+    console.debug("invoking 'get_span_attribute_values'");
+    return await invoke<AttributeValueCount[]>("get_span_attribute_values", filter);
+}
+
 export async function parseSpanFilter(filter: string): Promise<Input[]> {
     console.debug("invoking 'parse_span_filter'");
     return await invoke<Input[]>("parse_span_filter", { filter });
 }
 
-export async function deleteEntities(start: Timestamp | null, end: Timestamp | null, inside: boolean, dryRun: boolean): Promise<DeleteMetrics> {
+export async function deleteEntities(start: Timestamp | null, end: Timestamp | null, inside: boolean, dryRun: boolean, spanFilter: FilterPredicate[] | null = null, eventFilter: FilterPredicate[] | null = null, wholeTraces: boolean = true): Promise<DeleteMetrics> {
     console.debug("invoking 'delete_entities'");
-    return await invoke<DeleteMetrics>("delete_entities", { start, end, inside, dryRun });
+    return await invoke<DeleteMetrics>("delete_entities", { start, end, inside, dryRun, spanFilter, eventFilter, wholeTraces });
+}
+
+export async function setSpanPinned(span: Timestamp, pinned: boolean): Promise<void> {
+    console.debug("invoking 'set_span_pinned'");
+    return await invoke<void>("set_span_pinned", { span, pinned });
+}
+
+export async function setEventPinned(event: Timestamp, pinned: boolean): Promise<void> {
+    console.debug("invoking 'set_event_pinned'");
+    return await invoke<void>("set_event_pinned", { event, pinned });
+}
+
+export async function getAnnotations(): Promise<Annotation[]> {
+    console.debug("invoking 'get_annotations'");
+    return await invoke<Annotation[]>("get_annotations");
+}
+
+export async function addAnnotation(target: AnnotationTarget, text: string): Promise<Timestamp> {
+    console.debug("invoking 'add_annotation'");
+    return await invoke<Timestamp>("add_annotation", { ...target, text });
+}
+
+export async function updateAnnotation(annotation: Timestamp, text: string): Promise<void> {
+    console.debug("invoking 'update_annotation'");
+    return await invoke<void>("update_annotation", { annotation, text });
+}
+
+export async function removeAnnotation(annotation: Timestamp): Promise<void> {
+    console.debug("invoking 'remove_annotation'");
+    return await invoke<void>("remove_annotation", { annotation });
+}
+
+export async function undoDelete(tombstone: Timestamp): Promise<DeleteMetrics> {
+    console.debug("invoking 'undo_delete'");
+    return await invoke<DeleteMetrics>("undo_delete", { tombstone });
+}
+
+export async function compactDataset(): Promise<CompactMetrics> {
+    console.debug("invoking 'compact_dataset'");
+    return await invoke<CompactMetrics>("compact_dataset");
+}
+
+export async function mergeDataset(path: string): Promise<MergeMetrics> {
+    console.debug("invoking 'merge_dataset'");
+    return await invoke<MergeMetrics>("merge_dataset", { path });
+}
+
+export async function verifyDataset(): Promise<IntegrityReport> {
+    console.debug("invoking 'verify_dataset'");
+    return await invoke<IntegrityReport>("verify_dataset");
+}
+
+export async function repairDataset(): Promise<IntegrityReport> {
+    console.debug("invoking 'repair_dataset'");
+    return await invoke<IntegrityReport>("repair_dataset");
 }
 
 export async function subscribeToSpans(filter: FilterPredicate[], channel: Channel<SubscriptionResponse<Span>>): Promise<number> {
@@ -16,6 +16,7 @@ export type PartialFilter = {
 export type PartialCountFilter = {
     start: Timestamp;
     end: Timestamp;
+    buckets: number;
 };
 
 export type PaginationFilter = {
@@ -27,7 +27,7 @@ export type EventsScreenProps = {
     columnRemove: (i: number) => void,
 
     getEvents: (filter: PartialFilter, wait?: boolean) => Promise<Event[] | null>,
-    getEventCounts: (filter: PartialCountFilter, wait?: boolean, cache?: boolean) => Promise<Counts | null>,
+    getEventCounts: (filter: PartialCountFilter, wait?: boolean, cache?: boolean) => Promise<Counts[] | null>,
 
     live: boolean,
     setLive: (live: boolean) => void,
@@ -28,7 +28,7 @@ export type SpansScreenProps = {
 
     getSpans: (filter: PartialFilter, wait?: boolean) => Promise<Span[] | null>,
     getPositionedSpans: (filter: PartialFilter, wait?: boolean) => Promise<PositionedSpan[] | null>,
-    getSpanCounts: (filter: PartialCountFilter, wait?: boolean, cache?: boolean) => Promise<Counts | null>,
+    getSpanCounts: (filter: PartialCountFilter, wait?: boolean, cache?: boolean) => Promise<Counts[] | null>,
 
     live: boolean,
     setLive: (live: boolean) => void,
@@ -1,4 +1,4 @@
-import { Event, EventFilter, FilterPredicate, FullSpanId, getEventCount, getEvents, getSpanCount, getSpans, Input, Span, SpanFilter, subscribeToEvents, subscribeToSpans, SubscriptionResponse, Timestamp, unsubscribeFromEvents, unsubscribeFromSpans } from "../invoke";
+import { Event, EventFilter, FilterPredicate, FullSpanId, getEventHistogram, getEvents, getSpanHistogram, getSpans, Input, Span, SpanFilter, subscribeToEvents, subscribeToSpans, SubscriptionResponse, Timestamp, unsubscribeFromEvents, unsubscribeFromSpans } from "../invoke";
 import { Counts, PaginationFilter, PartialCountFilter, PartialFilter, PositionedSpan, Timespan } from "../models";
 import { Channel } from "@tauri-apps/api/core";
 
@@ -17,7 +17,7 @@ export class EventDataLayer {
     #events: Event[];
 
     // the cached event counts by "{start}-{end}" and level
-    #counts: { [range: string]: [Counts, IsVolatile] };
+    #counts: { [range: string]: [Counts[], IsVolatile] };
 
     #expandStartTask: Promise<void> | null;
     #expandEndTask: Promise<void> | null;
@@ -74,20 +74,34 @@ export class EventDataLayer {
             return await getEvents({ filter: this.#filter, ...filter });
         }
 
+        // `previous` is a key, so its event is needed to know where it is in
+        // time and it is only looked for in the cache
+        let previous = (filter.previous != null) ? this.#events.find(e => e.key == filter.previous) : undefined;
+        if (filter.previous != null && previous == undefined) {
+            if (wait === false) {
+                return null;
+            }
+
+            return await getEvents({ filter: this.#filter, ...filter });
+        }
+
+        let after = (filter.order == 'asc') ? previous : undefined;
+        let before = (filter.order == 'desc') ? previous : undefined;
+
         let start: number, end: number;
         if (filter.order == 'asc') {
-            start = (filter.previous) ? filter.previous + 1 : filter.start;
+            start = after ? after.timestamp : filter.start;
             end = filter.end;
         } else {
             start = filter.start;
-            end = filter.previous ? filter.previous - 1 : filter.end;
+            end = before ? before.timestamp : filter.end;
         }
 
         if (within(this.#range, start, end)) {
             // events are cached
 
-            let startIndex = partitionPointEventsLower(this.#events, start);
-            let endIndex = partitionPointEventsUpper(this.#events, end);
+            let startIndex = this.#startIndex(start, after);
+            let endIndex = this.#endIndex(end, before);
 
             if (filter.limit != Infinity) {
                 if (filter.order == 'asc') {
@@ -125,8 +139,8 @@ export class EventDataLayer {
                     await this.#expandStartTask;
 
                     if (start >= this.#range[0]) {
-                        let startIndex = partitionPointEventsLower(this.#events, start);
-                        let endIndex = partitionPointEventsUpper(this.#events, end);
+                        let startIndex = this.#startIndex(start, after);
+                        let endIndex = this.#endIndex(end, before);
                         let cachedEvents = this.#events.slice(startIndex, endIndex);
 
                         cachedEvents.splice(50);
@@ -163,8 +177,8 @@ export class EventDataLayer {
                     this.#range = [start, this.#range[1]];
                     this.#events = [...newEvents, ...this.#events]
 
-                    let startIndex = partitionPointEventsLower(this.#events, start);
-                    let endIndex = partitionPointEventsUpper(this.#events, end);
+                    let startIndex = this.#startIndex(start, after);
+                    let endIndex = this.#endIndex(end, before);
                     let cachedEvents = this.#events.slice(startIndex, endIndex);
 
                     cachedEvents.splice(50);
@@ -184,8 +198,8 @@ export class EventDataLayer {
                     await this.#expandEndTask;
 
                     if (end <= this.#range[1]) {
-                        let startIndex = partitionPointEventsLower(this.#events, start);
-                        let endIndex = partitionPointEventsUpper(this.#events, end);
+                        let startIndex = this.#startIndex(start, after);
+                        let endIndex = this.#endIndex(end, before);
                         let cachedEvents = this.#events.slice(startIndex, endIndex);
 
                         cachedEvents.reverse();
@@ -223,8 +237,8 @@ export class EventDataLayer {
                     this.#range = [this.#range[0], end];
                     this.#events = [...this.#events, ...newEvents.reverse()]
 
-                    let startIndex = partitionPointEventsLower(this.#events, start);
-                    let endIndex = partitionPointEventsUpper(this.#events, end);
+                    let startIndex = this.#startIndex(start, after);
+                    let endIndex = this.#endIndex(end, before);
                     let cachedEvents = this.#events.slice(startIndex, endIndex);
 
                     cachedEvents.reverse();
@@ -237,7 +251,7 @@ export class EventDataLayer {
             }
 
             if (start < this.#range[0] && filter.order == 'desc') {
-                let endIndex = partitionPointEventsUpper(this.#events, end);
+                let endIndex = this.#endIndex(end, before);
                 let cachedEvents = this.#events.slice(0, endIndex);
                 cachedEvents.reverse();
                 if (filter.limit == Infinity) {
@@ -255,7 +269,7 @@ export class EventDataLayer {
 
                 await this.#expandStart(filter.end - filter.start);
 
-                endIndex = partitionPointEventsUpper(this.#events, end);
+                endIndex = this.#endIndex(end, before);
                 cachedEvents = this.#events.slice(0, endIndex);
                 cachedEvents.reverse();
                 cachedEvents.splice(50);
@@ -266,7 +280,7 @@ export class EventDataLayer {
             }
 
             if (end > this.#range[1] && filter.order == 'asc') {
-                let startIndex = partitionPointEventsLower(this.#events, start);
+                let startIndex = this.#startIndex(start, after);
                 let cachedEvents = this.#events.slice(startIndex);
                 if (filter.limit == Infinity) {
                     return cachedEvents;
@@ -282,7 +296,7 @@ export class EventDataLayer {
 
                 await this.#expandEnd(filter.end - filter.start);
 
-                startIndex = partitionPointEventsLower(this.#events, start);
+                startIndex = this.#startIndex(start, after);
                 cachedEvents = this.#events.slice(startIndex);
                 cachedEvents.splice(50);
 
@@ -320,8 +334,8 @@ export class EventDataLayer {
         }
     }
 
-    getEventCounts = async (filter: PartialCountFilter, wait?: boolean, cache?: boolean): Promise<Counts | null> => {
-        let key = `${filter.start}-${filter.end}`;
+    getEventCounts = async (filter: PartialCountFilter, wait?: boolean, cache?: boolean): Promise<Counts[] | null> => {
+        let key = `${filter.start}-${filter.end}-${filter.buckets}`;
 
         // the latest second should not be considered reliable
         let inVolatileRange = filter.end >= Date.now() * 1000 - 1000000;
@@ -334,14 +348,8 @@ export class EventDataLayer {
             return this.#counts[key] != undefined ? this.#counts[key][0] : null;
         }
 
-        let counts = await Promise.all([
-            getEventCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "TRACE"] } }], ...filter }),
-            getEventCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "DEBUG"] } }], ...filter }),
-            getEventCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "INFO"] } }], ...filter }),
-            getEventCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "WARN"] } }], ...filter }),
-            getEventCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "ERROR"] } }], ...filter }),
-            getEventCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "FATAL"] } }], ...filter }),
-        ]);
+        let histogram = await getEventHistogram({ filter: this.#filter, ...filter });
+        let counts: Counts[] = histogram.map(bucket => bucket.counts);
 
         // cache if enabled
         if (cache == undefined || cache == true) {
@@ -351,9 +359,29 @@ export class EventDataLayer {
         return counts;
     }
 
+    // returns the index of the first cached event at or after `start`, events
+    // can share a microsecond so it is also past the `after` event if given
+    #startIndex = (start: Timestamp, after?: Event): number => {
+        let index = partitionPointEventsLower(this.#events, start);
+        if (after != undefined) {
+            index = Math.max(index, partitionPointEntry(this.#events, after) + 1);
+        }
+        return index;
+    }
+
+    // returns the index past the last cached event at or before `end`, events
+    // can share a microsecond so it is also before the `before` event if given
+    #endIndex = (end: Timestamp, before?: Event): number => {
+        let index = partitionPointEventsUpper(this.#events, end);
+        if (before != undefined) {
+            index = Math.min(index, partitionPointEntry(this.#events, before));
+        }
+        return index;
+    }
+
     #insertEvent = (event: Event) => {
-        let insertIdx = partitionPointEventsLower(this.#events, event.timestamp);
-        if (insertIdx >= this.#events.length || this.#events[insertIdx].timestamp != event.timestamp) {
+        let insertIdx = partitionPointEntry(this.#events, event);
+        if (insertIdx >= this.#events.length || this.#events[insertIdx].key != event.key) {
             this.#events.splice(insertIdx, 0, event);
             if (event.timestamp > this.#range[1]) {
                 this.#range = [this.#range[0], event.timestamp];
@@ -362,9 +390,9 @@ export class EventDataLayer {
     }
 
     #removeEvent = (event_key: Timestamp) => {
-        let insertIdx = partitionPointEventsLower(this.#events, event_key);
-        if (insertIdx < this.#events.length && this.#events[insertIdx].timestamp == event_key) {
-            this.#events.splice(insertIdx, 1);
+        let removeIdx = this.#events.findIndex(e => e.key == event_key);
+        if (removeIdx != -1) {
+            this.#events.splice(removeIdx, 1);
         }
     }
 
@@ -452,7 +480,7 @@ export class SpanDataLayer {
     #spans: Span[];
 
     // the cached event counts by "{start}-{end}" and level
-    #counts: { [range: string]: [Counts, IsVolatile] };
+    #counts: { [range: string]: [Counts[], IsVolatile] };
 
     #slots: Timespan[][];
     #slotmap: { [span_id: FullSpanId]: number };
@@ -515,8 +543,8 @@ export class SpanDataLayer {
         }))
     }
 
-    getSpanCounts = async (filter: PartialCountFilter, wait?: boolean, cache?: boolean): Promise<Counts | null> => {
-        let key = `${filter.start}-${filter.end}`;
+    getSpanCounts = async (filter: PartialCountFilter, wait?: boolean, cache?: boolean): Promise<Counts[] | null> => {
+        let key = `${filter.start}-${filter.end}-${filter.buckets}`;
 
         // the latest second should not be considered reliable
         let inVolatileRange = filter.end >= Date.now() * 1000 - 1000000;
@@ -529,14 +557,8 @@ export class SpanDataLayer {
             return this.#counts[key] != undefined ? this.#counts[key][0] : null;
         }
 
-        let counts = await Promise.all([
-            getSpanCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "TRACE"] } }], ...filter }),
-            getSpanCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "DEBUG"] } }], ...filter }),
-            getSpanCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "INFO"] } }], ...filter }),
-            getSpanCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "WARN"] } }], ...filter }),
-            getSpanCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "ERROR"] } }], ...filter }),
-            getSpanCount({ filter: [...this.#filter, { predicate_kind: 'single', predicate: { text: '', property: "level", value_kind: 'comparison', value: ['Eq', "FATAL"] } }], ...filter }),
-        ]);
+        let histogram = await getSpanHistogram({ filter: this.#filter, ...filter });
+        let counts: Counts[] = histogram.map(bucket => bucket.counts);
 
         // cache if enabled
         if (cache == undefined || cache == true) {
@@ -606,7 +628,7 @@ export class SpanDataLayer {
             }
 
             if (filter.end > this.#range[1] && filter.order == 'asc') {
-                let startIndex = partitionPointSpansLower(this.#spans, filter.previous ?? 0);
+                let startIndex = partitionPointSpansLower(this.#spans, this.#previousAt(filter.previous) ?? 0);
                 let endIndex = partitionPointSpansUpper(this.#spans, filter.end);
                 if (endIndex - startIndex >= 50 || filter.limit == Infinity) {
                     return this.#getSpansInCache(filter);
@@ -624,7 +646,7 @@ export class SpanDataLayer {
 
             if (filter.start < this.#range[0] && filter.order == 'desc') {
                 let startIndex = partitionPointSpansLower(this.#spans, filter.start);
-                let endIndex = partitionPointSpansUpper(this.#spans, filter.previous ?? filter.end);
+                let endIndex = partitionPointSpansUpper(this.#spans, this.#previousAt(filter.previous) ?? filter.end);
                 if (endIndex - startIndex >= 50 || filter.limit == Infinity) {
                     return this.#getSpansInCache(filter);
                 }
@@ -756,14 +778,23 @@ export class SpanDataLayer {
             return [];
         }
 
+        let previousSpan = this.#findPrevious(filter.previous);
+        let previous = previousSpan?.created_at ?? filter.previous;
+
+        // spans can share a microsecond, so the previous span itself is found
+        // when it is cached to continue right after it
+        let indexAfter = (previous: Timestamp) => previousSpan
+            ? partitionPointEntry(this.#spans, previousSpan) + 1
+            : partitionPointSpansUpper(this.#spans, previous);
+
         let startIndex = partitionPointSpansLower(this.#spans, filter.start);
         let endIndex = partitionPointSpansUpper(this.#spans, filter.end);
 
         if (filter.order == 'asc') {
-            if (!filter.previous || filter.previous < filter.start) {
+            if (!previous || previous < filter.start) {
                 // beginning is sparse
-                let preRangeStart = (filter.previous)
-                    ? partitionPointSpansUpper(this.#spans, filter.previous)
+                let preRangeStart = (previous)
+                    ? indexAfter(previous)
                     : 0;
 
                 let preRangeSpansInFilter = [];
@@ -789,15 +820,15 @@ export class SpanDataLayer {
                 if (this.#spans.length - endIndex < 50 && filter.end > this.#range[1] - (filter.end - filter.start)) {
                     this.#expandEnd(filter.end - filter.start);
                 }
-                if (startIndex < 50 && Math.max(filter.start, filter.previous ?? 0) < this.#range[0] + (filter.end - filter.start)) {
+                if (startIndex < 50 && Math.max(filter.start, previous ?? 0) < this.#range[0] + (filter.end - filter.start)) {
                     this.#expandStart(filter.end - filter.start);
                 }
 
                 return [...preRangeSpansInFilter, ...this.#spans.slice(startIndex, endIndex)];
             } else {
                 // beginning is dense
-                if (filter.previous && filter.previous > filter.start) {
-                    startIndex = partitionPointSpansUpper(this.#spans, filter.previous);
+                if (previous && previous > filter.start) {
+                    startIndex = indexAfter(previous);
                 }
 
                 if ((endIndex - startIndex) > 50) {
@@ -810,17 +841,17 @@ export class SpanDataLayer {
                 if (this.#spans.length - endIndex < 50 && filter.end > this.#range[1] - (filter.end - filter.start)) {
                     this.#expandEnd(filter.end - filter.start);
                 }
-                if (startIndex < 50 && Math.max(filter.start, filter.previous ?? 0) < this.#range[0] + (filter.end - filter.start)) {
+                if (startIndex < 50 && Math.max(filter.start, previous ?? 0) < this.#range[0] + (filter.end - filter.start)) {
                     this.#expandStart(filter.end - filter.start);
                 }
 
                 return this.#spans.slice(startIndex, endIndex);
             }
         } else /* filter.order == 'desc' */ {
-            if (!filter.previous || filter.previous > filter.start) {
+            if (!previous || previous > filter.start) {
                 // beginning (end?) is dense
-                let actualEndIndex = filter.previous
-                    ? partitionPointSpansUpper(this.#spans, filter.previous)
+                let actualEndIndex = previous
+                    ? indexAfter(previous)
                     : endIndex;
 
                 if (actualEndIndex - startIndex >= 50 && filter.limit != Infinity) {
@@ -840,7 +871,7 @@ export class SpanDataLayer {
                             if (this.#spans.length - actualEndIndex < 50 && filter.end > this.#range[1] - (filter.end - filter.start)) {
                                 this.#expandEnd(filter.end - filter.start);
                             }
-                            if (startIndex < 50 && Math.max(filter.start, filter.previous ?? 0) < this.#range[0] + (filter.end - filter.start)) {
+                            if (startIndex < 50 && Math.max(filter.start, previous ?? 0) < this.#range[0] + (filter.end - filter.start)) {
                                 this.#expandStart(filter.end - filter.start);
                             }
 
@@ -854,7 +885,7 @@ export class SpanDataLayer {
                 return spans;
             } else {
                 // sparse only
-                let actualEndIndex = partitionPointSpansUpper(this.#spans, filter.previous);
+                let actualEndIndex = indexAfter(previous);
 
                 let spans = [];
                 let span_rev_idx = 0;
@@ -867,7 +898,7 @@ export class SpanDataLayer {
                             if (this.#spans.length - actualEndIndex < 50 && filter.end > this.#range[1] - (filter.end - filter.start)) {
                                 this.#expandEnd(filter.end - filter.start);
                             }
-                            if (startIndex < 50 && Math.max(filter.start, filter.previous ?? 0) < this.#range[0] + (filter.end - filter.start)) {
+                            if (startIndex < 50 && Math.max(filter.start, previous ?? 0) < this.#range[0] + (filter.end - filter.start)) {
                                 this.#expandStart(filter.end - filter.start);
                             }
 
@@ -892,7 +923,7 @@ export class SpanDataLayer {
             if (a_order != b_order) {
                 return a_order - b_order;
             } else {
-                return a.created_at - b.created_at;
+                return compareEntries(a, b);
             }
         });
 
@@ -922,17 +953,27 @@ export class SpanDataLayer {
         }
     }
 
+    // `previous` is usually a key, but the span graph continues from when its
+    // last span was created instead, so this finds the span for the former
+    #findPrevious = (previous?: Timestamp): Span | undefined => {
+        return (previous != null) ? this.#spans.find(s => s.key == previous) : undefined;
+    }
+
+    #previousAt = (previous?: Timestamp): Timestamp | undefined => {
+        return this.#findPrevious(previous)?.created_at ?? previous;
+    }
+
     #insertSpan = (span: Span) => {
-        let insertIdx = partitionPointSpansLower(this.#spans, span.created_at);
-        if (insertIdx >= this.#spans.length || this.#spans[insertIdx].created_at != span.created_at) {
+        let insertIdx = partitionPointEntry(this.#spans, span);
+        if (insertIdx >= this.#spans.length || this.#spans[insertIdx].key != span.key) {
             this.#spans.splice(insertIdx, 0, span);
         }
     }
 
     #removeSpan = (span_key: Timestamp) => {
-        let insertIdx = partitionPointSpansLower(this.#spans, span_key);
-        if (insertIdx < this.#spans.length && this.#spans[insertIdx].created_at == span_key) {
-            this.#spans.splice(insertIdx, 1);
+        let removeIdx = this.#spans.findIndex(s => s.key == span_key);
+        if (removeIdx != -1) {
+            this.#spans.splice(removeIdx, 1);
         }
     }
 
@@ -1108,7 +1149,7 @@ export class TraceDataLayer {
                     break;
                 }
 
-                previous = newSpans[newSpans.length - 1].created_at;
+                previous = newSpans[newSpans.length - 1].key;
             }
 
             let events = [];
@@ -1128,15 +1169,11 @@ export class TraceDataLayer {
                     break;
                 }
 
-                previous = newEvents[newEvents.length - 1].timestamp;
-            }
-
-            function getTimestamp(e: Event | Span): Timestamp {
-                return (e as any).timestamp || (e as any).created_at;
+                previous = newEvents[newEvents.length - 1].key;
             }
 
             let spansById = Object.fromEntries(spans.map(s => [s.id, s]));
-            let ordering = (e: Event | Span) => e.ancestors.map(a => spansById[a.id]?.created_at ?? '*').concat(getTimestamp(e)).join('-');
+            let ordering = (e: Event | Span) => e.ancestors.map(a => spansById[a.id] ? entryOrdering(spansById[a.id]) : '*').concat(entryOrdering(e)).join('-');
 
             let entries = [...spans, ...events];
             entries.sort((a, b) => {
@@ -1201,140 +1238,94 @@ function overlaps(range: Timespan, start: Timestamp, end: Timestamp): boolean {
     return (start >= range[0] && start - 1 <= range[1]) || (end + 1 >= range[0] && end <= range[1]);
 }
 
-// returns the index where an event with the timestamp should be if it exists
+// returns the index of the first event at or after the timestamp
 export function partitionPointEventsLower(events: Event[], timestamp: Timestamp): number {
-    let start = 0;
-    let end = events.length - 1;
-
-    while (start <= end) {
-        let mid = Math.floor((start + end) / 2);
-
-        if (events[mid].timestamp == timestamp) {
-            return mid;
-        } else if (events[mid].timestamp > timestamp) {
-            end = mid - 1;
-        } else {
-            start = mid + 1;
-        }
-    }
-
-    return start;
+    return partitionPoint(events, e => e.timestamp < timestamp);
 }
 
+// returns the index of the first event after the timestamp
 export function partitionPointEventsUpper(events: Event[], timestamp: Timestamp): number {
-    let start = 0;
-    let end = events.length - 1;
-
-    while (start <= end) {
-        let mid = Math.floor((start + end) / 2);
-
-        if (events[mid].timestamp == timestamp) {
-            return mid + 1;
-        } else if (events[mid].timestamp > timestamp) {
-            end = mid - 1;
-        } else {
-            start = mid + 1;
-        }
-    }
-
-    return start;
+    return partitionPoint(events, e => e.timestamp <= timestamp);
 }
 
-// returns the index where a span created at the timestamp should be if it
-// exists 
+// returns the index of the first span created at or after the timestamp
 export function partitionPointSpansLower(spans: Span[], timestamp: Timestamp): number {
-    let start = 0;
-    let end = spans.length - 1;
-
-    while (start <= end) {
-        let mid = Math.floor((start + end) / 2);
-
-        if (spans[mid].created_at == timestamp) {
-            return mid;
-        } else if (spans[mid].created_at > timestamp) {
-            end = mid - 1;
-        } else {
-            start = mid + 1;
-        }
-    }
-
-    return start;
+    return partitionPoint(spans, s => s.created_at < timestamp);
 }
 
+// returns the index of the first span created after the timestamp
 export function partitionPointSpansUpper(spans: Span[], timestamp: Timestamp): number {
-    let start = 0;
-    let end = spans.length - 1;
-
-    while (start <= end) {
-        let mid = Math.floor((start + end) / 2);
+    return partitionPoint(spans, s => s.created_at <= timestamp);
+}
 
-        if (spans[mid].created_at == timestamp) {
-            return mid + 1;
-        } else if (spans[mid].created_at > timestamp) {
-            end = mid - 1;
-        } else {
-            start = mid + 1;
-        }
-    }
+// returns the index of the first entry at or after the timestamp
+export function partitionPointEntriesLower(entries: (Event | Span)[], timestamp: Timestamp): number {
+    return partitionPoint(entries, e => getTimestamp(e) < timestamp);
+}
 
-    return start;
+// returns the index of the first entry after the timestamp
+export function partitionPointEntriesUpper(entries: (Event | Span)[], timestamp: Timestamp): number {
+    return partitionPoint(entries, e => getTimestamp(e) <= timestamp);
 }
 
-// returns the index where a span created at the timestamp should be if it
-// exists 
-export function partitionPointEntriesLower(entries: (Event | Span)[], timestamp: Timestamp): number {
+// returns the index of the first item that is not `before`, the items must be
+// ordered such that all those that are `before` come first
+function partitionPoint<T>(items: T[], before: (item: T) => boolean): number {
     let start = 0;
-    let end = entries.length - 1;
+    let end = items.length;
 
-    function getTimestamp(entry: Event | Span): Timestamp {
-        return (entry as any).timestamp || (entry as any).created_at;
-    }
-
-    while (start <= end) {
+    while (start < end) {
         let mid = Math.floor((start + end) / 2);
 
-        if (getTimestamp(entries[mid]) == timestamp) {
-            return mid;
-        } else if (getTimestamp(entries[mid]) > timestamp) {
-            end = mid - 1;
-        } else {
+        if (before(items[mid])) {
             start = mid + 1;
+        } else {
+            end = mid;
         }
     }
 
     return start;
 }
 
-export function partitionPointEntriesUpper(entries: (Event | Span)[], timestamp: Timestamp): number {
-    let start = 0;
-    let end = entries.length - 1;
+function getTimestamp(entry: Event | Span): Timestamp {
+    return (entry as any).timestamp ?? (entry as any).created_at;
+}
 
-    function getTimestamp(entry: Event | Span): Timestamp {
-        return (entry as any).timestamp || (entry as any).created_at;
-    }
+function getPreciseTimestamp(entry: Event | Span): bigint {
+    return BigInt((entry as any).precise_timestamp ?? (entry as any).precise_created_at);
+}
 
-    while (start <= end) {
-        let mid = Math.floor((start + end) / 2);
+// orders entries the same way they are returned: by their precise timestamp
+// and then by their key
+function compareEntries(a: Event | Span, b: Event | Span): number {
+    let a_at = getPreciseTimestamp(a);
+    let b_at = getPreciseTimestamp(b);
 
-        if (getTimestamp(entries[mid]) == timestamp) {
-            return mid + 1;
-        } else if (getTimestamp(entries[mid]) > timestamp) {
-            end = mid - 1;
-        } else {
-            start = mid + 1;
-        }
+    if (a_at != b_at) {
+        return a_at < b_at ? -1 : 1;
+    } else {
+        return a.key - b.key;
     }
+}
 
-    return start;
+// gets a string that sorts entries in the same order as `compareEntries` as
+// long as their timestamps and keys have the same number of digits
+function entryOrdering(entry: Event | Span): string {
+    return `${getPreciseTimestamp(entry)}.${entry.key}`;
+}
+
+// returns the index where the entry is or should be inserted
+function partitionPointEntry<T extends Event | Span>(entries: T[], entry: T): number {
+    return partitionPoint(entries, e => compareEntries(e, entry) < 0);
 }
 
 // partitionPoint([] as any, 0) == 0
-// partitionPoint([{ timestamp: 1 }] as any, 0) == 0
-// partitionPoint([{ timestamp: 1 }] as any, 1) == 0
-// partitionPoint([{ timestamp: 1 }] as any, 2) == 1
-// partitionPoint([{ timestamp: 1 }, { timestamp: 3 }] as any, 2) == 1
-// partitionPoint([{ timestamp: 1 }, { timestamp: 3 }] as any, 3) == 1
-// partitionPoint([{ timestamp: 1 }, { timestamp: 3 }] as any, 4) == 2
+// partitionPoint([{ key: 1 }] as any, 0) == 0
+// partitionPoint([{ key: 1 }] as any, 1) == 0
+// partitionPoint([{ key: 1 }] as any, 2) == 1
+// partitionPoint([{ key: 1 }, { key: 3 }] as any, 2) == 1
+// partitionPoint([{ key: 1 }, { key: 3 }] as any, 3) == 1
+// partitionPoint([{ key: 1 }, { key: 3 }] as any, 4) == 2
 
 function spanMeasure(span: Span): number {
     if (span.closed_at == null) {
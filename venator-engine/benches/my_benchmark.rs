@@ -7,7 +7,7 @@ use venator_engine::storage::{FileStorage, TransientStorage};
 use venator_engine::Timestamp;
 
 fn event_counts_benchmark(c: &mut Criterion) {
-    let file_storage = FileStorage::new(Path::new("./benches/test.vena.db"));
+    let file_storage = FileStorage::new(Path::new("./benches/test.vena.db")).unwrap();
     let file_engine = SyncEngine::new(file_storage).unwrap();
 
     let mut mem_storage = TransientStorage::new();
@@ -62,7 +62,7 @@ fn event_counts_benchmark(c: &mut Criterion) {
 }
 
 fn span_counts_benchmark(c: &mut Criterion) {
-    let file_storage = FileStorage::new(Path::new("./benches/test.vena.db"));
+    let file_storage = FileStorage::new(Path::new("./benches/test.vena.db")).unwrap();
     let file_engine = SyncEngine::new(file_storage).unwrap();
 
     let mut mem_storage = TransientStorage::new();
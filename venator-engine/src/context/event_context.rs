@@ -214,6 +214,7 @@ where
                 ancestors.reverse();
                 ancestors
             },
+            key: event.key,
             timestamp: event.timestamp,
             level: event.level.into_simple_level(),
             content: event.content.clone(),
@@ -95,6 +95,43 @@ where
             .map(|p| &**p)
     }
 
+    /// Gets the time in microseconds that the span was open while none of the
+    /// provided children were, or `None` if the span is not closed. Children
+    /// that are still open are considered to last until the span closed.
+    pub(crate) fn self_time(&self, children: &[SpanKey]) -> Option<u64> {
+        let span = self.span();
+        let closed_at = span.closed_at?;
+
+        let mut intervals = children
+            .iter()
+            .filter_map(|key| {
+                self.storage
+                    .get_span(*key)
+                    .inspect_err(|err| tracing::warn!(?err, "failed to load span"))
+                    .ok()
+            })
+            .map(|child| {
+                let start = child.created_at.clamp(span.created_at, closed_at);
+                let end = child.closed_at.unwrap_or(closed_at).clamp(start, closed_at);
+                (start, end)
+            })
+            .collect::<Vec<_>>();
+
+        intervals.sort_unstable();
+
+        let mut covered = 0;
+        let mut cursor = span.created_at;
+        for (start, end) in intervals {
+            let start = start.max(cursor);
+            if end > start {
+                covered += end - start;
+                cursor = end;
+            }
+        }
+
+        Some((closed_at - span.created_at - covered) / 1000)
+    }
+
     pub(crate) fn resource(&self) -> &Resource {
         let span = self.span();
 
@@ -224,6 +261,7 @@ where
                 ancestors.reverse();
                 ancestors
             },
+            key: span.key,
             created_at: span.created_at,
             closed_at: span.closed_at,
             busy: span.busy,
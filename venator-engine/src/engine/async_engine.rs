@@ -1,22 +1,28 @@
 use std::panic::AssertUnwindSafe;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Error as AnyError};
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::sync::oneshot::{self, Receiver as OneshotReceiver, Sender as OneshotSender};
 use tracing::instrument;
 
-use crate::filter::{FilterPredicate, Query};
+use crate::filter::{FilterPredicate, Query, SpanEventQuery};
 use crate::storage::Storage;
 use crate::subscription::Subscriber;
 use crate::{
-    ComposedEvent, ComposedSpan, DatasetStats, DeleteFilter, DeleteMetrics, EngineStatus,
-    InstanceId, NewEvent, NewResource, NewSpanEvent, ResourceKey, SpanEvent, SpanKey,
-    SubscriptionId,
+    AggregateQuery, AggregateRow, Annotation, AnnotationKey, AttributeSummary, AttributeValueCount,
+    CompactMetrics, ComposedEvent, ComposedSpan, CriticalPathSpan, DatasetStats, DeleteFilter,
+    DeleteMetrics, EngineStatus, FlameGraph, HistogramBucket, InstanceId, IntegrityReport,
+    LatencyGroup, LatencyStats, MergeMetrics, NewAnnotation, NewEvent, NewResource, NewSpanEvent,
+    Pin, ResourceKey, ServiceDependency, SpanEvent, SpanKey, SubscriptionId, Timestamp,
+    TombstoneKey, Trace, TraceRoot,
 };
 
 use super::SyncEngine;
 
+/// How often the engine checks for deleted entities that can be purged.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
 /// Provides the core engine functionality with an async interface.
 ///
 /// Internally this wraps a `SyncEngine` in a thread and coordinates with it via
@@ -39,6 +45,7 @@ impl AsyncEngine {
         std::thread::spawn(move || {
             let mut last_check = Instant::now();
             let mut computed_ms_since_last_check: u128 = 0;
+            let mut last_purge = Instant::now();
 
             fn recv(
                 query: &mut Receiver<(tracing::Span, EngineCommand)>,
@@ -82,10 +89,66 @@ impl AsyncEngine {
                         let events = engine.query_event_count(query);
                         let _ = sender.send(events);
                     }
+                    EngineCommand::QuerySpanHistogram(filter, start, end, buckets, sender) => {
+                        let histogram = engine.query_span_histogram(filter, start, end, buckets);
+                        let _ = sender.send(histogram);
+                    }
+                    EngineCommand::QueryEventHistogram(filter, start, end, buckets, sender) => {
+                        let histogram = engine.query_event_histogram(filter, start, end, buckets);
+                        let _ = sender.send(histogram);
+                    }
+                    EngineCommand::QuerySpanAggregate(query, sender) => {
+                        let rows = engine.query_span_aggregate(query);
+                        let _ = sender.send(rows);
+                    }
+                    EngineCommand::QueryEventAggregate(query, sender) => {
+                        let rows = engine.query_event_aggregate(query);
+                        let _ = sender.send(rows);
+                    }
+                    EngineCommand::QuerySpanLatency(filter, start, end, group_by, sender) => {
+                        let stats = engine.query_span_latency(filter, start, end, group_by);
+                        let _ = sender.send(stats);
+                    }
+                    EngineCommand::QueryEventAttributeNames(filter, start, end, sender) => {
+                        let names = engine.query_event_attribute_names(filter, start, end);
+                        let _ = sender.send(names);
+                    }
+                    EngineCommand::QuerySpanAttributeNames(filter, start, end, sender) => {
+                        let names = engine.query_span_attribute_names(filter, start, end);
+                        let _ = sender.send(names);
+                    }
+                    EngineCommand::QueryEventAttributeValues(name, prefix, limit, sender) => {
+                        let values = engine.query_event_attribute_values(&name, &prefix, limit);
+                        let _ = sender.send(values);
+                    }
+                    EngineCommand::QuerySpanAttributeValues(name, prefix, limit, sender) => {
+                        let values = engine.query_span_attribute_values(&name, &prefix, limit);
+                        let _ = sender.send(values);
+                    }
+                    EngineCommand::QueryTrace(root, sender) => {
+                        let trace = engine.query_trace(root);
+                        let _ = sender.send(trace);
+                    }
+                    EngineCommand::QueryCriticalPath(root, sender) => {
+                        let path = engine.query_critical_path(root);
+                        let _ = sender.send(path);
+                    }
+                    EngineCommand::QueryFlameGraph(filter, start, end, sender) => {
+                        let flame_graph = engine.query_flame_graph(filter, start, end);
+                        let _ = sender.send(flame_graph);
+                    }
+                    EngineCommand::QueryServiceDependencies(filter, start, end, sender) => {
+                        let dependencies = engine.query_service_dependencies(filter, start, end);
+                        let _ = sender.send(dependencies);
+                    }
                     EngineCommand::QueryStats(sender) => {
                         let stats = engine.query_stats();
                         let _ = sender.send(stats);
                     }
+                    EngineCommand::QueryAnnotations(sender) => {
+                        let annotations = engine.query_annotations();
+                        let _ = sender.send(annotations);
+                    }
                     EngineCommand::InsertResource(resource, sender) => {
                         let res = engine.insert_resource(resource);
                         if let Err(err) = &res {
@@ -118,6 +181,46 @@ impl AsyncEngine {
                         let res = engine.delete(filter);
                         let _ = sender.send(res);
                     }
+                    EngineCommand::Compact(sender) => {
+                        let res = engine.compact();
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::Pin(pin, sender) => {
+                        let res = engine.pin(pin);
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::Unpin(pin, sender) => {
+                        let res = engine.unpin(pin);
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::AddAnnotation(annotation, sender) => {
+                        let res = engine.add_annotation(annotation);
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::UpdateAnnotation(key, text, sender) => {
+                        let res = engine.update_annotation(key, text);
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::RemoveAnnotation(key, sender) => {
+                        let res = engine.remove_annotation(key);
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::UndoDelete(key, sender) => {
+                        let res = engine.undo_delete(key);
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::DropPartitionsBefore(at, sender) => {
+                        let res = engine.drop_partitions_before(at);
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::PurgeDeleted(sender) => {
+                        let res = engine.purge_deleted();
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::SetDeleteGracePeriod(grace_period, sender) => {
+                        engine.set_delete_grace_period(grace_period);
+                        let _ = sender.send(());
+                    }
                     EngineCommand::SpanSubscribe(filter, sender) => {
                         let res = engine.subscribe_to_spans(filter);
                         let _ = sender.send(res);
@@ -136,6 +239,21 @@ impl AsyncEngine {
                     }
                     EngineCommand::CopyDataset(mut to, sender) => {
                         let res = engine.copy_dataset(&mut *to);
+                        // close the target before reporting back so that the
+                        // caller can immediately move or open it
+                        drop(to);
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::MergeDataset(from, sender) => {
+                        let res = engine.merge_dataset(&*from);
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::Verify(sender) => {
+                        let res = engine.verify();
+                        let _ = sender.send(res);
+                    }
+                    EngineCommand::Repair(sender) => {
+                        let res = engine.repair();
                         let _ = sender.send(res);
                     }
                     EngineCommand::GetStatus(sender) => {
@@ -149,6 +267,7 @@ impl AsyncEngine {
 
                         let _ = sender.send(EngineStatus {
                             load: load.min(1.0) * 100.0,
+                            cache: engine.storage.cache_stats(),
                         });
                     }
                     EngineCommand::Shutdown(sender) => {
@@ -191,6 +310,19 @@ impl AsyncEngine {
                     tracing::error!("engine call panicked: {err:?}");
                 }
 
+                if last_purge.elapsed() >= PURGE_INTERVAL {
+                    last_purge = Instant::now();
+
+                    let panic_result =
+                        std::panic::catch_unwind(AssertUnwindSafe(|| engine.purge_deleted()));
+
+                    match panic_result {
+                        Ok(Ok(_)) => {}
+                        Ok(Err(err)) => tracing::warn!("failed to purge deleted entities: {err:?}"),
+                        Err(err) => tracing::error!("engine purge panicked: {err:?}"),
+                    }
+                }
+
                 let cmd_elapsed = cmd_start.elapsed().as_millis();
                 computed_ms_since_last_check += cmd_elapsed;
             }
@@ -218,9 +350,11 @@ impl AsyncEngine {
         receiver.await.context("failed to get result")
     }
 
-    #[doc(hidden)]
     #[instrument(skip_all)]
-    pub async fn query_span_event(&self, query: Query) -> Result<Vec<SpanEvent>, AnyError> {
+    pub async fn query_span_event(
+        &self,
+        query: SpanEventQuery,
+    ) -> Result<Vec<SpanEvent>, AnyError> {
         let (sender, receiver) = oneshot::channel();
         self.emit_query(EngineCommand::QuerySpanEvent(query, sender))
             .await;
@@ -243,6 +377,183 @@ impl AsyncEngine {
         receiver.await.context("failed to get result")
     }
 
+    #[instrument(skip_all)]
+    pub async fn query_span_histogram(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+        buckets: usize,
+    ) -> Result<Vec<HistogramBucket>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QuerySpanHistogram(
+            filter, start, end, buckets, sender,
+        ))
+        .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_event_histogram(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+        buckets: usize,
+    ) -> Result<Vec<HistogramBucket>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QueryEventHistogram(
+            filter, start, end, buckets, sender,
+        ))
+        .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_span_aggregate(
+        &self,
+        query: AggregateQuery,
+    ) -> Result<Vec<AggregateRow>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QuerySpanAggregate(query, sender))
+            .await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_event_aggregate(
+        &self,
+        query: AggregateQuery,
+    ) -> Result<Vec<AggregateRow>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QueryEventAggregate(query, sender))
+            .await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_span_latency(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+        group_by: Option<LatencyGroup>,
+    ) -> Result<Vec<LatencyStats>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QuerySpanLatency(
+            filter, start, end, group_by, sender,
+        ))
+        .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_event_attribute_names(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Result<Vec<AttributeSummary>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QueryEventAttributeNames(
+            filter, start, end, sender,
+        ))
+        .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_span_attribute_names(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Result<Vec<AttributeSummary>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QuerySpanAttributeNames(
+            filter, start, end, sender,
+        ))
+        .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_event_attribute_values(
+        &self,
+        name: String,
+        prefix: String,
+        limit: usize,
+    ) -> Result<Vec<AttributeValueCount>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QueryEventAttributeValues(
+            name, prefix, limit, sender,
+        ))
+        .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_span_attribute_values(
+        &self,
+        name: String,
+        prefix: String,
+        limit: usize,
+    ) -> Result<Vec<AttributeValueCount>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QuerySpanAttributeValues(
+            name, prefix, limit, sender,
+        ))
+        .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_trace(&self, root: TraceRoot) -> Result<Option<Trace>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QueryTrace(root, sender))
+            .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_critical_path(
+        &self,
+        root: TraceRoot,
+    ) -> Result<Option<Vec<CriticalPathSpan>>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QueryCriticalPath(root, sender))
+            .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_flame_graph(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Result<FlameGraph, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QueryFlameGraph(filter, start, end, sender))
+            .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn query_service_dependencies(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Result<Vec<ServiceDependency>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QueryServiceDependencies(
+            filter, start, end, sender,
+        ))
+        .await;
+        receiver.await.context("failed to get result")
+    }
+
     #[instrument(skip_all)]
     pub async fn query_stats(&self) -> Result<DatasetStats, AnyError> {
         let (sender, receiver) = oneshot::channel();
@@ -250,6 +561,14 @@ impl AsyncEngine {
         receiver.await.context("failed to get result")
     }
 
+    #[instrument(skip_all)]
+    pub async fn query_annotations(&self) -> Result<Vec<Annotation>, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::QueryAnnotations(sender))
+            .await;
+        receiver.await.context("failed to get result")
+    }
+
     #[instrument(skip_all)]
     #[allow(clippy::async_yields_async)]
     pub async fn insert_resource(
@@ -303,6 +622,89 @@ impl AsyncEngine {
         receiver.await.context("failed to get result")?
     }
 
+    #[instrument(skip_all)]
+    pub async fn pin(&self, pin: Pin) -> Result<(), AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::Pin(pin, sender)).await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn unpin(&self, pin: Pin) -> Result<(), AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::Unpin(pin, sender)).await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn add_annotation(
+        &self,
+        annotation: NewAnnotation,
+    ) -> Result<AnnotationKey, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::AddAnnotation(annotation, sender))
+            .await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn update_annotation(
+        &self,
+        key: AnnotationKey,
+        text: String,
+    ) -> Result<(), AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::UpdateAnnotation(key, text, sender))
+            .await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn remove_annotation(&self, key: AnnotationKey) -> Result<(), AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::RemoveAnnotation(key, sender))
+            .await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn undo_delete(&self, key: TombstoneKey) -> Result<DeleteMetrics, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::UndoDelete(key, sender))
+            .await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn purge_deleted(&self) -> Result<DeleteMetrics, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::PurgeDeleted(sender)).await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn drop_partitions_before(&self, at: Timestamp) -> Result<DeleteMetrics, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::DropPartitionsBefore(at, sender))
+            .await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn set_delete_grace_period(&self, grace_period: Duration) -> Result<(), AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::SetDeleteGracePeriod(grace_period, sender))
+            .await;
+        receiver.await.context("failed to get result")
+    }
+
+    #[instrument(skip_all)]
+    pub async fn compact(&self) -> Result<CompactMetrics, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::Compact(sender)).await;
+        receiver.await.context("failed to get result")?
+    }
+
     #[instrument(skip_all)]
     pub async fn subscribe_to_spans(
         &self,
@@ -349,6 +751,31 @@ impl AsyncEngine {
         receiver.await.context("failed to get result")?
     }
 
+    #[instrument(skip_all)]
+    pub async fn merge_dataset(
+        &self,
+        from: Box<dyn Storage + Send>,
+    ) -> Result<MergeMetrics, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::MergeDataset(from, sender))
+            .await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn verify(&self) -> Result<IntegrityReport, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_query(EngineCommand::Verify(sender)).await;
+        receiver.await.context("failed to get result")?
+    }
+
+    #[instrument(skip_all)]
+    pub async fn repair(&self) -> Result<IntegrityReport, AnyError> {
+        let (sender, receiver) = oneshot::channel();
+        self.emit_insert(EngineCommand::Repair(sender)).await;
+        receiver.await.context("failed to get result")?
+    }
+
     #[instrument(skip_all)]
     pub async fn get_status(&self) -> Result<EngineStatus, AnyError> {
         let (sender, receiver) = oneshot::channel();
@@ -385,15 +812,96 @@ impl AsyncEngine {
 enum EngineCommand {
     QuerySpan(Query, OneshotSender<Vec<ComposedSpan>>),
     QuerySpanCount(Query, OneshotSender<usize>),
-    QuerySpanEvent(Query, OneshotSender<Vec<SpanEvent>>),
+    QuerySpanEvent(SpanEventQuery, OneshotSender<Vec<SpanEvent>>),
     QueryEvent(Query, OneshotSender<Vec<ComposedEvent>>),
     QueryEventCount(Query, OneshotSender<usize>),
+    QuerySpanHistogram(
+        Vec<FilterPredicate>,
+        Timestamp,
+        Timestamp,
+        usize,
+        OneshotSender<Vec<HistogramBucket>>,
+    ),
+    QueryEventHistogram(
+        Vec<FilterPredicate>,
+        Timestamp,
+        Timestamp,
+        usize,
+        OneshotSender<Vec<HistogramBucket>>,
+    ),
+    QuerySpanAggregate(
+        AggregateQuery,
+        OneshotSender<Result<Vec<AggregateRow>, AnyError>>,
+    ),
+    QueryEventAggregate(
+        AggregateQuery,
+        OneshotSender<Result<Vec<AggregateRow>, AnyError>>,
+    ),
+    QuerySpanLatency(
+        Vec<FilterPredicate>,
+        Timestamp,
+        Timestamp,
+        Option<LatencyGroup>,
+        OneshotSender<Vec<LatencyStats>>,
+    ),
+    QueryEventAttributeNames(
+        Vec<FilterPredicate>,
+        Timestamp,
+        Timestamp,
+        OneshotSender<Vec<AttributeSummary>>,
+    ),
+    QuerySpanAttributeNames(
+        Vec<FilterPredicate>,
+        Timestamp,
+        Timestamp,
+        OneshotSender<Vec<AttributeSummary>>,
+    ),
+    QueryEventAttributeValues(
+        String,
+        String,
+        usize,
+        OneshotSender<Vec<AttributeValueCount>>,
+    ),
+    QuerySpanAttributeValues(
+        String,
+        String,
+        usize,
+        OneshotSender<Vec<AttributeValueCount>>,
+    ),
+    QueryTrace(TraceRoot, OneshotSender<Option<Trace>>),
+    QueryCriticalPath(TraceRoot, OneshotSender<Option<Vec<CriticalPathSpan>>>),
+    QueryFlameGraph(
+        Vec<FilterPredicate>,
+        Timestamp,
+        Timestamp,
+        OneshotSender<FlameGraph>,
+    ),
+    QueryServiceDependencies(
+        Vec<FilterPredicate>,
+        Timestamp,
+        Timestamp,
+        OneshotSender<Vec<ServiceDependency>>,
+    ),
     QueryStats(OneshotSender<DatasetStats>),
+    QueryAnnotations(OneshotSender<Vec<Annotation>>),
     InsertResource(NewResource, OneshotSender<Result<ResourceKey, AnyError>>),
     DisconnectTracingInstance(InstanceId, OneshotSender<Result<(), AnyError>>),
     InsertSpanEvent(NewSpanEvent, OneshotSender<Result<SpanKey, AnyError>>),
     InsertEvent(NewEvent, OneshotSender<Result<(), AnyError>>),
     Delete(DeleteFilter, OneshotSender<Result<DeleteMetrics, AnyError>>),
+    Compact(OneshotSender<Result<CompactMetrics, AnyError>>),
+    Pin(Pin, OneshotSender<Result<(), AnyError>>),
+    Unpin(Pin, OneshotSender<Result<(), AnyError>>),
+    AddAnnotation(
+        NewAnnotation,
+        OneshotSender<Result<AnnotationKey, AnyError>>,
+    ),
+    UpdateAnnotation(AnnotationKey, String, OneshotSender<Result<(), AnyError>>),
+    RemoveAnnotation(AnnotationKey, OneshotSender<Result<(), AnyError>>),
+    UndoDelete(TombstoneKey, OneshotSender<Result<DeleteMetrics, AnyError>>),
+    PurgeDeleted(OneshotSender<Result<DeleteMetrics, AnyError>>),
+    DropPartitionsBefore(Timestamp, OneshotSender<Result<DeleteMetrics, AnyError>>),
+    SetDeleteGracePeriod(Duration, OneshotSender<()>),
 
     SpanSubscribe(
         Vec<FilterPredicate>,
@@ -407,6 +915,12 @@ enum EngineCommand {
     EventUnsubscribe(SubscriptionId, OneshotSender<()>),
 
     CopyDataset(Box<dyn Storage + Send>, OneshotSender<Result<(), AnyError>>),
+    MergeDataset(
+        Box<dyn Storage + Send>,
+        OneshotSender<Result<MergeMetrics, AnyError>>,
+    ),
+    Verify(OneshotSender<Result<IntegrityReport, AnyError>>),
+    Repair(OneshotSender<Result<IntegrityReport, AnyError>>),
     GetStatus(OneshotSender<EngineStatus>),
 
     Shutdown(OneshotSender<Result<(), AnyError>>),
@@ -1,4 +1,9 @@
-use std::collections::{BTreeMap, HashMap};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, Context, Error as AnyError};
 use tokio::sync::mpsc::{self};
@@ -7,19 +12,34 @@ use tracing::instrument;
 use crate::context::{EventContext, SpanContext};
 use crate::filter::{
     BasicEventFilter, BasicSpanFilter, BoundSearch, FilterPredicate, IndexedEventFilter,
-    IndexedEventFilterIterator, IndexedSpanFilter, IndexedSpanFilterIterator, Query,
+    IndexedEventFilterIterator, IndexedSpanFilter, IndexedSpanFilterIterator, Order, Query,
+    SpanEventQuery,
 };
-use crate::index::{EventIndexes, SpanEventIndexes, SpanIndexes};
-use crate::models::{CloseSpanEvent, EnterSpanEvent, EventKey, FollowsSpanEvent};
-use crate::storage::Storage;
+use crate::index::{
+    entries_of, EventIndexes, IndexEntry, SortedIndexLookup, SpanEventIndexes, SpanIndexes,
+    ValueIndex,
+};
+use crate::models::{to_micros, CloseSpanEvent, EnterSpanEvent, EventKey, FollowsSpanEvent};
+use crate::storage::{IndexSnapshot, Storage, StorageError};
 use crate::subscription::{EventSubscription, SpanSubscription, Subscriber};
 use crate::{
-    ComposedEvent, ComposedSpan, CreateSpanEvent, DatasetStats, DeleteFilter, DeleteMetrics, Event,
-    FullSpanId, InstanceId, NewEvent, NewResource, NewSpanEvent, NewSpanEventKind, Resource,
-    ResourceKey, Span, SpanEvent, SpanEventKey, SpanEventKind, SpanKey, SubscriptionId, Timestamp,
-    UpdateSpanEvent, ValueOperator,
+    Aggregate, AggregateField, AggregateQuery, AggregateRow, Annotation, AnnotationKey,
+    AnnotationTarget, AttributeSummary, AttributeValueCount, CompactMetrics, ComposedEvent,
+    ComposedSpan, CreateSpanEvent, CriticalPathSpan, DatasetStats, DeleteFilter, DeleteMetrics,
+    DeleteMode, Event, FlameGraph, FlameNode, FullSpanId, GroupKey, HistogramBucket, InstanceId,
+    IntegrityReport, LatencyBucket, LatencyGroup, LatencyStats, Level, MergeMetrics, NewAnnotation,
+    NewEvent, NewResource, NewSpanEvent, NewSpanEventKind, Pin, PreciseTimestamp, Resource,
+    ResourceKey, ServiceDependency, Span, SpanEvent, SpanEventKey, SpanEventKind, SpanKey,
+    SubscriptionId, Timestamp, Tombstone, TombstoneKey, Trace, TraceEvent, TraceRoot, TraceSpan,
+    UpdateSpanEvent, Value, ValueOperator,
 };
 
+/// The spans, span events, and events that an operation applies to.
+type EntityKeys = (Vec<SpanKey>, Vec<SpanEventKey>, Vec<EventKey>);
+
+/// How long deleted entities can be restored by default before they are purged.
+pub const DEFAULT_DELETE_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
 /// Provides the core engine functionality.
 pub struct SyncEngine<S> {
     pub(crate) storage: S,
@@ -28,6 +48,11 @@ pub struct SyncEngine<S> {
     pub(crate) event_indexes: EventIndexes,
 
     resources: HashMap<ResourceKey, Resource>,
+    disconnected_instances: HashSet<InstanceId>,
+    tombstones: BTreeMap<TombstoneKey, Tombstone>,
+    pins: BTreeSet<Pin>,
+    annotations: BTreeMap<AnnotationKey, Annotation>,
+    delete_grace_period: Duration,
 
     next_subscriber_id: usize,
     span_subscribers: HashMap<usize, SpanSubscription>,
@@ -43,6 +68,11 @@ impl<S: Storage> SyncEngine<S> {
             event_indexes: EventIndexes::new(),
 
             resources: HashMap::new(),
+            disconnected_instances: HashSet::new(),
+            tombstones: BTreeMap::new(),
+            pins: BTreeSet::new(),
+            annotations: BTreeMap::new(),
+            delete_grace_period: DEFAULT_DELETE_GRACE_PERIOD,
 
             next_subscriber_id: 0,
             span_subscribers: HashMap::new(),
@@ -62,10 +92,40 @@ impl<S: Storage> SyncEngine<S> {
             engine.insert_resource_bookeeping(&resource);
         }
 
+        if let Some(s) = engine.storage.as_tombstone_storage() {
+            match s.get_tombstones() {
+                Ok(tombstones) => {
+                    for tombstone in tombstones {
+                        engine.tombstones.insert(tombstone.key, tombstone);
+                    }
+                }
+                Err(err) => tracing::warn!(?err, "failed to load tombstones from storage"),
+            }
+        }
+
+        if let Some(s) = engine.storage.as_pin_storage() {
+            match s.get_pins() {
+                Ok(pins) => engine.pins.extend(pins),
+                Err(err) => tracing::warn!(?err, "failed to load pins from storage"),
+            }
+        }
+
+        if let Some(s) = engine.storage.as_annotation_storage() {
+            match s.get_annotations() {
+                Ok(annotations) => {
+                    for annotation in annotations {
+                        engine.annotations.insert(annotation.key, annotation);
+                    }
+                }
+                Err(err) => tracing::warn!(?err, "failed to load annotations from storage"),
+            }
+        }
+
         let indexes_result = engine
             .storage
             .as_index_storage()
-            .and_then(|s| s.get_indexes().transpose());
+            .and_then(|s| s.get_indexes().transpose())
+            .map(|snapshot| snapshot.and_then(|snapshot| snapshot.decode()));
 
         match indexes_result {
             Some(Ok(indexes)) => {
@@ -76,78 +136,17 @@ impl<S: Storage> SyncEngine<S> {
                 engine.span_indexes = span_indexes;
                 engine.span_event_indexes = span_event_indexes;
                 engine.event_indexes = event_indexes;
+                engine.update_pinned_indexes();
             }
             Some(Err(err)) => {
                 tracing::warn!(?err, "failed to load indexes from storage");
 
-                let spans = engine
-                    .storage
-                    .get_all_spans()
-                    .context("failed to load spans")?
-                    .collect::<Vec<_>>();
-
-                for span in spans {
-                    let span = span.context("failed to load span")?;
-                    engine.insert_span_bookeeping(&span);
-                }
-
-                let span_events = engine
-                    .storage
-                    .get_all_span_events()
-                    .context("failed to load span events")?
-                    .collect::<Vec<_>>();
-
-                for span_event in span_events {
-                    let span_event = span_event.context("failed to load span event")?;
-                    engine.insert_span_event_bookeeping(&span_event);
-                }
-
-                let events = engine
-                    .storage
-                    .get_all_events()
-                    .context("failed to load events")?
-                    .collect::<Vec<_>>();
-
-                for event in events {
-                    let event = event.context("failed to load event")?;
-                    engine.insert_event_bookeeping(&event);
-                }
+                engine.rebuild_indexes()?;
             }
             None => {
                 tracing::warn!("no indexes from storage");
 
-                let spans = engine
-                    .storage
-                    .get_all_spans()
-                    .context("failed to load spans")?
-                    .collect::<Vec<_>>();
-
-                for span in spans {
-                    let span = span.context("failed to load span")?;
-                    engine.insert_span_bookeeping(&span);
-                }
-
-                let span_events = engine
-                    .storage
-                    .get_all_span_events()
-                    .context("failed to load span events")?
-                    .collect::<Vec<_>>();
-
-                for span_event in span_events {
-                    let span_event = span_event.context("failed to load span event")?;
-                    engine.insert_span_event_bookeeping(&span_event);
-                }
-
-                let events = engine
-                    .storage
-                    .get_all_events()
-                    .context("failed to load events")?
-                    .collect::<Vec<_>>();
-
-                for event in events {
-                    let event = event.context("failed to load event")?;
-                    engine.insert_event_bookeeping(&event);
-                }
+                engine.rebuild_indexes()?;
             }
         }
 
@@ -155,20 +154,30 @@ impl<S: Storage> SyncEngine<S> {
             let last_event = engine.event_indexes.all.last();
             let last_span_event = engine.span_event_indexes.all.last();
             let last_at = match (last_event, last_span_event) {
-                (Some(event), Some(span_event)) => Ord::max(*event, *span_event),
-                (None, Some(span_event)) => *span_event,
-                (Some(event), None) => *event,
+                (Some(event), Some(span_event)) => Ord::max(event.at, span_event.at),
+                (None, Some(span_event)) => span_event.at,
+                (Some(event), None) => event.at,
                 (None, None) => panic!("not possible to have open span but no span events"),
             };
 
-            let at = last_at.saturating_add(1);
+            let at = last_at + 1;
 
-            for span_key in engine.span_indexes.durations.open.clone() {
-                engine.span_indexes.update_with_closed(span_key, at);
-                engine
+            for entry in engine.span_indexes.durations.open.clone() {
+                let span_key = entry.key;
+                let span = engine
                     .storage
-                    .update_span_closed(span_key, at, None)
-                    .context("failed to close span")?;
+                    .get_span(span_key)
+                    .context("failed to load span")?;
+
+                let duration = at.saturating_sub(span.created_at) / 1000;
+                engine.span_indexes.update_with_closed(entry, duration);
+
+                // a read-only storage may be observing a dataset that is still
+                // being written to, so the spans are only closed in the indexes
+                match engine.storage.update_span_closed(span_key, at, None) {
+                    Ok(()) | Err(StorageError::ReadOnly) => {}
+                    Err(err) => return Err(err).context("failed to close span"),
+                }
             }
         }
 
@@ -237,989 +246,4959 @@ impl<S: Storage> SyncEngine<S> {
         }
     }
 
+    /// Counts the events matching the filter in each of `buckets` evenly sized
+    /// slices of `start..=end`, broken down by level.
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    #[doc(hidden)]
-    pub fn query_span_event(&self, _query: Query) -> Vec<SpanEvent> {
-        unimplemented!()
-    }
+    pub fn query_event_histogram(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+        buckets: usize,
+    ) -> Vec<HistogramBucket> {
+        tracing::debug!(?filter, %start, %end, buckets, "querying for event histogram");
 
-    #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn query_stats(&self) -> DatasetStats {
-        tracing::debug!("querying for stats");
+        let mut histogram = histogram_buckets(start, end, buckets);
+        if histogram.is_empty() {
+            return histogram;
+        }
 
-        let event_start = self.event_indexes.all.first().copied();
-        let event_end = self.event_indexes.all.last().copied();
-        let span_start = self.span_indexes.all.first().copied();
-        let span_end = self.span_indexes.all.last().copied(); // TODO: not technically right, but maybe okay
+        let query = Query {
+            filter,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start,
+            end,
+            previous: None,
+        };
 
-        DatasetStats {
-            start: crate::filter::merge(event_start, span_start, Ord::min),
-            end: crate::filter::merge(event_end, span_end, Ord::max),
-            total_events: self.event_indexes.all.len(),
-            total_spans: self.span_indexes.all.len(),
+        // the entries are visited in order, so the level indexes are walked
+        // alongside them rather than searched for each event
+        let mut levels = SortedIndexLookup::new(
+            self.event_indexes
+                .levels
+                .iter()
+                .map(Vec::as_slice)
+                .collect(),
+        );
+
+        let mut events = IndexedEventFilterIterator::new(query, self);
+        while let Some(entry) = events.next_entry() {
+            let Some(level) = levels.find(&entry) else {
+                continue;
+            };
+
+            let bucket = histogram_bucket_of(start, end, histogram.len(), to_micros(entry.at));
+            histogram[bucket].counts[level] += 1;
         }
+
+        histogram
     }
 
+    /// Counts the spans matching the filter in each of `buckets` evenly sized
+    /// slices of `start..=end`, broken down by level. Like `query_span_count`,
+    /// a span is counted in every slice that it overlaps.
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn insert_resource(&mut self, resource: NewResource) -> Result<ResourceKey, AnyError> {
-        tracing::debug!(?resource, "inserting resource");
+    pub fn query_span_histogram(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+        buckets: usize,
+    ) -> Vec<HistogramBucket> {
+        tracing::debug!(?filter, %start, %end, buckets, "querying for span histogram");
 
-        if let Some((key, _)) = self
-            .resources
-            .iter()
-            .find(|(_, r)| r.attributes == resource.attributes)
-        {
-            return Ok(*key);
+        let mut histogram = histogram_buckets(start, end, buckets);
+        if histogram.is_empty() {
+            return histogram;
         }
 
-        let keys = self.resources.keys().copied().collect::<Vec<_>>();
-        let now = now();
-        let resource_key = get_unique_timestamp(now, &keys);
-        let resource = Resource {
-            created_at: resource_key,
-            attributes: resource.attributes,
+        let query = Query {
+            filter,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start,
+            end,
+            previous: None,
         };
 
-        self.insert_resource_bookeeping(&resource);
-        self.storage
-            .insert_resource(resource)
-            .context("failed to insert resource")?;
+        // the entries are visited in order, so the level and duration indexes
+        // are walked alongside them rather than loading each span
+        let mut levels =
+            SortedIndexLookup::new(self.span_indexes.levels.iter().map(Vec::as_slice).collect());
+        let durations = self.span_indexes.durations.to_stratified_indexes();
+        let (open, closed) = durations.split_last().unwrap();
+        let mut open = SortedIndexLookup::new(vec![open.0]);
+        let mut closed_durations =
+            SortedIndexLookup::new(closed.iter().map(|(index, _)| *index).collect());
+
+        let buckets = histogram.len();
+        let bucket_of =
+            |at: Timestamp| histogram_bucket_of(start, end, buckets, at.clamp(start, end));
+
+        let mut spans = IndexedSpanFilterIterator::new(query, self);
+        while let Some(entry) = spans.next_entry() {
+            let Some(level) = levels.find(&entry) else {
+                continue;
+            };
 
-        Ok(resource_key)
-    }
+            // the span overlaps every slice from where it was created up to
+            // the one holding the last moment it was still open
+            let created_at = to_micros(entry.at);
+            let first = bucket_of(created_at);
+            if open.find(&entry).is_some() {
+                for bucket in &mut histogram[first..] {
+                    bucket.counts[level] += 1;
+                }
+                continue;
+            }
 
-    fn insert_resource_bookeeping(&mut self, resource: &Resource) {
-        self.resources.insert(resource.key(), resource.clone());
+            // the duration stratum bounds when the span closed, so it is only
+            // loaded if that could be past the slice it was created in
+            let duration = closed_durations.find(&entry).map(|i| &closed[i].1);
+            let last = match duration {
+                Some(duration) if bucket_of(created_at.saturating_add(duration.end)) == first => {
+                    first
+                }
+                _ => match self.storage.get_span(entry.key) {
+                    Ok(span) => match span.closed_at {
+                        Some(closed_at) => bucket_of(to_micros(closed_at.saturating_sub(1))),
+                        None => buckets - 1,
+                    },
+                    Err(err) => {
+                        tracing::warn!(?err, "failed to load span");
+                        continue;
+                    }
+                },
+            };
+
+            for bucket in &mut histogram[first..=last.max(first)] {
+                bucket.counts[level] += 1;
+            }
+        }
+
+        histogram
     }
 
+    /// Groups the events matching the filter by the query's keys and computes
+    /// its aggregates for each group. The rows are ordered by group values.
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn disconnect_tracing_instance(&mut self, instance_id: InstanceId) -> Result<(), AnyError> {
-        tracing::debug!(instance_id, "disconnecting tracing instance");
+    pub fn query_event_aggregate(
+        &self,
+        query: AggregateQuery,
+    ) -> Result<Vec<AggregateRow>, AnyError> {
+        tracing::debug!(?query, "querying for event aggregates");
+
+        for key in &query.group_by {
+            match key {
+                GroupKey::Inherent(name) if !EVENT_GROUP_PROPERTIES.contains(&name.as_str()) => {
+                    return Err(anyhow!("events cannot be grouped by #{name}"));
+                }
+                _ => {}
+            }
+        }
 
-        let at = now();
+        for aggregate in &query.aggregates {
+            if let Some(AggregateField::Duration) = aggregate.field() {
+                return Err(anyhow!("events do not have a #duration"));
+            }
+        }
 
-        let filter = IndexedSpanFilter::And(vec![
-            IndexedSpanFilter::Single(&self.span_indexes.durations.open, None),
-            IndexedSpanFilter::Single(
-                self.span_indexes
-                    .instances
-                    .get(&instance_id)
-                    .map(Vec::as_slice)
-                    .unwrap_or_default(),
-                None,
-            ),
-        ]);
+        let mut groups = AggregateGroups::new(&query.aggregates);
 
-        let open_spans = IndexedSpanFilterIterator::new_internal(filter, self).collect::<Vec<_>>();
+        let filter_query = Query {
+            filter: query.filter,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start: query.start,
+            end: query.end,
+            previous: None,
+        };
 
-        for span_key in open_spans {
-            self.span_indexes.update_with_closed(span_key, at);
-            self.storage
-                .update_span_closed(span_key, at, None)
-                .context("failed to close span")?;
+        for event_key in IndexedEventFilterIterator::new(filter_query, self) {
+            let event = match self.storage.get_event(event_key) {
+                Ok(event) => event,
+                Err(err) => {
+                    tracing::warn!(?err, "failed to load event");
+                    continue;
+                }
+            };
+
+            let context = EventContext::with_event(&event, &self.storage);
+
+            let group = query
+                .group_by
+                .iter()
+                .map(|key| match key {
+                    GroupKey::Inherent(name) => match name.as_str() {
+                        "level" => Some(event.level.into_simple_level().to_string()),
+                        "content" => Some(event.content.to_string()),
+                        "namespace" => event.namespace.clone(),
+                        "function" => event.function.clone(),
+                        "file" => event.file_name.clone(),
+                        _ => None,
+                    },
+                    GroupKey::Attribute(name) => context.attribute(name).map(Value::to_string),
+                })
+                .collect();
+
+            groups.add(group, |field| match field {
+                AggregateField::Duration => None,
+                AggregateField::Attribute(name) => context.attribute(name).and_then(value_to_f64),
+            });
         }
 
-        Ok(())
+        Ok(groups.finish())
     }
 
+    /// Groups the spans matching the filter by the query's keys and computes
+    /// its aggregates for each group. The rows are ordered by group values.
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn insert_span_event(
-        &mut self,
-        mut new_span_event: NewSpanEvent,
-    ) -> Result<SpanEventKey, AnyError> {
-        tracing::debug!(span_event = ?new_span_event, "inserting span event");
+    pub fn query_span_aggregate(
+        &self,
+        query: AggregateQuery,
+    ) -> Result<Vec<AggregateRow>, AnyError> {
+        tracing::debug!(?query, "querying for span aggregates");
+
+        for key in &query.group_by {
+            match key {
+                GroupKey::Inherent(name) if !SPAN_GROUP_PROPERTIES.contains(&name.as_str()) => {
+                    return Err(anyhow!("spans cannot be grouped by #{name}"));
+                }
+                _ => {}
+            }
+        }
 
-        let span_event_key =
-            get_unique_timestamp(new_span_event.timestamp, &self.span_event_indexes.all);
-        new_span_event.timestamp = span_event_key;
+        let mut groups = AggregateGroups::new(&query.aggregates);
 
-        match new_span_event.kind {
-            NewSpanEventKind::Create(new_create_event) => {
-                if self.span_indexes.ids.contains_key(&new_span_event.span_id) {
-                    return Err(anyhow::anyhow!("duplicate span id"));
+        let filter_query = Query {
+            filter: query.filter,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start: query.start,
+            end: query.end,
+            previous: None,
+        };
+
+        for span_key in IndexedSpanFilterIterator::new(filter_query, self) {
+            let span = match self.storage.get_span(span_key) {
+                Ok(span) => span,
+                Err(err) => {
+                    tracing::warn!(?err, "failed to load span");
+                    continue;
                 }
+            };
 
-                // parent may not yet exist, that is ok
-                let parent_id = new_create_event.parent_id;
-                let parent_key = parent_id.and_then(|id| self.span_indexes.ids.get(&id).copied());
+            let context = SpanContext::with_span(&span, &self.storage);
+
+            let group = query
+                .group_by
+                .iter()
+                .map(|key| match key {
+                    GroupKey::Inherent(name) => match name.as_str() {
+                        "level" => Some(span.level.into_simple_level().to_string()),
+                        "name" => Some(span.name.clone()),
+                        "namespace" => span.namespace.clone(),
+                        "function" => span.function.clone(),
+                        "file" => span.file_name.clone(),
+                        _ => None,
+                    },
+                    GroupKey::Attribute(name) => context.attribute(name).map(Value::to_string),
+                })
+                .collect();
+
+            groups.add(group, |field| match field {
+                AggregateField::Duration => span
+                    .precise_duration()
+                    .map(|duration| duration as f64 / 1000.0),
+                AggregateField::Attribute(name) => context.attribute(name).and_then(value_to_f64),
+            });
+        }
 
-                let created_at =
-                    get_unique_timestamp(new_span_event.timestamp, &self.span_indexes.all);
+        Ok(groups.finish())
+    }
 
-                let span = Span {
-                    kind: new_create_event.kind,
-                    resource_key: new_create_event.resource_key,
-                    id: new_span_event.span_id,
-                    created_at,
-                    closed_at: None,
-                    busy: None,
-                    parent_id,
-                    parent_key,
-                    links: Vec::new(),
-                    name: new_create_event.name.clone(),
-                    namespace: new_create_event.namespace.clone(),
-                    function: new_create_event.function.clone(),
-                    level: new_create_event.level,
-                    file_name: new_create_event.file_name.clone(),
-                    file_line: new_create_event.file_line,
-                    file_column: new_create_event.file_column,
-                    instrumentation_attributes: new_create_event.instrumentation_attributes.clone(),
-                    attributes: new_create_event.attributes.clone(),
-                };
+    /// Computes duration percentiles and a latency histogram for the closed
+    /// spans matching the filter, optionally grouped by name or function. The
+    /// histogram uses the same ranges as the span duration index.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_span_latency(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+        group_by: Option<LatencyGroup>,
+    ) -> Vec<LatencyStats> {
+        tracing::debug!(?filter, %start, %end, ?group_by, "querying for span latency");
 
-                let span_event = SpanEvent {
-                    timestamp: new_span_event.timestamp,
-                    span_key: span.created_at,
-                    kind: SpanEventKind::Create(CreateSpanEvent {
-                        kind: new_create_event.kind,
-                        resource_key: new_create_event.resource_key,
-                        parent_key,
-                        name: new_create_event.name,
-                        namespace: new_create_event.namespace,
-                        function: new_create_event.function,
-                        level: new_create_event.level,
-                        file_name: new_create_event.file_name,
-                        file_line: new_create_event.file_line,
-                        file_column: new_create_event.file_column,
-                        instrumentation_attributes: new_create_event.instrumentation_attributes,
-                        attributes: new_create_event.attributes,
-                    }),
-                };
+        let query = Query {
+            filter,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start,
+            end,
+            previous: None,
+        };
 
-                let (child_spans, child_events) = self.insert_span_bookeeping(&span);
-                self.storage
-                    .insert_span(span.clone())
-                    .context("failed to insert span")?;
-                self.storage
-                    .update_span_parents(span.key(), &child_spans)
-                    .context("failed to update span parents")?;
-                self.storage
-                    .update_event_parents(span.key(), &child_events)
-                    .context("failed to update event parents")?;
+        let mut groups: BTreeMap<Option<String>, Vec<u64>> = BTreeMap::new();
+        for span_key in IndexedSpanFilterIterator::new(query, self) {
+            let span = match self.storage.get_span(span_key) {
+                Ok(span) => span,
+                Err(err) => {
+                    tracing::warn!(?err, "failed to load span");
+                    continue;
+                }
+            };
 
-                self.insert_span_event_bookeeping(&span_event);
-                self.storage
-                    .insert_span_event(span_event)
-                    .context("failed to insert span event")?;
+            let Some(duration) = span.duration() else {
+                continue;
+            };
 
-                if !self.event_subscribers.is_empty() {
-                    let root = SpanContext::with_span(&span, &self.storage).trace_root();
-                    let descendent_events = self
-                        .event_indexes
-                        .traces
-                        .get(&root)
-                        .map(Vec::as_slice)
-                        .unwrap_or_default()
-                        .iter()
-                        .copied()
-                        .filter(|key| {
-                            EventContext::new(*key, &self.storage)
-                                .parents()
-                                .any(|p| p.key() == span.key())
-                        });
+            let group = match group_by {
+                None => None,
+                Some(LatencyGroup::Name) => Some(span.name.clone()),
+                Some(LatencyGroup::Function) => span.function.clone(),
+            };
 
-                    // update subscribers for events that may have been updated
-                    // by a new parent
-                    for event_key in descendent_events {
-                        let context = EventContext::new(event_key, &self.storage);
-                        for subscriber in self.event_subscribers.values_mut() {
-                            subscriber.on_event(&context);
-                        }
-                    }
+            groups.entry(group).or_default().push(duration);
+        }
 
-                    self.event_subscribers.retain(|_, s| s.connected());
-                }
+        let ranges = self.latency_ranges();
 
-                if !self.span_subscribers.is_empty() {
-                    for subscriber in self.span_subscribers.values_mut() {
-                        subscriber.on_span(&SpanContext::with_span(&span, &self.storage));
-                    }
+        groups
+            .into_iter()
+            .map(|(group, durations)| latency_stats(group, durations, &ranges))
+            .collect()
+    }
 
-                    let root = SpanContext::with_span(&span, &self.storage).trace_root();
-                    let descendent_spans = self
-                        .span_indexes
-                        .traces
-                        .get(&root)
-                        .map(Vec::as_slice)
-                        .unwrap_or_default()
-                        .iter()
-                        .copied()
-                        .filter(|key| {
-                            SpanContext::new(*key, &self.storage)
-                                .parents()
-                                .any(|p| p.key() == span.key())
-                        });
+    /// Finds the calls between resources by looking for spans matching the
+    /// filter whose parent span belongs to a different resource.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_service_dependencies(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Vec<ServiceDependency> {
+        tracing::debug!(?filter, %start, %end, "querying for service dependencies");
 
-                    // update subscribers for spans that may have been updated
-                    // by a new parent
-                    for span_key in descendent_spans {
-                        let context = SpanContext::new(span_key, &self.storage);
-                        for subscriber in self.span_subscribers.values_mut() {
-                            subscriber.on_span(&context);
-                        }
-                    }
+        let query = Query {
+            filter,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start,
+            end,
+            previous: None,
+        };
 
-                    self.span_subscribers.retain(|_, s| s.connected());
+        #[derive(Default)]
+        struct Calls {
+            count: usize,
+            errors: usize,
+            durations: Vec<u64>,
+        }
+
+        let mut parent_resources: HashMap<SpanKey, Option<ResourceKey>> = HashMap::new();
+        let mut edges: BTreeMap<(ResourceKey, ResourceKey), Calls> = BTreeMap::new();
+        for span_key in IndexedSpanFilterIterator::new(query, self) {
+            let span = match self.storage.get_span(span_key) {
+                Ok(span) => span,
+                Err(err) => {
+                    tracing::warn!(?err, "failed to load span");
+                    continue;
                 }
+            };
+
+            let Some(parent_key) = span.parent_key else {
+                continue;
+            };
+
+            let parent_resource = *parent_resources
+                .entry(parent_key)
+                .or_insert_with(|| match self.storage.get_span(parent_key) {
+                    Ok(parent) => Some(parent.resource_key),
+                    Err(err) => {
+                        tracing::warn!(?err, "failed to load span");
+                        None
+                    }
+                });
+
+            let Some(parent_resource) = parent_resource else {
+                continue;
+            };
+
+            if parent_resource == span.resource_key {
+                continue;
             }
-            NewSpanEventKind::Update(new_update_event) => {
-                let span_key = self
-                    .span_indexes
-                    .ids
-                    .get(&new_span_event.span_id)
-                    .copied()
-                    .ok_or(anyhow!("unknown span id"))?;
 
-                let span = SpanContext::new(span_key, &self.storage);
-                let trace = span.trace_root();
+            let calls = edges
+                .entry((parent_resource, span.resource_key))
+                .or_default();
 
-                let update_event = UpdateSpanEvent {
-                    attributes: new_update_event.attributes.clone(),
-                };
+            calls.count += 1;
+            if span.level.to_db() >= Level::Error.to_db() {
+                calls.errors += 1;
+            }
+            if let Some(duration) = span.duration() {
+                calls.durations.push(duration);
+            }
+        }
 
-                let descendent_spans = self
-                    .span_indexes
-                    .traces
-                    .get(&trace)
-                    .map(Vec::as_slice)
-                    .unwrap_or_default()
-                    .iter()
-                    .cloned()
-                    .filter(|key| {
-                        SpanContext::new(*key, &self.storage)
-                            .parents()
-                            .any(|p| p.key() == span_key)
-                            || *key == span_key // also update self
-                    })
-                    .collect::<Vec<_>>();
+        let ranges = self.latency_ranges();
+
+        edges
+            .into_iter()
+            .map(|((caller, callee), calls)| ServiceDependency {
+                caller,
+                callee,
+                count: calls.count,
+                errors: calls.errors,
+                latency: (!calls.durations.is_empty())
+                    .then(|| latency_stats(None, calls.durations, &ranges)),
+            })
+            .collect()
+    }
 
-                for child_span_key in descendent_spans {
-                    // check if nested span attribute changed
-                    self.span_indexes.update_with_new_field_on_parent(
-                        &SpanContext::new(child_span_key, &self.storage),
-                        span_key,
-                        &update_event.attributes,
-                    );
-                }
+    /// The duration ranges used for latency histograms, excluding the range
+    /// for open spans.
+    fn latency_ranges(&self) -> Vec<Range<u64>> {
+        let mut ranges = self
+            .span_indexes
+            .durations
+            .to_stratified_indexes()
+            .into_iter()
+            .map(|(_, range)| range)
+            .collect::<Vec<_>>();
 
-                let descendent_events = self
-                    .event_indexes
-                    .traces
-                    .get(&trace)
-                    .map(Vec::as_slice)
-                    .unwrap_or_default()
-                    .iter()
-                    .cloned()
-                    .filter(|key| {
-                        EventContext::new(*key, &self.storage)
-                            .parents()
-                            .any(|p| p.key() == span_key)
-                    })
-                    .collect::<Vec<_>>();
+        ranges.pop(); // the last range is for open spans
+        ranges
+    }
 
-                for event_key in descendent_events {
-                    // check if nested event attribute changed
-                    self.event_indexes.update_with_new_field_on_parent(
-                        &EventContext::new(event_key, &self.storage),
-                        span_key,
-                        &update_event.attributes,
-                    );
-                }
+    /// Lists the attribute names of the events matching the filter along with
+    /// how many events have them and what types of values they hold.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_event_attribute_names(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Vec<AttributeSummary> {
+        tracing::debug!(?filter, %start, %end, "querying for event attribute names");
 
-                let span_event = SpanEvent {
-                    timestamp: new_span_event.timestamp,
-                    span_key,
-                    kind: SpanEventKind::Update(update_event),
-                };
+        let query = Query {
+            filter,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start,
+            end,
+            previous: None,
+        };
 
-                self.storage
-                    .update_span_attributes(span_key, new_update_event.attributes)
-                    .context("failed to update span attributes")?;
+        let mut events = IndexedEventFilterIterator::new(query, self);
+        let event_entries = std::iter::from_fn(|| events.next_entry()).collect::<Vec<_>>();
 
-                self.insert_span_event_bookeeping(&span_event);
-                self.storage
-                    .insert_span_event(span_event)
-                    .context("failed to insert span event")?;
+        summarize_attributes(&self.event_indexes.attributes, &event_entries)
+    }
 
-                if !self.event_subscribers.is_empty() {
-                    let descendent_events = self
-                        .event_indexes
-                        .traces
-                        .get(&trace)
-                        .map(Vec::as_slice)
-                        .unwrap_or_default()
-                        .iter()
-                        .copied()
-                        .filter(|key| {
-                            EventContext::new(*key, &self.storage)
-                                .parents()
-                                .any(|p| p.key() == span_key)
-                        });
+    /// Lists the attribute names of the spans matching the filter along with
+    /// how many spans have them and what types of values they hold.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_span_attribute_names(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Vec<AttributeSummary> {
+        tracing::debug!(?filter, %start, %end, "querying for span attribute names");
 
-                    // update subscribers for events that may have been updated
-                    // by an updated parent
-                    for event_key in descendent_events {
-                        let context = EventContext::new(event_key, &self.storage);
-                        for subscriber in self.event_subscribers.values_mut() {
-                            subscriber.on_event(&context);
-                        }
-                    }
+        let query = Query {
+            filter,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start,
+            end,
+            previous: None,
+        };
 
-                    self.event_subscribers.retain(|_, s| s.connected());
-                }
+        let mut spans = IndexedSpanFilterIterator::new(query, self);
+        let span_entries = std::iter::from_fn(|| spans.next_entry()).collect::<Vec<_>>();
 
-                if !self.span_subscribers.is_empty() {
-                    let descendent_spans = self
-                        .span_indexes
-                        .traces
-                        .get(&trace)
-                        .map(Vec::as_slice)
-                        .unwrap_or_default()
-                        .iter()
-                        .copied()
-                        .filter(|key| {
-                            SpanContext::new(*key, &self.storage)
-                                .parents()
-                                .any(|p| p.key() == span_key)
-                        });
+        summarize_attributes(&self.span_indexes.attributes, &span_entries)
+    }
 
-                    // update subscribers for spans that may have been updated
-                    // by an updated parent
-                    for span_key in descendent_spans {
-                        let context = SpanContext::new(span_key, &self.storage);
-                        for subscriber in self.span_subscribers.values_mut() {
-                            subscriber.on_span(&context);
-                        }
-                    }
+    /// Lists the most common string values of an event attribute that start
+    /// with `prefix`.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_event_attribute_values(
+        &self,
+        name: &str,
+        prefix: &str,
+        limit: usize,
+    ) -> Vec<AttributeValueCount> {
+        tracing::debug!(%name, %prefix, limit, "querying for event attribute values");
 
-                    self.span_subscribers.retain(|_, s| s.connected());
-                }
-            }
-            NewSpanEventKind::Follows(new_follows_event) => {
-                let span_key = self
-                    .span_indexes
-                    .ids
-                    .get(&new_span_event.span_id)
-                    .copied()
-                    .ok_or(anyhow!("unknown span id"))?;
+        top_attribute_values(self.event_indexes.attributes.get(name), prefix, limit)
+    }
 
-                let FullSpanId::Tracing(instance_id, _) = new_span_event.span_id else {
-                    return Err(anyhow!("invalid span kind, expected tracing"));
-                };
+    /// Lists the most common string values of a span attribute that start with
+    /// `prefix`.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_span_attribute_values(
+        &self,
+        name: &str,
+        prefix: &str,
+        limit: usize,
+    ) -> Vec<AttributeValueCount> {
+        tracing::debug!(%name, %prefix, limit, "querying for span attribute values");
 
-                let follows_span_id = FullSpanId::Tracing(instance_id, new_follows_event.follows);
-                let follows_span_key = self
-                    .span_indexes
-                    .ids
-                    .get(&follows_span_id)
-                    .copied()
-                    .ok_or(anyhow!("unknown span id"))?;
+        top_attribute_values(self.span_indexes.attributes.get(name), prefix, limit)
+    }
 
-                // TODO: check against circular following
-                // TODO: check against duplicates
+    /// Returns all the spans and events of a trace arranged as a tree, or
+    /// `None` if the trace is not known.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_trace(&self, root: TraceRoot) -> Option<Trace> {
+        tracing::debug!(?root, "querying for trace");
 
-                let span_event = SpanEvent {
-                    timestamp: new_span_event.timestamp,
-                    span_key,
-                    kind: SpanEventKind::Follows(FollowsSpanEvent {
-                        follows: follows_span_key,
-                    }),
-                };
+        let span_entries = self.span_indexes.traces.get(&root);
+        let event_entries = self.event_indexes.traces.get(&root);
 
-                self.storage
-                    .update_span_link(span_key, follows_span_id, BTreeMap::new())
-                    .context("failed to update span link")?;
+        if span_entries.is_none() && event_entries.is_none() {
+            return None;
+        }
 
-                self.insert_span_event_bookeeping(&span_event);
-                self.storage
-                    .insert_span_event(span_event)
-                    .context("failed to insert span event")?;
+        let mut spans = HashMap::new();
+        for entry in span_entries.into_iter().flatten() {
+            match self.storage.get_span(entry.key) {
+                Ok(span) => {
+                    spans.insert(entry.key, span);
+                }
+                Err(err) => tracing::warn!(?err, "failed to load span"),
             }
-            NewSpanEventKind::Enter(new_enter_event) => {
-                let span_key = self
-                    .span_indexes
-                    .ids
-                    .get(&new_span_event.span_id)
-                    .copied()
-                    .ok_or(anyhow!("unknown span id"))?;
+        }
 
-                let span_event = SpanEvent {
-                    timestamp: new_span_event.timestamp,
-                    span_key,
-                    kind: SpanEventKind::Enter(EnterSpanEvent {
-                        thread_id: new_enter_event.thread_id,
-                    }),
-                };
+        // the trace index is ordered by creation time, so children are in order
+        let mut roots = vec![];
+        let mut children: HashMap<SpanKey, Vec<SpanKey>> = HashMap::new();
+        for entry in span_entries.into_iter().flatten() {
+            let Some(span) = spans.get(&entry.key) else {
+                continue;
+            };
 
-                self.insert_span_event_bookeeping(&span_event);
-                self.storage
-                    .insert_span_event(span_event)
-                    .context("failed to insert span event")?;
+            match span.parent_key {
+                Some(parent_key) if spans.contains_key(&parent_key) => {
+                    children.entry(parent_key).or_default().push(entry.key);
+                }
+                _ => roots.push(entry.key),
             }
-            NewSpanEventKind::Exit => {
-                let span_key = self
-                    .span_indexes
-                    .ids
-                    .get(&new_span_event.span_id)
-                    .copied()
-                    .ok_or(anyhow!("unknown span id"))?;
+        }
 
-                let span_event = SpanEvent {
-                    timestamp: new_span_event.timestamp,
-                    span_key,
-                    kind: SpanEventKind::Exit,
-                };
+        let mut depths = HashMap::new();
+        let mut trace_spans = Vec::with_capacity(spans.len());
+        let mut stack = roots
+            .into_iter()
+            .rev()
+            .map(|key| (key, 0))
+            .collect::<Vec<_>>();
+        while let Some((span_key, depth)) = stack.pop() {
+            let span = &spans[&span_key];
+            let orphaned = span.parent_id.is_some_and(|parent_id| {
+                self.span_indexes
+                    .orphanage
+                    .get(&parent_id)
+                    .is_some_and(|orphans| orphans.contains(&span_key))
+            });
 
-                self.insert_span_event_bookeeping(&span_event);
-                self.storage
-                    .insert_span_event(span_event)
-                    .context("failed to insert span event")?;
+            depths.insert(span_key, depth);
+            trace_spans.push(TraceSpan {
+                span: SpanContext::with_span(span, &self.storage).render(),
+                parent_key: span.parent_key.filter(|_| depth > 0),
+                depth,
+                orphaned,
+            });
+
+            if let Some(children) = children.get(&span_key) {
+                stack.extend(children.iter().rev().map(|key| (*key, depth + 1)));
             }
-            NewSpanEventKind::Close(new_close_event) => {
-                let span_key = self
-                    .span_indexes
-                    .ids
-                    .get(&new_span_event.span_id)
-                    .copied()
-                    .ok_or(anyhow!("unknown span id"))?;
+        }
 
-                let busy = if let Some(busy) = new_close_event.busy {
-                    Some(busy)
-                } else {
-                    let mut busy = 0;
-                    let mut last_enter = None;
-                    for span_event_key in &self.span_event_indexes.spans[&span_key] {
-                        let Ok(span_event) = self.storage.get_span_event(*span_event_key) else {
-                            tracing::warn!("failed to get span event, ignoring");
-                            continue;
-                        };
-                        match &span_event.kind {
-                            SpanEventKind::Enter(_) => {
-                                last_enter = Some(span_event.timestamp);
-                            }
-                            SpanEventKind::Exit => {
-                                if let Some(enter) = last_enter {
-                                    busy += span_event.timestamp.get() - enter.get();
-                                }
-                                last_enter = None;
-                            }
-                            _ => {}
-                        }
-                    }
+        let mut trace_events = vec![];
+        for entry in event_entries.into_iter().flatten() {
+            let event = match self.storage.get_event(entry.key) {
+                Ok(event) => event,
+                Err(err) => {
+                    tracing::warn!(?err, "failed to load event");
+                    continue;
+                }
+            };
 
-                    if busy == 0 {
-                        None
-                    } else {
-                        Some(busy)
-                    }
-                };
+            let orphaned = event.parent_id.is_some_and(|parent_id| {
+                self.event_indexes
+                    .orphanage
+                    .get(&parent_id)
+                    .is_some_and(|orphans| orphans.contains(&entry.key))
+            });
 
-                let span_event = SpanEvent {
-                    timestamp: new_span_event.timestamp,
-                    span_key,
-                    kind: SpanEventKind::Close(CloseSpanEvent { busy }),
-                };
+            let parent_depth = event.parent_key.and_then(|key| depths.get(&key).copied());
 
-                let updated = self
-                    .span_indexes
-                    .update_with_closed(span_key, new_span_event.timestamp);
+            trace_events.push(TraceEvent {
+                event: EventContext::with_event(&event, &self.storage).render(),
+                parent_key: event.parent_key.filter(|_| parent_depth.is_some()),
+                depth: parent_depth.map(|depth| depth + 1).unwrap_or(0),
+                orphaned,
+            });
+        }
 
-                if !updated {
-                    return Err(anyhow::anyhow!("span already closed"));
+        Some(Trace {
+            root,
+            spans: trace_spans,
+            events: trace_events,
+        })
+    }
+
+    /// Merges the closed spans matching the filter and all their descendants
+    /// into a call tree by the path of span names. Spans that are descendants
+    /// of another matching span are only counted once.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_flame_graph(
+        &self,
+        filter: Vec<FilterPredicate>,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> FlameGraph {
+        tracing::debug!(?filter, %start, %end, "querying for flame graph");
+
+        let query = Query {
+            filter,
+            order: Order::Asc,
+            limit: usize::MAX,
+            start,
+            end,
+            previous: None,
+        };
+
+        let mut roots = BTreeMap::new();
+        let mut nodes = Vec::new();
+        let mut visited = HashSet::new();
+        for span_key in IndexedSpanFilterIterator::new(query, self) {
+            // ancestors are created first, so any matching ones were visited
+            if visited.contains(&span_key) {
+                continue;
+            }
+
+            self.add_to_flame_graph(span_key, &mut roots, &mut nodes, &mut visited);
+        }
+
+        FlameGraph {
+            roots: finish_flame_nodes(roots, nodes),
+        }
+    }
+
+    fn add_to_flame_graph(
+        &self,
+        span_key: SpanKey,
+        roots: &mut BTreeMap<String, usize>,
+        nodes: &mut Vec<FlameNodeBuilder>,
+        visited: &mut HashSet<SpanKey>,
+    ) {
+        // each span is paired with the node holding the one for its parent
+        let mut stack = vec![(span_key, None::<usize>)];
+        while let Some((span_key, parent)) = stack.pop() {
+            visited.insert(span_key);
+
+            let span = match self.storage.get_span(span_key) {
+                Ok(span) => span,
+                Err(err) => {
+                    tracing::warn!(?err, "failed to load span");
+                    continue;
                 }
+            };
 
-                self.storage
-                    .update_span_closed(span_key, new_span_event.timestamp, busy)
-                    .context("failed to close span")?;
+            let children = self
+                .span_indexes
+                .children
+                .get(&span_key)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+
+            let context = SpanContext::with_span(&span, &self.storage);
+            let (Some(total_time), Some(self_time)) =
+                (span.duration(), context.self_time(children))
+            else {
+                continue; // the span is still open
+            };
 
-                self.insert_span_event_bookeeping(&span_event);
-                self.storage
-                    .insert_span_event(span_event)
-                    .context("failed to insert span event")?;
+            let next_idx = nodes.len();
+            let siblings = match parent {
+                Some(parent) => &mut nodes[parent].children,
+                None => &mut *roots,
+            };
+
+            let node_idx = *siblings.entry(span.name.clone()).or_insert(next_idx);
+            if node_idx == next_idx {
+                nodes.push(FlameNodeBuilder {
+                    name: span.name.clone(),
+                    ..FlameNodeBuilder::default()
+                });
+            }
+
+            let node = &mut nodes[node_idx];
+            node.count += 1;
+            node.total_time += total_time;
+            node.self_time += self_time;
+
+            stack.extend(children.iter().rev().map(|key| (*key, Some(node_idx))));
+        }
+    }
+
+    /// Computes the critical path of a trace, i.e. the chain of spans that
+    /// determined when the root span finished. This works backwards from the
+    /// end of the root span, always descending into the child that finished
+    /// last. Spans that are not closed are treated as lasting until the end of
+    /// the trace. The spans are returned in the order they join the path, or
+    /// `None` if the trace has no spans.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_critical_path(&self, root: TraceRoot) -> Option<Vec<CriticalPathSpan>> {
+        tracing::debug!(?root, "querying for critical path");
+
+        let span_entries = self.span_indexes.traces.get(&root)?;
+
+        let mut spans = BTreeMap::new();
+        for entry in span_entries {
+            match self.storage.get_span(entry.key) {
+                Ok(span) => {
+                    spans.insert(entry.key, span);
+                }
+                Err(err) => tracing::warn!(?err, "failed to load span"),
+            }
+        }
+
+        let trace_end = spans
+            .values()
+            .map(|span| span.closed_at.unwrap_or(span.created_at))
+            .max()?;
+
+        let mut roots = vec![];
+        let mut children: HashMap<SpanKey, Vec<SpanKey>> = HashMap::new();
+        for (span_key, span) in &spans {
+            match span.parent_key {
+                Some(parent_key) if spans.contains_key(&parent_key) => {
+                    children.entry(parent_key).or_default().push(*span_key);
+                }
+                _ => roots.push(*span_key),
+            }
+        }
+
+        // prefer the true root over any orphans
+        let root_key = roots
+            .iter()
+            .find(|key| spans[*key].parent_id.is_none())
+            .or(roots.first())
+            .copied()?;
+
+        let timings = spans
+            .iter()
+            .map(|(key, span)| {
+                let end = span.closed_at.unwrap_or(trace_end);
+                (*key, (span.created_at, end))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut path = Vec::new();
+        let (root_start, root_end) = timings[&root_key];
+        let walk = PathWalk {
+            span_key: root_key,
+            depth: 0,
+            joined_at: root_start,
+            cursor: root_end,
+        };
+        walk_critical_path(walk, &timings, &children, &mut path);
+
+        // a parent joins the path no later than its children, so it is kept
+        // before them when they join at the same time
+        path.sort_by_key(|(walk, _)| (walk.joined_at, walk.depth));
+
+        Some(
+            path.into_iter()
+                .map(|(walk, self_time)| CriticalPathSpan {
+                    key: walk.span_key,
+                    name: spans[&walk.span_key].name.clone(),
+                    self_time: self_time / 1000,
+                    busy: spans[&walk.span_key].busy,
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the span events within the timeframe, optionally only those of
+    /// a specific span and of specific kinds.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_span_event(&self, query: SpanEventQuery) -> Vec<SpanEvent> {
+        tracing::debug!(?query, "querying for span events");
+
+        let index = match query.span {
+            Some(span_key) => self
+                .span_event_indexes
+                .spans
+                .get(&span_key)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+            None => &self.span_event_indexes.all,
+        };
+
+        let mut lower = index.lower_bound(&IndexEntry::first_at(query.start));
+        let mut upper = index.upper_bound(&IndexEntry::last_at(query.end));
+
+        // the index is ordered by time, so the previous span event is loaded
+        // to find where it is
+        let previous = query
+            .previous
+            .map(|prev| match self.storage.get_span_event(prev) {
+                Ok(span_event) => IndexEntry::new(span_event.timestamp, prev),
+                Err(_) => IndexEntry::new(prev.get().saturating_mul(1000), prev),
+            });
+
+        match (query.order, previous) {
+            (Order::Asc, Some(prev)) => lower = lower.max(index.upper_bound(&prev)),
+            (Order::Desc, Some(prev)) => upper = upper.min(index.lower_bound(&prev)),
+            (_, None) => {}
+        }
+
+        let entries = &index[lower..upper.max(lower)];
+        let entries: Box<dyn Iterator<Item = &IndexEntry>> = match query.order {
+            Order::Asc => Box::new(entries.iter()),
+            Order::Desc => Box::new(entries.iter().rev()),
+        };
+
+        let mut span_events = vec![];
+        for entry in entries {
+            if span_events.len() >= query.limit {
+                break;
+            }
+
+            let span_event = match self.storage.get_span_event(entry.key) {
+                Ok(span_event) => span_event,
+                Err(err) => {
+                    tracing::warn!(?err, "failed to load span event");
+                    continue;
+                }
+            };
+
+            if let Some(kinds) = &query.kinds {
+                if !kinds.contains(&span_event.kind.event_type()) {
+                    continue;
+                }
             }
+
+            span_events.push((*span_event).clone());
+        }
+
+        span_events
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_annotations(&self) -> Vec<Annotation> {
+        tracing::debug!("querying for annotations");
+
+        self.annotations.values().cloned().collect()
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn query_stats(&self) -> DatasetStats {
+        tracing::debug!("querying for stats");
+
+        let at = |entry: &IndexEntry| to_micros(entry.at);
+
+        let event_start = self.event_indexes.all.first().map(at);
+        let event_end = self.event_indexes.all.last().map(at);
+        let span_start = self.span_indexes.all.first().map(at);
+        let span_end = self.span_indexes.all.last().map(at); // TODO: not technically right, but maybe okay
+
+        DatasetStats {
+            start: crate::filter::merge(event_start, span_start, Ord::min),
+            end: crate::filter::merge(event_end, span_end, Ord::max),
+            total_events: self.event_indexes.all.len(),
+            total_spans: self.span_indexes.all.len(),
+        }
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn insert_resource(&mut self, resource: NewResource) -> Result<ResourceKey, AnyError> {
+        tracing::debug!(?resource, "inserting resource");
+
+        if let Some((key, _)) = self
+            .resources
+            .iter()
+            .find(|(_, r)| r.attributes == resource.attributes)
+        {
+            return Ok(*key);
+        }
+
+        let mut keys = self.resources.keys().copied().collect::<Vec<_>>();
+        keys.sort();
+
+        let now = now();
+        let resource_key = get_unique_timestamp(now, &keys);
+        let resource = Resource {
+            created_at: resource_key,
+            attributes: resource.attributes,
+        };
+
+        self.insert_resource_bookeeping(&resource);
+        self.storage
+            .insert_resource(resource)
+            .context("failed to insert resource")?;
+
+        Ok(resource_key)
+    }
+
+    fn insert_resource_bookeeping(&mut self, resource: &Resource) {
+        self.resources.insert(resource.key(), resource.clone());
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn disconnect_tracing_instance(&mut self, instance_id: InstanceId) -> Result<(), AnyError> {
+        tracing::debug!(instance_id, "disconnecting tracing instance");
+
+        self.disconnected_instances.insert(instance_id);
+
+        let at = now().get() * 1000;
+
+        let filter = IndexedSpanFilter::And(vec![
+            IndexedSpanFilter::Single(&self.span_indexes.durations.open, None),
+            IndexedSpanFilter::Single(
+                self.span_indexes
+                    .instances
+                    .get(&instance_id)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+                None,
+            ),
+        ]);
+
+        let open_spans = IndexedSpanFilterIterator::new_internal(filter, self).collect::<Vec<_>>();
+
+        for span_key in open_spans {
+            let span = self
+                .storage
+                .get_span(span_key)
+                .context("failed to load span")?;
+
+            let duration = at.saturating_sub(span.created_at) / 1000;
+            let entry = IndexEntry::new(span.created_at, span_key);
+            self.span_indexes.update_with_closed(entry, duration);
+            self.storage
+                .update_span_closed(span_key, at, None)
+                .context("failed to close span")?;
         }
 
-        Ok(span_event_key)
-    }
+        Ok(())
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn insert_span_event(
+        &mut self,
+        new_span_event: NewSpanEvent,
+    ) -> Result<SpanEventKey, AnyError> {
+        tracing::debug!(span_event = ?new_span_event, "inserting span event");
+
+        let span_event_key = get_unique_timestamp(
+            to_micros(new_span_event.timestamp),
+            &self.span_event_indexes.keys,
+        );
+
+        match new_span_event.kind {
+            NewSpanEventKind::Create(new_create_event) => {
+                if self.span_indexes.ids.contains_key(&new_span_event.span_id) {
+                    return Err(anyhow::anyhow!("duplicate span id"));
+                }
+
+                // parent may not yet exist, that is ok
+                let parent_id = new_create_event.parent_id;
+                let parent_key = parent_id.and_then(|id| self.span_indexes.ids.get(&id).copied());
+
+                let span_key = get_unique_timestamp(span_event_key, &self.span_indexes.keys);
+
+                let span = Span {
+                    kind: new_create_event.kind,
+                    resource_key: new_create_event.resource_key,
+                    id: new_span_event.span_id,
+                    key: span_key,
+                    created_at: new_span_event.timestamp,
+                    closed_at: None,
+                    busy: None,
+                    parent_id,
+                    parent_key,
+                    links: Vec::new(),
+                    name: new_create_event.name.clone(),
+                    namespace: new_create_event.namespace.clone(),
+                    function: new_create_event.function.clone(),
+                    level: new_create_event.level,
+                    file_name: new_create_event.file_name.clone(),
+                    file_line: new_create_event.file_line,
+                    file_column: new_create_event.file_column,
+                    instrumentation_attributes: new_create_event.instrumentation_attributes.clone(),
+                    attributes: new_create_event.attributes.clone(),
+                };
+
+                let span_event = SpanEvent {
+                    key: span_event_key,
+                    timestamp: new_span_event.timestamp,
+                    span_key: span.key,
+                    kind: SpanEventKind::Create(CreateSpanEvent {
+                        kind: new_create_event.kind,
+                        resource_key: new_create_event.resource_key,
+                        parent_key,
+                        name: new_create_event.name,
+                        namespace: new_create_event.namespace,
+                        function: new_create_event.function,
+                        level: new_create_event.level,
+                        file_name: new_create_event.file_name,
+                        file_line: new_create_event.file_line,
+                        file_column: new_create_event.file_column,
+                        instrumentation_attributes: new_create_event.instrumentation_attributes,
+                        attributes: new_create_event.attributes,
+                    }),
+                };
+
+                let (child_spans, child_events) = self.insert_span_bookeeping(&span);
+                self.storage
+                    .insert_span(span.clone())
+                    .context("failed to insert span")?;
+                self.storage
+                    .update_span_parents(span.key(), &child_spans)
+                    .context("failed to update span parents")?;
+                self.storage
+                    .update_event_parents(span.key(), &child_events)
+                    .context("failed to update event parents")?;
+
+                self.insert_span_event_bookeeping(&span_event);
+                self.storage
+                    .insert_span_event(span_event)
+                    .context("failed to insert span event")?;
+
+                if !self.event_subscribers.is_empty() {
+                    let root = SpanContext::with_span(&span, &self.storage).trace_root();
+                    let descendent_events = self
+                        .event_indexes
+                        .traces
+                        .get(&root)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|entry| entry.key)
+                        .filter(|key| {
+                            EventContext::new(*key, &self.storage)
+                                .parents()
+                                .any(|p| p.key() == span.key())
+                        });
+
+                    // update subscribers for events that may have been updated
+                    // by a new parent
+                    for event_key in descendent_events {
+                        let context = EventContext::new(event_key, &self.storage);
+                        for subscriber in self.event_subscribers.values_mut() {
+                            subscriber.on_event(&context);
+                        }
+                    }
+
+                    self.event_subscribers.retain(|_, s| s.connected());
+                }
+
+                if !self.span_subscribers.is_empty() {
+                    for subscriber in self.span_subscribers.values_mut() {
+                        subscriber.on_span(
+                            &SpanContext::with_span(&span, &self.storage),
+                            &self.span_indexes.children,
+                        );
+                    }
+
+                    let root = SpanContext::with_span(&span, &self.storage).trace_root();
+                    let descendent_spans = self
+                        .span_indexes
+                        .traces
+                        .get(&root)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|entry| entry.key)
+                        .filter(|key| {
+                            SpanContext::new(*key, &self.storage)
+                                .parents()
+                                .any(|p| p.key() == span.key())
+                        });
+
+                    // update subscribers for spans that may have been updated
+                    // by a new parent
+                    for span_key in descendent_spans {
+                        let context = SpanContext::new(span_key, &self.storage);
+                        for subscriber in self.span_subscribers.values_mut() {
+                            subscriber.on_span(&context, &self.span_indexes.children);
+                        }
+                    }
+
+                    self.span_subscribers.retain(|_, s| s.connected());
+                }
+            }
+            NewSpanEventKind::Update(new_update_event) => {
+                let span_key = self
+                    .span_indexes
+                    .ids
+                    .get(&new_span_event.span_id)
+                    .copied()
+                    .ok_or(anyhow!("unknown span id"))?;
+
+                let span = SpanContext::new(span_key, &self.storage);
+                let trace = span.trace_root();
+
+                let update_event = UpdateSpanEvent {
+                    attributes: new_update_event.attributes.clone(),
+                };
+
+                let descendent_spans = self
+                    .span_indexes
+                    .traces
+                    .get(&trace)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|entry| entry.key)
+                    .filter(|key| {
+                        SpanContext::new(*key, &self.storage)
+                            .parents()
+                            .any(|p| p.key() == span_key)
+                            || *key == span_key // also update self
+                    })
+                    .collect::<Vec<_>>();
+
+                for child_span_key in descendent_spans {
+                    // check if nested span attribute changed
+                    self.span_indexes.update_with_new_field_on_parent(
+                        &SpanContext::new(child_span_key, &self.storage),
+                        span_key,
+                        &update_event.attributes,
+                    );
+                }
+
+                let descendent_events = self
+                    .event_indexes
+                    .traces
+                    .get(&trace)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|entry| entry.key)
+                    .filter(|key| {
+                        EventContext::new(*key, &self.storage)
+                            .parents()
+                            .any(|p| p.key() == span_key)
+                    })
+                    .collect::<Vec<_>>();
+
+                for event_key in descendent_events {
+                    // check if nested event attribute changed
+                    self.event_indexes.update_with_new_field_on_parent(
+                        &EventContext::new(event_key, &self.storage),
+                        span_key,
+                        &update_event.attributes,
+                    );
+                }
+
+                let span_event = SpanEvent {
+                    key: span_event_key,
+                    timestamp: new_span_event.timestamp,
+                    span_key,
+                    kind: SpanEventKind::Update(update_event),
+                };
+
+                self.storage
+                    .update_span_attributes(span_key, new_update_event.attributes)
+                    .context("failed to update span attributes")?;
+
+                self.insert_span_event_bookeeping(&span_event);
+                self.storage
+                    .insert_span_event(span_event)
+                    .context("failed to insert span event")?;
+
+                if !self.event_subscribers.is_empty() {
+                    let descendent_events = self
+                        .event_indexes
+                        .traces
+                        .get(&trace)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|entry| entry.key)
+                        .filter(|key| {
+                            EventContext::new(*key, &self.storage)
+                                .parents()
+                                .any(|p| p.key() == span_key)
+                        });
+
+                    // update subscribers for events that may have been updated
+                    // by an updated parent
+                    for event_key in descendent_events {
+                        let context = EventContext::new(event_key, &self.storage);
+                        for subscriber in self.event_subscribers.values_mut() {
+                            subscriber.on_event(&context);
+                        }
+                    }
+
+                    self.event_subscribers.retain(|_, s| s.connected());
+                }
+
+                if !self.span_subscribers.is_empty() {
+                    let descendent_spans = self
+                        .span_indexes
+                        .traces
+                        .get(&trace)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|entry| entry.key)
+                        .filter(|key| {
+                            SpanContext::new(*key, &self.storage)
+                                .parents()
+                                .any(|p| p.key() == span_key)
+                        });
+
+                    // update subscribers for spans that may have been updated
+                    // by an updated parent
+                    for span_key in descendent_spans {
+                        let context = SpanContext::new(span_key, &self.storage);
+                        for subscriber in self.span_subscribers.values_mut() {
+                            subscriber.on_span(&context, &self.span_indexes.children);
+                        }
+                    }
+
+                    self.span_subscribers.retain(|_, s| s.connected());
+                }
+            }
+            NewSpanEventKind::Follows(new_follows_event) => {
+                let span_key = self
+                    .span_indexes
+                    .ids
+                    .get(&new_span_event.span_id)
+                    .copied()
+                    .ok_or(anyhow!("unknown span id"))?;
+
+                let FullSpanId::Tracing(instance_id, _) = new_span_event.span_id else {
+                    return Err(anyhow!("invalid span kind, expected tracing"));
+                };
+
+                let follows_span_id = FullSpanId::Tracing(instance_id, new_follows_event.follows);
+                let follows_span_key = self
+                    .span_indexes
+                    .ids
+                    .get(&follows_span_id)
+                    .copied()
+                    .ok_or(anyhow!("unknown span id"))?;
+
+                // TODO: check against circular following
+                // TODO: check against duplicates
+
+                let span_event = SpanEvent {
+                    key: span_event_key,
+                    timestamp: new_span_event.timestamp,
+                    span_key,
+                    kind: SpanEventKind::Follows(FollowsSpanEvent {
+                        follows: follows_span_key,
+                    }),
+                };
+
+                self.storage
+                    .update_span_link(span_key, follows_span_id, BTreeMap::new())
+                    .context("failed to update span link")?;
+
+                self.insert_span_event_bookeeping(&span_event);
+                self.storage
+                    .insert_span_event(span_event)
+                    .context("failed to insert span event")?;
+            }
+            NewSpanEventKind::Enter(new_enter_event) => {
+                let span_key = self
+                    .span_indexes
+                    .ids
+                    .get(&new_span_event.span_id)
+                    .copied()
+                    .ok_or(anyhow!("unknown span id"))?;
+
+                let span_event = SpanEvent {
+                    key: span_event_key,
+                    timestamp: new_span_event.timestamp,
+                    span_key,
+                    kind: SpanEventKind::Enter(EnterSpanEvent {
+                        thread_id: new_enter_event.thread_id,
+                    }),
+                };
+
+                self.insert_span_event_bookeeping(&span_event);
+                self.storage
+                    .insert_span_event(span_event)
+                    .context("failed to insert span event")?;
+            }
+            NewSpanEventKind::Exit => {
+                let span_key = self
+                    .span_indexes
+                    .ids
+                    .get(&new_span_event.span_id)
+                    .copied()
+                    .ok_or(anyhow!("unknown span id"))?;
+
+                let span_event = SpanEvent {
+                    key: span_event_key,
+                    timestamp: new_span_event.timestamp,
+                    span_key,
+                    kind: SpanEventKind::Exit,
+                };
+
+                self.insert_span_event_bookeeping(&span_event);
+                self.storage
+                    .insert_span_event(span_event)
+                    .context("failed to insert span event")?;
+            }
+            NewSpanEventKind::Close(new_close_event) => {
+                let span_key = self
+                    .span_indexes
+                    .ids
+                    .get(&new_span_event.span_id)
+                    .copied()
+                    .ok_or(anyhow!("unknown span id"))?;
+
+                let busy = if let Some(busy) = new_close_event.busy {
+                    Some(busy)
+                } else {
+                    let mut busy = 0;
+                    let mut last_enter = None;
+                    for entry in &self.span_event_indexes.spans[&span_key] {
+                        let Ok(span_event) = self.storage.get_span_event(entry.key) else {
+                            tracing::warn!("failed to get span event, ignoring");
+                            continue;
+                        };
+                        match &span_event.kind {
+                            SpanEventKind::Enter(_) => {
+                                last_enter = Some(span_event.timestamp);
+                            }
+                            SpanEventKind::Exit => {
+                                if let Some(enter) = last_enter {
+                                    busy += span_event.timestamp.saturating_sub(enter);
+                                }
+                                last_enter = None;
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    if busy == 0 {
+                        None
+                    } else {
+                        Some(busy)
+                    }
+                };
+
+                let span_event = SpanEvent {
+                    key: span_event_key,
+                    timestamp: new_span_event.timestamp,
+                    span_key,
+                    kind: SpanEventKind::Close(CloseSpanEvent { busy }),
+                };
+
+                let span = self
+                    .storage
+                    .get_span(span_key)
+                    .context("failed to load span")?;
+
+                let duration = new_span_event.timestamp.saturating_sub(span.created_at) / 1000;
+                let entry = IndexEntry::new(span.created_at, span_key);
+                let updated = self.span_indexes.update_with_closed(entry, duration);
+
+                if !updated {
+                    return Err(anyhow::anyhow!("span already closed"));
+                }
+
+                self.storage
+                    .update_span_closed(span_key, new_span_event.timestamp, busy)
+                    .context("failed to close span")?;
+
+                self.insert_span_event_bookeeping(&span_event);
+                self.storage
+                    .insert_span_event(span_event)
+                    .context("failed to insert span event")?;
+            }
+        }
+
+        Ok(span_event_key)
+    }
+
+    fn insert_span_bookeeping(&mut self, span: &Span) -> (Vec<SpanKey>, Vec<EventKey>) {
+        let span_key = span.key;
+
+        let orphaned_spans = self
+            .span_indexes
+            .update_with_new_span(&SpanContext::with_span(span, &self.storage));
+
+        let trace = SpanContext::with_span(span, &self.storage).trace_root();
+
+        let descendent_spans = self
+            .span_indexes
+            .traces
+            .get(&trace)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(|entry| entry.key)
+            .filter(|key| *key != span_key)
+            .filter(|key| {
+                orphaned_spans.contains(key)
+                    || SpanContext::new(*key, &self.storage)
+                        .parents()
+                        .any(|p| orphaned_spans.contains(&p.key()))
+            })
+            .collect::<Vec<_>>();
+
+        for descendent in descendent_spans {
+            self.span_indexes.update_with_new_field_on_parent(
+                &SpanContext::new(descendent, &self.storage),
+                span.key(),
+                &span.attributes,
+            );
+        }
+
+        let orphaned_events = self
+            .event_indexes
+            .update_with_new_span(&SpanContext::with_span(span, &self.storage));
+
+        let descendent_events = self
+            .event_indexes
+            .traces
+            .get(&trace)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(|entry| entry.key)
+            .filter(|key| {
+                orphaned_events.contains(key)
+                    || EventContext::new(*key, &self.storage)
+                        .parents()
+                        .any(|p| orphaned_events.contains(&p.key()))
+            })
+            .collect::<Vec<_>>();
+
+        for descendent in descendent_events {
+            self.event_indexes.update_with_new_field_on_parent(
+                &EventContext::new(descendent, &self.storage),
+                span.key(),
+                &span.attributes,
+            );
+        }
+
+        (orphaned_spans, orphaned_events)
+    }
+
+    /// Clears the indexes and builds them again from what is in storage.
+    fn rebuild_indexes(&mut self) -> Result<(), AnyError> {
+        self.span_indexes = SpanIndexes::new();
+        self.span_event_indexes = SpanEventIndexes::new();
+        self.event_indexes = EventIndexes::new();
+
+        let (deleted_spans, deleted_span_events, deleted_events) = self.deleted_keys();
+
+        let spans = self
+            .storage
+            .get_all_spans()
+            .context("failed to load spans")?
+            .collect::<Vec<_>>();
+
+        for span in spans {
+            let span = span.context("failed to load span")?;
+            if !deleted_spans.contains(&span.key) {
+                self.insert_span_bookeeping(&span);
+            }
+        }
+
+        let span_events = self
+            .storage
+            .get_all_span_events()
+            .context("failed to load span events")?
+            .collect::<Vec<_>>();
+
+        for span_event in span_events {
+            let span_event = span_event.context("failed to load span event")?;
+            if !deleted_span_events.contains(&span_event.key) {
+                self.insert_span_event_bookeeping(&span_event);
+            }
+        }
+
+        let events = self
+            .storage
+            .get_all_events()
+            .context("failed to load events")?
+            .collect::<Vec<_>>();
+
+        for event in events {
+            let event = event.context("failed to load event")?;
+            if !deleted_events.contains(&event.key) {
+                self.insert_event_bookeeping(&event);
+            }
+        }
+
+        self.update_pinned_indexes();
+
+        Ok(())
+    }
+
+    fn update_pinned_indexes(&mut self) {
+        let mut pinned_spans = Vec::new();
+        let mut pinned_events = Vec::new();
+
+        // pins are ordered by kind and then key, so these are sorted
+        for pin in &self.pins {
+            match pin {
+                Pin::Span(key) => pinned_spans.push(*key),
+                Pin::Event(key) => pinned_events.push(*key),
+            }
+        }
+
+        self.span_indexes.pinned = entries_of(&self.span_indexes.all, &pinned_spans);
+        self.event_indexes.pinned = entries_of(&self.event_indexes.all, &pinned_events);
+    }
+
+    /// Gets the keys of all entities that are held by tombstones.
+    fn deleted_keys(&self) -> (HashSet<SpanKey>, HashSet<SpanEventKey>, HashSet<EventKey>) {
+        let spans = self.tombstones.values().flat_map(|t| &t.spans);
+        let span_events = self.tombstones.values().flat_map(|t| &t.span_events);
+        let events = self.tombstones.values().flat_map(|t| &t.events);
+
+        (
+            spans.copied().collect(),
+            span_events.copied().collect(),
+            events.copied().collect(),
+        )
+    }
+
+    fn insert_span_event_bookeeping(&mut self, span_event: &SpanEvent) {
+        self.span_event_indexes
+            .update_with_new_span_event(span_event);
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn insert_event(&mut self, new_event: NewEvent) -> Result<(), AnyError> {
+        let event_key =
+            get_unique_timestamp(to_micros(new_event.timestamp), &self.event_indexes.keys);
+
+        // parent may not yet exist, that is ok
+        let parent_id = new_event.span_id;
+        let parent_key = parent_id.and_then(|id| self.span_indexes.ids.get(&id).copied());
+
+        let event = Event {
+            kind: new_event.kind,
+            resource_key: new_event.resource_key,
+            key: event_key,
+            timestamp: new_event.timestamp,
+            parent_id,
+            parent_key,
+            content: new_event.content,
+            namespace: new_event.namespace,
+            function: new_event.function,
+            level: new_event.level,
+            file_name: new_event.file_name,
+            file_line: new_event.file_line,
+            file_column: new_event.file_column,
+            attributes: new_event.attributes,
+        };
+
+        self.insert_event_bookeeping(&event);
+        self.storage
+            .insert_event(event.clone())
+            .context("failed to insert event")?;
+
+        let context = EventContext::with_event(&event, &self.storage);
+        for subscriber in self.event_subscribers.values_mut() {
+            subscriber.on_event(&context);
+        }
+
+        self.event_subscribers.retain(|_, s| s.connected());
+
+        Ok(())
+    }
+
+    fn insert_event_bookeeping(&mut self, event: &Event) {
+        self.event_indexes
+            .update_with_new_event(&EventContext::with_event(event, &self.storage));
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn delete(&mut self, filter: DeleteFilter) -> Result<DeleteMetrics, AnyError> {
+        let (spans, span_events, events) =
+            if filter.span_filter.is_none() && filter.event_filter.is_none() {
+                self.get_entities_in_range(filter.start, filter.end, filter.inside)
+            } else {
+                self.get_entities_matching_filter(&filter)?
+            };
+
+        let (spans, span_events, events) = self.exclude_pinned((spans, span_events, events));
+
+        if filter.dry_run {
+            return Ok(DeleteMetrics {
+                spans: spans.len(),
+                span_events: span_events.len(),
+                events: events.len(),
+                tombstone: None,
+            });
+        }
+
+        if spans.is_empty() && events.is_empty() {
+            return Ok(DeleteMetrics {
+                spans: 0,
+                span_events: 0,
+                events: 0,
+                tombstone: None,
+            });
+        }
+
+        let tombstone_keys = self.tombstones.keys().copied().collect::<Vec<_>>();
+        let mut tombstone = Tombstone {
+            key: get_unique_timestamp(now(), &tombstone_keys),
+            spans,
+            span_events,
+            events,
+            resources: Vec::new(),
+        };
+
+        // the entities are only removed from the indexes here, they are kept
+        // in storage until the tombstone is purged so the delete can be undone
+
+        self.remove_spans_bookeeping(&tombstone.spans);
+        self.remove_span_events_bookeeping(&tombstone.span_events);
+        self.remove_events_bookeeping(&tombstone.events);
+
+        tombstone.resources = self.get_unused_resources();
+
+        if let Some(s) = self.storage.as_tombstone_storage_mut() {
+            if let Err(err) = s.insert_tombstone(&tombstone) {
+                self.rebuild_indexes()?;
+                return Err(err).context("failed to insert tombstone");
+            }
+        }
+
+        let metrics = DeleteMetrics {
+            spans: tombstone.spans.len(),
+            span_events: tombstone.span_events.len(),
+            events: tombstone.events.len(),
+            tombstone: Some(tombstone.key),
+        };
+
+        self.tombstones.insert(tombstone.key, tombstone);
+
+        Ok(metrics)
+    }
+
+    /// Pins a span or event so it is skipped by `delete`. Pinning a span
+    /// protects the whole trace it belongs to.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn pin(&mut self, pin: Pin) -> Result<(), AnyError> {
+        match pin {
+            Pin::Span(key) if self.span_indexes.keys.binary_search(&key).is_err() => {
+                return Err(anyhow!("unknown span"));
+            }
+            Pin::Event(key) if self.event_indexes.keys.binary_search(&key).is_err() => {
+                return Err(anyhow!("unknown event"));
+            }
+            _ => {}
+        }
+
+        if let Some(s) = self.storage.as_pin_storage_mut() {
+            s.insert_pin(pin).context("failed to insert pin")?;
+        }
+
+        self.pins.insert(pin);
+        self.update_pinned_indexes();
+
+        Ok(())
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn unpin(&mut self, pin: Pin) -> Result<(), AnyError> {
+        if let Some(s) = self.storage.as_pin_storage_mut() {
+            s.drop_pin(pin).context("failed to drop pin")?;
+        }
+
+        self.pins.remove(&pin);
+        self.update_pinned_indexes();
+
+        Ok(())
+    }
+
+    /// Attaches a note to a span, event, or time range. The annotation is kept
+    /// with the dataset.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn add_annotation(&mut self, annotation: NewAnnotation) -> Result<AnnotationKey, AnyError> {
+        match annotation.target {
+            AnnotationTarget::Span(key) if self.span_indexes.keys.binary_search(&key).is_err() => {
+                return Err(anyhow!("unknown span"));
+            }
+            AnnotationTarget::Event(key)
+                if self.event_indexes.keys.binary_search(&key).is_err() =>
+            {
+                return Err(anyhow!("unknown event"));
+            }
+            AnnotationTarget::Timespan(start, end) if start > end => {
+                return Err(anyhow!("timespan must not end before it starts"));
+            }
+            _ => {}
+        }
+
+        let keys = self.annotations.keys().copied().collect::<Vec<_>>();
+        let annotation = Annotation {
+            key: get_unique_timestamp(now(), &keys),
+            target: annotation.target,
+            text: annotation.text,
+        };
+
+        if let Some(s) = self.storage.as_annotation_storage_mut() {
+            s.insert_annotation(&annotation)
+                .context("failed to insert annotation")?;
+        }
+
+        let key = annotation.key;
+        self.annotations.insert(key, annotation);
+
+        Ok(key)
+    }
+
+    /// Replaces the text of an existing annotation.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn update_annotation(&mut self, key: AnnotationKey, text: String) -> Result<(), AnyError> {
+        let Some(existing) = self.annotations.get(&key) else {
+            return Err(anyhow!("unknown annotation"));
+        };
+
+        let annotation = Annotation {
+            text,
+            ..existing.clone()
+        };
+
+        if let Some(s) = self.storage.as_annotation_storage_mut() {
+            s.update_annotation(&annotation)
+                .context("failed to update annotation")?;
+        }
+
+        self.annotations.insert(key, annotation);
+
+        Ok(())
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn remove_annotation(&mut self, key: AnnotationKey) -> Result<(), AnyError> {
+        if let Some(s) = self.storage.as_annotation_storage_mut() {
+            s.drop_annotation(key)
+                .context("failed to drop annotation")?;
+        }
+
+        self.annotations.remove(&key);
+
+        Ok(())
+    }
+
+    /// Removes the entities of pinned traces and pinned events from the set to
+    /// be deleted.
+    fn exclude_pinned(&self, (spans, span_events, events): EntityKeys) -> EntityKeys {
+        if self.pins.is_empty() {
+            return (spans, span_events, events);
+        }
+
+        let pinned_traces = self
+            .span_indexes
+            .pinned
+            .iter()
+            .map(|entry| SpanContext::new(entry.key, &self.storage).trace_root())
+            .collect::<HashSet<_>>();
+
+        let (pinned_spans, _, pinned_events) = self.get_entities_in_traces(&pinned_traces);
+
+        // the ancestors of pinned events are kept as well so they are not left
+        // pointing at spans that no longer exist
+        let mut pinned_ancestors = self
+            .event_indexes
+            .pinned
+            .iter()
+            .flat_map(|entry| {
+                EventContext::new(entry.key, &self.storage)
+                    .parents()
+                    .map(|span| span.key)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        pinned_ancestors.sort();
+        pinned_ancestors.dedup();
+
+        let spans = spans
+            .into_iter()
+            .filter(|key| pinned_spans.binary_search(key).is_err())
+            .filter(|key| pinned_ancestors.binary_search(key).is_err())
+            .collect::<Vec<_>>();
+        let events = events
+            .into_iter()
+            .filter(|key| pinned_events.binary_search(key).is_err())
+            .filter(|key| !self.pins.contains(&Pin::Event(*key)))
+            .collect::<Vec<_>>();
+
+        let span_events = self.get_span_events_of_spans(&spans);
+
+        (spans, span_events, events)
+    }
+
+    /// Restores the entities removed by a previous delete. This is only
+    /// possible until the tombstone has been purged.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn undo_delete(&mut self, key: TombstoneKey) -> Result<DeleteMetrics, AnyError> {
+        if !self.tombstones.contains_key(&key) {
+            return Err(anyhow!("unknown tombstone, it may have been purged"));
+        }
+
+        if let Some(s) = self.storage.as_tombstone_storage_mut() {
+            s.drop_tombstone(key).context("failed to drop tombstone")?;
+        }
+
+        let tombstone = self.tombstones.remove(&key).unwrap();
+
+        for span_key in &tombstone.spans {
+            let span = self
+                .storage
+                .get_span(*span_key)
+                .context("failed to get span")?;
+            self.insert_span_bookeeping(&span);
+        }
+
+        for span_event_key in &tombstone.span_events {
+            let span_event = self
+                .storage
+                .get_span_event(*span_event_key)
+                .context("failed to get span event")?;
+            self.insert_span_event_bookeeping(&span_event);
+        }
+
+        for event_key in &tombstone.events {
+            let event = self
+                .storage
+                .get_event(*event_key)
+                .context("failed to get event")?;
+            self.insert_event_bookeeping(&event);
+        }
+
+        Ok(DeleteMetrics {
+            spans: tombstone.spans.len(),
+            span_events: tombstone.span_events.len(),
+            events: tombstone.events.len(),
+            tombstone: None,
+        })
+    }
+
+    /// Sets how long deleted entities are kept before `purge_deleted` will
+    /// remove them from storage.
+    pub fn set_delete_grace_period(&mut self, grace_period: Duration) {
+        self.delete_grace_period = grace_period;
+    }
+
+    /// This permanently removes entities from storage that were deleted longer
+    /// ago than the grace period.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn purge_deleted(&mut self) -> Result<DeleteMetrics, AnyError> {
+        let mut metrics = DeleteMetrics {
+            spans: 0,
+            span_events: 0,
+            events: 0,
+            tombstone: None,
+        };
+
+        let grace_period = self.delete_grace_period.as_micros() as u64;
+        let cutoff = now().get().saturating_sub(grace_period);
+
+        let expired = self
+            .tombstones
+            .keys()
+            .copied()
+            .take_while(|key| key.get() <= cutoff)
+            .collect::<Vec<_>>();
+
+        for key in expired {
+            let tombstone = self.tombstones.remove(&key).unwrap();
+
+            // drop smaller scoped entities from storage first to avoid
+            // integrity issues if things go wrong
+
+            self.storage
+                .drop_events(&tombstone.events)
+                .context("failed to drop events")?;
+            self.storage
+                .drop_span_events(&tombstone.span_events)
+                .context("failed to drop span events")?;
+            self.storage
+                .drop_spans(&tombstone.spans)
+                .context("failed to drop spans")?;
+
+            if let Some(s) = self.storage.as_tombstone_storage_mut() {
+                s.drop_tombstone(key).context("failed to drop tombstone")?;
+            }
+
+            // resources may have been used again since the delete or are still
+            // needed by entities held in other tombstones
+
+            let unused_resources = self.get_unused_resources();
+            let resources_to_delete = tombstone
+                .resources
+                .iter()
+                .copied()
+                .filter(|key| unused_resources.contains(key))
+                .filter(|key| !self.tombstones.values().any(|t| t.resources.contains(key)))
+                .collect::<Vec<_>>();
+
+            self.storage
+                .drop_resources(&resources_to_delete)
+                .context("failed to drop resources")?;
+
+            for resource_key in resources_to_delete {
+                self.resources.remove(&resource_key);
+            }
+
+            metrics.spans += tombstone.spans.len();
+            metrics.span_events += tombstone.span_events.len();
+            metrics.events += tombstone.events.len();
+        }
+
+        Ok(metrics)
+    }
+
+    /// This permanently removes all spans, span events, and events in storage
+    /// partitions that end at or before `at`, which is much faster than
+    /// deleting them individually. Unlike `delete` this cannot be undone and
+    /// does not spare pinned entities. Resources are kept since new entities
+    /// may still use them.
+    ///
+    /// This requires the storage to implement `PartitionStorage`.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn drop_partitions_before(&mut self, at: Timestamp) -> Result<DeleteMetrics, AnyError> {
+        let Some(s) = self.storage.as_partition_storage_mut() else {
+            return Err(anyhow!("storage does not support dropping partitions"));
+        };
+
+        let Some(cutoff) = s
+            .drop_partitions_before(at)
+            .context("failed to drop partitions")?
+        else {
+            return Ok(DeleteMetrics {
+                spans: 0,
+                span_events: 0,
+                events: 0,
+                tombstone: None,
+            });
+        };
+
+        fn keys_before(keys: &[Timestamp], cutoff: Timestamp) -> Vec<Timestamp> {
+            keys[..keys.partition_point(|key| *key < cutoff)].to_vec()
+        }
+
+        let spans = keys_before(&self.span_indexes.keys, cutoff);
+        let span_events = keys_before(&self.span_event_indexes.keys, cutoff);
+        let events = keys_before(&self.event_indexes.keys, cutoff);
+
+        self.remove_spans_bookeeping(&spans);
+        self.remove_span_events_bookeeping(&span_events);
+        self.remove_events_bookeeping(&events);
+        self.update_pinned_indexes();
+
+        // deleted entities in the dropped partitions are gone as well, so they
+        // can no longer be restored from their tombstones
+        let mut changed_tombstones = Vec::new();
+        for tombstone in self.tombstones.values_mut() {
+            let len = tombstone.spans.len() + tombstone.span_events.len() + tombstone.events.len();
+
+            tombstone.spans.retain(|key| *key >= cutoff);
+            tombstone.span_events.retain(|key| *key >= cutoff);
+            tombstone.events.retain(|key| *key >= cutoff);
+
+            if tombstone.spans.len() + tombstone.span_events.len() + tombstone.events.len() != len {
+                changed_tombstones.push(tombstone.key);
+            }
+        }
+
+        for key in changed_tombstones {
+            let tombstone = &self.tombstones[&key];
+            let is_empty = tombstone.spans.is_empty()
+                && tombstone.span_events.is_empty()
+                && tombstone.events.is_empty();
+
+            if let Some(s) = self.storage.as_tombstone_storage_mut() {
+                s.drop_tombstone(key).context("failed to drop tombstone")?;
+                if !is_empty {
+                    s.insert_tombstone(tombstone)
+                        .context("failed to insert tombstone")?;
+                }
+            }
+
+            if is_empty {
+                self.tombstones.remove(&key);
+            }
+        }
+
+        Ok(DeleteMetrics {
+            spans: spans.len(),
+            span_events: span_events.len(),
+            events: events.len(),
+            tombstone: None,
+        })
+    }
+
+    fn get_unused_resources(&self) -> Vec<ResourceKey> {
+        self.resources
+            .keys()
+            .copied()
+            .filter(|resource_key| {
+                let used_by_spans = self
+                    .span_indexes
+                    .resources
+                    .get(resource_key)
+                    .is_some_and(|r| !r.is_empty());
+
+                let used_by_events = self
+                    .event_indexes
+                    .resources
+                    .get(resource_key)
+                    .is_some_and(|r| !r.is_empty());
+
+                !used_by_spans && !used_by_events
+            })
+            .collect()
+    }
+
+    /// Gets the entities of all traces whose root is within (or outside) the
+    /// given range. The results are sorted.
+    fn get_entities_in_range(&self, start: Timestamp, end: Timestamp, inside: bool) -> EntityKeys {
+        let root_spans = self.get_root_spans_in_range_filter(start, end, inside);
+        let root_events = self.get_root_events_in_range_filter(start, end, inside);
+
+        let traces = root_spans
+            .iter()
+            .map(|root| SpanContext::new(*root, &self.storage).trace_root())
+            .collect::<HashSet<_>>();
+
+        let (spans, span_events, mut events) = self.get_entities_in_traces(&traces);
+
+        events.extend(root_events);
+        events.sort();
+        events.dedup();
+
+        (spans, span_events, events)
+    }
+
+    /// Gets the entities that match the span and event filters, either with
+    /// their whole traces or only what is nested within them depending on the
+    /// mode. The results are sorted.
+    fn get_entities_matching_filter(&self, filter: &DeleteFilter) -> Result<EntityKeys, AnyError> {
+        let matched_spans = match &filter.span_filter {
+            Some(predicates) => {
+                let mut predicates = predicates
+                    .iter()
+                    .cloned()
+                    .map(|p| BasicSpanFilter::from_predicate(p, &self.span_indexes.ids))
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("invalid span filter")?;
+                predicates.push(span_range_filter(filter.start, filter.end, filter.inside));
+
+                let mut basic_filter = BasicSpanFilter::And(predicates);
+                basic_filter.simplify();
+
+                let indexed_filter =
+                    IndexedSpanFilter::build(Some(basic_filter), &self.span_indexes, &self.storage);
+
+                IndexedSpanFilterIterator::new_internal(indexed_filter, self).collect()
+            }
+            None => Vec::new(),
+        };
+
+        let matched_events = match &filter.event_filter {
+            Some(predicates) => {
+                let mut predicates = predicates
+                    .iter()
+                    .cloned()
+                    .map(|p| BasicEventFilter::from_predicate(p, &self.span_indexes.ids))
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("invalid event filter")?;
+                predicates.push(event_range_filter(filter.start, filter.end, filter.inside));
+
+                let mut basic_filter = BasicEventFilter::And(predicates);
+                basic_filter.simplify();
+
+                let indexed_filter = IndexedEventFilter::build(
+                    Some(basic_filter),
+                    &self.event_indexes,
+                    &self.storage,
+                );
+
+                IndexedEventFilterIterator::new_internal(indexed_filter, self).collect()
+            }
+            None => Vec::new(),
+        };
+
+        let mut traces = matched_spans
+            .iter()
+            .map(|span| SpanContext::new(*span, &self.storage).trace_root())
+            .collect::<HashSet<_>>();
+
+        match filter.mode {
+            DeleteMode::Traces => {
+                let mut root_events = Vec::new();
+                for event_key in matched_events {
+                    let event = self
+                        .storage
+                        .get_event(event_key)
+                        .context("failed to get event")?;
+
+                    match event.parent_key {
+                        Some(parent_key) => {
+                            traces.insert(SpanContext::new(parent_key, &self.storage).trace_root());
+                        }
+                        None => root_events.push(event_key),
+                    }
+                }
+
+                let (spans, span_events, mut events) = self.get_entities_in_traces(&traces);
+
+                events.extend(root_events);
+                events.sort();
+                events.dedup();
+
+                Ok((spans, span_events, events))
+            }
+            DeleteMode::Matching => {
+                let matched_spans = matched_spans.into_iter().collect::<HashSet<_>>();
+
+                let (spans, _, events) = self.get_entities_in_traces(&traces);
+
+                let spans = spans
+                    .into_iter()
+                    .filter(|key| {
+                        matched_spans.contains(key)
+                            || SpanContext::new(*key, &self.storage)
+                                .parents()
+                                .any(|p| matched_spans.contains(&p.key))
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut events = events
+                    .into_iter()
+                    .filter(|key| {
+                        EventContext::new(*key, &self.storage)
+                            .parents()
+                            .any(|p| matched_spans.contains(&p.key))
+                    })
+                    .collect::<Vec<_>>();
+
+                events.extend(matched_events);
+                events.sort();
+                events.dedup();
+
+                let span_events = self.get_span_events_of_spans(&spans);
+
+                Ok((spans, span_events, events))
+            }
+        }
+    }
+
+    /// Gets all the spans, span events, and events in the given traces. The
+    /// results are sorted.
+    fn get_entities_in_traces(&self, traces: &HashSet<TraceRoot>) -> EntityKeys {
+        let mut spans = traces
+            .iter()
+            .flat_map(|trace| {
+                self.span_indexes
+                    .traces
+                    .get(trace)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|entry| entry.key)
+            })
+            .collect::<Vec<SpanKey>>();
+        let mut events = traces
+            .iter()
+            .flat_map(|trace| {
+                self.event_indexes
+                    .traces
+                    .get(trace)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|entry| entry.key)
+            })
+            .collect::<Vec<EventKey>>();
+
+        spans.sort();
+        events.sort();
+
+        let span_events = self.get_span_events_of_spans(&spans);
+
+        (spans, span_events, events)
+    }
+
+    fn get_span_events_of_spans(&self, spans: &[SpanKey]) -> Vec<SpanEventKey> {
+        let mut span_events = spans
+            .iter()
+            .flat_map(|span| {
+                self.span_event_indexes
+                    .spans
+                    .get(span)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|entry| entry.key)
+            })
+            .collect::<Vec<SpanEventKey>>();
+
+        span_events.sort();
+        span_events
+    }
+
+    fn get_root_spans_in_range_filter(
+        &self,
+        start: Timestamp,
+        end: Timestamp,
+        inside: bool,
+    ) -> Vec<SpanKey> {
+        let filter = BasicSpanFilter::And(vec![
+            span_range_filter(start, end, inside),
+            BasicSpanFilter::Root,
+        ]);
+
+        let indexed_filter =
+            IndexedSpanFilter::build(Some(filter), &self.span_indexes, &self.storage);
+        let iter = IndexedSpanFilterIterator::new_internal(indexed_filter, self);
+
+        iter.collect()
+    }
+
+    fn get_root_events_in_range_filter(
+        &self,
+        start: Timestamp,
+        end: Timestamp,
+        inside: bool,
+    ) -> Vec<SpanKey> {
+        let filter = BasicEventFilter::And(vec![
+            event_range_filter(start, end, inside),
+            BasicEventFilter::Root,
+        ]);
+
+        let indexed_filter =
+            IndexedEventFilter::build(Some(filter), &self.event_indexes, &self.storage);
+        let iter = IndexedEventFilterIterator::new_internal(indexed_filter, self);
+
+        iter.collect()
+    }
+
+    fn remove_spans_bookeeping(&mut self, spans: &[SpanKey]) {
+        self.span_indexes.remove_spans(spans);
+        self.span_event_indexes.remove_spans(spans);
+        self.event_indexes.remove_spans(spans);
+    }
+
+    fn remove_span_events_bookeeping(&mut self, span_events: &[SpanEventKey]) {
+        self.span_event_indexes.remove_span_events(span_events);
+    }
+
+    fn remove_events_bookeeping(&mut self, events: &[EventKey]) {
+        self.event_indexes.remove_events(events);
+    }
+
+    /// This reclaims space in the underlying storage that was left by deleted
+    /// entities. The indexes are persisted beforehand (if supported) so that
+    /// they are rewritten compactly as well.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn compact(&mut self) -> Result<CompactMetrics, AnyError> {
+        if let Some(s) = self.storage.as_index_storage_mut() {
+            let snapshot = IndexSnapshot::new(
+                &self.span_indexes,
+                &self.span_event_indexes,
+                &self.event_indexes,
+            );
+
+            s.update_indexes(&snapshot)
+                .context("failed to update indexes")?;
+        }
+
+        let bytes_reclaimed = self
+            .storage
+            .compact()
+            .context("failed to compact storage")?;
+
+        Ok(CompactMetrics { bytes_reclaimed })
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn copy_dataset(&self, target_storage: &mut dyn Storage) -> Result<(), AnyError> {
+        let (deleted_spans, deleted_span_events, deleted_events) = self.deleted_keys();
+
+        let resources = self
+            .storage
+            .get_all_resources()
+            .context("failed to get resources")?
+            .collect::<Vec<_>>();
+
+        for resource in resources {
+            let resource = resource.context("failed to get resource")?;
+            target_storage
+                .insert_resource((*resource).clone())
+                .context("failed to insert resource")?;
+        }
+
+        let spans = self
+            .storage
+            .get_all_spans()
+            .context("failed to get spans")?
+            .collect::<Vec<_>>();
+
+        for span in spans {
+            let span = span.context("failed to get span")?;
+            if deleted_spans.contains(&span.key) {
+                continue;
+            }
+
+            target_storage
+                .insert_span((*span).clone())
+                .context("failed to insert span")?;
+        }
+
+        let span_events = self
+            .storage
+            .get_all_span_events()
+            .context("failed to get span events")?
+            .collect::<Vec<_>>();
+
+        for span_event in span_events {
+            let span_event = span_event.context("failed to get span event")?;
+            if deleted_span_events.contains(&span_event.key) {
+                continue;
+            }
+
+            target_storage
+                .insert_span_event((*span_event).clone())
+                .context("failed to insert span event")?;
+        }
+
+        let events = self
+            .storage
+            .get_all_events()
+            .context("failed to get events")?
+            .collect::<Vec<_>>();
+
+        for event in events {
+            let event = event.context("failed to get event")?;
+            if deleted_events.contains(&event.key) {
+                continue;
+            }
+
+            target_storage
+                .insert_event((*event).clone())
+                .context("failed to insert event")?;
+        }
+
+        if let Some(s) = target_storage.as_pin_storage_mut() {
+            for pin in &self.pins {
+                s.insert_pin(*pin).context("failed to insert pin")?;
+            }
+        }
+
+        if let Some(s) = target_storage.as_annotation_storage_mut() {
+            for annotation in self.annotations.values() {
+                let deleted = match annotation.target {
+                    AnnotationTarget::Span(key) => deleted_spans.contains(&key),
+                    AnnotationTarget::Event(key) => deleted_events.contains(&key),
+                    AnnotationTarget::Timespan(_, _) => false,
+                };
+
+                if deleted {
+                    continue;
+                }
+
+                s.insert_annotation(annotation)
+                    .context("failed to insert annotation")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Imports the contents of another storage into this dataset.
+    ///
+    /// Resources with identical attributes are shared and spans that already
+    /// exist (by their `FullSpanId`) are skipped along with their span events
+    /// and child events. Everything else is given a unique key in this dataset
+    /// and re-linked to its parent span by id.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn merge_dataset(
+        &mut self,
+        source_storage: &dyn Storage,
+    ) -> Result<MergeMetrics, AnyError> {
+        let mut metrics = MergeMetrics {
+            resources: 0,
+            spans: 0,
+            span_events: 0,
+            events: 0,
+        };
+
+        let mut resource_keys = HashMap::new();
+        let mut skipped_spans = HashSet::new();
+        let mut span_keys = HashMap::new();
+
+        let source_tombstones = match source_storage.as_tombstone_storage() {
+            Some(s) => s.get_tombstones().context("failed to get tombstones")?,
+            None => Vec::new(),
+        };
+        let deleted_spans = source_tombstones
+            .iter()
+            .flat_map(|t| &t.spans)
+            .copied()
+            .collect::<HashSet<_>>();
+        let deleted_span_events = source_tombstones
+            .iter()
+            .flat_map(|t| &t.span_events)
+            .copied()
+            .collect::<HashSet<_>>();
+        let deleted_events = source_tombstones
+            .iter()
+            .flat_map(|t| &t.events)
+            .copied()
+            .collect::<HashSet<_>>();
+
+        let resources = source_storage
+            .get_all_resources()
+            .context("failed to get resources")?
+            .collect::<Vec<_>>();
+
+        for resource in resources {
+            let resource = resource.context("failed to get resource")?;
+
+            if let Some((key, _)) = self
+                .resources
+                .iter()
+                .find(|(_, r)| r.attributes == resource.attributes)
+            {
+                resource_keys.insert(resource.key(), *key);
+                continue;
+            }
+
+            let mut keys = self.resources.keys().copied().collect::<Vec<_>>();
+            keys.sort();
+
+            let resource_key = get_unique_timestamp(resource.created_at, &keys);
+            let new_resource = Resource {
+                created_at: resource_key,
+                attributes: resource.attributes.clone(),
+            };
+
+            self.insert_resource_bookeeping(&new_resource);
+            self.storage
+                .insert_resource(new_resource)
+                .context("failed to insert resource")?;
+
+            resource_keys.insert(resource.key(), resource_key);
+            metrics.resources += 1;
+        }
+
+        let spans = source_storage
+            .get_all_spans()
+            .context("failed to get spans")?
+            .collect::<Vec<_>>();
+
+        for span in spans {
+            let span = span.context("failed to get span")?;
+
+            if deleted_spans.contains(&span.key) {
+                continue;
+            }
+
+            if let Some(existing_key) = self.span_indexes.ids.get(&span.id) {
+                span_keys.insert(span.key(), *existing_key);
+                skipped_spans.insert(span.key());
+                continue;
+            }
+
+            let resource_key = *resource_keys
+                .get(&span.resource_key)
+                .ok_or_else(|| anyhow!("span references an unknown resource"))?;
+
+            let span_key = get_unique_timestamp(span.key, &self.span_indexes.keys);
+            let parent_key = span
+                .parent_id
+                .and_then(|id| self.span_indexes.ids.get(&id).copied());
+
+            let new_span = Span {
+                resource_key,
+                key: span_key,
+                parent_key,
+                ..(*span).clone()
+            };
+
+            let (child_spans, child_events) = self.insert_span_bookeeping(&new_span);
+            self.storage
+                .insert_span(new_span)
+                .context("failed to insert span")?;
+            self.storage
+                .update_span_parents(span_key, &child_spans)
+                .context("failed to update span parents")?;
+            self.storage
+                .update_event_parents(span_key, &child_events)
+                .context("failed to update event parents")?;
+
+            span_keys.insert(span.key(), span_key);
+            metrics.spans += 1;
+        }
+
+        let span_events = source_storage
+            .get_all_span_events()
+            .context("failed to get span events")?
+            .collect::<Vec<_>>();
+
+        for span_event in span_events {
+            let span_event = span_event.context("failed to get span event")?;
+
+            if deleted_span_events.contains(&span_event.key) {
+                continue;
+            }
+
+            if skipped_spans.contains(&span_event.span_key) {
+                continue;
+            }
+
+            let Some(span_key) = span_keys.get(&span_event.span_key).copied() else {
+                continue;
+            };
+
+            let kind = match &span_event.kind {
+                SpanEventKind::Create(create_event) => {
+                    let span = self
+                        .storage
+                        .get_span(span_key)
+                        .context("failed to get span")?;
+
+                    SpanEventKind::Create(CreateSpanEvent {
+                        resource_key: span.resource_key,
+                        parent_key: span.parent_key,
+                        ..create_event.clone()
+                    })
+                }
+                SpanEventKind::Follows(follows_event) => {
+                    let Some(follows) = span_keys.get(&follows_event.follows).copied() else {
+                        continue;
+                    };
+
+                    SpanEventKind::Follows(FollowsSpanEvent { follows })
+                }
+                kind => kind.clone(),
+            };
+
+            let new_span_event = SpanEvent {
+                key: get_unique_timestamp(span_event.key, &self.span_event_indexes.keys),
+                timestamp: span_event.timestamp,
+                span_key,
+                kind,
+            };
+
+            self.insert_span_event_bookeeping(&new_span_event);
+            self.storage
+                .insert_span_event(new_span_event)
+                .context("failed to insert span event")?;
+
+            metrics.span_events += 1;
+        }
+
+        let events = source_storage
+            .get_all_events()
+            .context("failed to get events")?
+            .collect::<Vec<_>>();
+
+        for event in events {
+            let event = event.context("failed to get event")?;
+
+            if deleted_events.contains(&event.key) {
+                continue;
+            }
+
+            if event
+                .parent_key
+                .is_some_and(|key| skipped_spans.contains(&key))
+            {
+                continue;
+            }
+
+            let resource_key = *resource_keys
+                .get(&event.resource_key)
+                .ok_or_else(|| anyhow!("event references an unknown resource"))?;
+
+            // events without a parent can't be matched up by their span, so
+            // they are compared directly against existing events
+            if event.parent_key.is_none() && self.has_equivalent_event(&event, resource_key) {
+                continue;
+            }
+
+            let event_key = get_unique_timestamp(event.key, &self.event_indexes.keys);
+            let parent_key = event
+                .parent_id
+                .and_then(|id| self.span_indexes.ids.get(&id).copied());
+
+            let new_event = Event {
+                resource_key,
+                key: event_key,
+                parent_key,
+                ..(*event).clone()
+            };
+
+            self.insert_event_bookeeping(&new_event);
+            self.storage
+                .insert_event(new_event)
+                .context("failed to insert event")?;
+
+            metrics.events += 1;
+        }
+
+        Ok(metrics)
+    }
+
+    /// Checks if an event with the same timestamp, resource, and contents
+    /// already exists. Only the events at that exact timestamp are considered.
+    fn has_equivalent_event(&self, event: &Event, resource_key: ResourceKey) -> bool {
+        let index = &self.event_indexes.all;
+        let start = index.lower_bound(&IndexEntry::new(event.timestamp, Timestamp::MIN));
+        let end = index.upper_bound(&IndexEntry::new(event.timestamp, Timestamp::MAX));
+
+        for entry in &index[start..end] {
+            let existing = match self.storage.get_event(entry.key) {
+                Ok(event) => event,
+                Err(err) => {
+                    tracing::warn!(?err, "failed to load event");
+                    continue;
+                }
+            };
+
+            if existing.timestamp == event.timestamp
+                && existing.resource_key == resource_key
+                && existing.parent_id == event.parent_id
+                && existing.kind == event.kind
+                && existing.level == event.level
+                && existing.content == event.content
+                && existing.attributes == event.attributes
+            {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Cross-checks the entities in storage against each other and against the
+    /// indexes. Nothing is changed, use `repair` to fix what is found.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn verify(&self) -> Result<IntegrityReport, AnyError> {
+        let spans = self.load_spans()?;
+        let span_events = self.load_span_events()?;
+        let events = self.load_events()?;
+
+        let mut span_ids = HashMap::new();
+        let mut duplicate_span_ids = Vec::new();
+        for span in &spans {
+            match span_ids.entry(span.id) {
+                Entry::Occupied(_) => {
+                    if !duplicate_span_ids.contains(&span.id) {
+                        duplicate_span_ids.push(span.id);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(span.key);
+                }
+            }
+        }
+
+        let span_keys = spans.iter().map(|span| span.key).collect::<Vec<_>>();
+
+        let mut dangling_span_parents = Vec::new();
+        let mut orphaned_spans = Vec::new();
+        let mut unclosed_spans = Vec::new();
+        for span in &spans {
+            let parent_key = span.parent_id.and_then(|id| span_ids.get(&id).copied());
+            match (span.parent_key, parent_key) {
+                (Some(_), _) if span.parent_key != parent_key => {
+                    dangling_span_parents.push(span.key);
+                }
+                (None, Some(_)) => orphaned_spans.push(span.key),
+                _ => {}
+            }
+
+            if span.closed_at.is_none() {
+                let disconnected = matches!(
+                    span.id,
+                    FullSpanId::Tracing(instance_id, _)
+                        if self.disconnected_instances.contains(&instance_id)
+                );
+                let closed_in_indexes = self
+                    .span_indexes
+                    .durations
+                    .open
+                    .binary_search(&IndexEntry::new(span.created_at, span.key))
+                    .is_err();
+
+                if disconnected || closed_in_indexes {
+                    unclosed_spans.push(span.key);
+                }
+            }
+        }
+
+        for (parent_id, orphans) in &self.span_indexes.orphanage {
+            if span_ids.contains_key(parent_id) {
+                orphaned_spans.extend(orphans);
+            }
+        }
+
+        let mut dangling_event_parents = Vec::new();
+        let mut orphaned_events = Vec::new();
+        for event in &events {
+            let parent_key = event.parent_id.and_then(|id| span_ids.get(&id).copied());
+            match (event.parent_key, parent_key) {
+                (Some(_), _) if event.parent_key != parent_key => {
+                    dangling_event_parents.push(event.key);
+                }
+                (None, Some(_)) => orphaned_events.push(event.key),
+                _ => {}
+            }
+        }
+
+        for (parent_id, orphans) in &self.event_indexes.orphanage {
+            if span_ids.contains_key(parent_id) {
+                orphaned_events.extend(orphans);
+            }
+        }
+
+        let dangling_span_events = span_events
+            .iter()
+            .filter(|span_event| span_keys.binary_search(&span_event.span_key).is_err())
+            .map(|span_event| span_event.key)
+            .collect();
+
+        let mut mismatched_spans = mismatched_keys(&span_keys, &self.span_indexes.keys);
+        for span_key in self.span_indexes.ids.values() {
+            if span_keys.binary_search(span_key).is_err() {
+                mismatched_spans.push(*span_key);
+            }
+        }
+
+        let span_event_keys = span_events.iter().map(|e| e.key).collect::<Vec<_>>();
+        let mismatched_span_events =
+            mismatched_keys(&span_event_keys, &self.span_event_indexes.keys);
+
+        let event_keys = events.iter().map(|e| e.key).collect::<Vec<_>>();
+        let mismatched_events = mismatched_keys(&event_keys, &self.event_indexes.keys);
+
+        orphaned_spans.sort();
+        orphaned_spans.dedup();
+        orphaned_events.sort();
+        orphaned_events.dedup();
+        mismatched_spans.sort();
+        mismatched_spans.dedup();
+
+        Ok(IntegrityReport {
+            dangling_span_parents,
+            dangling_event_parents,
+            dangling_span_events,
+            orphaned_spans,
+            orphaned_events,
+            duplicate_span_ids,
+            unclosed_spans,
+            mismatched_spans,
+            mismatched_span_events,
+            mismatched_events,
+        })
+    }
+
+    /// Fixes the problems found by `verify` and returns them.
+    ///
+    /// For duplicate span ids the first span is kept and the later copies are
+    /// removed along with their span events. Parent references are then
+    /// re-linked by id, span events without a span are removed, and spans that
+    /// can't be closed anymore are closed now. Lastly the indexes are rebuilt
+    /// from storage.
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn repair(&mut self) -> Result<IntegrityReport, AnyError> {
+        let report = self.verify()?;
+        if report.is_ok() {
+            return Ok(report);
+        }
+
+        let spans = self.load_spans()?;
+        let span_events = self.load_span_events()?;
+        let events = self.load_events()?;
+
+        let mut span_ids = HashMap::new();
+        let mut duplicate_spans = Vec::new();
+        for span in &spans {
+            match span_ids.entry(span.id) {
+                Entry::Occupied(_) => duplicate_spans.push(span.key),
+                Entry::Vacant(entry) => {
+                    entry.insert(span.key);
+                }
+            }
+        }
+
+        let span_keys = span_ids.values().copied().collect::<HashSet<_>>();
+        let span_events_to_delete = span_events
+            .iter()
+            .filter(|span_event| !span_keys.contains(&span_event.span_key))
+            .map(|span_event| span_event.key)
+            .collect::<Vec<_>>();
+
+        self.storage
+            .drop_span_events(&span_events_to_delete)
+            .context("failed to drop span events")?;
+        self.storage
+            .drop_spans(&duplicate_spans)
+            .context("failed to drop spans")?;
+
+        for span in &spans {
+            if !span_keys.contains(&span.key) {
+                continue;
+            }
+
+            let parent_key = span.parent_id.and_then(|id| span_ids.get(&id).copied());
+            if parent_key != span.parent_key {
+                self.storage
+                    .drop_spans(&[span.key])
+                    .context("failed to drop span")?;
+                self.storage
+                    .insert_span(Span {
+                        parent_key,
+                        ..(**span).clone()
+                    })
+                    .context("failed to insert span")?;
+            }
+        }
+
+        for event in &events {
+            let parent_key = event.parent_id.and_then(|id| span_ids.get(&id).copied());
+            if parent_key != event.parent_key {
+                self.storage
+                    .drop_events(&[event.key])
+                    .context("failed to drop event")?;
+                self.storage
+                    .insert_event(Event {
+                        parent_key,
+                        ..(**event).clone()
+                    })
+                    .context("failed to insert event")?;
+            }
+        }
+
+        let at = now().get() * 1000;
+        for span_key in &report.unclosed_spans {
+            if span_keys.contains(span_key) {
+                self.storage
+                    .update_span_closed(*span_key, at, None)
+                    .context("failed to close span")?;
+            }
+        }
+
+        self.rebuild_indexes()?;
+
+        Ok(report)
+    }
+
+    fn load_spans(&self) -> Result<Vec<Arc<Span>>, AnyError> {
+        let mut spans = self
+            .storage
+            .get_all_spans()
+            .context("failed to get spans")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to get span")?;
+
+        let (deleted_spans, _, _) = self.deleted_keys();
+        spans.retain(|span| !deleted_spans.contains(&span.key));
+        spans.sort_by_key(|span| span.key);
+
+        Ok(spans)
+    }
+
+    fn load_span_events(&self) -> Result<Vec<Arc<SpanEvent>>, AnyError> {
+        let mut span_events = self
+            .storage
+            .get_all_span_events()
+            .context("failed to get span events")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to get span event")?;
+
+        let (_, deleted_span_events, _) = self.deleted_keys();
+        span_events.retain(|span_event| !deleted_span_events.contains(&span_event.key));
+        span_events.sort_by_key(|span_event| span_event.key);
+
+        Ok(span_events)
+    }
+
+    fn load_events(&self) -> Result<Vec<Arc<Event>>, AnyError> {
+        let mut events = self
+            .storage
+            .get_all_events()
+            .context("failed to get events")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to get event")?;
+
+        let (_, _, deleted_events) = self.deleted_keys();
+        events.retain(|event| !deleted_events.contains(&event.key));
+        events.sort_by_key(|event| event.key);
+
+        Ok(events)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn subscribe_to_spans(
+        &mut self,
+        filter: Vec<FilterPredicate>,
+    ) -> Result<Subscriber<ComposedSpan>, AnyError> {
+        let mut filter = BasicSpanFilter::And(
+            filter
+                .into_iter()
+                .map(|p| BasicSpanFilter::from_predicate(p, &self.span_indexes.ids))
+                .collect::<Result<_, _>>()
+                .context("invalid span filter")?,
+        );
+        filter.simplify();
+
+        let id = self.next_subscriber_id;
+        self.next_subscriber_id += 1;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        self.span_subscribers
+            .insert(id, SpanSubscription::new(filter, sender));
+
+        Ok((id, receiver))
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn unsubscribe_from_spans(&mut self, id: SubscriptionId) {
+        self.span_subscribers.remove(&id);
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn subscribe_to_events(
+        &mut self,
+        filter: Vec<FilterPredicate>,
+    ) -> Result<Subscriber<ComposedEvent>, AnyError> {
+        let mut filter = BasicEventFilter::And(
+            filter
+                .into_iter()
+                .map(|p| BasicEventFilter::from_predicate(p, &self.span_indexes.ids))
+                .collect::<Result<_, _>>()
+                .context("invalid event filter")?,
+        );
+        filter.simplify();
+
+        let id = self.next_subscriber_id;
+        self.next_subscriber_id += 1;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        self.event_subscribers
+            .insert(id, EventSubscription::new(filter, sender));
+
+        Ok((id, receiver))
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn unsubscribe_from_events(&mut self, id: SubscriptionId) {
+        self.event_subscribers.remove(&id);
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    pub fn shutdown(&mut self) -> Result<(), AnyError> {
+        if let Some(s) = self.storage.as_index_storage_mut() {
+            let snapshot = IndexSnapshot::new(
+                &self.span_indexes,
+                &self.span_event_indexes,
+                &self.event_indexes,
+            );
+
+            s.update_indexes(&snapshot)
+                .context("failed to update indexes")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Gets a filter for spans that were open at some point between `start` and
+/// `end`, or for spans that were not if `inside` is false.
+fn span_range_filter(start: Timestamp, end: Timestamp, inside: bool) -> BasicSpanFilter {
+    if inside {
+        BasicSpanFilter::And(vec![
+            BasicSpanFilter::Created(ValueOperator::Lte, end),
+            BasicSpanFilter::Closed(ValueOperator::Gte, start),
+        ])
+    } else {
+        BasicSpanFilter::Or(vec![
+            BasicSpanFilter::Created(ValueOperator::Gt, end),
+            BasicSpanFilter::Closed(ValueOperator::Lt, start),
+        ])
+    }
+}
+
+fn event_range_filter(start: Timestamp, end: Timestamp, inside: bool) -> BasicEventFilter {
+    if inside {
+        BasicEventFilter::And(vec![
+            BasicEventFilter::Timestamp(ValueOperator::Lte, end),
+            BasicEventFilter::Timestamp(ValueOperator::Gte, start),
+        ])
+    } else {
+        BasicEventFilter::Or(vec![
+            BasicEventFilter::Timestamp(ValueOperator::Gt, end),
+            BasicEventFilter::Timestamp(ValueOperator::Lt, start),
+        ])
+    }
+}
+
+/// The inherent properties that events can be grouped by.
+const EVENT_GROUP_PROPERTIES: &[&str] = &["level", "content", "namespace", "function", "file"];
+
+/// The inherent properties that spans can be grouped by.
+const SPAN_GROUP_PROPERTIES: &[&str] = &["level", "name", "namespace", "function", "file"];
+
+/// Collects the values needed to compute the aggregates for each group.
+struct AggregateGroups<'a> {
+    aggregates: &'a [Aggregate],
+    groups: BTreeMap<Vec<Option<String>>, AggregateGroup>,
+}
+
+struct AggregateGroup {
+    count: usize,
+    // the numeric values found for each aggregate
+    values: Vec<AggregateValues>,
+}
+
+/// The most values kept per group to compute a percentile from. Beyond this,
+/// every other kept value is dropped and only every other new value is kept,
+/// so percentiles of large groups are taken from an evenly spaced sample.
+const MAX_PERCENTILE_SAMPLES: usize = 10_000;
+
+/// Summarizes the numeric values found for an aggregate in a group without
+/// keeping more than [`MAX_PERCENTILE_SAMPLES`] of them.
+#[derive(Clone)]
+struct AggregateValues {
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    samples: Vec<f64>,
+    // only every `stride`-th value is added to `samples`
+    stride: usize,
+}
+
+impl AggregateValues {
+    fn new() -> AggregateValues {
+        AggregateValues {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            samples: Vec::new(),
+            stride: 1,
+        }
+    }
+
+    fn add(&mut self, value: f64, sample: bool) {
+        if sample && self.count.is_multiple_of(self.stride) {
+            if self.samples.len() == MAX_PERCENTILE_SAMPLES {
+                let mut i = 0;
+                self.samples.retain(|_| {
+                    i += 1;
+                    i % 2 == 1
+                });
+                self.stride *= 2;
+            }
+
+            if self.count.is_multiple_of(self.stride) {
+                self.samples.push(value);
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+}
+
+impl<'a> AggregateGroups<'a> {
+    fn new(aggregates: &'a [Aggregate]) -> AggregateGroups<'a> {
+        AggregateGroups {
+            aggregates,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    fn add(
+        &mut self,
+        group: Vec<Option<String>>,
+        get_value: impl Fn(&AggregateField) -> Option<f64>,
+    ) {
+        let aggregates = self.aggregates;
+        let entry = self.groups.entry(group).or_insert_with(|| AggregateGroup {
+            count: 0,
+            values: vec![AggregateValues::new(); aggregates.len()],
+        });
+
+        entry.count += 1;
+        for (aggregate, values) in aggregates.iter().zip(&mut entry.values) {
+            if let Some(value) = aggregate.field().and_then(&get_value) {
+                values.add(value, matches!(aggregate, Aggregate::Percentile(..)));
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<AggregateRow> {
+        self.groups
+            .into_iter()
+            .map(|(group, entry)| AggregateRow {
+                group,
+                values: self
+                    .aggregates
+                    .iter()
+                    .zip(entry.values)
+                    .map(|(aggregate, values)| compute_aggregate(aggregate, entry.count, values))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+fn compute_aggregate(
+    aggregate: &Aggregate,
+    count: usize,
+    mut values: AggregateValues,
+) -> Option<f64> {
+    if let Aggregate::Count = aggregate {
+        return Some(count as f64);
+    }
+
+    if values.count == 0 {
+        return None;
+    }
+
+    match aggregate {
+        Aggregate::Count => unreachable!(),
+        Aggregate::Min(_) => Some(values.min),
+        Aggregate::Max(_) => Some(values.max),
+        Aggregate::Sum(_) => Some(values.sum),
+        Aggregate::Avg(_) => Some(values.sum / values.count as f64),
+        Aggregate::Percentile(_, percentile) => {
+            values.samples.sort_by(f64::total_cmp);
+            Some(nearest_rank(&values.samples, *percentile))
+        }
+    }
+}
+
+fn latency_stats(
+    group: Option<String>,
+    mut durations: Vec<u64>,
+    ranges: &[Range<u64>],
+) -> LatencyStats {
+    durations.sort_unstable();
+
+    let histogram = ranges
+        .iter()
+        .map(|range| {
+            let lower = durations.partition_point(|d| *d < range.start);
+            let upper = durations.partition_point(|d| *d < range.end);
+
+            LatencyBucket {
+                start: range.start,
+                end: range.end,
+                count: upper - lower,
+            }
+        })
+        .collect();
+
+    LatencyStats {
+        group,
+        count: durations.len(),
+        p50: nearest_rank(&durations, 50.0),
+        p90: nearest_rank(&durations, 90.0),
+        p99: nearest_rank(&durations, 99.0),
+        max: *durations.last().unwrap(),
+        histogram,
+    }
+}
+
+/// A flame graph node that is still being built. The nodes are kept in a list
+/// and refer to their children by index so that building them doesn't recurse
+/// as deep as the spans are nested.
+#[derive(Default)]
+struct FlameNodeBuilder {
+    name: String,
+    count: usize,
+    total_time: u64,
+    self_time: u64,
+    children: BTreeMap<String, usize>,
+}
+
+fn finish_flame_nodes(
+    roots: BTreeMap<String, usize>,
+    nodes: Vec<FlameNodeBuilder>,
+) -> Vec<FlameNode> {
+    // children are always added after their parent, so going backwards
+    // finishes every node's children before the node itself
+    let mut finished = nodes
+        .iter()
+        .map(|_| None)
+        .collect::<Vec<Option<FlameNode>>>();
+    for (idx, node) in nodes.into_iter().enumerate().rev() {
+        let children = node
+            .children
+            .into_values()
+            .filter_map(|child| finished[child].take())
+            .collect();
+
+        finished[idx] = Some(FlameNode {
+            name: node.name,
+            count: node.count,
+            total_time: node.total_time,
+            self_time: node.self_time,
+            children,
+        });
+    }
+
+    roots
+        .into_values()
+        .filter_map(|idx| finished[idx].take())
+        .collect()
+}
+
+/// Where a span is on the critical path.
+struct PathWalk {
+    span_key: SpanKey,
+    depth: usize,
+    /// When the span joins the path, which is when it was created unless it
+    /// was created before its parent.
+    joined_at: PreciseTimestamp,
+    /// When the span leaves the path.
+    cursor: PreciseTimestamp,
+}
+
+/// Adds the span and its self-time before `cursor` to `path`, walking back
+/// from `cursor` through the child that finished last, then from the start of
+/// that child through the one that finished last before it, and so on until
+/// no children remain. Each child taken is walked the same way.
+fn walk_critical_path(
+    walk: PathWalk,
+    timings: &HashMap<SpanKey, (PreciseTimestamp, PreciseTimestamp)>,
+    children: &HashMap<SpanKey, Vec<SpanKey>>,
+    path: &mut Vec<(PathWalk, u64)>,
+) {
+    let span_key = walk.span_key;
+    let (start, end) = timings[&span_key];
+    let mut cursor = walk.cursor.min(end);
+    let mut self_time = 0;
+
+    let span_children = children
+        .get(&span_key)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    loop {
+        let last_child = span_children
+            .iter()
+            .filter(|child| timings[*child].0.max(start) < cursor)
+            .max_by_key(|child| timings[*child].1.min(cursor));
+
+        let Some(&child) = last_child else {
+            break;
+        };
+
+        let (child_start, child_end) = timings[&child];
+        let child_end = child_end.min(cursor);
+
+        self_time += cursor - child_end;
+        let child_walk = PathWalk {
+            span_key: child,
+            depth: walk.depth + 1,
+            joined_at: child_start.max(start),
+            cursor: child_end,
+        };
+        walk_critical_path(child_walk, timings, children, path);
+        cursor = child_start.max(start);
+    }
+
+    self_time += cursor.saturating_sub(start);
+    path.push((walk, self_time));
+}
+
+fn summarize_attributes(
+    indexes: &BTreeMap<String, ValueIndex>,
+    entries: &[IndexEntry],
+) -> Vec<AttributeSummary> {
+    indexes
+        .iter()
+        .filter_map(|(name, index)| {
+            let kinds = index.count_kinds(entries);
+            if kinds.is_empty() {
+                return None;
+            }
+
+            Some(AttributeSummary {
+                name: name.clone(),
+                count: kinds.iter().map(|(_, count)| count).sum(),
+                kinds: kinds.into_iter().map(|(kind, _)| kind).collect(),
+            })
+        })
+        .collect()
+}
+
+fn top_attribute_values(
+    index: Option<&ValueIndex>,
+    prefix: &str,
+    limit: usize,
+) -> Vec<AttributeValueCount> {
+    let Some(index) = index else {
+        return Vec::new();
+    };
+
+    let mut values = index
+        .string_values(prefix)
+        .map(|(value, count)| AttributeValueCount {
+            value: value.to_owned(),
+            count,
+        })
+        .collect::<Vec<_>>();
+
+    // the sort is stable so values with the same count stay in order
+    values.sort_by_key(|v| Reverse(v.count));
+    values.truncate(limit);
+    values
+}
+
+/// Gets the percentile from non-empty sorted values. This uses the nearest
+/// rank, so the result is always one of the values.
+fn nearest_rank<T: Copy>(sorted: &[T], percentile: f64) -> T {
+    let rank = (percentile / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank]
+}
+
+fn value_to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::F64(value) => Some(*value),
+        Value::I64(value) => Some(*value as f64),
+        Value::U64(value) => Some(*value as f64),
+        Value::I128(value) => Some(*value as f64),
+        Value::U128(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+/// Splits `start..=end` into at most `buckets` slices of (nearly) equal size.
+fn histogram_buckets(start: Timestamp, end: Timestamp, buckets: usize) -> Vec<HistogramBucket> {
+    if start > end {
+        return Vec::new();
+    }
+
+    let total = (end.get() - start.get()) as u128 + 1;
+    let buckets = (buckets as u128).min(total);
+
+    // slice `i` starts at the first offset that `histogram_bucket_of` maps to
+    // it, which is `ceil(i * total / buckets)`
+    let offset = |i: u128| (i * total).div_ceil(buckets) as u64;
+
+    (0..buckets)
+        .map(|i| HistogramBucket {
+            start: Timestamp::new(start.get() + offset(i)).unwrap(),
+            end: Timestamp::new(start.get() + offset(i + 1) - 1).unwrap(),
+            counts: [0; 6],
+        })
+        .collect()
+}
+
+/// Gets the index of the histogram slice that holds `at`, which must be within
+/// `start..=end`.
+fn histogram_bucket_of(start: Timestamp, end: Timestamp, buckets: usize, at: Timestamp) -> usize {
+    let total = (end.get() - start.get()) as u128 + 1;
+    let offset = (at.get() - start.get()) as u128;
+
+    (offset * buckets as u128 / total) as usize
+}
+
+/// Gets the keys that are in only one of the two sorted lists.
+fn mismatched_keys(a: &[Timestamp], b: &[Timestamp]) -> Vec<Timestamp> {
+    let mut mismatched = Vec::new();
+    let mut a = a.iter().peekable();
+    let mut b = b.iter().peekable();
+
+    loop {
+        match (a.peek(), b.peek()) {
+            (Some(x), Some(y)) if x == y => {
+                a.next();
+                b.next();
+            }
+            (Some(x), Some(y)) if x < y => mismatched.extend(a.next()),
+            (Some(_), Some(_)) => mismatched.extend(b.next()),
+            (Some(_), None) => mismatched.extend(a.next()),
+            (None, Some(_)) => mismatched.extend(b.next()),
+            (None, None) => break,
+        }
+    }
+
+    mismatched
+}
+
+fn get_unique_timestamp(mut timestamp: Timestamp, existing: &[Timestamp]) -> Timestamp {
+    let mut idx = existing.lower_bound(&timestamp);
+
+    while idx < existing.len() && timestamp == existing[idx] {
+        idx += 1;
+        timestamp = timestamp.saturating_add(1);
+    }
+
+    timestamp
+}
+
+fn now() -> Timestamp {
+    #[cfg(test)]
+    return Timestamp::new(1000).unwrap();
+
+    #[cfg(not(test))]
+    {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("now should not be before the UNIX epoch")
+            .as_micros();
+
+        let timestamp = u64::try_from(timestamp)
+            .expect("microseconds shouldn't exceed a u64 until the year 586,912 AD");
+
+        Timestamp::new(timestamp).expect("now should not be at the UNIX epoch")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::{validate_span_filter, Order};
+    use crate::models::{
+        Level, NewCloseSpanEvent, NewCreateSpanEvent, NewEnterSpanEvent, NewFollowsSpanEvent,
+        NewUpdateSpanEvent, SourceKind,
+    };
+    use crate::storage::TransientStorage;
+    use crate::{SpanEventType, SubscriptionResponse, Value, ValueKind};
+
+    use super::*;
+
+    #[test]
+    fn test_event_filters() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        let simple = |id: u64, level: i32, attribute1: &str, attribute2: &str| -> NewEvent {
+            NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: id * 1000,
+                span_id: None,
+                content: Value::Str("event".to_owned()),
+                namespace: Some("crate::storage::tests".to_owned()),
+                function: Some("test".to_owned()),
+                level: Level::from_tracing_level(level).unwrap(),
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::from_iter([
+                    ("attribute1".to_owned(), Value::Str(attribute1.to_owned())),
+                    ("attribute2".to_owned(), Value::Str(attribute2.to_owned())),
+                ]),
+            }
+        };
+
+        engine.insert_event(simple(1, 4, "test", "A")).unwrap(); // excluded by timestamp
+        engine.insert_event(simple(2, 1, "test", "A")).unwrap(); // excluded by level
+        engine.insert_event(simple(3, 2, "test", "A")).unwrap(); // excluded by level
+        engine.insert_event(simple(4, 3, "test", "A")).unwrap();
+        engine.insert_event(simple(5, 4, "test", "A")).unwrap();
+        engine.insert_event(simple(6, 4, "blah", "A")).unwrap(); // excluded by "blah"
+        engine.insert_event(simple(7, 4, "test", "B")).unwrap(); // excluded by "B"
+        engine.insert_event(simple(8, 4, "test", "C")).unwrap(); // excluded by "C"
+        engine.insert_event(simple(9, 4, "test", "A")).unwrap(); // excluded by timestamp
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse(
+                "#level: >=WARN @\"attribute1\": test @\"attribute2\": A",
+            )
+            .unwrap(),
+            order: Order::Asc,
+            limit: 3,
+            start: Timestamp::new(2).unwrap(),
+            end: Timestamp::new(8).unwrap(),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].key, Timestamp::new(4).unwrap());
+        assert_eq!(events[1].key, Timestamp::new(5).unwrap());
+    }
+
+    #[test]
+    fn test_span_filters() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        let simple_open =
+            |open: u64, level: i32, attribute1: &str, attribute2: &str| -> NewSpanEvent {
+                NewSpanEvent {
+                    timestamp: open * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), open),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Tracing,
+                        resource_key,
+                        parent_id: None,
+                        name: "test".to_owned(),
+                        namespace: Some("crate::storage::tests".to_owned()),
+                        function: None,
+                        level: Level::from_tracing_level(level).unwrap(),
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::from_iter([
+                            ("attribute1".to_owned(), Value::Str(attribute1.to_owned())),
+                            ("attribute2".to_owned(), Value::Str(attribute2.to_owned())),
+                        ]),
+                    }),
+                }
+            };
+
+        let simple_close = |open: u64, close: u64| -> NewSpanEvent {
+            NewSpanEvent {
+                timestamp: close * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), open),
+                kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+            }
+        };
+
+        engine
+            .insert_span_event(simple_open(1, 4, "test", "A"))
+            .unwrap(); // excluded by timestamp
+        engine.insert_span_event(simple_close(1, 2)).unwrap();
+        engine
+            .insert_span_event(simple_open(3, 1, "test", "A"))
+            .unwrap(); // excluded by level
+        engine.insert_span_event(simple_close(3, 6)).unwrap();
+        engine
+            .insert_span_event(simple_open(4, 2, "test", "A"))
+            .unwrap(); // excluded by level
+        engine.insert_span_event(simple_close(4, 7)).unwrap();
+        engine
+            .insert_span_event(simple_open(5, 3, "test", "A"))
+            .unwrap();
+        engine.insert_span_event(simple_close(5, 8)).unwrap();
+        engine
+            .insert_span_event(simple_open(9, 4, "test", "A"))
+            .unwrap();
+        engine
+            .insert_span_event(simple_open(10, 4, "blah", "A"))
+            .unwrap(); // excluded by "blah"
+        engine
+            .insert_span_event(simple_open(11, 4, "test", "B"))
+            .unwrap(); // excluded by "B"
+        engine
+            .insert_span_event(simple_open(12, 4, "test", "C"))
+            .unwrap(); // excluded by "C"
+        engine
+            .insert_span_event(simple_open(13, 4, "test", "A"))
+            .unwrap(); // excluded by timestamp
+
+        let spans = engine.query_span(Query {
+            filter: FilterPredicate::parse(
+                "#level: >=WARN @\"attribute1\": test @\"attribute2\": A",
+            )
+            .unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: Timestamp::new(2).unwrap(),
+            end: Timestamp::new(10).unwrap(),
+            previous: None,
+        });
+
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].key, Timestamp::new(5).unwrap());
+        assert_eq!(spans[1].key, Timestamp::new(9).unwrap());
+    }
+
+    #[test]
+    fn event_found_with_resource_attribute() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+            })
+            .unwrap();
+
+        let now = now();
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: now.saturating_add(1).get() * 1000,
+                span_id: None,
+                content: Value::Str("event".to_owned()),
+                namespace: Some("crate::storage::tests".to_owned()),
+                function: Some("test".to_owned()),
+                level: Level::Error,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 1);
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": B").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 0);
+    }
+
+    #[test]
+    fn event_found_with_inherent_attribute() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+            })
+            .unwrap();
+
+        let now = now();
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: now.saturating_add(1).get() * 1000,
+                span_id: None,
+                content: Value::Str("event".to_owned()),
+                namespace: Some("crate::storage::tests".to_owned()),
+                function: Some("test".to_owned()),
+                level: Level::Error,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("B".to_owned()))]),
+            })
+            .unwrap();
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 0);
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": B").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn event_found_with_span_attribute() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+            })
+            .unwrap();
+
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: now().get() * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    parent_id: None,
+                    name: "test".to_owned(),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: None,
+                    level: Level::Error,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    instrumentation_attributes: BTreeMap::default(),
+                    attributes: BTreeMap::from_iter([(
+                        "attr1".to_owned(),
+                        Value::Str("C".to_owned()),
+                    )]),
+                }),
+            })
+            .unwrap();
+
+        let now = now();
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: now.saturating_add(1).get() * 1000,
+                span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), 1)),
+                content: Value::Str("event".to_owned()),
+                namespace: Some("crate::storage::tests".to_owned()),
+                function: Some("test".to_owned()),
+                level: Level::Error,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 0);
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn event_found_with_nonindexed_updated_span_attribute() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+            })
+            .unwrap();
+
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: now().get() * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    parent_id: None,
+                    name: "test".to_owned(),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: None,
+                    level: Level::Error,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    instrumentation_attributes: BTreeMap::default(),
+                    attributes: BTreeMap::new(),
+                }),
+            })
+            .unwrap();
+
+        let now = now();
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: now.saturating_add(1).get() * 1000,
+                span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), 1)),
+                content: Value::Str("event".to_owned()),
+                namespace: Some("crate::storage::tests".to_owned()),
+                function: Some("test".to_owned()),
+                level: Level::Error,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: super::now().get() * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Update(NewUpdateSpanEvent {
+                    attributes: BTreeMap::from_iter([(
+                        "attr1".to_owned(),
+                        Value::Str("C".to_owned()),
+                    )]),
+                }),
+            })
+            .unwrap();
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 0);
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn event_found_with_indexed_updated_span_attribute() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+            })
+            .unwrap();
+
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: now().get() * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    parent_id: None,
+                    name: "test".to_owned(),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: None,
+                    level: Level::Error,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    instrumentation_attributes: BTreeMap::default(),
+                    attributes: BTreeMap::new(),
+                }),
+            })
+            .unwrap();
+
+        let now = now();
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: now.saturating_add(1).get() * 1000,
+                span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), 1)),
+                content: Value::Str("event".to_owned()),
+                namespace: Some("crate::storage::tests".to_owned()),
+                function: Some("test".to_owned()),
+                level: Level::Error,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: super::now().get() * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Update(NewUpdateSpanEvent {
+                    attributes: BTreeMap::from_iter([(
+                        "attr1".to_owned(),
+                        Value::Str("C".to_owned()),
+                    )]),
+                }),
+            })
+            .unwrap();
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 0);
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn empty_filter_returns_all_event_counts() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+            })
+            .unwrap();
+
+        let now = now();
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: now.saturating_add(1).get() * 1000,
+                span_id: None,
+                content: Value::Str("event".to_owned()),
+                namespace: None,
+                function: None,
+                level: Level::Error,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        let query = Query {
+            filter: FilterPredicate::parse("").unwrap(),
+            order: Order::Asc,
+            limit: 0,
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            previous: None,
+        };
+
+        assert_eq!(engine.query_event_count(query.clone()), 1)
+    }
+
+    #[test]
+    fn span_found_with_updated_attribute() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+            })
+            .unwrap();
+
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: now().get() * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    parent_id: None,
+                    name: "test".to_owned(),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: None,
+                    level: Level::Error,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    instrumentation_attributes: BTreeMap::default(),
+                    attributes: BTreeMap::new(),
+                }),
+            })
+            .unwrap();
+
+        let now = now();
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: now.get() * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Update(NewUpdateSpanEvent {
+                    attributes: BTreeMap::from_iter([(
+                        "attr1".to_owned(),
+                        Value::Str("C".to_owned()),
+                    )]),
+                }),
+            })
+            .unwrap();
+
+        let spans = engine.query_span(Query {
+            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now,
+            end: now.saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(spans.len(), 1);
+    }
+
+    #[test]
+    fn span_found_with_post_parent_attribute() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::from_iter([]),
+            })
+            .unwrap();
+
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: 1001 * 1000,
+                span_id: FullSpanId::Opentelemetry(1, 2),
+                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                    kind: SourceKind::Opentelemetry,
+                    resource_key,
+                    parent_id: Some(FullSpanId::Opentelemetry(1, 1)),
+                    name: "test".to_owned(),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: None,
+                    level: Level::Error,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    instrumentation_attributes: BTreeMap::default(),
+                    attributes: BTreeMap::new(),
+                }),
+            })
+            .unwrap();
+
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: 1000 * 1000,
+                span_id: FullSpanId::Opentelemetry(1, 1),
+                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                    kind: SourceKind::Opentelemetry,
+                    resource_key,
+                    parent_id: None,
+                    name: "test".to_owned(),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: None,
+                    level: Level::Error,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    instrumentation_attributes: BTreeMap::default(),
+                    attributes: BTreeMap::from_iter([(
+                        "attr1".to_owned(),
+                        Value::Str("C".to_owned()),
+                    )]),
+                }),
+            })
+            .unwrap();
+
+        let spans = engine.query_span(Query {
+            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: now(),
+            end: now().saturating_add(2),
+            previous: None,
+        });
+
+        assert_eq!(spans.len(), 2);
+    }
+
+    #[test]
+    fn opentelemetry_nanosecond_timestamps_round_trip() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        // both spans start within the same microsecond, so one of them gets a
+        // key that was moved later to keep it unique
+        for (span, created_at) in [(1, 1000 * 1000 + 123), (2, 1000 * 1000 + 456)] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: created_at,
+                    span_id: FullSpanId::Opentelemetry(1, span),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Opentelemetry,
+                        resource_key,
+                        parent_id: None,
+                        name: "test".to_owned(),
+                        namespace: None,
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::new(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
+
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: 1002 * 1000 + 789,
+                    span_id: FullSpanId::Opentelemetry(1, span),
+                    kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+                })
+                .unwrap();
+        }
+
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Opentelemetry,
+                resource_key,
+                timestamp: 1001 * 1000 + 999,
+                span_id: Some(FullSpanId::Opentelemetry(1, 2)),
+                content: Value::Str("event".to_owned()),
+                namespace: None,
+                function: None,
+                level: Level::Info,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        let spans = engine.query_span(Query {
+            filter: FilterPredicate::parse("#created: 1000").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            previous: None,
+        });
+
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].created_at, 1000 * 1000 + 123);
+        assert_eq!(spans[1].created_at, 1000 * 1000 + 456);
+        assert_ne!(spans[0].key, spans[1].key);
+        assert_eq!(spans[0].closed_at, Some(1002 * 1000 + 789));
+        assert_eq!(spans[1].closed_at, Some(1002 * 1000 + 789));
+
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse(
+                "#parent: otel-00000000000000000000000000000001-0000000000000002",
+            )
+            .unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            previous: None,
+        });
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].timestamp, 1001 * 1000 + 999);
+    }
+
+    #[test]
+    fn out_of_order_inserts_are_ordered_by_precise_timestamp() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        // the second entity of each kind arrives late and gets a key that was
+        // moved past the first, and the third gets the key after that
+        let timestamps = [1000 * 1000 + 900, 1000 * 1000 + 100, 1001 * 1000 + 500];
+
+        for (span, created_at) in (1..).zip(timestamps) {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: created_at,
+                    span_id: FullSpanId::Opentelemetry(1, span),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Opentelemetry,
+                        resource_key,
+                        parent_id: None,
+                        name: "test".to_owned(),
+                        namespace: None,
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::new(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
+        }
+
+        for timestamp in timestamps {
+            engine
+                .insert_event(NewEvent {
+                    kind: SourceKind::Opentelemetry,
+                    resource_key,
+                    timestamp,
+                    span_id: None,
+                    content: Value::Str("event".to_owned()),
+                    namespace: None,
+                    function: None,
+                    level: Level::Info,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    attributes: BTreeMap::new(),
+                })
+                .unwrap();
+        }
+
+        let spans = engine.query_span(Query {
+            filter: FilterPredicate::parse("#created: 1000").unwrap(),
+            order: Order::Asc,
+            limit: 5,
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            previous: None,
+        });
+
+        let created = spans.iter().map(|s| s.created_at).collect::<Vec<_>>();
+        assert_eq!(created, [timestamps[1], timestamps[0]]);
 
-    fn insert_span_bookeeping(&mut self, span: &Span) -> (Vec<SpanKey>, Vec<EventKey>) {
-        let span_key = span.created_at;
+        let events = engine.query_event(Query {
+            filter: vec![],
+            order: Order::Asc,
+            limit: 5,
+            start: now(),
+            end: now(),
+            previous: None,
+        });
 
-        let orphaned_spans = self
-            .span_indexes
-            .update_with_new_span(&SpanContext::with_span(span, &self.storage));
+        let times = events.iter().map(|e| e.timestamp).collect::<Vec<_>>();
+        assert_eq!(times, [timestamps[1], timestamps[0]]);
 
-        let trace = SpanContext::with_span(span, &self.storage).trace_root();
+        let events = engine.query_event(Query {
+            filter: vec![],
+            order: Order::Desc,
+            limit: 5,
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            previous: Some(events[1].key),
+        });
 
-        let descendent_spans = self
-            .span_indexes
-            .traces
-            .get(&trace)
-            .map(Vec::as_slice)
-            .unwrap_or_default()
+        let times = events.iter().map(|e| e.timestamp).collect::<Vec<_>>();
+        assert_eq!(times, [timestamps[1]]);
+
+        let histogram = engine.query_event_histogram(vec![], now(), now().saturating_add(1), 2);
+        let counts = histogram
             .iter()
-            .cloned()
-            .filter(|key| *key != span_key)
-            .filter(|key| {
-                orphaned_spans.contains(key)
-                    || SpanContext::new(*key, &self.storage)
-                        .parents()
-                        .any(|p| orphaned_spans.contains(&p.key()))
-            })
+            .map(|b| b.counts.iter().sum::<usize>())
             .collect::<Vec<_>>();
+        assert_eq!(counts, [2, 1]);
 
-        for descendent in descendent_spans {
-            self.span_indexes.update_with_new_field_on_parent(
-                &SpanContext::new(descendent, &self.storage),
-                span.key(),
-                &span.attributes,
-            );
-        }
-
-        let orphaned_events = self
-            .event_indexes
-            .update_with_new_span(&SpanContext::with_span(span, &self.storage));
-
-        let descendent_events = self
-            .event_indexes
-            .traces
-            .get(&trace)
-            .map(Vec::as_slice)
-            .unwrap_or_default()
+        let histogram = engine.query_span_histogram(vec![], now(), now().saturating_add(1), 2);
+        let counts = histogram
             .iter()
-            .cloned()
-            .filter(|key| {
-                orphaned_events.contains(key)
-                    || EventContext::new(*key, &self.storage)
-                        .parents()
-                        .any(|p| orphaned_events.contains(&p.key()))
-            })
+            .map(|b| b.counts.iter().sum::<usize>())
             .collect::<Vec<_>>();
+        assert_eq!(counts, [2, 3]); // the spans are still open
+    }
 
-        for descendent in descendent_events {
-            self.event_indexes.update_with_new_field_on_parent(
-                &EventContext::new(descendent, &self.storage),
-                span.key(),
-                &span.attributes,
-            );
+    #[test]
+    fn merged_dataset_is_deduplicated_and_relinked() {
+        fn populate(engine: &mut SyncEngine<TransientStorage>, span_ids: &[(u64, Option<u64>)]) {
+            let resource_key = engine
+                .insert_resource(NewResource {
+                    attributes: BTreeMap::from_iter([(
+                        "attr1".to_owned(),
+                        Value::Str("A".to_owned()),
+                    )]),
+                })
+                .unwrap();
+
+            for (id, parent_id) in span_ids {
+                engine
+                    .insert_span_event(NewSpanEvent {
+                        timestamp: now().get() * 1000,
+                        span_id: FullSpanId::Tracing(1.try_into().unwrap(), *id),
+                        kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                            kind: SourceKind::Tracing,
+                            resource_key,
+                            parent_id: parent_id
+                                .map(|p| FullSpanId::Tracing(1.try_into().unwrap(), p)),
+                            name: "test".to_owned(),
+                            namespace: Some("crate::storage::tests".to_owned()),
+                            function: None,
+                            level: Level::Error,
+                            file_name: None,
+                            file_line: None,
+                            file_column: None,
+                            instrumentation_attributes: BTreeMap::default(),
+                            attributes: BTreeMap::new(),
+                        }),
+                    })
+                    .unwrap();
+
+                engine
+                    .insert_event(NewEvent {
+                        kind: SourceKind::Tracing,
+                        resource_key,
+                        timestamp: now().get() * 1000,
+                        span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), *id)),
+                        content: Value::Str("event".to_owned()),
+                        namespace: Some("crate::storage::tests".to_owned()),
+                        function: Some("test".to_owned()),
+                        level: Level::Error,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        attributes: BTreeMap::new(),
+                    })
+                    .unwrap();
+            }
         }
 
-        (orphaned_spans, orphaned_events)
-    }
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+        populate(&mut engine, &[(1, None)]);
 
-    fn insert_span_event_bookeeping(&mut self, span_event: &SpanEvent) {
-        self.span_event_indexes
-            .update_with_new_span_event(span_event);
-    }
+        let mut other = SyncEngine::new(TransientStorage::new()).unwrap();
+        populate(&mut other, &[(1, None), (2, Some(1))]);
 
-    #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn insert_event(&mut self, mut new_event: NewEvent) -> Result<(), AnyError> {
-        let event_key = get_unique_timestamp(new_event.timestamp, &self.event_indexes.all);
-        new_event.timestamp = event_key;
+        let metrics = engine.merge_dataset(&other.storage).unwrap();
 
-        // parent may not yet exist, that is ok
-        let parent_id = new_event.span_id;
-        let parent_key = parent_id.and_then(|id| self.span_indexes.ids.get(&id).copied());
+        assert_eq!(metrics.resources, 0);
+        assert_eq!(metrics.spans, 1);
+        assert_eq!(metrics.span_events, 1);
+        assert_eq!(metrics.events, 1);
 
-        let event = Event {
-            kind: new_event.kind,
-            resource_key: new_event.resource_key,
-            timestamp: new_event.timestamp,
-            parent_id,
-            parent_key,
-            content: new_event.content,
-            namespace: new_event.namespace,
-            function: new_event.function,
-            level: new_event.level,
-            file_name: new_event.file_name,
-            file_line: new_event.file_line,
-            file_column: new_event.file_column,
-            attributes: new_event.attributes,
-        };
+        let parent_key = engine.span_indexes.ids[&FullSpanId::Tracing(1.try_into().unwrap(), 1)];
+        let child_key = engine.span_indexes.ids[&FullSpanId::Tracing(1.try_into().unwrap(), 2)];
+        assert_ne!(parent_key, child_key);
+        assert_eq!(
+            engine.storage.get_span(child_key).unwrap().parent_key,
+            Some(parent_key)
+        );
+        assert_eq!(engine.event_indexes.all.len(), 2);
+        assert_eq!(engine.resources.len(), 1);
+    }
 
-        self.insert_event_bookeeping(&event);
-        self.storage
-            .insert_event(event.clone())
-            .context("failed to insert event")?;
+    #[test]
+    fn merging_the_same_dataset_twice_adds_nothing() {
+        let mut other = SyncEngine::new(TransientStorage::new()).unwrap();
+        let resource_key = other
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
 
-        let context = EventContext::with_event(&event, &self.storage);
-        for subscriber in self.event_subscribers.values_mut() {
-            subscriber.on_event(&context);
+        for (timestamp, content) in [(1000, "a"), (1000, "b"), (1001, "c")] {
+            other
+                .insert_event(NewEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    timestamp: timestamp * 1000,
+                    span_id: None,
+                    content: Value::Str(content.to_owned()),
+                    namespace: None,
+                    function: None,
+                    level: Level::Info,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    attributes: BTreeMap::new(),
+                })
+                .unwrap();
         }
 
-        self.event_subscribers.retain(|_, s| s.connected());
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
-        Ok(())
-    }
+        let metrics = engine.merge_dataset(&other.storage).unwrap();
+        assert_eq!(metrics.events, 3);
 
-    fn insert_event_bookeeping(&mut self, event: &Event) {
-        self.event_indexes
-            .update_with_new_event(&EventContext::with_event(event, &self.storage));
+        let metrics = engine.merge_dataset(&other.storage).unwrap();
+        assert_eq!(metrics.resources, 0);
+        assert_eq!(metrics.events, 0);
+        assert_eq!(engine.event_indexes.all.len(), 3);
     }
 
-    #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn delete(&mut self, filter: DeleteFilter) -> Result<DeleteMetrics, AnyError> {
-        let root_spans =
-            self.get_root_spans_in_range_filter(filter.start, filter.end, filter.inside);
-        let root_events =
-            self.get_root_events_in_range_filter(filter.start, filter.end, filter.inside);
+    #[test]
+    fn verify_finds_and_repair_fixes_inconsistencies() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
-        let spans_from_root_spans = root_spans
-            .iter()
-            .flat_map(|root| {
-                self.span_indexes
-                    .traces
-                    .get(&SpanContext::new(*root, &self.storage).trace_root())
-                    .map(Vec::as_slice)
-                    .unwrap_or_default()
-                    .iter()
-                    .cloned()
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
             })
-            .collect::<Vec<SpanKey>>();
-        let events_from_root_spans = root_spans
-            .iter()
-            .flat_map(|root| {
-                self.event_indexes
-                    .traces
-                    .get(&SpanContext::new(*root, &self.storage).trace_root())
-                    .map(Vec::as_slice)
-                    .unwrap_or_default()
-                    .iter()
-                    .cloned()
+            .unwrap();
+
+        for (id, parent_id) in [(1, None), (2, Some(1))] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: now().get() * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Tracing,
+                        resource_key,
+                        parent_id: parent_id.map(|p| FullSpanId::Tracing(1.try_into().unwrap(), p)),
+                        name: "test".to_owned(),
+                        namespace: Some("crate::storage::tests".to_owned()),
+                        function: None,
+                        level: Level::Error,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
+        }
+
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: now().get() * 1000,
+                span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), 2)),
+                content: Value::Str("event".to_owned()),
+                namespace: Some("crate::storage::tests".to_owned()),
+                function: Some("test".to_owned()),
+                level: Level::Error,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
             })
-            .collect::<Vec<EventKey>>();
-        let span_events = spans_from_root_spans
-            .iter()
-            .flat_map(|span| {
-                self.span_event_indexes
-                    .spans
-                    .get(span)
-                    .map(Vec::as_slice)
-                    .unwrap_or_default()
-                    .iter()
-                    .cloned()
+            .unwrap();
+
+        assert!(engine.verify().unwrap().is_ok());
+
+        let parent_key = engine.span_indexes.ids[&FullSpanId::Tracing(1.try_into().unwrap(), 1)];
+        let child_key = engine.span_indexes.ids[&FullSpanId::Tracing(1.try_into().unwrap(), 2)];
+        let event_key = engine.event_indexes.keys[0];
+
+        // a copy of the parent that only made it into storage
+        let parent = engine.storage.get_span(parent_key).unwrap();
+        let duplicate_key = Timestamp::new(5000).unwrap();
+        engine
+            .storage
+            .insert_span(Span {
+                key: duplicate_key,
+                ..(*parent).clone()
             })
-            .collect::<Vec<SpanEventKey>>();
+            .unwrap();
 
-        if filter.dry_run {
-            return Ok(DeleteMetrics {
-                spans: spans_from_root_spans.len(),
-                span_events: span_events.len(),
-                events: root_events.len() + events_from_root_spans.len(),
-            });
-        }
+        // the child refers to a parent that doesn't exist
+        let child = engine.storage.get_span(child_key).unwrap();
+        engine.storage.drop_spans(&[child_key]).unwrap();
+        engine
+            .storage
+            .insert_span(Span {
+                parent_key: Timestamp::new(9999),
+                ..(*child).clone()
+            })
+            .unwrap();
 
-        let mut spans_to_delete = spans_from_root_spans;
-        let mut span_events_to_delete = span_events;
-        let mut events_to_delete = root_events;
-        events_to_delete.extend(events_from_root_spans);
+        // the event lost the link to its parent
+        let event = engine.storage.get_event(event_key).unwrap();
+        engine.storage.drop_events(&[event_key]).unwrap();
+        engine
+            .storage
+            .insert_event(Event {
+                parent_key: None,
+                ..(*event).clone()
+            })
+            .unwrap();
 
-        spans_to_delete.sort();
-        span_events_to_delete.sort();
-        events_to_delete.sort();
+        let report = engine.verify().unwrap();
+        assert_eq!(
+            report.duplicate_span_ids,
+            [FullSpanId::Tracing(1.try_into().unwrap(), 1)]
+        );
+        assert_eq!(report.dangling_span_parents, [child_key]);
+        assert_eq!(report.orphaned_events, [event_key]);
+        assert_eq!(report.mismatched_spans, [duplicate_key]);
 
-        // drop smaller scoped entities from storage first to avoid integrity
-        // issues if things go wrong
+        engine.repair().unwrap();
 
-        self.storage
-            .drop_events(&events_to_delete)
-            .context("failed to drop events")?;
-        self.storage
-            .drop_span_events(&span_events_to_delete)
-            .context("failed to drop span events")?;
-        self.storage
-            .drop_spans(&spans_to_delete)
-            .context("failed to drop spans")?;
+        assert!(engine.verify().unwrap().is_ok());
+        assert_eq!(engine.span_indexes.keys, [parent_key, child_key]);
+        assert_eq!(
+            engine.storage.get_span(child_key).unwrap().parent_key,
+            Some(parent_key)
+        );
+        assert_eq!(
+            engine.storage.get_event(event_key).unwrap().parent_key,
+            Some(child_key)
+        );
+    }
 
-        // remove smaller scoped entities from indexes last for some efficiency
+    #[test]
+    fn deleted_entities_can_be_restored_until_purged() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
-        self.remove_spans_bookeeping(&spans_to_delete);
-        self.remove_span_events_bookeeping(&span_events_to_delete);
-        self.remove_events_bookeeping(&events_to_delete);
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
 
-        let resources_to_delete = self
-            .resources
-            .keys()
-            .copied()
-            .filter(|resource_key| {
-                let used_by_spans = self
-                    .span_indexes
-                    .resources
-                    .get(resource_key)
-                    .is_some_and(|r| !r.is_empty());
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: now().get() * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    parent_id: None,
+                    name: "test".to_owned(),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: None,
+                    level: Level::Error,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    instrumentation_attributes: BTreeMap::default(),
+                    attributes: BTreeMap::new(),
+                }),
+            })
+            .unwrap();
 
-                let used_by_events = self
-                    .event_indexes
-                    .resources
-                    .get(resource_key)
-                    .is_some_and(|r| !r.is_empty());
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: now().get() * 1000 + 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+            })
+            .unwrap();
 
-                !used_by_spans && !used_by_events
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: now().get() * 1000,
+                span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), 1)),
+                content: Value::Str("event".to_owned()),
+                namespace: Some("crate::storage::tests".to_owned()),
+                function: Some("test".to_owned()),
+                level: Level::Error,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
             })
-            .collect::<Vec<_>>();
+            .unwrap();
 
-        self.storage
-            .drop_resources(&resources_to_delete)
-            .context("failed to drop events")?;
+        let span_key = engine.span_indexes.keys[0];
+        let event_key = engine.event_indexes.keys[0];
 
-        for resource_key in resources_to_delete {
-            self.resources.remove(&resource_key);
-        }
+        let filter = || DeleteFilter {
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            inside: true,
+            span_filter: None,
+            event_filter: None,
+            mode: DeleteMode::Traces,
+            dry_run: false,
+        };
 
-        Ok(DeleteMetrics {
-            spans: spans_to_delete.len(),
-            span_events: span_events_to_delete.len(),
-            events: events_to_delete.len(),
-        })
+        let metrics = engine.delete(filter()).unwrap();
+        assert_eq!(metrics.spans, 1);
+        assert_eq!(metrics.events, 1);
+        assert!(engine.span_indexes.all.is_empty());
+        assert!(engine.event_indexes.all.is_empty());
+        assert!(engine.verify().unwrap().is_ok());
+
+        engine.undo_delete(metrics.tombstone.unwrap()).unwrap();
+        assert_eq!(engine.span_indexes.keys, [span_key]);
+        assert_eq!(engine.event_indexes.keys, [event_key]);
+        assert_eq!(engine.span_event_indexes.all.len(), 2);
+
+        let metrics = engine.delete(filter()).unwrap();
+
+        // the tombstone is still within the grace period
+        engine.purge_deleted().unwrap();
+        assert!(engine.storage.get_span(span_key).is_ok());
+
+        engine.set_delete_grace_period(Duration::ZERO);
+        let purged = engine.purge_deleted().unwrap();
+        assert_eq!(purged.spans, 1);
+        assert_eq!(purged.span_events, 2);
+        assert_eq!(purged.events, 1);
+        assert!(engine.storage.get_span(span_key).is_err());
+        assert!(engine.storage.get_event(event_key).is_err());
+        assert!(engine.resources.is_empty());
+        assert!(engine.undo_delete(metrics.tombstone.unwrap()).is_err());
     }
 
-    fn get_root_spans_in_range_filter(
-        &self,
-        start: Timestamp,
-        end: Timestamp,
-        inside: bool,
-    ) -> Vec<SpanKey> {
-        let filter = if inside {
-            BasicSpanFilter::And(vec![
-                BasicSpanFilter::Created(ValueOperator::Lte, end),
-                BasicSpanFilter::Closed(ValueOperator::Gte, start),
-                BasicSpanFilter::Root,
-            ])
-        } else {
-            BasicSpanFilter::And(vec![
-                BasicSpanFilter::Or(vec![
-                    BasicSpanFilter::Created(ValueOperator::Gt, end),
-                    BasicSpanFilter::Closed(ValueOperator::Lt, start),
-                ]),
-                BasicSpanFilter::Root,
-            ])
-        };
+    #[test]
+    fn delete_by_filter_removes_traces_or_matching_entities() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
-        let indexed_filter =
-            IndexedSpanFilter::build(Some(filter), &self.span_indexes, &self.storage);
-        let iter = IndexedSpanFilterIterator::new_internal(indexed_filter, self);
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
 
-        iter.collect()
-    }
+        for (id, parent_id, name) in [(1, None, "parent"), (2, Some(1), "child")] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: now().get() * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Tracing,
+                        resource_key,
+                        parent_id: parent_id.map(|p| FullSpanId::Tracing(1.try_into().unwrap(), p)),
+                        name: name.to_owned(),
+                        namespace: Some("crate::storage::tests".to_owned()),
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
+        }
 
-    fn get_root_events_in_range_filter(
-        &self,
-        start: Timestamp,
-        end: Timestamp,
-        inside: bool,
-    ) -> Vec<SpanKey> {
-        let filter = if inside {
-            BasicEventFilter::And(vec![
-                BasicEventFilter::Timestamp(ValueOperator::Lte, end),
-                BasicEventFilter::Timestamp(ValueOperator::Gte, start),
-                BasicEventFilter::Root,
-            ])
-        } else {
-            BasicEventFilter::And(vec![
-                BasicEventFilter::Or(vec![
-                    BasicEventFilter::Timestamp(ValueOperator::Gt, end),
-                    BasicEventFilter::Timestamp(ValueOperator::Lt, start),
-                ]),
-                BasicEventFilter::Root,
-            ])
-        };
+        for id in [2, 1] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: now().get() * 1000 + 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+                })
+                .unwrap();
+        }
 
-        let indexed_filter =
-            IndexedEventFilter::build(Some(filter), &self.event_indexes, &self.storage);
-        let iter = IndexedEventFilterIterator::new_internal(indexed_filter, self);
+        for (span_id, level) in [
+            (Some(2), Level::Info),
+            (Some(2), Level::Trace),
+            (None, Level::Trace),
+        ] {
+            engine
+                .insert_event(NewEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    timestamp: now().get() * 1000,
+                    span_id: span_id.map(|id| FullSpanId::Tracing(1.try_into().unwrap(), id)),
+                    content: Value::Str("event".to_owned()),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: Some("test".to_owned()),
+                    level,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    attributes: BTreeMap::new(),
+                })
+                .unwrap();
+        }
 
-        iter.collect()
-    }
+        let filter =
+            |span_filter: Option<&str>, event_filter: Option<&str>, mode, dry_run| DeleteFilter {
+                start: Timestamp::MIN,
+                end: Timestamp::MAX,
+                inside: true,
+                span_filter: span_filter.map(|f| FilterPredicate::parse(f).unwrap()),
+                event_filter: event_filter.map(|f| FilterPredicate::parse(f).unwrap()),
+                mode,
+                dry_run,
+            };
 
-    fn remove_spans_bookeeping(&mut self, spans: &[SpanKey]) {
-        self.span_indexes.remove_spans(spans);
-        self.span_event_indexes.remove_spans(spans);
-        self.event_indexes.remove_spans(spans);
-    }
+        let metrics = engine
+            .delete(filter(
+                None,
+                Some("#level: TRACE"),
+                DeleteMode::Matching,
+                true,
+            ))
+            .unwrap();
+        assert_eq!(metrics.spans, 0);
+        assert_eq!(metrics.events, 2);
+        assert_eq!(engine.event_indexes.all.len(), 3);
 
-    fn remove_span_events_bookeeping(&mut self, span_events: &[SpanEventKey]) {
-        self.span_event_indexes.remove_span_events(span_events);
-    }
+        let metrics = engine
+            .delete(filter(
+                None,
+                Some("#level: TRACE"),
+                DeleteMode::Traces,
+                true,
+            ))
+            .unwrap();
+        assert_eq!(metrics.spans, 2);
+        assert_eq!(metrics.span_events, 4);
+        assert_eq!(metrics.events, 3);
 
-    fn remove_events_bookeeping(&mut self, events: &[EventKey]) {
-        self.event_indexes.remove_events(events);
+        let metrics = engine
+            .delete(filter(
+                Some("#name: child"),
+                None,
+                DeleteMode::Matching,
+                false,
+            ))
+            .unwrap();
+        assert_eq!(metrics.spans, 1);
+        assert_eq!(metrics.span_events, 2);
+        assert_eq!(metrics.events, 2);
+        assert_eq!(engine.span_indexes.all.len(), 1);
+        assert_eq!(engine.event_indexes.all.len(), 1);
+        assert!(engine.verify().unwrap().is_ok());
     }
 
-    #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn copy_dataset(&self, target_storage: &mut dyn Storage) -> Result<(), AnyError> {
-        let resources = self
-            .storage
-            .get_all_resources()
-            .context("failed to get resources")?
-            .collect::<Vec<_>>();
-
-        for resource in resources {
-            let resource = resource.context("failed to get resource")?;
-            target_storage
-                .insert_resource((*resource).clone())
-                .context("failed to insert resource")?;
-        }
+    #[test]
+    fn pinned_traces_and_events_are_not_deleted() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
-        let spans = self
-            .storage
-            .get_all_spans()
-            .context("failed to get spans")?
-            .collect::<Vec<_>>();
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
 
-        for span in spans {
-            let span = span.context("failed to get span")?;
-            target_storage
-                .insert_span((*span).clone())
-                .context("failed to insert span")?;
+        for (id, parent_id) in [(1, None), (2, Some(1)), (3, None)] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: now().get() * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Tracing,
+                        resource_key,
+                        parent_id: parent_id.map(|p| FullSpanId::Tracing(1.try_into().unwrap(), p)),
+                        name: "test".to_owned(),
+                        namespace: Some("crate::storage::tests".to_owned()),
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
         }
 
-        let span_events = self
-            .storage
-            .get_all_span_events()
-            .context("failed to get span events")?
-            .collect::<Vec<_>>();
-
-        for span_event in span_events {
-            let span_event = span_event.context("failed to get span event")?;
-            target_storage
-                .insert_span_event((*span_event).clone())
-                .context("failed to insert span event")?;
+        for id in [2, 1, 3] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: now().get() * 1000 + 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+                })
+                .unwrap();
         }
 
-        let events = self
-            .storage
-            .get_all_events()
-            .context("failed to get events")?
-            .collect::<Vec<_>>();
-
-        for event in events {
-            let event = event.context("failed to get event")?;
-            target_storage
-                .insert_event((*event).clone())
-                .context("failed to insert event")?;
+        for span_id in [Some(2), None, None] {
+            engine
+                .insert_event(NewEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    timestamp: now().get() * 1000,
+                    span_id: span_id.map(|id| FullSpanId::Tracing(1.try_into().unwrap(), id)),
+                    content: Value::Str("event".to_owned()),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: Some("test".to_owned()),
+                    level: Level::Info,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    attributes: BTreeMap::new(),
+                })
+                .unwrap();
         }
 
-        Ok(())
-    }
-
-    #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn subscribe_to_spans(
-        &mut self,
-        filter: Vec<FilterPredicate>,
-    ) -> Result<Subscriber<ComposedSpan>, AnyError> {
-        let mut filter = BasicSpanFilter::And(
-            filter
-                .into_iter()
-                .map(|p| BasicSpanFilter::from_predicate(p, &self.span_indexes.ids))
-                .collect::<Result<_, _>>()
-                .context("invalid span filter")?,
-        );
-        filter.simplify();
-
-        let id = self.next_subscriber_id;
-        self.next_subscriber_id += 1;
-
-        let (sender, receiver) = mpsc::unbounded_channel();
-
-        self.span_subscribers
-            .insert(id, SpanSubscription::new(filter, sender));
-
-        Ok((id, receiver))
-    }
+        let child_key = engine.span_indexes.ids[&FullSpanId::Tracing(1.try_into().unwrap(), 2)];
+        let root_event_key = engine.event_indexes.roots[0].key;
 
-    #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn unsubscribe_from_spans(&mut self, id: SubscriptionId) {
-        self.span_subscribers.remove(&id);
-    }
+        engine.pin(Pin::Span(child_key)).unwrap();
+        engine.pin(Pin::Event(root_event_key)).unwrap();
 
-    #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn subscribe_to_events(
-        &mut self,
-        filter: Vec<FilterPredicate>,
-    ) -> Result<Subscriber<ComposedEvent>, AnyError> {
-        let mut filter = BasicEventFilter::And(
-            filter
-                .into_iter()
-                .map(|p| BasicEventFilter::from_predicate(p, &self.span_indexes.ids))
-                .collect::<Result<_, _>>()
-                .context("invalid event filter")?,
-        );
-        filter.simplify();
+        let query = |filter: &str| Query {
+            filter: FilterPredicate::parse(filter).unwrap(),
+            order: Order::Asc,
+            limit: 10,
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            previous: None,
+        };
 
-        let id = self.next_subscriber_id;
-        self.next_subscriber_id += 1;
+        assert_eq!(engine.query_span_count(query("#pinned: true")), 1);
+        assert_eq!(engine.query_event_count(query("#pinned: true")), 1);
+        assert_eq!(engine.query_event_count(query("#pinned: false")), 2);
 
-        let (sender, receiver) = mpsc::unbounded_channel();
+        let filter = || DeleteFilter {
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            inside: true,
+            span_filter: None,
+            event_filter: None,
+            mode: DeleteMode::Traces,
+            dry_run: false,
+        };
 
-        self.event_subscribers
-            .insert(id, EventSubscription::new(filter, sender));
+        let metrics = engine.delete(filter()).unwrap();
+        assert_eq!(metrics.spans, 1);
+        assert_eq!(metrics.span_events, 2);
+        assert_eq!(metrics.events, 1);
+        assert_eq!(engine.span_indexes.all.len(), 2);
+        assert_eq!(engine.event_indexes.all.len(), 2);
 
-        Ok((id, receiver))
-    }
+        engine.unpin(Pin::Span(child_key)).unwrap();
+        assert_eq!(engine.query_span_count(query("#pinned: true")), 0);
 
-    #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn unsubscribe_from_events(&mut self, id: SubscriptionId) {
-        self.event_subscribers.remove(&id);
+        let metrics = engine.delete(filter()).unwrap();
+        assert_eq!(metrics.spans, 2);
+        assert_eq!(metrics.events, 1);
+        assert_eq!(engine.event_indexes.keys, [root_event_key]);
     }
 
-    #[instrument(level = tracing::Level::TRACE, skip_all)]
-    pub fn shutdown(&mut self) -> Result<(), AnyError> {
-        if let Some(s) = self.storage.as_index_storage_mut() {
-            s.update_indexes(
-                &self.span_indexes,
-                &self.span_event_indexes,
-                &self.event_indexes,
-            )
-            .context("failed to update indexes")?;
-        }
+    #[test]
+    fn pinned_events_keep_their_ancestors() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
-        Ok(())
-    }
-}
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
 
-fn get_unique_timestamp(mut timestamp: Timestamp, existing: &[Timestamp]) -> Timestamp {
-    let mut idx = existing.lower_bound(&timestamp);
+        for (id, parent_id) in [(1, None), (2, Some(1)), (3, None)] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: now().get() * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Tracing,
+                        resource_key,
+                        parent_id: parent_id.map(|p| FullSpanId::Tracing(1.try_into().unwrap(), p)),
+                        name: "test".to_owned(),
+                        namespace: Some("crate::storage::tests".to_owned()),
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
+        }
 
-    while idx < existing.len() && timestamp == existing[idx] {
-        idx += 1;
-        timestamp = timestamp.saturating_add(1);
-    }
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: now().get() * 1000,
+                span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), 2)),
+                content: Value::Str("event".to_owned()),
+                namespace: Some("crate::storage::tests".to_owned()),
+                function: Some("test".to_owned()),
+                level: Level::Info,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
 
-    timestamp
-}
+        for id in [2, 1, 3] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: now().get() * 1000 + 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+                })
+                .unwrap();
+        }
 
-fn now() -> Timestamp {
-    #[cfg(test)]
-    return Timestamp::new(1000).unwrap();
+        let event_key = engine.event_indexes.keys[0];
+        engine.pin(Pin::Event(event_key)).unwrap();
+
+        let metrics = engine
+            .delete(DeleteFilter {
+                start: Timestamp::MIN,
+                end: Timestamp::MAX,
+                inside: true,
+                span_filter: None,
+                event_filter: None,
+                mode: DeleteMode::Traces,
+                dry_run: false,
+            })
+            .unwrap();
 
-    #[cfg(not(test))]
-    {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("now should not be before the UNIX epoch")
-            .as_micros();
+        let root_key = engine.span_indexes.ids[&FullSpanId::Tracing(1.try_into().unwrap(), 1)];
+        let parent_key = engine.span_indexes.ids[&FullSpanId::Tracing(1.try_into().unwrap(), 2)];
 
-        let timestamp = u64::try_from(timestamp)
-            .expect("microseconds shouldn't exceed a u64 until the year 586,912 AD");
+        assert_eq!(metrics.spans, 1);
+        assert_eq!(metrics.events, 0);
+        assert_eq!(engine.span_indexes.keys, [root_key, parent_key]);
+        assert_eq!(engine.event_indexes.keys, [event_key]);
 
-        Timestamp::new(timestamp).expect("now should not be at the UNIX epoch")
+        let events = engine.query_event(Query {
+            filter: FilterPredicate::parse("#pinned: true").unwrap(),
+            order: Order::Asc,
+            limit: 10,
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            previous: None,
+        });
+        assert_eq!(events[0].ancestors.len(), 2);
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::filter::Order;
-    use crate::models::{
-        Level, NewCloseSpanEvent, NewCreateSpanEvent, NewUpdateSpanEvent, SourceKind,
-    };
-    use crate::storage::TransientStorage;
-    use crate::Value;
-
-    use super::*;
 
     #[test]
-    fn test_event_filters() {
+    fn annotations_are_kept_with_the_dataset() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
         let resource_key = engine
@@ -1228,55 +5207,102 @@ mod tests {
             })
             .unwrap();
 
-        let simple = |id: u64, level: i32, attribute1: &str, attribute2: &str| -> NewEvent {
-            NewEvent {
+        engine
+            .insert_span_event(NewSpanEvent {
+                timestamp: now().get() * 1000,
+                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
+                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    parent_id: None,
+                    name: "test".to_owned(),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: None,
+                    level: Level::Info,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    instrumentation_attributes: BTreeMap::default(),
+                    attributes: BTreeMap::new(),
+                }),
+            })
+            .unwrap();
+
+        engine
+            .insert_event(NewEvent {
                 kind: SourceKind::Tracing,
                 resource_key,
-                timestamp: id.try_into().unwrap(),
+                timestamp: now().get() * 1000,
                 span_id: None,
                 content: Value::Str("event".to_owned()),
                 namespace: Some("crate::storage::tests".to_owned()),
                 function: Some("test".to_owned()),
-                level: Level::from_tracing_level(level).unwrap(),
+                level: Level::Info,
                 file_name: None,
                 file_line: None,
                 file_column: None,
-                attributes: BTreeMap::from_iter([
-                    ("attribute1".to_owned(), Value::Str(attribute1.to_owned())),
-                    ("attribute2".to_owned(), Value::Str(attribute2.to_owned())),
-                ]),
-            }
-        };
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
 
-        engine.insert_event(simple(1, 4, "test", "A")).unwrap(); // excluded by timestamp
-        engine.insert_event(simple(2, 1, "test", "A")).unwrap(); // excluded by level
-        engine.insert_event(simple(3, 2, "test", "A")).unwrap(); // excluded by level
-        engine.insert_event(simple(4, 3, "test", "A")).unwrap();
-        engine.insert_event(simple(5, 4, "test", "A")).unwrap();
-        engine.insert_event(simple(6, 4, "blah", "A")).unwrap(); // excluded by "blah"
-        engine.insert_event(simple(7, 4, "test", "B")).unwrap(); // excluded by "B"
-        engine.insert_event(simple(8, 4, "test", "C")).unwrap(); // excluded by "C"
-        engine.insert_event(simple(9, 4, "test", "A")).unwrap(); // excluded by timestamp
+        let span_key = engine.span_indexes.keys[0];
+        let event_key = engine.event_indexes.keys[0];
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse(
-                "#level: >=WARN @\"attribute1\": test @\"attribute2\": A",
-            )
-            .unwrap(),
-            order: Order::Asc,
-            limit: 3,
-            start: Timestamp::new(2).unwrap(),
-            end: Timestamp::new(8).unwrap(),
-            previous: None,
-        });
+        let unknown = AnnotationTarget::Span(event_key.checked_add(100).unwrap());
+        let backwards = AnnotationTarget::Timespan(span_key.checked_add(1).unwrap(), span_key);
 
-        assert_eq!(events.len(), 2);
-        assert_eq!(events[0].timestamp, Timestamp::new(4).unwrap());
-        assert_eq!(events[1].timestamp, Timestamp::new(5).unwrap());
+        for target in [unknown, backwards] {
+            let result = engine.add_annotation(NewAnnotation {
+                target,
+                text: "invalid".to_owned(),
+            });
+
+            assert!(result.is_err());
+        }
+
+        let span_annotation = engine
+            .add_annotation(NewAnnotation {
+                target: AnnotationTarget::Span(span_key),
+                text: "this is where the deadlock starts".to_owned(),
+            })
+            .unwrap();
+        let event_annotation = engine
+            .add_annotation(NewAnnotation {
+                target: AnnotationTarget::Event(event_key),
+                text: String::new(),
+            })
+            .unwrap();
+        let timespan_annotation = engine
+            .add_annotation(NewAnnotation {
+                target: AnnotationTarget::Timespan(span_key, event_key),
+                text: "slow".to_owned(),
+            })
+            .unwrap();
+
+        engine
+            .update_annotation(timespan_annotation, "very slow".to_owned())
+            .unwrap();
+        engine.remove_annotation(event_annotation).unwrap();
+
+        assert!(engine
+            .update_annotation(event_annotation, "removed".to_owned())
+            .is_err());
+
+        let mut copy = TransientStorage::new();
+        engine.copy_dataset(&mut copy).unwrap();
+        let copied_engine = SyncEngine::new(copy).unwrap();
+
+        let annotations = copied_engine.query_annotations();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].key, span_annotation);
+        assert_eq!(annotations[0].target, AnnotationTarget::Span(span_key));
+        assert_eq!(annotations[0].text, "this is where the deadlock starts");
+        assert_eq!(annotations[1].key, timespan_annotation);
+        assert_eq!(annotations[1].text, "very slow");
     }
 
     #[test]
-    fn test_span_filters() {
+    fn histogram_counts_entities_by_level_per_bucket() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
         let resource_key = engine
@@ -1285,11 +5311,39 @@ mod tests {
             })
             .unwrap();
 
-        let simple_open =
-            |open: u64, level: i32, attribute1: &str, attribute2: &str| -> NewSpanEvent {
-                NewSpanEvent {
-                    timestamp: Timestamp::new(open).unwrap(),
-                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), open),
+        for (timestamp, level) in [
+            (1000, Level::Info),
+            (1005, Level::Error),
+            (1010, Level::Info),
+            (1019, Level::Warn),
+        ] {
+            engine
+                .insert_event(NewEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    timestamp: timestamp * 1000,
+                    span_id: None,
+                    content: Value::Str("event".to_owned()),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: Some("test".to_owned()),
+                    level,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    attributes: BTreeMap::new(),
+                })
+                .unwrap();
+        }
+
+        for (id, created_at, closed_at, level) in [
+            (1, 1000, Some(1010), Level::Debug),
+            (2, 1002, Some(1012), Level::Info),
+            (3, 1015, None, Level::Warn),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: created_at * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
                     kind: NewSpanEventKind::Create(NewCreateSpanEvent {
                         kind: SourceKind::Tracing,
                         resource_key,
@@ -1297,584 +5351,1076 @@ mod tests {
                         name: "test".to_owned(),
                         namespace: Some("crate::storage::tests".to_owned()),
                         function: None,
-                        level: Level::from_tracing_level(level).unwrap(),
+                        level,
                         file_name: None,
                         file_line: None,
                         file_column: None,
                         instrumentation_attributes: BTreeMap::default(),
-                        attributes: BTreeMap::from_iter([
-                            ("attribute1".to_owned(), Value::Str(attribute1.to_owned())),
-                            ("attribute2".to_owned(), Value::Str(attribute2.to_owned())),
-                        ]),
+                        attributes: BTreeMap::new(),
                     }),
-                }
-            };
+                })
+                .unwrap();
 
-        let simple_close = |open: u64, close: u64| -> NewSpanEvent {
-            NewSpanEvent {
-                timestamp: Timestamp::new(close).unwrap(),
-                span_id: FullSpanId::Tracing(1.try_into().unwrap(), open),
-                kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+            if let Some(closed_at) = closed_at {
+                engine
+                    .insert_span_event(NewSpanEvent {
+                        timestamp: closed_at * 1000,
+                        span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                        kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+                    })
+                    .unwrap();
             }
-        };
-
-        engine
-            .insert_span_event(simple_open(1, 4, "test", "A"))
-            .unwrap(); // excluded by timestamp
-        engine.insert_span_event(simple_close(1, 2)).unwrap();
-        engine
-            .insert_span_event(simple_open(3, 1, "test", "A"))
-            .unwrap(); // excluded by level
-        engine.insert_span_event(simple_close(3, 6)).unwrap();
-        engine
-            .insert_span_event(simple_open(4, 2, "test", "A"))
-            .unwrap(); // excluded by level
-        engine.insert_span_event(simple_close(4, 7)).unwrap();
-        engine
-            .insert_span_event(simple_open(5, 3, "test", "A"))
-            .unwrap();
-        engine.insert_span_event(simple_close(5, 8)).unwrap();
-        engine
-            .insert_span_event(simple_open(9, 4, "test", "A"))
-            .unwrap();
-        engine
-            .insert_span_event(simple_open(10, 4, "blah", "A"))
-            .unwrap(); // excluded by "blah"
-        engine
-            .insert_span_event(simple_open(11, 4, "test", "B"))
-            .unwrap(); // excluded by "B"
-        engine
-            .insert_span_event(simple_open(12, 4, "test", "C"))
-            .unwrap(); // excluded by "C"
-        engine
-            .insert_span_event(simple_open(13, 4, "test", "A"))
-            .unwrap(); // excluded by timestamp
+        }
 
-        let spans = engine.query_span(Query {
-            filter: FilterPredicate::parse(
-                "#level: >=WARN @\"attribute1\": test @\"attribute2\": A",
-            )
-            .unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: Timestamp::new(2).unwrap(),
-            end: Timestamp::new(10).unwrap(),
-            previous: None,
-        });
+        let start = Timestamp::new(1000).unwrap();
+        let end = Timestamp::new(1019).unwrap();
 
-        assert_eq!(spans.len(), 2);
-        assert_eq!(spans[0].created_at, Timestamp::new(5).unwrap());
-        assert_eq!(spans[1].created_at, Timestamp::new(9).unwrap());
+        let events = engine.query_event_histogram(vec![], start, end, 2);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].start.get(), 1000);
+        assert_eq!(events[0].end.get(), 1009);
+        assert_eq!(events[0].counts, [0, 0, 1, 0, 1, 0]);
+        assert_eq!(events[1].start.get(), 1010);
+        assert_eq!(events[1].end.get(), 1019);
+        assert_eq!(events[1].counts, [0, 0, 1, 1, 0, 0]);
+
+        let filter = FilterPredicate::parse("#level: INFO").unwrap();
+        let events = engine.query_event_histogram(filter, start, end, 2);
+        assert_eq!(events[0].counts, [0, 0, 1, 0, 0, 0]);
+        assert_eq!(events[1].counts, [0, 0, 1, 0, 0, 0]);
+
+        // a span is counted in every bucket it overlaps, but not one that
+        // starts exactly when it closed
+        let spans = engine.query_span_histogram(vec![], start, end, 2);
+        assert_eq!(spans[0].counts, [0, 1, 1, 0, 0, 0]);
+        assert_eq!(spans[1].counts, [0, 0, 1, 1, 0, 0]);
+
+        let spans = engine.query_span_histogram(vec![], start, end, 1);
+        assert_eq!(spans[0].counts, [0, 1, 1, 1, 0, 0]);
+
+        // there cannot be more buckets than microseconds in the range
+        let events = engine.query_event_histogram(vec![], start, start, 10);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].counts, [0, 0, 1, 0, 0, 0]);
+
+        assert!(engine
+            .query_event_histogram(vec![], end, start, 2)
+            .is_empty());
+        assert!(engine
+            .query_span_histogram(vec![], start, end, 0)
+            .is_empty());
     }
 
     #[test]
-    fn event_found_with_resource_attribute() {
+    fn aggregate_groups_spans_and_events() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
-        let resource_key = engine
+        let resource_a = engine
             .insert_resource(NewResource {
-                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+                attributes: BTreeMap::from_iter([(
+                    "service".to_owned(),
+                    Value::Str("a".to_owned()),
+                )]),
             })
             .unwrap();
-
-        let now = now();
-        engine
-            .insert_event(NewEvent {
-                kind: SourceKind::Tracing,
-                resource_key,
-                timestamp: now.saturating_add(1),
-                span_id: None,
-                content: Value::Str("event".to_owned()),
-                namespace: Some("crate::storage::tests".to_owned()),
-                function: Some("test".to_owned()),
-                level: Level::Error,
-                file_name: None,
-                file_line: None,
-                file_column: None,
-                attributes: BTreeMap::new(),
+        let resource_b = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::from_iter([(
+                    "service".to_owned(),
+                    Value::Str("b".to_owned()),
+                )]),
             })
             .unwrap();
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        for (id, resource_key, created_at, duration, bytes) in [
+            (1, resource_a, 1000, 10, Value::U64(5)),
+            (2, resource_a, 1001, 30, Value::Str("x".to_owned())),
+            (3, resource_b, 1002, 20, Value::I64(-2)),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: created_at * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Tracing,
+                        resource_key,
+                        parent_id: None,
+                        name: "test".to_owned(),
+                        namespace: Some("crate::storage::tests".to_owned()),
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::from_iter([("bytes".to_owned(), bytes)]),
+                    }),
+                })
+                .unwrap();
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: (created_at + duration) * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+                })
+                .unwrap();
+        }
+
+        for (resource_key, level) in [
+            (resource_a, Level::Info),
+            (resource_a, Level::Info),
+            (resource_b, Level::Error),
+        ] {
+            engine
+                .insert_event(NewEvent {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    timestamp: now().get() * 1000,
+                    span_id: None,
+                    content: Value::Str("event".to_owned()),
+                    namespace: Some("crate::storage::tests".to_owned()),
+                    function: Some("test".to_owned()),
+                    level,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    attributes: BTreeMap::new(),
+                })
+                .unwrap();
+        }
+
+        let query = |group_by: &[&str], aggregates: &[&str]| AggregateQuery {
+            filter: vec![],
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            group_by: group_by.iter().map(|k| k.parse().unwrap()).collect(),
+            aggregates: aggregates.iter().map(|a| a.parse().unwrap()).collect(),
+        };
+
+        let rows = engine
+            .query_span_aggregate(query(
+                &["@service"],
+                &["count", "avg(#duration)", "p100(#duration)", "sum(@bytes)"],
+            ))
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            [
+                AggregateRow {
+                    group: vec![Some("a".to_owned())],
+                    values: vec![Some(2.0), Some(20.0), Some(30.0), Some(5.0)],
+                },
+                AggregateRow {
+                    group: vec![Some("b".to_owned())],
+                    values: vec![Some(1.0), Some(20.0), Some(20.0), Some(-2.0)],
+                },
+            ]
+        );
 
-        assert_eq!(events.len(), 1);
+        let rows = engine
+            .query_event_aggregate(query(&["#level", "@missing"], &["count", "min(@missing)"]))
+            .unwrap();
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": B").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        assert_eq!(
+            rows,
+            [
+                AggregateRow {
+                    group: vec![Some("ERROR".to_owned()), None],
+                    values: vec![Some(1.0), None],
+                },
+                AggregateRow {
+                    group: vec![Some("INFO".to_owned()), None],
+                    values: vec![Some(2.0), None],
+                },
+            ]
+        );
 
-        assert_eq!(events.len(), 0);
+        assert!(engine
+            .query_event_aggregate(query(&["#name"], &["count"]))
+            .is_err());
+        assert!(engine
+            .query_event_aggregate(query(&[], &["max(#duration)"]))
+            .is_err());
+
+        assert_eq!(
+            "p99.9(#duration)".parse::<Aggregate>().unwrap(),
+            Aggregate::Percentile(AggregateField::Duration, 99.9)
+        );
+        assert!("p101(#duration)".parse::<Aggregate>().is_err());
+        assert!("avg(#name)".parse::<Aggregate>().is_err());
+        assert!("service".parse::<GroupKey>().is_err());
     }
 
     #[test]
-    fn event_found_with_inherent_attribute() {
+    fn aggregate_percentiles_keep_a_bounded_sample() {
+        let mut values = AggregateValues::new();
+        for value in 1..=MAX_PERCENTILE_SAMPLES * 5 {
+            values.add(value as f64, true);
+        }
+
+        assert!(values.samples.len() <= MAX_PERCENTILE_SAMPLES);
+
+        let count = values.count;
+        let p50 = compute_aggregate(
+            &Aggregate::Percentile(AggregateField::Duration, 50.0),
+            count,
+            values.clone(),
+        )
+        .unwrap();
+        let p99 = compute_aggregate(
+            &Aggregate::Percentile(AggregateField::Duration, 99.0),
+            count,
+            values.clone(),
+        )
+        .unwrap();
+        let avg =
+            compute_aggregate(&Aggregate::Avg(AggregateField::Duration), count, values).unwrap();
+
+        let total = (MAX_PERCENTILE_SAMPLES * 5) as f64;
+        assert!((p50 - total * 0.50).abs() < total * 0.01);
+        assert!((p99 - total * 0.99).abs() < total * 0.01);
+        assert_eq!(avg, (total + 1.0) / 2.0);
+    }
+
+    #[test]
+    fn span_latency_percentiles_and_histogram() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
         let resource_key = engine
             .insert_resource(NewResource {
-                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
-            })
-            .unwrap();
-
-        let now = now();
-        engine
-            .insert_event(NewEvent {
-                kind: SourceKind::Tracing,
-                resource_key,
-                timestamp: now.saturating_add(1),
-                span_id: None,
-                content: Value::Str("event".to_owned()),
-                namespace: Some("crate::storage::tests".to_owned()),
-                function: Some("test".to_owned()),
-                level: Level::Error,
-                file_name: None,
-                file_line: None,
-                file_column: None,
-                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("B".to_owned()))]),
+                attributes: BTreeMap::new(),
             })
             .unwrap();
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        for (id, name, duration) in [
+            (1, "a", Some(1000)),
+            (2, "a", Some(2000)),
+            (3, "a", Some(10000)),
+            (4, "a", None),
+            (5, "b", Some(100000)),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: (1000 + id) * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Tracing,
+                        resource_key,
+                        parent_id: None,
+                        name: name.to_owned(),
+                        namespace: Some("crate::storage::tests".to_owned()),
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
 
-        assert_eq!(events.len(), 0);
+            if let Some(duration) = duration {
+                engine
+                    .insert_span_event(NewSpanEvent {
+                        timestamp: (1000 + id + duration) * 1000,
+                        span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                        kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+                    })
+                    .unwrap();
+            }
+        }
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": B").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        let stats = engine.query_span_latency(
+            vec![],
+            Timestamp::MIN,
+            Timestamp::MAX,
+            Some(LatencyGroup::Name),
+        );
 
-        assert_eq!(events.len(), 1);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].group.as_deref(), Some("a"));
+        assert_eq!(stats[0].count, 3);
+        assert_eq!(stats[0].p50, 2000);
+        assert_eq!(stats[0].p90, 10000);
+        assert_eq!(stats[0].p99, 10000);
+        assert_eq!(stats[0].max, 10000);
+        assert_eq!(stats[0].histogram.len(), 9);
+        assert_eq!(stats[0].histogram[0].count, 2);
+        assert_eq!(stats[0].histogram[1].count, 1);
+        assert_eq!(stats[1].group.as_deref(), Some("b"));
+        assert_eq!(stats[1].p50, 100000);
+        assert_eq!(stats[1].histogram[3].start, 64000);
+        assert_eq!(stats[1].histogram[3].count, 1);
+
+        let stats = engine.query_span_latency(vec![], Timestamp::MIN, Timestamp::MAX, None);
+
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].group, None);
+        assert_eq!(stats[0].count, 4);
+        assert_eq!(stats[0].max, 100000);
+
+        let filter = FilterPredicate::parse("#name: c").unwrap();
+        let stats = engine.query_span_latency(filter, Timestamp::MIN, Timestamp::MAX, None);
+
+        assert!(stats.is_empty());
     }
 
     #[test]
-    fn event_found_with_span_attribute() {
+    fn attribute_catalog_lists_names_and_values() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
         let resource_key = engine
             .insert_resource(NewResource {
-                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+                attributes: BTreeMap::new(),
             })
             .unwrap();
 
-        engine
-            .insert_span_event(NewSpanEvent {
-                timestamp: now(),
-                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
-                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+        for (method, status) in [
+            ("GET", Value::U64(200)),
+            ("GET", Value::Str("ok".to_owned())),
+            ("POST", Value::Bool(true)),
+            ("PUT", Value::U64(404)),
+        ] {
+            engine
+                .insert_event(NewEvent {
                     kind: SourceKind::Tracing,
                     resource_key,
-                    parent_id: None,
-                    name: "test".to_owned(),
+                    timestamp: now().get() * 1000,
+                    span_id: None,
+                    content: Value::Str("event".to_owned()),
                     namespace: Some("crate::storage::tests".to_owned()),
-                    function: None,
-                    level: Level::Error,
+                    function: Some("test".to_owned()),
+                    level: Level::Info,
                     file_name: None,
                     file_line: None,
                     file_column: None,
-                    instrumentation_attributes: BTreeMap::default(),
-                    attributes: BTreeMap::from_iter([(
-                        "attr1".to_owned(),
-                        Value::Str("C".to_owned()),
-                    )]),
-                }),
-            })
-            .unwrap();
+                    attributes: BTreeMap::from_iter([
+                        ("method".to_owned(), Value::Str(method.to_owned())),
+                        ("status".to_owned(), status),
+                    ]),
+                })
+                .unwrap();
+        }
 
-        let now = now();
-        engine
-            .insert_event(NewEvent {
-                kind: SourceKind::Tracing,
-                resource_key,
-                timestamp: now.saturating_add(1),
-                span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), 1)),
-                content: Value::Str("event".to_owned()),
-                namespace: Some("crate::storage::tests".to_owned()),
-                function: Some("test".to_owned()),
-                level: Level::Error,
-                file_name: None,
-                file_line: None,
-                file_column: None,
+        let names = engine.query_event_attribute_names(vec![], Timestamp::MIN, Timestamp::MAX);
+
+        assert_eq!(
+            names,
+            [
+                AttributeSummary {
+                    name: "method".to_owned(),
+                    count: 4,
+                    kinds: vec![ValueKind::Str],
+                },
+                AttributeSummary {
+                    name: "status".to_owned(),
+                    count: 4,
+                    kinds: vec![ValueKind::U64, ValueKind::Bool, ValueKind::Str],
+                },
+            ]
+        );
+
+        let filter = FilterPredicate::parse("@method: GET").unwrap();
+        let names = engine.query_event_attribute_names(filter, Timestamp::MIN, Timestamp::MAX);
+
+        assert_eq!(names[1].count, 2);
+        assert_eq!(names[1].kinds, [ValueKind::U64, ValueKind::Str]);
+
+        let filter = FilterPredicate::parse("@status: true").unwrap();
+        let names = engine.query_event_attribute_names(filter, Timestamp::MIN, Timestamp::MAX);
+
+        assert_eq!(names[1].count, 1);
+        assert_eq!(names[1].kinds, [ValueKind::Bool]);
+
+        let values = engine.query_event_attribute_values("method", "", 2);
+
+        assert_eq!(
+            values,
+            [
+                AttributeValueCount {
+                    value: "GET".to_owned(),
+                    count: 2,
+                },
+                AttributeValueCount {
+                    value: "POST".to_owned(),
+                    count: 1,
+                },
+            ]
+        );
+
+        let values = engine.query_event_attribute_values("method", "P", 10);
+
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].value, "POST");
+        assert_eq!(values[1].value, "PUT");
+
+        assert!(engine
+            .query_event_attribute_values("missing", "", 10)
+            .is_empty());
+        assert!(engine
+            .query_span_attribute_names(vec![], Timestamp::MIN, Timestamp::MAX)
+            .is_empty());
+    }
+
+    #[test]
+    fn span_events_are_queried_by_span_and_kind() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let resource_key = engine
+            .insert_resource(NewResource {
                 attributes: BTreeMap::new(),
             })
             .unwrap();
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        for (id, created_at) in [(1, 1000), (2, 1001)] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: created_at * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Tracing,
+                        resource_key,
+                        parent_id: None,
+                        name: "test".to_owned(),
+                        namespace: Some("crate::storage::tests".to_owned()),
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
+        }
 
-        assert_eq!(events.len(), 0);
+        for (id, timestamp, kind) in [
+            (
+                2,
+                1002,
+                NewSpanEventKind::Follows(NewFollowsSpanEvent { follows: 1 }),
+            ),
+            (
+                1,
+                1003,
+                NewSpanEventKind::Enter(NewEnterSpanEvent { thread_id: 7 }),
+            ),
+            (
+                1,
+                1004,
+                NewSpanEventKind::Update(NewUpdateSpanEvent {
+                    attributes: BTreeMap::from_iter([("a".to_owned(), Value::I64(1))]),
+                }),
+            ),
+            (1, 1005, NewSpanEventKind::Exit),
+            (
+                1,
+                1006,
+                NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+            ),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: timestamp * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind,
+                })
+                .unwrap();
+        }
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
+        let span_key = Timestamp::new(1000).unwrap();
+        let query = |span, kinds, order, previous| SpanEventQuery {
+            span,
+            kinds,
+            order,
+            limit: 2,
+            start: Timestamp::MIN,
+            end: Timestamp::MAX,
+            previous,
+        };
+
+        let span_events = engine.query_span_event(SpanEventQuery {
+            limit: usize::MAX,
+            ..query(Some(span_key), None, Order::Asc, None)
         });
+        let types = span_events
+            .iter()
+            .map(|e| e.kind.event_type())
+            .collect::<Vec<_>>();
 
-        assert_eq!(events.len(), 1);
+        assert_eq!(
+            types,
+            [
+                SpanEventType::Create,
+                SpanEventType::Enter,
+                SpanEventType::Update,
+                SpanEventType::Exit,
+                SpanEventType::Close,
+            ]
+        );
+
+        let span_events = engine.query_span_event(query(
+            Some(span_key),
+            Some(vec![SpanEventType::Enter]),
+            Order::Asc,
+            None,
+        ));
+
+        assert_eq!(span_events.len(), 1);
+        assert!(matches!(
+            span_events[0].kind,
+            SpanEventKind::Enter(EnterSpanEvent { thread_id: 7 })
+        ));
+
+        let span_events = engine.query_span_event(query(Some(span_key), None, Order::Desc, None));
+
+        assert_eq!(span_events.len(), 2);
+        assert_eq!(span_events[0].key.get(), 1006);
+        assert_eq!(span_events[1].key.get(), 1005);
+
+        let previous = Some(span_events[1].key);
+        let span_events =
+            engine.query_span_event(query(Some(span_key), None, Order::Desc, previous));
+
+        assert_eq!(span_events.len(), 2);
+        assert_eq!(span_events[0].key.get(), 1004);
+        assert_eq!(span_events[1].key.get(), 1003);
+
+        let span_events = engine.query_span_event(query(
+            None,
+            Some(vec![SpanEventType::Follows]),
+            Order::Asc,
+            None,
+        ));
+
+        assert_eq!(span_events.len(), 1);
+        assert_eq!(span_events[0].span_key.get(), 1001);
+        assert!(matches!(
+            span_events[0].kind,
+            SpanEventKind::Follows(FollowsSpanEvent { follows }) if follows == span_key
+        ));
+
+        assert!(engine
+            .query_span_event(query(
+                Some(Timestamp::new(1).unwrap()),
+                None,
+                Order::Asc,
+                None
+            ))
+            .is_empty());
     }
 
     #[test]
-    fn event_found_with_nonindexed_updated_span_attribute() {
+    fn trace_is_queried_as_a_tree() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
         let resource_key = engine
             .insert_resource(NewResource {
-                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+                attributes: BTreeMap::new(),
             })
             .unwrap();
 
-        engine
-            .insert_span_event(NewSpanEvent {
-                timestamp: now(),
-                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
-                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
-                    kind: SourceKind::Tracing,
+        for (id, parent, created_at) in [
+            (1, None, 1000),
+            (2, Some(1), 1001),
+            (3, Some(2), 1002),
+            (4, Some(1), 1003),
+            (5, Some(9), 1004),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: created_at * 1000,
+                    span_id: FullSpanId::Opentelemetry(1, id),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Opentelemetry,
+                        resource_key,
+                        parent_id: parent.map(|p| FullSpanId::Opentelemetry(1, p)),
+                        name: format!("span{id}"),
+                        namespace: None,
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
+        }
+
+        for (parent, timestamp) in [(3, 1005), (8, 1006)] {
+            engine
+                .insert_event(NewEvent {
+                    kind: SourceKind::Opentelemetry,
                     resource_key,
-                    parent_id: None,
-                    name: "test".to_owned(),
-                    namespace: Some("crate::storage::tests".to_owned()),
+                    timestamp: timestamp * 1000,
+                    span_id: Some(FullSpanId::Opentelemetry(1, parent)),
+                    content: Value::Str("event".to_owned()),
+                    namespace: None,
                     function: None,
-                    level: Level::Error,
+                    level: Level::Info,
                     file_name: None,
                     file_line: None,
                     file_column: None,
-                    instrumentation_attributes: BTreeMap::default(),
                     attributes: BTreeMap::new(),
-                }),
-            })
-            .unwrap();
-
-        let now = now();
-        engine
-            .insert_event(NewEvent {
-                kind: SourceKind::Tracing,
-                resource_key,
-                timestamp: now.saturating_add(1),
-                span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), 1)),
-                content: Value::Str("event".to_owned()),
-                namespace: Some("crate::storage::tests".to_owned()),
-                function: Some("test".to_owned()),
-                level: Level::Error,
-                file_name: None,
-                file_line: None,
-                file_column: None,
-                attributes: BTreeMap::new(),
-            })
-            .unwrap();
-
-        engine
-            .insert_span_event(NewSpanEvent {
-                timestamp: super::now(),
-                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
-                kind: NewSpanEventKind::Update(NewUpdateSpanEvent {
-                    attributes: BTreeMap::from_iter([(
-                        "attr1".to_owned(),
-                        Value::Str("C".to_owned()),
-                    )]),
-                }),
-            })
-            .unwrap();
+                })
+                .unwrap();
+        }
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        let trace = engine.query_trace(TraceRoot::Opentelemetry(1)).unwrap();
 
-        assert_eq!(events.len(), 0);
+        let spans = trace
+            .spans
+            .iter()
+            .map(|s| (s.span.name.as_str(), s.depth, s.orphaned))
+            .collect::<Vec<_>>();
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        assert_eq!(
+            spans,
+            [
+                ("span1", 0, false),
+                ("span2", 1, false),
+                ("span3", 2, false),
+                ("span4", 1, false),
+                ("span5", 0, true),
+            ]
+        );
+        assert_eq!(
+            trace.spans[2].parent_key,
+            Some(Timestamp::new(1001).unwrap())
+        );
+        assert_eq!(trace.spans[4].parent_key, None);
 
-        assert_eq!(events.len(), 1);
+        assert_eq!(trace.events.len(), 2);
+        assert_eq!(
+            trace.events[0].parent_key,
+            Some(Timestamp::new(1002).unwrap())
+        );
+        assert_eq!(trace.events[0].depth, 3);
+        assert!(!trace.events[0].orphaned);
+        assert_eq!(trace.events[1].parent_key, None);
+        assert_eq!(trace.events[1].depth, 0);
+        assert!(trace.events[1].orphaned);
+
+        assert!(engine.query_trace(TraceRoot::Opentelemetry(2)).is_none());
     }
 
     #[test]
-    fn event_found_with_indexed_updated_span_attribute() {
+    fn critical_path_follows_the_last_finishing_children() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
         let resource_key = engine
             .insert_resource(NewResource {
-                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+                attributes: BTreeMap::new(),
             })
             .unwrap();
 
-        engine
-            .insert_span_event(NewSpanEvent {
-                timestamp: now(),
-                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
-                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
-                    kind: SourceKind::Tracing,
-                    resource_key,
-                    parent_id: None,
-                    name: "test".to_owned(),
-                    namespace: Some("crate::storage::tests".to_owned()),
-                    function: None,
-                    level: Level::Error,
-                    file_name: None,
-                    file_line: None,
-                    file_column: None,
-                    instrumentation_attributes: BTreeMap::default(),
-                    attributes: BTreeMap::new(),
-                }),
-            })
-            .unwrap();
+        // span 7 claims to start before its parent but still joins the path
+        // after it, and span 8 is inserted after span 9
+        for (trace, id, parent, created_at, closed_at) in [
+            (1, 1, None, 1000, Some(1100)),
+            (1, 2, Some(1), 1010, Some(1050)),
+            (1, 3, Some(1), 1020, Some(1090)),
+            (1, 4, Some(3), 1030, Some(1060)),
+            (1, 5, Some(1), 1095, None),
+            (3, 6, None, 2000, Some(2100)),
+            (3, 7, Some(6), 1990, Some(2010)),
+            (3, 9, Some(6), 2060, Some(2100)),
+            (3, 8, Some(6), 2020, Some(2060)),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: created_at * 1000,
+                    span_id: FullSpanId::Opentelemetry(trace, id),
+                    kind: NewSpanEventKind::Create(NewCreateSpanEvent {
+                        kind: SourceKind::Opentelemetry,
+                        resource_key,
+                        parent_id: parent.map(|p| FullSpanId::Opentelemetry(trace, p)),
+                        name: format!("span{id}"),
+                        namespace: None,
+                        function: None,
+                        level: Level::Info,
+                        file_name: None,
+                        file_line: None,
+                        file_column: None,
+                        instrumentation_attributes: BTreeMap::default(),
+                        attributes: BTreeMap::new(),
+                    }),
+                })
+                .unwrap();
 
-        let now = now();
-        engine
-            .insert_event(NewEvent {
-                kind: SourceKind::Tracing,
-                resource_key,
-                timestamp: now.saturating_add(1),
-                span_id: Some(FullSpanId::Tracing(1.try_into().unwrap(), 1)),
-                content: Value::Str("event".to_owned()),
-                namespace: Some("crate::storage::tests".to_owned()),
-                function: Some("test".to_owned()),
-                level: Level::Error,
-                file_name: None,
-                file_line: None,
-                file_column: None,
-                attributes: BTreeMap::new(),
-            })
-            .unwrap();
+            if let Some(closed_at) = closed_at {
+                engine
+                    .insert_span_event(NewSpanEvent {
+                        timestamp: closed_at * 1000,
+                        span_id: FullSpanId::Opentelemetry(trace, id),
+                        kind: NewSpanEventKind::Close(NewCloseSpanEvent { busy: None }),
+                    })
+                    .unwrap();
+            }
+        }
 
-        engine
-            .insert_span_event(NewSpanEvent {
-                timestamp: super::now(),
-                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
-                kind: NewSpanEventKind::Update(NewUpdateSpanEvent {
-                    attributes: BTreeMap::from_iter([(
-                        "attr1".to_owned(),
-                        Value::Str("C".to_owned()),
-                    )]),
-                }),
-            })
+        let path = engine
+            .query_critical_path(TraceRoot::Opentelemetry(1))
             .unwrap();
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": A").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        let path = path
+            .iter()
+            .map(|s| (s.name.as_str(), s.self_time))
+            .collect::<Vec<_>>();
 
-        assert_eq!(events.len(), 0);
+        assert_eq!(
+            path,
+            [
+                ("span1", 15),
+                ("span2", 10),
+                ("span3", 40),
+                ("span4", 30),
+                ("span5", 5),
+            ]
+        );
 
-        let events = engine.query_event(Query {
-            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        assert!(engine
+            .query_critical_path(TraceRoot::Opentelemetry(2))
+            .is_none());
 
-        assert_eq!(events.len(), 1);
+        let path = engine
+            .query_critical_path(TraceRoot::Opentelemetry(3))
+            .unwrap();
+
+        let names = path.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["span6", "span7", "span8", "span9"]);
     }
 
     #[test]
-    fn empty_filter_returns_all_event_counts() {
+    fn spans_filtered_by_self_and_idle_time() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
         let resource_key = engine
             .insert_resource(NewResource {
-                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+                attributes: BTreeMap::new(),
             })
             .unwrap();
 
-        let now = now();
-        engine
-            .insert_event(NewEvent {
+        let create = |id: u64, parent: Option<u64>| {
+            NewSpanEventKind::Create(NewCreateSpanEvent {
                 kind: SourceKind::Tracing,
                 resource_key,
-                timestamp: now.saturating_add(1),
-                span_id: None,
-                content: Value::Str("event".to_owned()),
+                parent_id: parent.map(|p| FullSpanId::Tracing(1.try_into().unwrap(), p)),
+                name: format!("span{id}"),
                 namespace: None,
                 function: None,
-                level: Level::Error,
+                level: Level::Info,
                 file_name: None,
                 file_line: None,
                 file_column: None,
+                instrumentation_attributes: BTreeMap::default(),
                 attributes: BTreeMap::new(),
             })
-            .unwrap();
+        };
+        let close = || NewSpanEventKind::Close(NewCloseSpanEvent { busy: None });
+
+        // span 5 is inserted before its parent to check that it is adopted
+        for (id, timestamp, kind) in [
+            (1, 1000, create(1, None)),
+            (
+                1,
+                1000,
+                NewSpanEventKind::Enter(NewEnterSpanEvent { thread_id: 1 }),
+            ),
+            (2, 1010, create(2, Some(1))),
+            (1, 1020, NewSpanEventKind::Exit),
+            (3, 1030, create(3, Some(1))),
+            (2, 1050, close()),
+            (3, 1060, close()),
+            (1, 1100, close()),
+            (5, 1210, create(5, Some(6))),
+            (6, 1200, create(6, None)),
+            (5, 1290, close()),
+            (6, 1300, close()),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: timestamp * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind,
+                })
+                .unwrap();
+        }
 
-        let query = Query {
-            filter: FilterPredicate::parse("").unwrap(),
-            order: Order::Asc,
-            limit: 0,
-            start: Timestamp::MIN,
-            end: Timestamp::MAX,
-            previous: None,
+        let query_names = |filter: &str| {
+            engine
+                .query_span(Query {
+                    filter: FilterPredicate::parse(filter).unwrap(),
+                    order: Order::Asc,
+                    limit: 10,
+                    start: Timestamp::MIN,
+                    end: Timestamp::MAX,
+                    previous: None,
+                })
+                .into_iter()
+                .map(|span| span.name)
+                .collect::<Vec<_>>()
         };
 
-        assert_eq!(engine.query_event_count(query.clone()), 1)
+        assert_eq!(query_names("#self: >45us"), ["span1", "span5"]);
+        assert_eq!(query_names("#self: <35"), ["span3", "span6"]);
+        assert_eq!(query_names("#self: 20"), ["span6"]);
+        assert_eq!(query_names("#idle: >=80us"), ["span1"]);
+        assert_eq!(query_names("#idle: <1s"), ["span1"]);
+
+        let mut predicates = FilterPredicate::parse("#self: >1s #idle: fast").unwrap();
+        assert!(validate_span_filter(predicates.pop().unwrap()).is_err());
+        assert!(validate_span_filter(predicates.pop().unwrap()).is_ok());
     }
 
     #[test]
-    fn span_found_with_updated_attribute() {
+    fn span_subscription_matches_self_time_of_adopted_spans() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
         let resource_key = engine
             .insert_resource(NewResource {
-                attributes: BTreeMap::from_iter([("attr1".to_owned(), Value::Str("A".to_owned()))]),
+                attributes: BTreeMap::new(),
             })
             .unwrap();
 
-        engine
-            .insert_span_event(NewSpanEvent {
-                timestamp: now(),
-                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
-                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
-                    kind: SourceKind::Tracing,
-                    resource_key,
-                    parent_id: None,
-                    name: "test".to_owned(),
-                    namespace: Some("crate::storage::tests".to_owned()),
-                    function: None,
-                    level: Level::Error,
-                    file_name: None,
-                    file_line: None,
-                    file_column: None,
-                    instrumentation_attributes: BTreeMap::default(),
-                    attributes: BTreeMap::new(),
-                }),
-            })
+        let (_, mut receiver) = engine
+            .subscribe_to_spans(FilterPredicate::parse("#self: >45us").unwrap())
             .unwrap();
 
-        let now = now();
-        engine
-            .insert_span_event(NewSpanEvent {
-                timestamp: now,
-                span_id: FullSpanId::Tracing(1.try_into().unwrap(), 1),
-                kind: NewSpanEventKind::Update(NewUpdateSpanEvent {
-                    attributes: BTreeMap::from_iter([(
-                        "attr1".to_owned(),
-                        Value::Str("C".to_owned()),
-                    )]),
-                }),
+        let create = |id: u64, parent: Option<u64>| {
+            NewSpanEventKind::Create(NewCreateSpanEvent {
+                kind: SourceKind::Opentelemetry,
+                resource_key,
+                parent_id: parent.map(|p| FullSpanId::Opentelemetry(1, p)),
+                name: format!("span{id}"),
+                namespace: None,
+                function: None,
+                level: Level::Info,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                instrumentation_attributes: BTreeMap::default(),
+                attributes: BTreeMap::new(),
             })
-            .unwrap();
+        };
+        let close = || NewSpanEventKind::Close(NewCloseSpanEvent { busy: None });
+
+        // span 2 is closed before its parent arrives, so it is only checked
+        // against the subscription once it is adopted
+        for (id, timestamp, kind) in [
+            (2, 1010, create(2, Some(1))),
+            (2, 1090, close()),
+            (1, 1000, create(1, None)),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: timestamp * 1000,
+                    span_id: FullSpanId::Opentelemetry(1, id),
+                    kind,
+                })
+                .unwrap();
+        }
 
-        let spans = engine.query_span(Query {
-            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now,
-            end: now.saturating_add(2),
-            previous: None,
-        });
+        let mut added = Vec::new();
+        while let Ok(response) = receiver.try_recv() {
+            if let SubscriptionResponse::Add(span) = response {
+                added.push(span.name);
+            }
+        }
 
-        assert_eq!(spans.len(), 1);
+        assert_eq!(added, ["span2"]);
     }
 
     #[test]
-    fn span_found_with_post_parent_attribute() {
+    fn flame_graph_merges_matching_traces() {
         let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
 
         let resource_key = engine
             .insert_resource(NewResource {
-                attributes: BTreeMap::from_iter([]),
+                attributes: BTreeMap::new(),
             })
             .unwrap();
 
-        engine
-            .insert_span_event(NewSpanEvent {
-                timestamp: 1001.try_into().unwrap(),
-                span_id: FullSpanId::Opentelemetry(1, 2),
-                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
-                    kind: SourceKind::Opentelemetry,
-                    resource_key,
-                    parent_id: Some(FullSpanId::Opentelemetry(1, 1)),
-                    name: "test".to_owned(),
-                    namespace: Some("crate::storage::tests".to_owned()),
-                    function: None,
-                    level: Level::Error,
-                    file_name: None,
-                    file_line: None,
-                    file_column: None,
-                    instrumentation_attributes: BTreeMap::default(),
-                    attributes: BTreeMap::new(),
-                }),
+        let create = |name: &str, parent: Option<u64>| {
+            NewSpanEventKind::Create(NewCreateSpanEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                parent_id: parent.map(|p| FullSpanId::Tracing(1.try_into().unwrap(), p)),
+                name: name.to_owned(),
+                namespace: None,
+                function: None,
+                level: Level::Info,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                instrumentation_attributes: BTreeMap::default(),
+                attributes: BTreeMap::new(),
             })
-            .unwrap();
+        };
+        let close = || NewSpanEventKind::Close(NewCloseSpanEvent { busy: None });
+
+        // span 7 is never closed and should not be counted
+        for (id, timestamp, kind) in [
+            (1, 1000, create("request", None)),
+            (2, 1010, create("db", Some(1))),
+            (2, 1050, close()),
+            (3, 1060, create("render", Some(1))),
+            (4, 1070, create("db", Some(3))),
+            (4, 1080, close()),
+            (3, 1090, close()),
+            (1, 1100, close()),
+            (5, 1200, create("request", None)),
+            (6, 1210, create("db", Some(5))),
+            (6, 1250, close()),
+            (5, 1300, close()),
+            (7, 1400, create("request", None)),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: timestamp * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind,
+                })
+                .unwrap();
+        }
 
-        engine
-            .insert_span_event(NewSpanEvent {
-                timestamp: 1000.try_into().unwrap(),
-                span_id: FullSpanId::Opentelemetry(1, 1),
-                kind: NewSpanEventKind::Create(NewCreateSpanEvent {
-                    kind: SourceKind::Opentelemetry,
-                    resource_key,
-                    parent_id: None,
-                    name: "test".to_owned(),
-                    namespace: Some("crate::storage::tests".to_owned()),
-                    function: None,
-                    level: Level::Error,
-                    file_name: None,
-                    file_line: None,
-                    file_column: None,
-                    instrumentation_attributes: BTreeMap::default(),
+        let flame_graph = engine.query_flame_graph(
+            FilterPredicate::parse("#name: request").unwrap(),
+            Timestamp::MIN,
+            Timestamp::MAX,
+        );
+
+        assert_eq!(flame_graph.roots.len(), 1);
+        let request = &flame_graph.roots[0];
+        assert_eq!(request.name, "request");
+        assert_eq!(request.count, 2);
+        assert_eq!(request.total_time, 200);
+        assert_eq!(request.self_time, 90);
+
+        let names = request.children.iter().map(|n| &n.name).collect::<Vec<_>>();
+        assert_eq!(names, ["db", "render"]);
+        assert_eq!(request.children[0].count, 2);
+        assert_eq!(request.children[0].total_time, 80);
+        assert_eq!(request.children[1].self_time, 20);
+
+        assert_eq!(
+            flame_graph.to_folded(),
+            "request 90\nrequest;db 80\nrequest;render 20\nrequest;render;db 10\n"
+        );
+
+        // "render" spans are already included by their matching "request" root
+        let overlapping = engine.query_flame_graph(
+            FilterPredicate::parse("#name: re*").unwrap(),
+            Timestamp::MIN,
+            Timestamp::MAX,
+        );
+        assert_eq!(overlapping, flame_graph);
+    }
+
+    #[test]
+    fn service_dependencies_are_found_across_resources() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        let mut insert_service = |name: &str| {
+            engine
+                .insert_resource(NewResource {
                     attributes: BTreeMap::from_iter([(
-                        "attr1".to_owned(),
-                        Value::Str("C".to_owned()),
+                        "service".to_owned(),
+                        Value::Str(name.to_owned()),
                     )]),
-                }),
+                })
+                .unwrap()
+        };
+
+        let gateway = insert_service("gateway");
+        let users = insert_service("users");
+        let billing = insert_service("billing");
+
+        let create = |resource_key: ResourceKey, parent: Option<u64>, level: Level| {
+            NewSpanEventKind::Create(NewCreateSpanEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                parent_id: parent.map(|p| FullSpanId::Tracing(1.try_into().unwrap(), p)),
+                name: "handle".to_owned(),
+                namespace: None,
+                function: None,
+                level,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                instrumentation_attributes: BTreeMap::default(),
+                attributes: BTreeMap::new(),
             })
-            .unwrap();
+        };
+        let close = || NewSpanEventKind::Close(NewCloseSpanEvent { busy: None });
+
+        // span 3 stays within the "users" service and span 5 is never closed
+        for (id, timestamp, kind) in [
+            (1, 1000, create(gateway, None, Level::Info)),
+            (2, 1010, create(users, Some(1), Level::Info)),
+            (3, 1020, create(users, Some(2), Level::Info)),
+            (3, 1030, close()),
+            (2, 1050, close()),
+            (4, 1060, create(users, Some(1), Level::Error)),
+            (4, 1080, close()),
+            (5, 1090, create(billing, Some(1), Level::Info)),
+            (1, 1100, close()),
+        ] {
+            engine
+                .insert_span_event(NewSpanEvent {
+                    timestamp: timestamp * 1000,
+                    span_id: FullSpanId::Tracing(1.try_into().unwrap(), id),
+                    kind,
+                })
+                .unwrap();
+        }
 
-        let spans = engine.query_span(Query {
-            filter: FilterPredicate::parse("@\"attr1\": C").unwrap(),
-            order: Order::Asc,
-            limit: 5,
-            start: now(),
-            end: now().saturating_add(2),
-            previous: None,
-        });
+        let dependencies =
+            engine.query_service_dependencies(vec![], Timestamp::MIN, Timestamp::MAX);
 
-        assert_eq!(spans.len(), 2);
+        assert_eq!(dependencies.len(), 2);
+
+        assert_eq!(dependencies[0].caller, gateway);
+        assert_eq!(dependencies[0].callee, users);
+        assert_eq!(dependencies[0].count, 2);
+        assert_eq!(dependencies[0].errors, 1);
+        let latency = dependencies[0].latency.as_ref().unwrap();
+        assert_eq!(latency.count, 2);
+        assert_eq!(latency.max, 40);
+
+        assert_eq!(dependencies[1].caller, gateway);
+        assert_eq!(dependencies[1].callee, billing);
+        assert_eq!(dependencies[1].count, 1);
+        assert_eq!(dependencies[1].errors, 0);
+        assert_eq!(dependencies[1].latency, None);
+    }
+
+    #[test]
+    fn inserted_resources_get_unique_keys() {
+        let mut engine = SyncEngine::new(TransientStorage::new()).unwrap();
+
+        // the keys are all derived from the same "now" in tests, so they must
+        // be made unique against the existing ones regardless of map order
+        let mut resource_keys = (0..10)
+            .map(|i| {
+                engine
+                    .insert_resource(NewResource {
+                        attributes: BTreeMap::from_iter([(
+                            "service".to_owned(),
+                            Value::Str(format!("service{i}")),
+                        )]),
+                    })
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        resource_keys.sort();
+        resource_keys.dedup();
+        assert_eq!(resource_keys.len(), 10);
     }
 }
@@ -6,9 +6,9 @@ use wildcard::WildcardBuilder;
 
 use crate::context::{EventContext, SpanContext};
 use crate::engine::SyncEngine;
-use crate::index::EventIndexes;
+use crate::index::{EventIndexes, IndexEntry};
 use crate::models::{
-    EventKey, FullSpanId, Level, SimpleLevel, SourceKind, SpanKey, Timestamp, TraceRoot,
+    to_micros, EventKey, FullSpanId, Level, SimpleLevel, SourceKind, SpanKey, Timestamp, TraceRoot,
     ValueOperator,
 };
 use crate::storage::Storage;
@@ -16,13 +16,13 @@ use crate::storage::Storage;
 use super::input::{FilterPredicate, FilterPredicateSingle, FilterPropertyKind, ValuePredicate};
 use super::value::{ValueFilter, ValueStringComparison};
 use super::{
-    merge, validate_value_predicate, BoundSearch, FallibleFilterPredicate, FileFilter, InputError,
-    Order, Query,
+    merge, timestamp_range, validate_value_predicate, BoundSearch, FallibleFilterPredicate,
+    FileFilter, InputError, Order, Query,
 };
 
 pub(crate) enum IndexedEventFilter<'i> {
-    Single(&'i [Timestamp], Option<NonIndexedEventFilter>),
-    Not(&'i [Timestamp], Box<IndexedEventFilter<'i>>),
+    Single(&'i [IndexEntry], Option<NonIndexedEventFilter>),
+    Not(&'i [IndexEntry], Box<IndexedEventFilter<'i>>),
     And(Vec<IndexedEventFilter<'i>>),
     Or(Vec<IndexedEventFilter<'i>>),
 }
@@ -39,29 +39,11 @@ impl IndexedEventFilter<'_> {
 
         match filter {
             BasicEventFilter::All => IndexedEventFilter::Single(&event_indexes.all, None),
-            BasicEventFilter::Timestamp(op, value) => match op {
-                ValueOperator::Gt => {
-                    let idx = event_indexes.all.upper_bound(&value);
-                    IndexedEventFilter::Single(&event_indexes.all[idx..], None)
-                }
-                ValueOperator::Gte => {
-                    let idx = event_indexes.all.lower_bound(&value);
-                    IndexedEventFilter::Single(&event_indexes.all[idx..], None)
-                }
-                ValueOperator::Eq => {
-                    let start = event_indexes.all.lower_bound(&value);
-                    let end = event_indexes.all.upper_bound(&value);
-                    IndexedEventFilter::Single(&event_indexes.all[start..end], None)
-                }
-                ValueOperator::Lte => {
-                    let idx = event_indexes.all.upper_bound(&value);
-                    IndexedEventFilter::Single(&event_indexes.all[..idx], None)
-                }
-                ValueOperator::Lt => {
-                    let idx = event_indexes.all.lower_bound(&value);
-                    IndexedEventFilter::Single(&event_indexes.all[..idx], None)
-                }
-            },
+            BasicEventFilter::Timestamp(op, value) => {
+                let range = timestamp_range(&event_indexes.all, op, value);
+
+                IndexedEventFilter::Single(&event_indexes.all[range], None)
+            }
             BasicEventFilter::Level(level) => {
                 IndexedEventFilter::Single(&event_indexes.levels[level as usize], None)
             }
@@ -157,6 +139,7 @@ impl IndexedEventFilter<'_> {
                 ),
             },
             BasicEventFilter::Root => IndexedEventFilter::Single(&event_indexes.roots, None),
+            BasicEventFilter::Pinned => IndexedEventFilter::Single(&event_indexes.pinned, None),
             BasicEventFilter::Trace(trace) => {
                 let index = event_indexes
                     .traces
@@ -241,10 +224,10 @@ impl IndexedEventFilter<'_> {
     pub fn search<S: Storage>(
         &mut self,
         storage: &S,
-        mut entry: Timestamp,
+        mut entry: IndexEntry,
         order: Order,
-        bound: Timestamp,
-    ) -> Option<Timestamp> {
+        bound: IndexEntry,
+    ) -> Option<IndexEntry> {
         match self {
             IndexedEventFilter::Single(entries, filter) => match order {
                 Order::Asc => loop {
@@ -258,10 +241,10 @@ impl IndexedEventFilter<'_> {
                     }
 
                     if let Some(filter) = filter {
-                        if filter.matches(EventContext::new(found_entry, storage)) {
+                        if filter.matches(EventContext::new(found_entry.key, storage)) {
                             return Some(found_entry);
                         } else {
-                            entry = found_entry.saturating_add(1);
+                            entry = found_entry.next();
                         }
                     } else {
                         return Some(found_entry);
@@ -278,10 +261,10 @@ impl IndexedEventFilter<'_> {
                     }
 
                     if let Some(filter) = filter {
-                        if filter.matches(EventContext::new(found_entry, storage)) {
+                        if filter.matches(EventContext::new(found_entry.key, storage)) {
                             return Some(found_entry);
                         } else {
-                            entry = Timestamp::new(found_entry.get() - 1).unwrap();
+                            entry = found_entry.prev();
                         }
                     } else {
                         return Some(found_entry);
@@ -304,7 +287,7 @@ impl IndexedEventFilter<'_> {
                     if nested_entry != Some(found_entry) {
                         return Some(found_entry);
                     } else {
-                        entry = found_entry.saturating_add(1);
+                        entry = found_entry.next();
                     }
                 },
                 Order::Desc => loop {
@@ -322,7 +305,7 @@ impl IndexedEventFilter<'_> {
                     if nested_entry != Some(found_entry) {
                         return Some(found_entry);
                     } else {
-                        entry = Timestamp::new(found_entry.get() - 1).unwrap();
+                        entry = found_entry.prev();
                     }
                 },
             },
@@ -340,10 +323,8 @@ impl IndexedEventFilter<'_> {
                             Some(_) => { /* continue */ }
                             None => {
                                 match order {
-                                    Order::Asc => current = current.saturating_add(1),
-                                    Order::Desc => {
-                                        current = Timestamp::new(current.get() - 1).unwrap()
-                                    }
+                                    Order::Asc => current = current.next(),
+                                    Order::Desc => current = current.prev(),
                                 }
                                 continue 'outer;
                             }
@@ -452,7 +433,7 @@ impl IndexedEventFilter<'_> {
         }
     }
 
-    pub fn trim_to_timeframe(&mut self, start: Timestamp, end: Timestamp) {
+    pub fn trim_to_timeframe(&mut self, start: IndexEntry, end: IndexEntry) {
         match self {
             IndexedEventFilter::Single(index, _) => {
                 let start_idx = index.lower_bound(&start);
@@ -497,6 +478,7 @@ pub(crate) enum BasicEventFilter {
     File(FileFilter),
     Root,
     Trace(TraceRoot),
+    Pinned,
     Parent(SpanKey),
     Content(ValueFilter),
     Attribute(String, ValueFilter),
@@ -517,6 +499,7 @@ impl BasicEventFilter {
             BasicEventFilter::File(_) => {}
             BasicEventFilter::Root => {}
             BasicEventFilter::Trace(_) => {}
+            BasicEventFilter::Pinned => {}
             BasicEventFilter::Parent(_) => {}
             BasicEventFilter::Content(_) => {}
             BasicEventFilter::Attribute(_, _) => {}
@@ -712,6 +695,24 @@ impl BasicEventFilter {
                     |_| Err(InputError::InvalidTraceValue),
                 )?;
             }
+            (Inherent, "pinned") => {
+                validate_value_predicate(
+                    &predicate.value,
+                    |op, value| {
+                        if *op != ValueOperator::Eq {
+                            return Err(InputError::InvalidPinnedOperator);
+                        }
+
+                        if value != "true" && value != "false" {
+                            return Err(InputError::InvalidPinnedValue);
+                        }
+
+                        Ok(())
+                    },
+                    |_| Err(InputError::InvalidPinnedValue),
+                    |_| Err(InputError::InvalidPinnedValue),
+                )?;
+            }
             (Inherent, "content") => {
                 validate_value_predicate(
                     &predicate.value,
@@ -1008,6 +1009,22 @@ impl BasicEventFilter {
                 |_| Err(InputError::InvalidTraceValue),
                 |_| Err(InputError::InvalidTraceValue),
             )?,
+            (Inherent, "pinned") => filterify_event_filter(
+                predicate.value,
+                |op, value| {
+                    if op != ValueOperator::Eq {
+                        return Err(InputError::InvalidPinnedOperator);
+                    }
+
+                    match value.as_str() {
+                        "true" => Ok(BasicEventFilter::Pinned),
+                        "false" => Ok(BasicEventFilter::Not(Box::new(BasicEventFilter::Pinned))),
+                        _ => Err(InputError::InvalidPinnedValue),
+                    }
+                },
+                |_| Err(InputError::InvalidPinnedValue),
+                |_| Err(InputError::InvalidPinnedValue),
+            )?,
             (Inherent, "content") => filterify_event_filter(
                 predicate.value,
                 |op, value| {
@@ -1050,7 +1067,9 @@ impl BasicEventFilter {
         let event = context.event();
         match self {
             BasicEventFilter::All => true,
-            BasicEventFilter::Timestamp(op, timestamp) => op.compare(&event.timestamp, timestamp),
+            BasicEventFilter::Timestamp(op, timestamp) => {
+                op.compare(to_micros(event.timestamp), *timestamp)
+            }
             BasicEventFilter::Level(level) => event.level.into_simple_level() == *level,
             BasicEventFilter::Kind(kind) => kind == &event.kind,
             BasicEventFilter::Namespace(filter) => filter.matches_opt(event.namespace.as_deref()),
@@ -1060,6 +1079,7 @@ impl BasicEventFilter {
             }
             BasicEventFilter::Root => event.parent_key.is_none(),
             BasicEventFilter::Trace(trace) => context.trace_root() == Some(*trace),
+            BasicEventFilter::Pinned => false, // new events cannot be pinned yet
             BasicEventFilter::Parent(parent_key) => event.parent_key == Some(*parent_key),
             BasicEventFilter::Content(value_filter) => value_filter.matches(&event.content),
             BasicEventFilter::Attribute(attribute, value_filter) => context
@@ -1110,8 +1130,8 @@ impl NonIndexedEventFilter {
 pub(crate) struct IndexedEventFilterIterator<'i, S> {
     filter: IndexedEventFilter<'i>,
     order: Order,
-    start_key: Timestamp,
-    end_key: Timestamp,
+    start_key: IndexEntry,
+    end_key: IndexEntry,
     storage: &'i S,
 }
 
@@ -1129,13 +1149,14 @@ impl<'i, S: Storage> IndexedEventFilterIterator<'i, S> {
         let mut filter =
             IndexedEventFilter::build(Some(filter), &engine.event_indexes, &engine.storage);
 
-        let mut start = query.start;
-        let mut end = query.end;
+        let mut start = IndexEntry::first_at(query.start);
+        let mut end = IndexEntry::last_at(query.end);
 
         if let Some(prev) = query.previous {
+            let prev = previous_entry(&engine.storage, prev);
             match query.order {
-                Order::Asc => start = prev.saturating_add(1),
-                Order::Desc => end = Timestamp::new(prev.get() - 1).unwrap(),
+                Order::Asc => start = prev.next(),
+                Order::Desc => end = prev.prev(),
             }
         }
 
@@ -1163,13 +1184,30 @@ impl<'i, S: Storage> IndexedEventFilterIterator<'i, S> {
         IndexedEventFilterIterator {
             filter,
             order: Order::Asc,
-            end_key: Timestamp::MAX,
-            start_key: Timestamp::MIN,
+            end_key: IndexEntry::new(u64::MAX, Timestamp::MAX),
+            start_key: IndexEntry::new(0, Timestamp::MIN),
             storage: &engine.storage,
         }
     }
 }
 
+impl<S: Storage> IndexedEventFilterIterator<'_, S> {
+    /// Gets the index entry of the next matching event, which unlike `next`
+    /// also has when the event happened.
+    pub fn next_entry(&mut self) -> Option<IndexEntry> {
+        let entry = self
+            .filter
+            .search(self.storage, self.start_key, self.order, self.end_key)?;
+
+        match self.order {
+            Order::Asc => self.start_key = entry.next(),
+            Order::Desc => self.start_key = entry.prev(),
+        };
+
+        Some(entry)
+    }
+}
+
 impl<S> Iterator for IndexedEventFilterIterator<'_, S>
 where
     S: Storage,
@@ -1177,16 +1215,7 @@ where
     type Item = EventKey;
 
     fn next(&mut self) -> Option<EventKey> {
-        let event_key =
-            self.filter
-                .search(self.storage, self.start_key, self.order, self.end_key)?;
-
-        match self.order {
-            Order::Asc => self.start_key = event_key.saturating_add(1),
-            Order::Desc => self.start_key = Timestamp::new(event_key.get() - 1).unwrap(),
-        };
-
-        Some(event_key)
+        self.next_entry().map(|entry| entry.key)
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -1194,6 +1223,16 @@ where
     }
 }
 
+// The indexes are ordered by time, so continuing after the previous event needs
+// its timestamp and not just its key. If it can't be loaded, its key is used in
+// its place since keys are made from the timestamps.
+fn previous_entry<S: Storage>(storage: &S, key: EventKey) -> IndexEntry {
+    match storage.get_event(key) {
+        Ok(event) => IndexEntry::new(event.timestamp, key),
+        Err(_) => IndexEntry::new(key.get().saturating_mul(1000), key),
+    }
+}
+
 fn filterify_event_filter(
     value: ValuePredicate,
     comparison_filterifier: impl Fn(ValueOperator, String) -> Result<BasicEventFilter, InputError>
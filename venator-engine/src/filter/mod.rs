@@ -12,7 +12,7 @@ use std::fmt::{Display, Error as FmtError, Formatter};
 
 use serde::Deserialize;
 
-use crate::models::{Timestamp, ValueOperator};
+use crate::models::{SpanEventType, SpanKey, Timestamp, ValueOperator};
 
 mod event_filter;
 mod input;
@@ -45,6 +45,20 @@ pub struct Query {
     pub previous: Option<Timestamp>,
 }
 
+/// A query for span events. Unlike [`Query`], span events are not filtered by
+/// predicates but only by the span they belong to and their kind.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpanEventQuery {
+    pub span: Option<SpanKey>,
+    pub kinds: Option<Vec<SpanEventType>>,
+    pub order: Order,
+    pub limit: usize,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    // when paginating, this is the last key of the previous call
+    pub previous: Option<Timestamp>,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum InputError {
     InvalidLevelValue,
@@ -62,12 +76,16 @@ pub enum InputError {
     InvalidParentOperator,
     InvalidTraceValue,
     InvalidTraceOperator,
+    InvalidPinnedValue,
+    InvalidPinnedOperator,
     InvalidConnectedValue,
     InvalidDisconnectedValue,
     InvalidWildcardValue,
     InvalidRegexValue,
     InvalidFileOperator,
     InvalidFileValue,
+    InvalidGroupKey,
+    InvalidAggregate,
 }
 
 impl Display for InputError {
@@ -88,12 +106,16 @@ impl Display for InputError {
             InputError::InvalidParentOperator => write!(f, "invalid #parent operator"),
             InputError::InvalidTraceValue => write!(f, "invalid #trace value"),
             InputError::InvalidTraceOperator => write!(f, "invalid #trace operator"),
+            InputError::InvalidPinnedValue => write!(f, "invalid #pinned value"),
+            InputError::InvalidPinnedOperator => write!(f, "invalid #pinned operator"),
             InputError::InvalidConnectedValue => write!(f, "invalid #connected value"),
             InputError::InvalidDisconnectedValue => write!(f, "invalid #disconnected value"),
             InputError::InvalidWildcardValue => write!(f, "invalid wildcard syntax"),
             InputError::InvalidRegexValue => write!(f, "invalid regex syntax"),
             InputError::InvalidFileOperator => write!(f, "invalid #file operator"),
             InputError::InvalidFileValue => write!(f, "invalid #file value"),
+            InputError::InvalidGroupKey => write!(f, "invalid group key"),
+            InputError::InvalidAggregate => write!(f, "invalid aggregate"),
         }
     }
 }
@@ -7,23 +7,28 @@ use wildcard::WildcardBuilder;
 
 use crate::context::SpanContext;
 use crate::engine::SyncEngine;
-use crate::index::{SpanDurationIndex, SpanIndexes};
+use crate::index::{IndexEntry, SpanDurationIndex, SpanIndexes};
 use crate::models::{
-    FullSpanId, Level, SimpleLevel, SourceKind, SpanKey, Timestamp, TraceRoot, ValueOperator,
+    to_micros, FullSpanId, Level, SimpleLevel, SourceKind, SpanKey, Timestamp, TraceRoot,
+    ValueOperator,
 };
 use crate::storage::Storage;
 
 use super::input::{FilterPredicate, FilterPredicateSingle, FilterPropertyKind, ValuePredicate};
 use super::value::{ValueFilter, ValueStringComparison};
 use super::{
-    validate_value_predicate, BoundSearch, FallibleFilterPredicate, FileFilter, InputError, Order,
-    Query,
+    timestamp_range, validate_value_predicate, BoundSearch, FallibleFilterPredicate, FileFilter,
+    InputError, Order, Query,
 };
 
 pub(crate) enum IndexedSpanFilter<'i> {
-    Single(&'i [Timestamp], Option<NonIndexedSpanFilter>),
-    Stratified(&'i [Timestamp], Range<u64>, Option<NonIndexedSpanFilter>),
-    Not(&'i [Timestamp], Box<IndexedSpanFilter<'i>>),
+    Single(&'i [IndexEntry], Option<NonIndexedSpanFilter<'i>>),
+    Stratified(
+        &'i [IndexEntry],
+        Range<u64>,
+        Option<NonIndexedSpanFilter<'i>>,
+    ),
+    Not(&'i [IndexEntry], Box<IndexedSpanFilter<'i>>),
     And(Vec<IndexedSpanFilter<'i>>),
     Or(Vec<IndexedSpanFilter<'i>>),
 }
@@ -61,29 +66,50 @@ impl IndexedSpanFilter<'_> {
 
                 IndexedSpanFilter::Or(filters)
             }
-            BasicSpanFilter::Created(op, value) => match op {
-                ValueOperator::Gt => {
-                    let idx = span_indexes.all.upper_bound(&value);
-                    IndexedSpanFilter::Single(&span_indexes.all[idx..], None)
-                }
-                ValueOperator::Gte => {
-                    let idx = span_indexes.all.lower_bound(&value);
-                    IndexedSpanFilter::Single(&span_indexes.all[idx..], None)
-                }
-                ValueOperator::Eq => {
-                    let start = span_indexes.all.lower_bound(&value);
-                    let end = span_indexes.all.upper_bound(&value);
-                    IndexedSpanFilter::Single(&span_indexes.all[start..end], None)
-                }
-                ValueOperator::Lte => {
-                    let idx = span_indexes.all.upper_bound(&value);
-                    IndexedSpanFilter::Single(&span_indexes.all[..idx], None)
-                }
-                ValueOperator::Lt => {
-                    let idx = span_indexes.all.lower_bound(&value);
-                    IndexedSpanFilter::Single(&span_indexes.all[..idx], None)
-                }
-            },
+            BasicSpanFilter::SelfTime(self_filter) => {
+                let mut filters = span_indexes.durations.to_stratified_indexes();
+                filters.pop(); // the last range is for open spans
+
+                let filters = filters
+                    .into_iter()
+                    .filter(|(_, range)| self_filter.may_match_portion_of_range(range))
+                    .map(|(index, range)| {
+                        IndexedSpanFilter::Stratified(
+                            index,
+                            range,
+                            Some(NonIndexedSpanFilter::SelfTime(
+                                self_filter.clone(),
+                                &span_indexes.children,
+                            )),
+                        )
+                    })
+                    .collect();
+
+                IndexedSpanFilter::Or(filters)
+            }
+            BasicSpanFilter::Idle(idle_filter) => {
+                let mut filters = span_indexes.durations.to_stratified_indexes();
+                filters.pop(); // the last range is for open spans
+
+                let filters = filters
+                    .into_iter()
+                    .filter(|(_, range)| idle_filter.may_match_portion_of_range(range))
+                    .map(|(index, range)| {
+                        IndexedSpanFilter::Stratified(
+                            index,
+                            range,
+                            Some(NonIndexedSpanFilter::Idle(idle_filter.clone())),
+                        )
+                    })
+                    .collect();
+
+                IndexedSpanFilter::Or(filters)
+            }
+            BasicSpanFilter::Created(op, value) => {
+                let range = timestamp_range(&span_indexes.all, op, value);
+
+                IndexedSpanFilter::Single(&span_indexes.all[range], None)
+            }
             BasicSpanFilter::Closed(op, value) => {
                 let filters = span_indexes.durations.to_stratified_indexes();
                 let filters = filters
@@ -93,7 +119,7 @@ impl IndexedSpanFilter<'_> {
                             ValueOperator::Gt => {
                                 let v = value.get().saturating_sub(range.end - 1); // use the max of range
                                 let v = Timestamp::new(v).unwrap_or(Timestamp::MIN);
-                                let idx = index.upper_bound(&v);
+                                let idx = index.upper_bound(&IndexEntry::last_at(v));
                                 IndexedSpanFilter::Single(
                                     &index[idx..],
                                     Some(NonIndexedSpanFilter::Closed(op, value)),
@@ -102,7 +128,7 @@ impl IndexedSpanFilter<'_> {
                             ValueOperator::Gte => {
                                 let v = value.get().saturating_sub(range.end - 1); // use the max of range
                                 let v = Timestamp::new(v).unwrap_or(Timestamp::MIN);
-                                let idx = index.lower_bound(&v);
+                                let idx = index.lower_bound(&IndexEntry::first_at(v));
                                 IndexedSpanFilter::Single(
                                     &index[idx..],
                                     Some(NonIndexedSpanFilter::Closed(op, value)),
@@ -113,8 +139,8 @@ impl IndexedSpanFilter<'_> {
                                 let vstart = Timestamp::new(vstart).unwrap_or(Timestamp::MIN);
                                 let vend = value.get().saturating_sub(range.start); // use the min of range
                                 let vend = Timestamp::new(vend).unwrap_or(Timestamp::MIN);
-                                let start = index.lower_bound(&vstart);
-                                let end = index.upper_bound(&vend);
+                                let start = index.lower_bound(&IndexEntry::first_at(vstart));
+                                let end = index.upper_bound(&IndexEntry::last_at(vend));
                                 IndexedSpanFilter::Single(
                                     &index[start..end],
                                     Some(NonIndexedSpanFilter::Closed(op, value)),
@@ -123,7 +149,7 @@ impl IndexedSpanFilter<'_> {
                             ValueOperator::Lt => {
                                 let v = value.get().saturating_sub(range.start); // use the min of range
                                 let v = Timestamp::new(v).unwrap_or(Timestamp::MIN);
-                                let idx = index.lower_bound(&v);
+                                let idx = index.lower_bound(&IndexEntry::first_at(v));
                                 IndexedSpanFilter::Single(
                                     &index[..idx],
                                     Some(NonIndexedSpanFilter::Closed(op, value)),
@@ -132,7 +158,7 @@ impl IndexedSpanFilter<'_> {
                             ValueOperator::Lte => {
                                 let v = value.get().saturating_sub(range.start); // use the min of range
                                 let v = Timestamp::new(v).unwrap_or(Timestamp::MIN);
-                                let idx = index.upper_bound(&v);
+                                let idx = index.upper_bound(&IndexEntry::last_at(v));
                                 IndexedSpanFilter::Single(
                                     &index[..idx],
                                     Some(NonIndexedSpanFilter::Closed(op, value)),
@@ -263,6 +289,7 @@ impl IndexedSpanFilter<'_> {
                 ),
             },
             BasicSpanFilter::Root => IndexedSpanFilter::Single(&span_indexes.roots, None),
+            BasicSpanFilter::Pinned => IndexedSpanFilter::Single(&span_indexes.pinned, None),
             BasicSpanFilter::Trace(trace) => {
                 let index = span_indexes
                     .traces
@@ -344,12 +371,12 @@ impl IndexedSpanFilter<'_> {
     pub fn search<S: Storage>(
         &mut self,
         storage: &S,
-        mut entry: Timestamp, // this is the current lower bound for span keys
+        mut entry: IndexEntry, // this is the current lower bound for span entries
         order: Order,
-        bound: Timestamp, // this is the current upper bound for span keys
-        start: Timestamp, // this is the original search start time
-                          // end: Timestamp,   // this is the original search end time
-    ) -> Option<Timestamp> {
+        bound: IndexEntry, // this is the current upper bound for span entries
+        start: IndexEntry, // this is the original search start time
+                           // end: IndexEntry,   // this is the original search end time
+    ) -> Option<IndexEntry> {
         match self {
             IndexedSpanFilter::Single(entries, filter) => match order {
                 Order::Asc => loop {
@@ -363,20 +390,20 @@ impl IndexedSpanFilter<'_> {
                     }
 
                     if found_entry < start {
-                        let span = storage.get_span(found_entry).unwrap();
+                        let span = storage.get_span(found_entry.key).unwrap();
                         if let Some(closed_at) = span.closed_at {
-                            if closed_at <= start {
-                                entry = found_entry.saturating_add(1);
+                            if closed_at <= start.at {
+                                entry = found_entry.next();
                                 continue;
                             }
                         }
                     }
 
                     if let Some(filter) = filter {
-                        if filter.matches(&SpanContext::new(found_entry, storage)) {
+                        if filter.matches(&SpanContext::new(found_entry.key, storage)) {
                             return Some(found_entry);
                         } else {
-                            entry = found_entry.saturating_add(1);
+                            entry = found_entry.next();
                         }
                     } else {
                         return Some(found_entry);
@@ -393,20 +420,20 @@ impl IndexedSpanFilter<'_> {
                     }
 
                     if found_entry < start {
-                        let span = storage.get_span(found_entry).unwrap();
+                        let span = storage.get_span(found_entry.key).unwrap();
                         if let Some(closed_at) = span.closed_at {
-                            if closed_at <= start {
-                                entry = Timestamp::new(found_entry.get() - 1).unwrap();
+                            if closed_at <= start.at {
+                                entry = found_entry.prev();
                                 continue;
                             }
                         }
                     }
 
                     if let Some(filter) = filter {
-                        if filter.matches(&SpanContext::new(found_entry, storage)) {
+                        if filter.matches(&SpanContext::new(found_entry.key, storage)) {
                             return Some(found_entry);
                         } else {
-                            entry = Timestamp::new(found_entry.get() - 1).unwrap();
+                            entry = found_entry.prev();
                         }
                     } else {
                         return Some(found_entry);
@@ -425,20 +452,20 @@ impl IndexedSpanFilter<'_> {
                     }
 
                     if found_entry < start {
-                        let span = storage.get_span(found_entry).unwrap();
+                        let span = storage.get_span(found_entry.key).unwrap();
                         if let Some(closed_at) = span.closed_at {
-                            if closed_at <= start {
-                                entry = found_entry.saturating_add(1);
+                            if closed_at <= start.at {
+                                entry = found_entry.next();
                                 continue;
                             }
                         }
                     }
 
                     if let Some(filter) = filter {
-                        if filter.matches(&SpanContext::new(found_entry, storage)) {
+                        if filter.matches(&SpanContext::new(found_entry.key, storage)) {
                             return Some(found_entry);
                         } else {
-                            entry = found_entry.saturating_add(1);
+                            entry = found_entry.next();
                         }
                     } else {
                         return Some(found_entry);
@@ -455,20 +482,20 @@ impl IndexedSpanFilter<'_> {
                     }
 
                     if found_entry < start {
-                        let span = storage.get_span(found_entry).unwrap();
+                        let span = storage.get_span(found_entry.key).unwrap();
                         if let Some(closed_at) = span.closed_at {
-                            if closed_at <= start {
-                                entry = Timestamp::new(found_entry.get() - 1).unwrap();
+                            if closed_at <= start.at {
+                                entry = found_entry.prev();
                                 continue;
                             }
                         }
                     }
 
                     if let Some(filter) = filter {
-                        if filter.matches(&SpanContext::new(found_entry, storage)) {
+                        if filter.matches(&SpanContext::new(found_entry.key, storage)) {
                             return Some(found_entry);
                         } else {
-                            entry = Timestamp::new(found_entry.get() - 1).unwrap();
+                            entry = found_entry.prev();
                         }
                     } else {
                         return Some(found_entry);
@@ -487,10 +514,10 @@ impl IndexedSpanFilter<'_> {
                     }
 
                     if found_entry < start {
-                        let span = storage.get_span(found_entry).unwrap();
+                        let span = storage.get_span(found_entry.key).unwrap();
                         if let Some(closed_at) = span.closed_at {
-                            if closed_at <= start {
-                                entry = found_entry.saturating_add(1);
+                            if closed_at <= start.at {
+                                entry = found_entry.next();
                                 continue;
                             }
                         }
@@ -502,7 +529,7 @@ impl IndexedSpanFilter<'_> {
                     if nested_entry != Some(found_entry) {
                         return Some(found_entry);
                     } else {
-                        entry = found_entry.saturating_add(1);
+                        entry = found_entry.next();
                     }
                 },
                 Order::Desc => loop {
@@ -518,10 +545,10 @@ impl IndexedSpanFilter<'_> {
                     // even if we're negating the filter, the span needs to be
                     // in range
                     if found_entry < start {
-                        let span = storage.get_span(found_entry).unwrap();
+                        let span = storage.get_span(found_entry.key).unwrap();
                         if let Some(closed_at) = span.closed_at {
-                            if closed_at <= start {
-                                entry = Timestamp::new(found_entry.get() - 1).unwrap();
+                            if closed_at <= start.at {
+                                entry = found_entry.prev();
                                 continue;
                             }
                         }
@@ -533,7 +560,7 @@ impl IndexedSpanFilter<'_> {
                     if nested_entry != Some(found_entry) {
                         return Some(found_entry);
                     } else {
-                        entry = Timestamp::new(found_entry.get() - 1).unwrap();
+                        entry = found_entry.prev();
                     }
                 },
             },
@@ -550,10 +577,8 @@ impl IndexedSpanFilter<'_> {
                             Some(_) => { /* continue */ }
                             None => {
                                 match order {
-                                    Order::Asc => current = current.saturating_add(1),
-                                    Order::Desc => {
-                                        current = Timestamp::new(current.get() - 1).unwrap()
-                                    }
+                                    Order::Asc => current = current.next(),
+                                    Order::Desc => current = current.prev(),
                                 }
                                 continue 'outer;
                             }
@@ -635,7 +660,7 @@ impl IndexedSpanFilter<'_> {
         }
     }
 
-    pub fn trim_to_timeframe(&mut self, start: Timestamp, end: Timestamp) {
+    pub fn trim_to_timeframe(&mut self, start: IndexEntry, end: IndexEntry) {
         match self {
             IndexedSpanFilter::Single(index, _) => {
                 // we can trim the end
@@ -647,8 +672,12 @@ impl IndexedSpanFilter<'_> {
             }
             IndexedSpanFilter::Stratified(index, duration_range, _) => {
                 // we can trim to "max duration" before `start`
-                let trim_start = Timestamp::new(start.get().saturating_sub(duration_range.end))
-                    .unwrap_or(Timestamp::MIN);
+                let trim_start = IndexEntry::new(
+                    start
+                        .at
+                        .saturating_sub(duration_range.end.saturating_mul(1000)),
+                    Timestamp::MIN,
+                );
 
                 // we can trim by the end
                 let trim_end = end;
@@ -715,6 +744,8 @@ impl<'a> IndexedSpanFilter<'a> {
 pub(crate) enum BasicSpanFilter {
     Level(SimpleLevel),
     Duration(DurationFilter),
+    SelfTime(DurationFilter),
+    Idle(DurationFilter),
     Created(ValueOperator, Timestamp),
     Closed(ValueOperator, Timestamp),
     Kind(SourceKind),
@@ -724,6 +755,7 @@ pub(crate) enum BasicSpanFilter {
     File(FileFilter),
     Root,
     Trace(TraceRoot),
+    Pinned,
     Parent(SpanKey),
     Attribute(String, ValueFilter),
     Not(Box<BasicSpanFilter>),
@@ -736,6 +768,8 @@ impl BasicSpanFilter {
         match self {
             BasicSpanFilter::Level(_) => {}
             BasicSpanFilter::Duration(_) => {}
+            BasicSpanFilter::SelfTime(_) => {}
+            BasicSpanFilter::Idle(_) => {}
             BasicSpanFilter::Created(_, _) => {}
             BasicSpanFilter::Closed(_, _) => {}
             BasicSpanFilter::Kind(_) => {}
@@ -745,6 +779,7 @@ impl BasicSpanFilter {
             BasicSpanFilter::File(_) => {}
             BasicSpanFilter::Root => {}
             BasicSpanFilter::Trace(_) => {}
+            BasicSpanFilter::Pinned => {}
             BasicSpanFilter::Parent(_) => {}
             BasicSpanFilter::Attribute(_, _) => {}
             BasicSpanFilter::Not(_) => {}
@@ -773,14 +808,30 @@ impl BasicSpanFilter {
         }
     }
 
-    pub fn matches<S: Storage>(&self, context: &SpanContext<'_, S>) -> bool {
+    /// Checks if the span matches the filter. The `children` index is used to
+    /// compute the self time of spans that are already closed, which can be
+    /// the case when spans are re-checked after a parent arrived late.
+    pub fn matches<S: Storage>(
+        &self,
+        context: &SpanContext<'_, S>,
+        children: &HashMap<SpanKey, Vec<SpanKey>>,
+    ) -> bool {
         let span = context.span();
         match self {
             BasicSpanFilter::Level(level) => span.level.into_simple_level() == *level,
             BasicSpanFilter::Duration(filter) => filter.matches(span.duration()),
-            BasicSpanFilter::Created(op, value) => op.compare(span.created_at, *value),
+            BasicSpanFilter::SelfTime(filter) => {
+                let children = children
+                    .get(&span.key)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+
+                filter.matches(context.self_time(children))
+            }
+            BasicSpanFilter::Idle(filter) => filter.matches(span.idle()),
+            BasicSpanFilter::Created(op, value) => op.compare(to_micros(span.created_at), *value),
             BasicSpanFilter::Closed(op, value) => {
-                let Some(closed_at) = span.closed_at else {
+                let Some(closed_at) = span.closed_at_micros() else {
                     return false; // never match an open span
                 };
 
@@ -795,14 +846,15 @@ impl BasicSpanFilter {
             }
             BasicSpanFilter::Root => span.parent_key.is_none(),
             BasicSpanFilter::Trace(trace) => context.trace_root() == *trace,
+            BasicSpanFilter::Pinned => false, // new spans cannot be pinned yet
             BasicSpanFilter::Parent(parent_key) => span.parent_key == Some(*parent_key),
             BasicSpanFilter::Attribute(attribute, value_filter) => context
                 .attribute(attribute)
                 .map(|v| value_filter.matches(v))
                 .unwrap_or(false),
-            BasicSpanFilter::Not(inner_filter) => !inner_filter.matches(context),
-            BasicSpanFilter::And(filters) => filters.iter().all(|f| f.matches(context)),
-            BasicSpanFilter::Or(filters) => filters.iter().any(|f| f.matches(context)),
+            BasicSpanFilter::Not(inner_filter) => !inner_filter.matches(context, children),
+            BasicSpanFilter::And(filters) => filters.iter().all(|f| f.matches(context, children)),
+            BasicSpanFilter::Or(filters) => filters.iter().any(|f| f.matches(context, children)),
         }
     }
 
@@ -833,8 +885,8 @@ impl BasicSpanFilter {
         let property_kind = predicate
             .property_kind
             .unwrap_or(match predicate.property.as_str() {
-                "level" | "duration" | "name" | "namespace" | "target" | "function" | "file"
-                | "parent" | "created" | "closed" | "trace" => Inherent,
+                "level" | "duration" | "self" | "idle" | "name" | "namespace" | "target"
+                | "function" | "file" | "parent" | "created" | "closed" | "trace" => Inherent,
                 _ => Attribute,
             });
 
@@ -860,7 +912,7 @@ impl BasicSpanFilter {
                     _ => return Err(InputError::InvalidLevelOperator),
                 };
             }
-            (Inherent, "duration") => validate_value_predicate(
+            (Inherent, "duration" | "self" | "idle") => validate_value_predicate(
                 &predicate.value,
                 |op, value| {
                     DurationFilter::from_input(*op, value)?;
@@ -1011,6 +1063,24 @@ impl BasicSpanFilter {
                     |_| Err(InputError::InvalidTraceValue),
                 )?;
             }
+            (Inherent, "pinned") => {
+                validate_value_predicate(
+                    &predicate.value,
+                    |op, value| {
+                        if *op != ValueOperator::Eq {
+                            return Err(InputError::InvalidPinnedOperator);
+                        }
+
+                        if value != "true" && value != "false" {
+                            return Err(InputError::InvalidPinnedValue);
+                        }
+
+                        Ok(())
+                    },
+                    |_| Err(InputError::InvalidPinnedValue),
+                    |_| Err(InputError::InvalidPinnedValue),
+                )?;
+            }
             (Inherent, _) => {
                 return Err(InputError::InvalidInherentProperty);
             }
@@ -1067,8 +1137,8 @@ impl BasicSpanFilter {
         let property_kind = predicate
             .property_kind
             .unwrap_or(match predicate.property.as_str() {
-                "level" | "duration" | "name" | "namespace" | "target" | "function" | "file"
-                | "parent" | "created" | "closed" | "trace" => Inherent,
+                "level" | "duration" | "self" | "idle" | "name" | "namespace" | "target"
+                | "function" | "file" | "parent" | "created" | "closed" | "trace" => Inherent,
                 _ => Attribute,
             });
 
@@ -1111,6 +1181,26 @@ impl BasicSpanFilter {
                 |_| Err(InputError::InvalidDurationValue),
                 |_| Err(InputError::InvalidDurationValue),
             )?,
+            (Inherent, "self") => filterify_span_filter(
+                predicate.value,
+                |op, value| {
+                    Ok(BasicSpanFilter::SelfTime(DurationFilter::from_input(
+                        op, &value,
+                    )?))
+                },
+                |_| Err(InputError::InvalidDurationValue),
+                |_| Err(InputError::InvalidDurationValue),
+            )?,
+            (Inherent, "idle") => filterify_span_filter(
+                predicate.value,
+                |op, value| {
+                    Ok(BasicSpanFilter::Idle(DurationFilter::from_input(
+                        op, &value,
+                    )?))
+                },
+                |_| Err(InputError::InvalidDurationValue),
+                |_| Err(InputError::InvalidDurationValue),
+            )?,
             (Inherent, "name") => filterify_span_filter(
                 predicate.value,
                 |op, value| {
@@ -1343,6 +1433,22 @@ impl BasicSpanFilter {
                 |_| Err(InputError::InvalidTraceValue),
                 |_| Err(InputError::InvalidTraceValue),
             )?,
+            (Inherent, "pinned") => filterify_span_filter(
+                predicate.value,
+                |op, value| {
+                    if op != ValueOperator::Eq {
+                        return Err(InputError::InvalidPinnedOperator);
+                    }
+
+                    match value.as_str() {
+                        "true" => Ok(BasicSpanFilter::Pinned),
+                        "false" => Ok(BasicSpanFilter::Not(Box::new(BasicSpanFilter::Pinned))),
+                        _ => Err(InputError::InvalidPinnedValue),
+                    }
+                },
+                |_| Err(InputError::InvalidPinnedValue),
+                |_| Err(InputError::InvalidPinnedValue),
+            )?,
             (Inherent, _) => {
                 return Err(InputError::InvalidInherentProperty);
             }
@@ -1367,8 +1473,10 @@ impl BasicSpanFilter {
     }
 }
 
-pub(crate) enum NonIndexedSpanFilter {
+pub(crate) enum NonIndexedSpanFilter<'i> {
     Duration(DurationFilter),
+    SelfTime(DurationFilter, &'i HashMap<SpanKey, Vec<Timestamp>>),
+    Idle(DurationFilter),
     Closed(ValueOperator, Timestamp),
     Kind(SourceKind),
     Name(ValueStringComparison),
@@ -1379,13 +1487,22 @@ pub(crate) enum NonIndexedSpanFilter {
     Attribute(String, ValueFilter),
 }
 
-impl NonIndexedSpanFilter {
+impl NonIndexedSpanFilter<'_> {
     fn matches<S: Storage>(&self, context: &SpanContext<'_, S>) -> bool {
         let span = context.span();
         match self {
             NonIndexedSpanFilter::Duration(filter) => filter.matches(span.duration()),
+            NonIndexedSpanFilter::SelfTime(filter, children) => {
+                let children = children
+                    .get(&span.key)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+
+                filter.matches(context.self_time(children))
+            }
+            NonIndexedSpanFilter::Idle(filter) => filter.matches(span.idle()),
             NonIndexedSpanFilter::Closed(op, value) => {
-                let Some(closed_at) = span.closed_at else {
+                let Some(closed_at) = span.closed_at_micros() else {
                     return false; // never match an open span
                 };
 
@@ -1459,6 +1576,17 @@ impl DurationFilter {
         self.op.compare(&duration, &self.measure)
     }
 
+    // This is for measures that are a portion of the duration (like self-time
+    // and idle time), so a range can only be excluded if all the durations in
+    // it are too short.
+    pub fn may_match_portion_of_range(&self, range: &Range<u64>) -> bool {
+        match self.op {
+            ValueOperator::Gt => self.measure.saturating_add(1) < range.end,
+            ValueOperator::Gte | ValueOperator::Eq => self.measure < range.end,
+            ValueOperator::Lt | ValueOperator::Lte => true,
+        }
+    }
+
     pub fn matches_duration_range(&self, range: &Range<u64>) -> Option<bool> {
         match self.op {
             // --y--[ p ]--n--
@@ -1485,9 +1613,9 @@ impl DurationFilter {
 pub(crate) struct IndexedSpanFilterIterator<'i, S> {
     filter: IndexedSpanFilter<'i>,
     order: Order,
-    curr_key: Timestamp,
-    start_key: Timestamp,
-    end_key: Timestamp,
+    curr_key: IndexEntry,
+    start_key: IndexEntry,
+    end_key: IndexEntry,
     storage: &'i S,
 }
 
@@ -1506,8 +1634,11 @@ impl<'i, S: Storage> IndexedSpanFilterIterator<'i, S> {
             IndexedSpanFilter::build(Some(filter), &engine.span_indexes, &engine.storage);
 
         let curr;
-        let mut start = query.start;
-        let mut end = query.end;
+        let mut start = IndexEntry::first_at(query.start);
+        let mut end = IndexEntry::last_at(query.end);
+        let previous = query
+            .previous
+            .map(|prev| previous_entry(&engine.storage, prev));
 
         // if order is asc
         // - if previous & greater than or = start, then start = previous + 1, curr = start
@@ -1518,24 +1649,24 @@ impl<'i, S: Storage> IndexedSpanFilterIterator<'i, S> {
         // - if previous & less than or = start, then end = start, curr = previous - 1
         // - if no previous, then end = end, curr = end
 
-        match (query.order, query.previous) {
-            (Order::Asc, Some(prev)) if prev >= query.start => {
-                start = prev.saturating_add(1);
+        match (query.order, previous) {
+            (Order::Asc, Some(prev)) if prev >= start => {
+                start = prev.next();
                 curr = start;
             }
             (Order::Asc, Some(prev)) => {
-                curr = prev.saturating_add(1);
+                curr = prev.next();
             }
             (Order::Asc, None) => {
-                curr = Timestamp::MIN;
+                curr = IndexEntry::new(0, Timestamp::MIN);
             }
-            (Order::Desc, Some(prev)) if prev > query.start => {
-                end = Timestamp::new(prev.get() - 1).unwrap();
+            (Order::Desc, Some(prev)) if prev > start => {
+                end = prev.prev();
                 curr = end;
             }
             (Order::Desc, Some(prev)) => {
                 end = start;
-                curr = Timestamp::new(prev.get() - 1).unwrap();
+                curr = prev.prev();
             }
             (Order::Desc, None) => {
                 curr = end;
@@ -1548,7 +1679,7 @@ impl<'i, S: Storage> IndexedSpanFilterIterator<'i, S> {
 
         let (start_key, end_key) = match query.order {
             Order::Asc => (start, end),
-            Order::Desc => (start, Timestamp::MIN),
+            Order::Desc => (start, IndexEntry::new(0, Timestamp::MIN)),
         };
 
         IndexedSpanFilterIterator {
@@ -1568,22 +1699,19 @@ impl<'i, S: Storage> IndexedSpanFilterIterator<'i, S> {
         IndexedSpanFilterIterator {
             filter,
             order: Order::Asc,
-            curr_key: Timestamp::MIN,
-            end_key: Timestamp::MAX,
-            start_key: Timestamp::MIN,
+            curr_key: IndexEntry::new(0, Timestamp::MIN),
+            end_key: IndexEntry::new(u64::MAX, Timestamp::MAX),
+            start_key: IndexEntry::new(0, Timestamp::MIN),
             storage: &engine.storage,
         }
     }
 }
 
-impl<S> Iterator for IndexedSpanFilterIterator<'_, S>
-where
-    S: Storage,
-{
-    type Item = SpanKey;
-
-    fn next(&mut self) -> Option<SpanKey> {
-        let span_key = self.filter.search(
+impl<S: Storage> IndexedSpanFilterIterator<'_, S> {
+    /// Gets the index entry of the next matching span, which unlike `next`
+    /// also has when the span was created.
+    pub fn next_entry(&mut self) -> Option<IndexEntry> {
+        let entry = self.filter.search(
             self.storage,
             self.curr_key,
             self.order,
@@ -1592,11 +1720,22 @@ where
         )?;
 
         match self.order {
-            Order::Asc => self.curr_key = span_key.saturating_add(1),
-            Order::Desc => self.curr_key = Timestamp::new(span_key.get() - 1).unwrap(),
+            Order::Asc => self.curr_key = entry.next(),
+            Order::Desc => self.curr_key = entry.prev(),
         };
 
-        Some(span_key)
+        Some(entry)
+    }
+}
+
+impl<S> Iterator for IndexedSpanFilterIterator<'_, S>
+where
+    S: Storage,
+{
+    type Item = SpanKey;
+
+    fn next(&mut self) -> Option<SpanKey> {
+        self.next_entry().map(|entry| entry.key)
     }
 
     // fn size_hint(&self) -> (usize, Option<usize>) {
@@ -1604,6 +1743,16 @@ where
     // }
 }
 
+// The indexes are ordered by time, so continuing after the previous span needs
+// its creation time and not just its key. If it can't be loaded, its key is
+// used in its place since keys are made from the timestamps.
+fn previous_entry<S: Storage>(storage: &S, key: SpanKey) -> IndexEntry {
+    match storage.get_span(key) {
+        Ok(span) => IndexEntry::new(span.created_at, key),
+        Err(_) => IndexEntry::new(key.get().saturating_mul(1000), key),
+    }
+}
+
 fn filterify_span_filter(
     value: ValuePredicate,
     comparison_filterifier: impl Fn(ValueOperator, String) -> Result<BasicSpanFilter, InputError>
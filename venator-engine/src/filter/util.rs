@@ -1,4 +1,8 @@
 use std::cmp::Ordering;
+use std::ops::Range;
+
+use crate::index::IndexEntry;
+use crate::models::{Timestamp, ValueOperator};
 
 #[allow(dead_code)]
 pub(crate) trait BoundSearch<T> {
@@ -94,6 +98,26 @@ pub(crate) fn merge<T>(a: Option<T>, b: Option<T>, f: impl FnOnce(T, T) -> T) ->
     }
 }
 
+/// Gets the range of the time-ordered `index` whose entries have a timestamp
+/// that compares with the value. The value is in microseconds, so it stands for
+/// every precise timestamp within that microsecond.
+pub(crate) fn timestamp_range(
+    index: &[IndexEntry],
+    op: ValueOperator,
+    value: Timestamp,
+) -> Range<usize> {
+    let first = IndexEntry::first_at(value);
+    let last = IndexEntry::last_at(value);
+
+    match op {
+        ValueOperator::Gt => index.upper_bound(&last)..index.len(),
+        ValueOperator::Gte => index.lower_bound(&first)..index.len(),
+        ValueOperator::Eq => index.lower_bound(&first)..index.upper_bound(&last),
+        ValueOperator::Lte => 0..index.upper_bound(&last),
+        ValueOperator::Lt => 0..index.lower_bound(&first),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,4 +183,27 @@ mod tests {
         assert_eq!([0, 0, 2, 2].lower_bound_via_expansion(&1), 2);
         assert_eq!([0, 0, 2, 2].upper_bound_via_expansion(&1), 2);
     }
+
+    #[test]
+    fn timestamp_range_covers_whole_microseconds() {
+        let ts = |t| Timestamp::new(t).unwrap();
+
+        // the keys are not in time order, as if they were inserted out of
+        // order or moved to be unique
+        let index = [
+            IndexEntry::new(1000, ts(5)),
+            IndexEntry::new(2000, ts(4)),
+            IndexEntry::new(2999, ts(1)),
+            IndexEntry::new(3000, ts(3)),
+            IndexEntry::new(4500, ts(2)),
+        ];
+
+        assert_eq!(timestamp_range(&index, ValueOperator::Gt, ts(2)), 3..5);
+        assert_eq!(timestamp_range(&index, ValueOperator::Gte, ts(3)), 3..5);
+        assert_eq!(timestamp_range(&index, ValueOperator::Eq, ts(2)), 1..3);
+        assert_eq!(timestamp_range(&index, ValueOperator::Lte, ts(2)), 0..3);
+        assert_eq!(timestamp_range(&index, ValueOperator::Lt, ts(3)), 0..3);
+        assert_eq!(timestamp_range(&index, ValueOperator::Lt, ts(2)), 0..1);
+        assert_eq!(timestamp_range(&index, ValueOperator::Eq, ts(6)), 5..5);
+    }
 }
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::{PreciseTimestamp, Timestamp};
+
+/// This is what the indexes hold for each entity. Entries are ordered by the
+/// precise timestamp of the entity and then by its key, so the indexes are in
+/// time order even if entities were inserted out of order or had their keys
+/// moved to keep them unique.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub(crate) struct IndexEntry {
+    pub at: PreciseTimestamp,
+    pub key: Timestamp,
+}
+
+impl IndexEntry {
+    pub(crate) fn new(at: PreciseTimestamp, key: Timestamp) -> IndexEntry {
+        IndexEntry { at, key }
+    }
+
+    /// Gets the lowest possible entry within the microsecond `at`.
+    pub(crate) fn first_at(at: Timestamp) -> IndexEntry {
+        IndexEntry {
+            at: at.get().saturating_mul(1000),
+            key: Timestamp::MIN,
+        }
+    }
+
+    /// Gets the highest possible entry within the microsecond `at`.
+    pub(crate) fn last_at(at: Timestamp) -> IndexEntry {
+        IndexEntry {
+            at: at.get().saturating_mul(1000).saturating_add(999),
+            key: Timestamp::MAX,
+        }
+    }
+
+    /// Gets the lowest possible entry that sorts after this one.
+    pub(crate) fn next(self) -> IndexEntry {
+        match self.key.checked_add(1) {
+            Some(key) => IndexEntry { at: self.at, key },
+            None => IndexEntry {
+                at: self.at.saturating_add(1),
+                key: Timestamp::MIN,
+            },
+        }
+    }
+
+    /// Gets the highest possible entry that sorts before this one.
+    pub(crate) fn prev(self) -> IndexEntry {
+        match Timestamp::new(self.key.get() - 1) {
+            Some(key) => IndexEntry { at: self.at, key },
+            None => IndexEntry {
+                at: self.at.saturating_sub(1),
+                key: Timestamp::MAX,
+            },
+        }
+    }
+}
+
+/// Gets the entries of the sorted `index` that are for one of the sorted
+/// `keys`. The result is sorted as well.
+pub(crate) fn entries_of(index: &[IndexEntry], keys: &[Timestamp]) -> Vec<IndexEntry> {
+    if keys.is_empty() {
+        return Vec::new();
+    }
+
+    index
+        .iter()
+        .filter(|entry| keys.binary_search(&entry.key).is_ok())
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_ordered_by_time_before_key() {
+        let ts = |t| Timestamp::new(t).unwrap();
+
+        let early = IndexEntry::new(1500, ts(9));
+        let late = IndexEntry::new(2000, ts(1));
+
+        assert!(early < late);
+        assert!(IndexEntry::first_at(ts(1)) < early);
+        assert!(early < IndexEntry::last_at(ts(1)));
+        assert!(IndexEntry::last_at(ts(1)) < late);
+        assert!(IndexEntry::first_at(ts(2)) <= late);
+
+        assert_eq!(early.next(), IndexEntry::new(1500, ts(10)));
+        assert_eq!(early.prev(), IndexEntry::new(1500, ts(8)));
+        assert_eq!(late.prev(), IndexEntry::new(1999, Timestamp::MAX));
+        assert_eq!(
+            IndexEntry::new(1500, Timestamp::MAX).next(),
+            IndexEntry::new(1501, Timestamp::MIN)
+        );
+    }
+}
@@ -7,21 +7,26 @@ use crate::filter::BoundSearch;
 use crate::models::{EventKey, FullSpanId, Timestamp, TraceRoot, Value};
 use crate::{ResourceKey, SpanKey, Storage};
 
-use super::{IndexExt, ValueIndex};
+use super::{entries_of, IndexEntry, IndexExt, ValueIndex};
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct EventIndexes {
-    pub all: Vec<Timestamp>,
-    pub levels: [Vec<Timestamp>; 6],
-    pub resources: BTreeMap<ResourceKey, Vec<Timestamp>>,
-    pub namespaces: BTreeMap<String, Vec<Timestamp>>,
-    pub functions: BTreeMap<String, Vec<Timestamp>>,
-    pub filenames: BTreeMap<String, Vec<Timestamp>>,
-    pub roots: Vec<Timestamp>,
-    pub traces: HashMap<TraceRoot, Vec<Timestamp>>,
+    pub all: Vec<IndexEntry>,
+    pub keys: Vec<EventKey>,
+    pub levels: [Vec<IndexEntry>; 6],
+    pub resources: BTreeMap<ResourceKey, Vec<IndexEntry>>,
+    pub namespaces: BTreeMap<String, Vec<IndexEntry>>,
+    pub functions: BTreeMap<String, Vec<IndexEntry>>,
+    pub filenames: BTreeMap<String, Vec<IndexEntry>>,
+    pub roots: Vec<IndexEntry>,
+    pub traces: HashMap<TraceRoot, Vec<IndexEntry>>,
     pub contents: ValueIndex,
     pub attributes: BTreeMap<String, ValueIndex>,
 
+    // pins are persisted separately, so this is filled in by the engine
+    #[serde(skip)]
+    pub pinned: Vec<IndexEntry>,
+
     // events whose `parent_id` has not been seen yet
     pub orphanage: HashMap<FullSpanId, Vec<Timestamp>>,
 }
@@ -30,6 +35,7 @@ impl EventIndexes {
     pub fn new() -> EventIndexes {
         EventIndexes {
             all: vec![],
+            keys: vec![],
             levels: [
                 Vec::new(),
                 Vec::new(),
@@ -46,6 +52,7 @@ impl EventIndexes {
             traces: HashMap::new(),
             contents: ValueIndex::new(),
             attributes: BTreeMap::new(),
+            pinned: Vec::new(),
 
             orphanage: HashMap::new(),
         }
@@ -53,47 +60,51 @@ impl EventIndexes {
 
     pub fn update_with_new_event<S: Storage>(&mut self, context: &EventContext<'_, S>) {
         let event = context.event();
-        let event_key = event.timestamp;
+        let event_key = event.key;
+        let entry = IndexEntry::new(event.timestamp, event_key);
+
+        let idx = self.all.upper_bound_via_expansion(&entry);
+        self.all.insert(idx, entry);
 
-        let idx = self.all.upper_bound_via_expansion(&event_key);
-        self.all.insert(idx, event_key);
+        let idx = self.keys.upper_bound_via_expansion(&event_key);
+        self.keys.insert(idx, event_key);
 
         let level_index = &mut self.levels[event.level.into_simple_level() as usize];
-        let idx = level_index.upper_bound_via_expansion(&event_key);
-        level_index.insert(idx, event_key);
+        let idx = level_index.upper_bound_via_expansion(&entry);
+        level_index.insert(idx, entry);
 
         // TODO: do I need a per-resource index?
         let resource_index = self.resources.entry(event.resource_key).or_default();
-        let idx = resource_index.upper_bound_via_expansion(&event_key);
-        resource_index.insert(idx, event_key);
+        let idx = resource_index.upper_bound_via_expansion(&entry);
+        resource_index.insert(idx, entry);
 
         if let Some(namespace) = event.namespace.clone() {
             let namespace_index = self.namespaces.entry(namespace).or_default();
-            let idx = namespace_index.upper_bound_via_expansion(&event_key);
-            namespace_index.insert(idx, event_key);
+            let idx = namespace_index.upper_bound_via_expansion(&entry);
+            namespace_index.insert(idx, entry);
         }
 
         if let Some(function) = event.function.clone() {
             let function_index = self.functions.entry(function).or_default();
-            let idx = function_index.upper_bound_via_expansion(&event_key);
-            function_index.insert(idx, event_key);
+            let idx = function_index.upper_bound_via_expansion(&entry);
+            function_index.insert(idx, entry);
         }
 
         if let Some(filename) = &event.file_name {
             let filename_index = self.filenames.entry(filename.clone()).or_default();
-            let idx = filename_index.upper_bound_via_expansion(&event_key);
-            filename_index.insert(idx, event_key);
+            let idx = filename_index.upper_bound_via_expansion(&entry);
+            filename_index.insert(idx, entry);
         }
 
         if let Some(trace) = context.trace_root() {
             let trace_index = self.traces.entry(trace).or_default();
-            let idx = trace_index.upper_bound_via_expansion(&event_key);
-            trace_index.insert(idx, event_key);
+            let idx = trace_index.upper_bound_via_expansion(&entry);
+            trace_index.insert(idx, entry);
         }
 
         if event.parent_id.is_none() {
-            let idx = self.roots.upper_bound_via_expansion(&event_key);
-            self.roots.insert(idx, event_key);
+            let idx = self.roots.upper_bound_via_expansion(&entry);
+            self.roots.insert(idx, entry);
         }
 
         if let (Some(parent_id), None) = (event.parent_id, event.parent_key) {
@@ -102,7 +113,7 @@ impl EventIndexes {
             orphan_index.insert(idx, event_key);
         }
 
-        self.contents.add_entry(event_key, &event.content);
+        self.contents.add_entry(entry, &event.content);
 
         for (attribute, value) in context.attributes() {
             let index = self
@@ -110,7 +121,7 @@ impl EventIndexes {
                 .entry(attribute.to_owned())
                 .or_insert_with(ValueIndex::new);
 
-            index.add_entry(event_key, value);
+            index.add_entry(entry, value);
         }
     }
 
@@ -129,6 +140,8 @@ impl EventIndexes {
         parent_key: Timestamp,
         parent_attributes: &BTreeMap<String, Value>,
     ) {
+        let entry = IndexEntry::new(context.event().timestamp, context.key());
+
         for (attribute, new_value) in parent_attributes {
             let attribute_index = self
                 .attributes
@@ -137,42 +150,53 @@ impl EventIndexes {
 
             if let Some((old_value, key)) = context.attribute_with_key(attribute) {
                 if key <= parent_key && new_value != old_value {
-                    attribute_index.remove_entry(context.key(), old_value);
-                    attribute_index.add_entry(context.key(), new_value);
+                    attribute_index.remove_entry(entry, old_value);
+                    attribute_index.add_entry(entry, new_value);
                 }
             } else {
                 // there was no old value, just insert
-                attribute_index.add_entry(context.key(), new_value);
+                attribute_index.add_entry(entry, new_value);
             }
         }
     }
 
     pub fn remove_events(&mut self, events: &[EventKey]) {
-        self.all.remove_list_sorted(events);
+        let entries = entries_of(&self.all, events);
+
+        self.all.remove_list_sorted(&entries);
+        self.keys.remove_list_sorted(events);
 
         for level_index in &mut self.levels {
-            level_index.remove_list_sorted(events);
+            level_index.remove_list_sorted(&entries);
         }
 
         for resource_index in self.resources.values_mut() {
-            resource_index.remove_list_sorted(events);
+            resource_index.remove_list_sorted(&entries);
         }
 
         for namespace_index in self.namespaces.values_mut() {
-            namespace_index.remove_list_sorted(events);
+            namespace_index.remove_list_sorted(&entries);
         }
 
         for function_index in self.functions.values_mut() {
-            function_index.remove_list_sorted(events);
+            function_index.remove_list_sorted(&entries);
         }
 
         for filename_index in self.filenames.values_mut() {
-            filename_index.remove_list_sorted(events);
+            filename_index.remove_list_sorted(&entries);
         }
-        self.roots.remove_list_sorted(events);
+        self.roots.remove_list_sorted(&entries);
+
+        for trace_index in self.traces.values_mut() {
+            trace_index.remove_list_sorted(&entries);
+        }
+
+        self.pinned.remove_list_sorted(&entries);
+
+        self.contents.remove_entries(&entries);
 
         for attribute_index in self.attributes.values_mut() {
-            attribute_index.remove_entries(events);
+            attribute_index.remove_entries(&entries);
         }
     }
 
@@ -1,12 +1,15 @@
+mod entry;
 mod event_indexes;
 mod span_event_indexes;
 mod span_indexes;
 mod util;
 mod value;
 
+pub(crate) use entry::{entries_of, IndexEntry};
 pub(crate) use event_indexes::EventIndexes;
 pub(crate) use span_event_indexes::SpanEventIndexes;
 pub(crate) use span_indexes::{SpanDurationIndex, SpanIndexes};
+pub(crate) use util::SortedIndexLookup;
 pub(crate) use value::ValueIndex;
 
 use util::IndexExt;
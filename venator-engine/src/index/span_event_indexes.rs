@@ -3,41 +3,48 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 
 use crate::filter::BoundSearch;
-use crate::models::Timestamp;
 use crate::{SpanEvent, SpanEventKey, SpanKey};
 
-use super::IndexExt;
+use super::{entries_of, IndexEntry, IndexExt};
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct SpanEventIndexes {
-    pub all: Vec<Timestamp>,
-    pub spans: HashMap<SpanKey, Vec<Timestamp>>,
+    pub all: Vec<IndexEntry>,
+    pub keys: Vec<SpanEventKey>,
+    pub spans: HashMap<SpanKey, Vec<IndexEntry>>,
 }
 
 impl SpanEventIndexes {
     pub fn new() -> SpanEventIndexes {
         SpanEventIndexes {
             all: Vec::new(),
+            keys: Vec::new(),
             spans: HashMap::new(),
         }
     }
 
     pub fn update_with_new_span_event(&mut self, span_event: &SpanEvent) {
-        let timestamp = span_event.timestamp;
+        let entry = IndexEntry::new(span_event.timestamp, span_event.key);
 
-        let idx = self.all.upper_bound_via_expansion(&timestamp);
-        self.all.insert(idx, timestamp);
+        let idx = self.all.upper_bound_via_expansion(&entry);
+        self.all.insert(idx, entry);
+
+        let idx = self.keys.upper_bound_via_expansion(&entry.key);
+        self.keys.insert(idx, entry.key);
 
         let span_index = self.spans.entry(span_event.span_key).or_default();
-        let idx = span_index.upper_bound_via_expansion(&timestamp);
-        span_index.insert(idx, timestamp);
+        let idx = span_index.upper_bound_via_expansion(&entry);
+        span_index.insert(idx, entry);
     }
 
     pub fn remove_span_events(&mut self, span_events: &[SpanEventKey]) {
-        self.all.remove_list_sorted(span_events);
+        let entries = entries_of(&self.all, span_events);
+
+        self.all.remove_list_sorted(&entries);
+        self.keys.remove_list_sorted(span_events);
 
         for span_index in self.spans.values_mut() {
-            span_index.remove_list_sorted(span_events);
+            span_index.remove_list_sorted(&entries);
         }
     }
 
@@ -8,24 +8,30 @@ use crate::filter::BoundSearch;
 use crate::models::{FullSpanId, Timestamp, TraceRoot, Value};
 use crate::{InstanceId, ResourceKey, SpanKey, Storage};
 
-use super::{IndexExt, ValueIndex};
+use super::{entries_of, IndexEntry, IndexExt, ValueIndex};
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct SpanIndexes {
-    pub all: Vec<Timestamp>,
+    pub all: Vec<IndexEntry>,
+    pub keys: Vec<SpanKey>,
     pub ids: HashMap<FullSpanId, SpanKey>,
-    pub levels: [Vec<Timestamp>; 6],
+    pub levels: [Vec<IndexEntry>; 6],
     pub durations: SpanDurationIndex,
-    pub instances: BTreeMap<InstanceId, Vec<Timestamp>>,
-    pub resources: BTreeMap<ResourceKey, Vec<Timestamp>>,
-    pub names: BTreeMap<String, Vec<Timestamp>>,
-    pub functions: BTreeMap<String, Vec<Timestamp>>,
-    pub namespaces: BTreeMap<String, Vec<Timestamp>>,
-    pub filenames: BTreeMap<String, Vec<Timestamp>>,
-    pub roots: Vec<Timestamp>,
-    pub traces: HashMap<TraceRoot, Vec<Timestamp>>,
+    pub instances: BTreeMap<InstanceId, Vec<IndexEntry>>,
+    pub resources: BTreeMap<ResourceKey, Vec<IndexEntry>>,
+    pub names: BTreeMap<String, Vec<IndexEntry>>,
+    pub functions: BTreeMap<String, Vec<IndexEntry>>,
+    pub namespaces: BTreeMap<String, Vec<IndexEntry>>,
+    pub filenames: BTreeMap<String, Vec<IndexEntry>>,
+    pub roots: Vec<IndexEntry>,
+    pub traces: HashMap<TraceRoot, Vec<IndexEntry>>,
+    pub children: HashMap<SpanKey, Vec<Timestamp>>,
     pub attributes: BTreeMap<String, ValueIndex>,
 
+    // pins are persisted separately, so this is filled in by the engine
+    #[serde(skip)]
+    pub pinned: Vec<IndexEntry>,
+
     // spans whose `parent_id` has not been seen yet
     pub orphanage: HashMap<FullSpanId, Vec<Timestamp>>,
 }
@@ -34,6 +40,7 @@ impl SpanIndexes {
     pub fn new() -> SpanIndexes {
         SpanIndexes {
             all: vec![],
+            keys: vec![],
             levels: [
                 Vec::new(),
                 Vec::new(),
@@ -51,7 +58,9 @@ impl SpanIndexes {
             filenames: BTreeMap::new(),
             roots: Vec::new(),
             traces: HashMap::new(),
+            children: HashMap::new(),
             attributes: BTreeMap::new(),
+            pinned: Vec::new(),
             ids: HashMap::new(),
             orphanage: HashMap::new(),
         }
@@ -62,16 +71,20 @@ impl SpanIndexes {
         context: &SpanContext<'_, S>,
     ) -> Vec<SpanKey> {
         let span = context.span();
-        let span_key = span.created_at;
+        let span_key = span.key;
+        let entry = IndexEntry::new(span.created_at, span_key);
+
+        let idx = self.all.upper_bound_via_expansion(&entry);
+        self.all.insert(idx, entry);
 
-        let idx = self.all.upper_bound_via_expansion(&span_key);
-        self.all.insert(idx, span_key);
+        let idx = self.keys.upper_bound_via_expansion(&span_key);
+        self.keys.insert(idx, span_key);
 
         self.ids.insert(span.id, span_key);
 
         let level_index = &mut self.levels[span.level.into_simple_level() as usize];
-        let idx = level_index.upper_bound_via_expansion(&span_key);
-        level_index.insert(idx, span_key);
+        let idx = level_index.upper_bound_via_expansion(&entry);
+        level_index.insert(idx, entry);
 
         let duration_index = match span.duration() {
             None => &mut self.durations.open,
@@ -85,49 +98,55 @@ impl SpanIndexes {
             Some(16000000..=63999999) => &mut self.durations.closed_64_s,
             Some(64000000..) => &mut self.durations.closed_long,
         };
-        let idx = duration_index.upper_bound_via_expansion(&span_key);
-        duration_index.insert(idx, span_key);
+        let idx = duration_index.upper_bound_via_expansion(&entry);
+        duration_index.insert(idx, entry);
 
         if let FullSpanId::Tracing(instance_id, _) = span.id {
             let instance_index = self.instances.entry(instance_id).or_default();
-            let idx = instance_index.upper_bound_via_expansion(&span_key);
-            instance_index.insert(idx, span_key);
+            let idx = instance_index.upper_bound_via_expansion(&entry);
+            instance_index.insert(idx, entry);
         }
 
         // TODO: do I need a per-resource index?
         let resource_index = self.resources.entry(span.resource_key).or_default();
-        let idx = resource_index.upper_bound_via_expansion(&span_key);
-        resource_index.insert(idx, span_key);
+        let idx = resource_index.upper_bound_via_expansion(&entry);
+        resource_index.insert(idx, entry);
 
         let name_index = self.names.entry(span.name.clone()).or_default();
-        let idx = name_index.upper_bound_via_expansion(&span_key);
-        name_index.insert(idx, span_key);
+        let idx = name_index.upper_bound_via_expansion(&entry);
+        name_index.insert(idx, entry);
 
         if let Some(function) = span.function.clone() {
             let function_index = self.functions.entry(function).or_default();
-            let idx = function_index.upper_bound_via_expansion(&span_key);
-            function_index.insert(idx, span_key);
+            let idx = function_index.upper_bound_via_expansion(&entry);
+            function_index.insert(idx, entry);
         }
 
         if let Some(namespace) = span.namespace.clone() {
             let namespace_index = self.namespaces.entry(namespace).or_default();
-            let idx = namespace_index.upper_bound_via_expansion(&span_key);
-            namespace_index.insert(idx, span_key);
+            let idx = namespace_index.upper_bound_via_expansion(&entry);
+            namespace_index.insert(idx, entry);
         }
 
         if let Some(filename) = &span.file_name {
             let filename_index = self.filenames.entry(filename.clone()).or_default();
-            let idx = filename_index.upper_bound_via_expansion(&span_key);
-            filename_index.insert(idx, span_key);
+            let idx = filename_index.upper_bound_via_expansion(&entry);
+            filename_index.insert(idx, entry);
         }
 
         let trace_index = self.traces.entry(context.trace_root()).or_default();
-        let idx = trace_index.upper_bound_via_expansion(&span_key);
-        trace_index.insert(idx, span_key);
+        let idx = trace_index.upper_bound_via_expansion(&entry);
+        trace_index.insert(idx, entry);
 
         if span.parent_id.is_none() {
-            let idx = self.roots.upper_bound_via_expansion(&span_key);
-            self.roots.insert(idx, span_key);
+            let idx = self.roots.upper_bound_via_expansion(&entry);
+            self.roots.insert(idx, entry);
+        }
+
+        if let Some(parent_key) = span.parent_key {
+            let children_index = self.children.entry(parent_key).or_default();
+            let idx = children_index.upper_bound_via_expansion(&span_key);
+            children_index.insert(idx, span_key);
         }
 
         if let (Some(parent_id), None) = (span.parent_id, span.parent_key) {
@@ -142,10 +161,19 @@ impl SpanIndexes {
                 .entry(attribute.to_owned())
                 .or_insert_with(ValueIndex::new);
 
-            index.add_entry(span_key, value);
+            index.add_entry(entry, value);
+        }
+
+        let orphans = self.orphanage.remove(&span.id).unwrap_or_default();
+        if !orphans.is_empty() {
+            let children_index = self.children.entry(span_key).or_default();
+            for orphan_key in &orphans {
+                let idx = children_index.upper_bound_via_expansion(orphan_key);
+                children_index.insert(idx, *orphan_key);
+            }
         }
 
-        self.orphanage.remove(&span.id).unwrap_or_default()
+        orphans
     }
 
     pub fn update_with_new_field_on_parent<S: Storage>(
@@ -154,6 +182,8 @@ impl SpanIndexes {
         parent_key: Timestamp,
         parent_attributes: &BTreeMap<String, Value>,
     ) {
+        let entry = IndexEntry::new(context.span().created_at, context.key());
+
         for (attribute, new_value) in parent_attributes {
             let attribute_index = self
                 .attributes
@@ -162,25 +192,25 @@ impl SpanIndexes {
 
             if let Some((old_value, key)) = context.attribute_with_key(attribute) {
                 if key <= parent_key && new_value != old_value {
-                    attribute_index.remove_entry(context.key(), old_value);
-                    attribute_index.add_entry(context.key(), new_value);
+                    attribute_index.remove_entry(entry, old_value);
+                    attribute_index.add_entry(entry, new_value);
                 }
             } else {
                 // there was no old value, just insert
-                attribute_index.add_entry(context.key(), new_value);
+                attribute_index.add_entry(entry, new_value);
             }
         }
     }
 
-    pub fn update_with_closed(&mut self, span_key: Timestamp, closed_at: Timestamp) -> bool {
-        let idx = self.durations.open.lower_bound(&span_key);
-        if self.durations.open.get(idx) == Some(&span_key) {
+    // the duration is in microseconds
+    pub fn update_with_closed(&mut self, entry: IndexEntry, duration: u64) -> bool {
+        let idx = self.durations.open.lower_bound(&entry);
+        if self.durations.open.get(idx) == Some(&entry) {
             self.durations.open.remove(idx);
         } else {
             return false;
         }
 
-        let duration = closed_at.get().saturating_sub(span_key.get());
         let index = match duration {
             0..=3999 => &mut self.durations.closed_4_ms,
             4000..=15999 => &mut self.durations.closed_16_ms,
@@ -193,62 +223,83 @@ impl SpanIndexes {
             64000000.. => &mut self.durations.closed_long,
         };
 
-        let idx = index.upper_bound_via_expansion(&span_key);
-        index.insert(idx, span_key);
+        let idx = index.upper_bound_via_expansion(&entry);
+        index.insert(idx, entry);
         true
     }
 
     pub fn remove_spans(&mut self, spans: &[SpanKey]) {
-        self.all.remove_list_sorted(spans);
+        let entries = entries_of(&self.all, spans);
+
+        self.all.remove_list_sorted(&entries);
+        self.keys.remove_list_sorted(spans);
 
         self.ids.retain(|_, key| !spans.contains(key));
 
         for level_index in &mut self.levels {
-            level_index.remove_list_sorted(spans);
+            level_index.remove_list_sorted(&entries);
         }
 
-        self.durations.remove_spans(spans);
+        self.durations.remove_entries(&entries);
 
         for resource_index in self.resources.values_mut() {
-            resource_index.remove_list_sorted(spans);
+            resource_index.remove_list_sorted(&entries);
         }
 
-        for name_index in self.functions.values_mut() {
-            name_index.remove_list_sorted(spans);
+        for instance_index in self.instances.values_mut() {
+            instance_index.remove_list_sorted(&entries);
+        }
+
+        for name_index in self.names.values_mut() {
+            name_index.remove_list_sorted(&entries);
         }
 
         for namespace_index in self.namespaces.values_mut() {
-            namespace_index.remove_list_sorted(spans);
+            namespace_index.remove_list_sorted(&entries);
         }
 
         for function_index in self.functions.values_mut() {
-            function_index.remove_list_sorted(spans);
+            function_index.remove_list_sorted(&entries);
         }
 
         for filename_index in self.filenames.values_mut() {
-            filename_index.remove_list_sorted(spans);
+            filename_index.remove_list_sorted(&entries);
         }
 
-        self.roots.remove_list_sorted(spans);
+        self.roots.remove_list_sorted(&entries);
+
+        for trace_index in self.traces.values_mut() {
+            trace_index.remove_list_sorted(&entries);
+        }
+
+        for span_key in spans {
+            self.children.remove(span_key);
+        }
+
+        for children_index in self.children.values_mut() {
+            children_index.remove_list_sorted(spans);
+        }
+
+        self.pinned.remove_list_sorted(&entries);
 
         for attribute_index in self.attributes.values_mut() {
-            attribute_index.remove_entries(spans);
+            attribute_index.remove_entries(&entries);
         }
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SpanDurationIndex {
-    closed_4_ms: Vec<Timestamp>,   // span ids with durations shorter than 4ms
-    closed_16_ms: Vec<Timestamp>,  // span ids with durations between [4ms and 16ms)
-    closed_64_ms: Vec<Timestamp>,  // span ids with durations between [16ms and 64ms)
-    closed_256_ms: Vec<Timestamp>, // span ids with durations between [64ms and 256ms)
-    closed_1_s: Vec<Timestamp>,    // span ids with durations between [256ms and 1s)
-    closed_4_s: Vec<Timestamp>,    // span ids with durations between [1s and 4s)
-    closed_16_s: Vec<Timestamp>,   // span ids with durations between [4s and 16s)
-    closed_64_s: Vec<Timestamp>,   // span ids with durations between [16s and 64s)
-    closed_long: Vec<Timestamp>,   // span ids with durations 64s and longer
-    pub open: Vec<Timestamp>,      // span ids that haven't finished yet
+    closed_4_ms: Vec<IndexEntry>, // span ids with durations shorter than 4ms
+    closed_16_ms: Vec<IndexEntry>, // span ids with durations between [4ms and 16ms)
+    closed_64_ms: Vec<IndexEntry>, // span ids with durations between [16ms and 64ms)
+    closed_256_ms: Vec<IndexEntry>, // span ids with durations between [64ms and 256ms)
+    closed_1_s: Vec<IndexEntry>,  // span ids with durations between [256ms and 1s)
+    closed_4_s: Vec<IndexEntry>,  // span ids with durations between [1s and 4s)
+    closed_16_s: Vec<IndexEntry>, // span ids with durations between [4s and 16s)
+    closed_64_s: Vec<IndexEntry>, // span ids with durations between [16s and 64s)
+    closed_long: Vec<IndexEntry>, // span ids with durations 64s and longer
+    pub open: Vec<IndexEntry>,    // span ids that haven't finished yet
 }
 
 impl SpanDurationIndex {
@@ -267,7 +318,7 @@ impl SpanDurationIndex {
         }
     }
 
-    pub fn to_stratified_indexes(&self) -> Vec<(&'_ [Timestamp], Range<u64>)> {
+    pub fn to_stratified_indexes(&self) -> Vec<(&'_ [IndexEntry], Range<u64>)> {
         vec![
             (&self.closed_4_ms, 0..4000),
             (&self.closed_16_ms, 4000..16000),
@@ -282,16 +333,16 @@ impl SpanDurationIndex {
         ]
     }
 
-    pub fn remove_spans(&mut self, spans: &[SpanKey]) {
-        self.closed_4_ms.remove_list_sorted(spans);
-        self.closed_16_ms.remove_list_sorted(spans);
-        self.closed_64_ms.remove_list_sorted(spans);
-        self.closed_256_ms.remove_list_sorted(spans);
-        self.closed_1_s.remove_list_sorted(spans);
-        self.closed_4_s.remove_list_sorted(spans);
-        self.closed_16_s.remove_list_sorted(spans);
-        self.closed_64_s.remove_list_sorted(spans);
-        self.closed_long.remove_list_sorted(spans);
-        self.open.remove_list_sorted(spans);
+    pub fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.closed_4_ms.remove_list_sorted(entries);
+        self.closed_16_ms.remove_list_sorted(entries);
+        self.closed_64_ms.remove_list_sorted(entries);
+        self.closed_256_ms.remove_list_sorted(entries);
+        self.closed_1_s.remove_list_sorted(entries);
+        self.closed_4_s.remove_list_sorted(entries);
+        self.closed_16_s.remove_list_sorted(entries);
+        self.closed_64_s.remove_list_sorted(entries);
+        self.closed_long.remove_list_sorted(entries);
+        self.open.remove_list_sorted(entries);
     }
 }
@@ -6,6 +6,9 @@ pub trait IndexExt<T> {
     /// This is intended to remove elements in an efficient way for sorted
     /// `self` and `list`.
     fn remove_list_sorted(&mut self, list: &[T]);
+
+    /// This counts the elements that are in both sorted `self` and `list`.
+    fn count_list_sorted(&self, list: &[T]) -> usize;
 }
 
 impl<T: Ord> IndexExt<T> for Vec<T> {
@@ -22,6 +25,49 @@ impl<T: Ord> IndexExt<T> for Vec<T> {
             j += jj + 1;
         }
     }
+
+    fn count_list_sorted(&self, list: &[T]) -> usize {
+        let mut count = 0;
+        let mut i = 0;
+        let mut j = 0;
+
+        while let Some((ii, jj)) = find_next_match(&self[i..], &list[j..]) {
+            count += 1;
+
+            i += ii + 1;
+            j += jj + 1;
+        }
+
+        count
+    }
+}
+
+/// Finds which of a set of sorted indexes holds each key, for keys that are
+/// looked up in ascending order. Each index is only searched forward from
+/// where the last lookup left it, so looking up every key of a sorted list is
+/// linear rather than a full binary search per index per key.
+pub(crate) struct SortedIndexLookup<'a, T> {
+    indexes: Vec<&'a [T]>,
+}
+
+impl<'a, T: Ord> SortedIndexLookup<'a, T> {
+    pub(crate) fn new(indexes: Vec<&'a [T]>) -> SortedIndexLookup<'a, T> {
+        SortedIndexLookup { indexes }
+    }
+
+    /// Returns the position of the first index that holds `item`. This must be
+    /// called with items in ascending order.
+    pub(crate) fn find(&mut self, item: &T) -> Option<usize> {
+        for (i, index) in self.indexes.iter_mut().enumerate() {
+            *index = &index[index.lower_bound_via_expansion(item)..];
+
+            if index.first() == Some(item) {
+                return Some(i);
+            }
+        }
+
+        None
+    }
 }
 
 // Returns the indexes from the respective lists of the first element that is
@@ -38,13 +84,13 @@ fn find_next_match<T: Ord>(a: &[T], b: &[T]) -> Option<(usize, usize)> {
         match Ord::cmp(&a[i], &b[j]) {
             Ordering::Equal => return Some((i, j)),
             Ordering::Less => {
-                i = a[i..].lower_bound_via_expansion(&b[j]);
+                i += a[i..].lower_bound_via_expansion(&b[j]);
                 if i == a.len() {
                     return None;
                 }
             }
             Ordering::Greater => {
-                j = b[j..].lower_bound_via_expansion(&a[i]);
+                j += b[j..].lower_bound_via_expansion(&a[i]);
                 if j == b.len() {
                     return None;
                 }
@@ -52,3 +98,37 @@ fn find_next_match<T: Ord>(a: &[T], b: &[T]) -> Option<(usize, usize)> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_index_lookup_finds_the_holding_index() {
+        let a = [1, 4, 6];
+        let b = [2, 3, 7];
+        let mut lookup = SortedIndexLookup::new(vec![&a[..], &b[..]]);
+
+        assert_eq!(lookup.find(&1), Some(0));
+        assert_eq!(lookup.find(&3), Some(1));
+        assert_eq!(lookup.find(&5), None);
+        assert_eq!(lookup.find(&6), Some(0));
+        assert_eq!(lookup.find(&7), Some(1));
+        assert_eq!(lookup.find(&8), None);
+    }
+
+    #[test]
+    fn find_next_match_advances_from_current_position() {
+        // reaching 7 requires advancing through `a` more than once, and
+        // each advance must be relative to where the previous one ended
+        let a = [1, 2, 3, 6, 7];
+        let b = [4, 7];
+
+        assert_eq!(find_next_match(&a, &b), Some((4, 1)));
+        assert_eq!(find_next_match(&a, &b[..1]), None);
+
+        let mut list = a.to_vec();
+        list.remove_list_sorted(&b);
+        assert_eq!(list, [1, 2, 3, 6]);
+    }
+}
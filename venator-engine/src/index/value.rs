@@ -1,12 +1,13 @@
 use std::collections::BTreeMap;
+use std::ops::Bound;
 
 use serde::{Deserialize, Serialize};
 
 use crate::filter::{BoundSearch, ValueComparison, ValueFilter, ValueStringComparison};
-use crate::models::ValueOperator;
-use crate::{Timestamp, Value};
+use crate::models::{ValueKind, ValueOperator};
+use crate::Value;
 
-use super::IndexExt;
+use super::{IndexEntry, IndexExt};
 
 /// This is an index for `Value`s (so @attributes and #content).
 
@@ -16,7 +17,7 @@ use super::IndexExt;
 #[derive(Serialize, Deserialize)]
 pub(crate) struct ValueIndex {
     kind: String,
-    nulls: Vec<Timestamp>,
+    nulls: Vec<IndexEntry>,
     f64s: ValueF64Index,
     i64s: ValueI64Index,
     u64s: ValueU64Index,
@@ -48,39 +49,44 @@ impl ValueIndex {
         }
     }
 
-    pub(crate) fn add_entry(&mut self, key: Timestamp, value: &Value) {
+    pub(crate) fn add_entry(&mut self, entry: IndexEntry, value: &Value) {
         match value {
             Value::Null => {
-                let idx = self.nulls.upper_bound_via_expansion(&key);
-                self.nulls.insert(idx, key);
+                let idx = self.nulls.upper_bound_via_expansion(&entry);
+                self.nulls.insert(idx, entry);
             }
             Value::F64(_) => {
-                let idx = self.f64s.index.upper_bound_via_expansion(&key);
-                self.f64s.index.insert(idx, key);
+                let idx = self.f64s.index.upper_bound_via_expansion(&entry);
+                self.f64s.index.insert(idx, entry);
             }
             Value::I64(_) => {
-                let idx = self.i64s.index.upper_bound_via_expansion(&key);
-                self.i64s.index.insert(idx, key);
+                let idx = self.i64s.index.upper_bound_via_expansion(&entry);
+                self.i64s.index.insert(idx, entry);
             }
             Value::U64(_) => {
-                let idx = self.u64s.index.upper_bound_via_expansion(&key);
-                self.u64s.index.insert(idx, key);
+                let idx = self.u64s.index.upper_bound_via_expansion(&entry);
+                self.u64s.index.insert(idx, entry);
             }
             Value::I128(_) => {
-                let idx = self.i128s.index.upper_bound_via_expansion(&key);
-                self.i128s.index.insert(idx, key);
+                let idx = self.i128s.index.upper_bound_via_expansion(&entry);
+                self.i128s.index.insert(idx, entry);
             }
             Value::U128(_) => {
-                let idx = self.u128s.index.upper_bound_via_expansion(&key);
-                self.u128s.index.insert(idx, key);
+                let idx = self.u128s.index.upper_bound_via_expansion(&entry);
+                self.u128s.index.insert(idx, entry);
             }
-            Value::Bool(_) => {
-                let idx = self.f64s.index.upper_bound_via_expansion(&key);
-                self.f64s.index.insert(idx, key);
+            Value::Bool(value) => {
+                let index = match value {
+                    true => &mut self.bools.trues,
+                    false => &mut self.bools.falses,
+                };
+
+                let idx = index.upper_bound_via_expansion(&entry);
+                index.insert(idx, entry);
             }
             Value::Str(value) => {
-                let idx = self.strings.total.upper_bound_via_expansion(&key);
-                self.strings.total.insert(idx, key);
+                let idx = self.strings.total.upper_bound_via_expansion(&entry);
+                self.strings.total.insert(idx, entry);
 
                 let value_index = self
                     .strings
@@ -88,56 +94,61 @@ impl ValueIndex {
                     .entry(value.to_owned())
                     .or_default();
 
-                let idx = value_index.upper_bound_via_expansion(&key);
-                value_index.insert(idx, key);
+                let idx = value_index.upper_bound_via_expansion(&entry);
+                value_index.insert(idx, entry);
             }
             Value::Bytes(_) => {
-                let idx = self.bytes.index.upper_bound_via_expansion(&key);
-                self.bytes.index.insert(idx, key);
+                let idx = self.bytes.index.upper_bound_via_expansion(&entry);
+                self.bytes.index.insert(idx, entry);
             }
             Value::Array(_) => {
-                let idx = self.arrays.index.upper_bound_via_expansion(&key);
-                self.arrays.index.insert(idx, key);
+                let idx = self.arrays.index.upper_bound_via_expansion(&entry);
+                self.arrays.index.insert(idx, entry);
             }
             Value::Object(_) => {
-                let idx = self.objects.index.upper_bound_via_expansion(&key);
-                self.objects.index.insert(idx, key);
+                let idx = self.objects.index.upper_bound_via_expansion(&entry);
+                self.objects.index.insert(idx, entry);
             }
         }
     }
 
-    pub(crate) fn remove_entry(&mut self, key: Timestamp, value: &Value) {
+    pub(crate) fn remove_entry(&mut self, entry: IndexEntry, value: &Value) {
         match value {
             Value::Null => {
-                let idx = self.nulls.lower_bound(&key);
+                let idx = self.nulls.lower_bound(&entry);
                 self.nulls.remove(idx);
             }
             Value::F64(_) => {
-                let idx = self.f64s.index.lower_bound(&key);
+                let idx = self.f64s.index.lower_bound(&entry);
                 self.f64s.index.remove(idx);
             }
             Value::I64(_) => {
-                let idx = self.i64s.index.lower_bound(&key);
+                let idx = self.i64s.index.lower_bound(&entry);
                 self.i64s.index.remove(idx);
             }
             Value::U64(_) => {
-                let idx = self.u64s.index.lower_bound(&key);
+                let idx = self.u64s.index.lower_bound(&entry);
                 self.u64s.index.remove(idx);
             }
             Value::I128(_) => {
-                let idx = self.i128s.index.lower_bound(&key);
+                let idx = self.i128s.index.lower_bound(&entry);
                 self.i128s.index.remove(idx);
             }
             Value::U128(_) => {
-                let idx = self.u128s.index.lower_bound(&key);
+                let idx = self.u128s.index.lower_bound(&entry);
                 self.u128s.index.remove(idx);
             }
-            Value::Bool(_) => {
-                let idx = self.f64s.index.lower_bound(&key);
-                self.f64s.index.remove(idx);
+            Value::Bool(value) => {
+                let index = match value {
+                    true => &mut self.bools.trues,
+                    false => &mut self.bools.falses,
+                };
+
+                let idx = index.lower_bound(&entry);
+                index.remove(idx);
             }
             Value::Str(value) => {
-                let idx = self.strings.total.lower_bound(&key);
+                let idx = self.strings.total.lower_bound(&entry);
                 self.strings.total.remove(idx);
 
                 let value_index = self
@@ -146,36 +157,86 @@ impl ValueIndex {
                     .entry(value.to_owned())
                     .or_default();
 
-                let idx = value_index.lower_bound(&key);
+                let idx = value_index.lower_bound(&entry);
                 value_index.remove(idx);
             }
             Value::Bytes(_) => {
-                let idx = self.bytes.index.lower_bound(&key);
+                let idx = self.bytes.index.lower_bound(&entry);
                 self.bytes.index.remove(idx);
             }
             Value::Array(_) => {
-                let idx = self.arrays.index.lower_bound(&key);
+                let idx = self.arrays.index.lower_bound(&entry);
                 self.arrays.index.remove(idx);
             }
             Value::Object(_) => {
-                let idx = self.objects.index.lower_bound(&key);
+                let idx = self.objects.index.lower_bound(&entry);
                 self.objects.index.remove(idx);
             }
         }
     }
 
-    pub(crate) fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.nulls.remove_list_sorted(keys);
-        self.f64s.remove_entries(keys);
-        self.i64s.remove_entries(keys);
-        self.u64s.remove_entries(keys);
-        self.i128s.remove_entries(keys);
-        self.u128s.remove_entries(keys);
-        self.bools.remove_entries(keys);
-        self.strings.remove_entries(keys);
-        self.bytes.remove_entries(keys);
-        self.arrays.remove_entries(keys);
-        self.objects.remove_entries(keys);
+    pub(crate) fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.nulls.remove_list_sorted(entries);
+        self.f64s.remove_entries(entries);
+        self.i64s.remove_entries(entries);
+        self.u64s.remove_entries(entries);
+        self.i128s.remove_entries(entries);
+        self.u128s.remove_entries(entries);
+        self.bools.remove_entries(entries);
+        self.strings.remove_entries(entries);
+        self.bytes.remove_entries(entries);
+        self.arrays.remove_entries(entries);
+        self.objects.remove_entries(entries);
+    }
+
+    /// This returns how many of the sorted `entries` have a value of each kind.
+    /// Kinds that none of the entries have are omitted.
+    pub(crate) fn count_kinds(&self, entries: &[IndexEntry]) -> Vec<(ValueKind, usize)> {
+        let counts = [
+            (ValueKind::Null, self.nulls.count_list_sorted(entries)),
+            (ValueKind::F64, self.f64s.index.count_list_sorted(entries)),
+            (ValueKind::I64, self.i64s.index.count_list_sorted(entries)),
+            (ValueKind::U64, self.u64s.index.count_list_sorted(entries)),
+            (ValueKind::I128, self.i128s.index.count_list_sorted(entries)),
+            (ValueKind::U128, self.u128s.index.count_list_sorted(entries)),
+            (
+                ValueKind::Bool,
+                self.bools.trues.count_list_sorted(entries)
+                    + self.bools.falses.count_list_sorted(entries),
+            ),
+            (
+                ValueKind::Str,
+                self.strings.total.count_list_sorted(entries),
+            ),
+            (
+                ValueKind::Bytes,
+                self.bytes.index.count_list_sorted(entries),
+            ),
+            (
+                ValueKind::Array,
+                self.arrays.index.count_list_sorted(entries),
+            ),
+            (
+                ValueKind::Object,
+                self.objects.index.count_list_sorted(entries),
+            ),
+        ];
+
+        counts.into_iter().filter(|(_, count)| *count > 0).collect()
+    }
+
+    /// This returns the string values that start with `prefix` along with how
+    /// many entries have that value.
+    pub(crate) fn string_values<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (&'a str, usize)> + 'a {
+        self.strings
+            .value_indexes
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(value, _)| value.starts_with(prefix))
+            .filter(|(_, index)| !index.is_empty())
+            .map(|(value, index)| (value.as_str(), index.len()))
     }
 
     /// This returns a set of indexed filters that when OR'd together will yield
@@ -183,8 +244,8 @@ impl ValueIndex {
     pub(crate) fn make_indexed_filter(
         &self,
         filter: ValueFilter,
-    ) -> Vec<(&[Timestamp], Option<ValueFilter>)> {
-        let mut filters: Vec<(&[Timestamp], _)> = vec![];
+    ) -> Vec<(&[IndexEntry], Option<ValueFilter>)> {
+        let mut filters: Vec<(&[IndexEntry], _)> = vec![];
 
         match &filter.f64s {
             ValueComparison::None => {}
@@ -271,8 +332,8 @@ impl ValueIndex {
 #[derive(Serialize, Deserialize)]
 struct AttributeStringIndex {
     kind: String,
-    total: Vec<Timestamp>,
-    value_indexes: BTreeMap<String, Vec<Timestamp>>,
+    total: Vec<IndexEntry>,
+    value_indexes: BTreeMap<String, Vec<IndexEntry>>,
 }
 
 impl AttributeStringIndex {
@@ -284,17 +345,17 @@ impl AttributeStringIndex {
         }
     }
 
-    fn value_index<'a>(&'a self, value: &str) -> &'a [Timestamp] {
+    fn value_index<'a>(&'a self, value: &str) -> &'a [IndexEntry] {
         self.value_indexes
             .get(value)
             .map(Vec::as_slice)
             .unwrap_or_default()
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.total.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.total.remove_list_sorted(entries);
         for value_index in self.value_indexes.values_mut() {
-            value_index.remove_list_sorted(keys);
+            value_index.remove_list_sorted(entries);
         }
     }
 }
@@ -303,7 +364,7 @@ impl AttributeStringIndex {
 struct ValueF64Index {
     // TODO: figure out how best to do categorical & numerical indexing
     kind: String,
-    index: Vec<Timestamp>,
+    index: Vec<IndexEntry>,
 }
 
 impl ValueF64Index {
@@ -314,8 +375,8 @@ impl ValueF64Index {
         }
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.index.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.index.remove_list_sorted(entries);
     }
 }
 
@@ -323,7 +384,7 @@ impl ValueF64Index {
 struct ValueI64Index {
     // TODO: figure out how best to do categorical & numerical indexing
     kind: String,
-    index: Vec<Timestamp>,
+    index: Vec<IndexEntry>,
 }
 
 impl ValueI64Index {
@@ -334,8 +395,8 @@ impl ValueI64Index {
         }
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.index.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.index.remove_list_sorted(entries);
     }
 }
 
@@ -343,7 +404,7 @@ impl ValueI64Index {
 struct ValueU64Index {
     // TODO: figure out how best to do categorical & numerical indexing
     kind: String,
-    index: Vec<Timestamp>,
+    index: Vec<IndexEntry>,
 }
 
 impl ValueU64Index {
@@ -354,8 +415,8 @@ impl ValueU64Index {
         }
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.index.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.index.remove_list_sorted(entries);
     }
 }
 
@@ -363,7 +424,7 @@ impl ValueU64Index {
 struct ValueI128Index {
     // TODO: figure out how best to do categorical & numerical indexing
     kind: String,
-    index: Vec<Timestamp>,
+    index: Vec<IndexEntry>,
 }
 
 impl ValueI128Index {
@@ -374,8 +435,8 @@ impl ValueI128Index {
         }
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.index.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.index.remove_list_sorted(entries);
     }
 }
 
@@ -383,7 +444,7 @@ impl ValueI128Index {
 struct ValueU128Index {
     // TODO: figure out how best to do categorical & numerical indexing
     kind: String,
-    index: Vec<Timestamp>,
+    index: Vec<IndexEntry>,
 }
 
 impl ValueU128Index {
@@ -394,16 +455,16 @@ impl ValueU128Index {
         }
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.index.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.index.remove_list_sorted(entries);
     }
 }
 
 #[derive(Serialize, Deserialize)]
 struct ValueBoolIndex {
     kind: String,
-    trues: Vec<Timestamp>,
-    falses: Vec<Timestamp>,
+    trues: Vec<IndexEntry>,
+    falses: Vec<IndexEntry>,
 }
 
 impl ValueBoolIndex {
@@ -415,9 +476,9 @@ impl ValueBoolIndex {
         }
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.trues.remove_list_sorted(keys);
-        self.falses.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.trues.remove_list_sorted(entries);
+        self.falses.remove_list_sorted(entries);
     }
 }
 
@@ -425,7 +486,7 @@ impl ValueBoolIndex {
 struct ValueByteIndex {
     // TODO: figure out how best to do indexing
     kind: String,
-    index: Vec<Timestamp>,
+    index: Vec<IndexEntry>,
 }
 
 impl ValueByteIndex {
@@ -436,8 +497,8 @@ impl ValueByteIndex {
         }
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.index.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.index.remove_list_sorted(entries);
     }
 }
 
@@ -445,7 +506,7 @@ impl ValueByteIndex {
 struct ValueArrayIndex {
     // TODO: figure out how best to do indexing
     kind: String,
-    index: Vec<Timestamp>,
+    index: Vec<IndexEntry>,
 }
 
 impl ValueArrayIndex {
@@ -456,8 +517,8 @@ impl ValueArrayIndex {
         }
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.index.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.index.remove_list_sorted(entries);
     }
 }
 
@@ -465,7 +526,7 @@ impl ValueArrayIndex {
 struct ValueObjectIndex {
     // TODO: figure out how best to do indexing
     kind: String,
-    index: Vec<Timestamp>,
+    index: Vec<IndexEntry>,
 }
 
 impl ValueObjectIndex {
@@ -476,7 +537,7 @@ impl ValueObjectIndex {
         }
     }
 
-    fn remove_entries(&mut self, keys: &[Timestamp]) {
-        self.index.remove_list_sorted(keys);
+    fn remove_entries(&mut self, entries: &[IndexEntry]) {
+        self.index.remove_list_sorted(entries);
     }
 }
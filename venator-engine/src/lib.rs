@@ -11,11 +11,17 @@ mod subscription;
 use storage::Storage;
 
 pub use models::{
-    Ancestor, Attribute, AttributeSource, ComposedEvent, ComposedSpan, CreateSpanEvent,
-    DatasetStats, DeleteFilter, DeleteMetrics, EngineStatus, Event, EventKey, FullSpanId,
-    InstanceId, Level, LevelConvertError, NewCloseSpanEvent, NewCreateSpanEvent, NewEnterSpanEvent,
+    to_micros, Aggregate, AggregateField, AggregateQuery, AggregateRow, Ancestor, Annotation,
+    AnnotationKey, AnnotationTarget, Attribute, AttributeSource, AttributeSummary,
+    AttributeValueCount, CompactMetrics, ComposedEvent, ComposedSpan, CreateSpanEvent,
+    CriticalPathSpan, DatasetStats, DeleteFilter, DeleteMetrics, DeleteMode, EngineStatus, Event,
+    EventKey, FlameGraph, FlameNode, FullSpanId, GroupKey, HistogramBucket, InstanceId,
+    IntegrityReport, LatencyBucket, LatencyGroup, LatencyStats, Level, LevelConvertError,
+    MergeMetrics, NewAnnotation, NewCloseSpanEvent, NewCreateSpanEvent, NewEnterSpanEvent,
     NewEvent, NewFollowsSpanEvent, NewResource, NewSpanEvent, NewSpanEventKind, NewUpdateSpanEvent,
-    Resource, ResourceKey, SourceKind, Span, SpanEvent, SpanEventKey, SpanEventKind, SpanId,
-    SpanKey, Timestamp, TraceId, TraceRoot, UpdateSpanEvent, Value, ValueOperator,
+    Pin, PreciseTimestamp, Resource, ResourceKey, ServiceDependency, SourceKind, Span, SpanEvent,
+    SpanEventKey, SpanEventKind, SpanEventType, SpanId, SpanKey, Timestamp, Tombstone,
+    TombstoneKey, Trace, TraceEvent, TraceId, TraceRoot, TraceSpan, UpdateSpanEvent, Value,
+    ValueKind, ValueOperator,
 };
 pub use subscription::{SubscriptionId, SubscriptionResponse};
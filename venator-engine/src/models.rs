@@ -7,8 +7,18 @@ use std::str::FromStr;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use crate::filter::{FilterPredicate, InputError};
+use crate::storage::CacheStats;
+
+/// This is the type used for the coarse time used to order and look up entities
+/// as well as for query bounds. The value is the number of microseconds since
+/// the Unix epoch.
 pub type Timestamp = NonZeroU64;
 
+/// This is the type used for the precise time that something happened. The
+/// value is the number of nanoseconds since the Unix epoch.
+pub type PreciseTimestamp = u64;
+
 /// This is the internal type used to identify resources. The value is the
 /// unique timestamp from when the resource was created.
 pub type ResourceKey = NonZeroU64;
@@ -17,19 +27,29 @@ pub type ResourceKey = NonZeroU64;
 /// generated client-side and should be random to make it unique.
 pub type InstanceId = u128;
 
-/// This is the internal type used to identify spans. The value is the unique
-/// timestamp from when the span was created.
+/// This is the internal type used to identify spans. The value is a unique id
+/// allocated in the order the spans were created, which is near the microsecond
+/// `Timestamp` they were created at. It is not the creation time itself, that
+/// is kept separately with full precision.
 pub type SpanKey = NonZeroU64;
 
-/// This is the internal type used to identify span eventss. The value is the
-/// semi-unique timestamp from when the span event was created. "Semi-unique"
-/// because the "create" event shares a timestamp with the span it creates.
+/// This is the internal type used to identify span events. The value is a
+/// unique id allocated in the order the span events happened, like `SpanKey`.
 pub type SpanEventKey = NonZeroU64;
 
-/// This is the internal type used to identify events. The value is the unique
-/// timestamp from when the event was created.
+/// This is the internal type used to identify events. The value is a unique id
+/// allocated in the order the events happened, like `SpanKey`.
 pub type EventKey = NonZeroU64;
 
+/// This is the internal type used to identify a set of deleted entities that
+/// can still be restored. The value is the unique timestamp from when they were
+/// deleted.
+pub type TombstoneKey = NonZeroU64;
+
+/// This is the internal type used to identify an annotation. The value is the
+/// unique timestamp from when it was created.
+pub type AnnotationKey = NonZeroU64;
+
 /// This is the external type used to identity a span. This is generated client-
 /// side and is either unique within that instance (for tracing data) or unique
 /// within that trace (for opentelemetry data).
@@ -37,6 +57,11 @@ pub type SpanId = u64;
 
 pub type TraceId = u128;
 
+/// Converts a precise nanosecond timestamp into the coarse microsecond one.
+pub fn to_micros(timestamp: PreciseTimestamp) -> Timestamp {
+    Timestamp::new(timestamp / 1000).unwrap_or(Timestamp::MIN)
+}
+
 #[derive(Debug)]
 pub struct FullSpanIdParseError;
 
@@ -290,6 +315,19 @@ pub enum SimpleLevel {
     Fatal = 5,
 }
 
+impl Display for SimpleLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        match self {
+            SimpleLevel::Trace => write!(f, "TRACE"),
+            SimpleLevel::Debug => write!(f, "DEBUG"),
+            SimpleLevel::Info => write!(f, "INFO"),
+            SimpleLevel::Warn => write!(f, "WARN"),
+            SimpleLevel::Error => write!(f, "ERROR"),
+            SimpleLevel::Fatal => write!(f, "FATAL"),
+        }
+    }
+}
+
 impl SimpleLevel {
     pub fn iter_gte(self) -> impl Iterator<Item = SimpleLevel> {
         use SimpleLevel::*;
@@ -305,7 +343,7 @@ pub struct NewResource {
     pub attributes: BTreeMap<String, Value>,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Resource {
     pub created_at: Timestamp,
     pub attributes: BTreeMap<String, Value>,
@@ -319,7 +357,7 @@ impl Resource {
 
 #[derive(Debug)]
 pub struct NewSpanEvent {
-    pub timestamp: Timestamp,
+    pub timestamp: PreciseTimestamp,
     pub span_id: FullSpanId,
     pub kind: NewSpanEventKind,
 }
@@ -334,14 +372,15 @@ pub enum NewSpanEventKind {
     Close(NewCloseSpanEvent),
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SpanEvent {
-    pub timestamp: Timestamp,
+    pub key: SpanEventKey,
+    pub timestamp: PreciseTimestamp,
     pub span_key: SpanKey,
     pub kind: SpanEventKind,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum SpanEventKind {
     Create(CreateSpanEvent),
     Update(UpdateSpanEvent),
@@ -351,6 +390,31 @@ pub enum SpanEventKind {
     Close(CloseSpanEvent),
 }
 
+impl SpanEventKind {
+    pub fn event_type(&self) -> SpanEventType {
+        match self {
+            SpanEventKind::Create(_) => SpanEventType::Create,
+            SpanEventKind::Update(_) => SpanEventType::Update,
+            SpanEventKind::Follows(_) => SpanEventType::Follows,
+            SpanEventKind::Enter(_) => SpanEventType::Enter,
+            SpanEventKind::Exit => SpanEventType::Exit,
+            SpanEventKind::Close(_) => SpanEventType::Close,
+        }
+    }
+}
+
+/// The kind of a span event without its data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpanEventType {
+    Create,
+    Update,
+    Follows,
+    Enter,
+    Exit,
+    Close,
+}
+
 #[derive(Debug)]
 pub struct NewCreateSpanEvent {
     pub kind: SourceKind,
@@ -405,7 +469,7 @@ pub struct NewEnterSpanEvent {
 
 #[derive(Debug)]
 pub struct NewCloseSpanEvent {
-    pub busy: Option<u64>,
+    pub busy: Option<u64>, // in nanoseconds
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -420,13 +484,13 @@ pub struct EnterSpanEvent {
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct CloseSpanEvent {
-    pub busy: Option<u64>,
+    pub busy: Option<u64>, // in nanoseconds
 }
 
 pub struct NewEvent {
     pub kind: SourceKind,
     pub resource_key: ResourceKey,
-    pub timestamp: Timestamp,
+    pub timestamp: PreciseTimestamp,
     pub span_id: Option<FullSpanId>,
     pub content: Value,
     pub namespace: Option<String>,
@@ -438,11 +502,12 @@ pub struct NewEvent {
     pub attributes: BTreeMap<String, Value>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Event {
     pub kind: SourceKind,
     pub resource_key: ResourceKey,
-    pub timestamp: Timestamp,
+    pub key: EventKey,
+    pub timestamp: PreciseTimestamp,
     pub parent_id: Option<FullSpanId>,
     pub parent_key: Option<SpanKey>,
     pub content: Value,
@@ -457,7 +522,7 @@ pub struct Event {
 
 impl Event {
     pub fn key(&self) -> EventKey {
-        self.timestamp
+        self.key
     }
 }
 
@@ -465,7 +530,8 @@ impl Event {
 pub struct ComposedEvent {
     pub kind: SourceKind,
     pub ancestors: Vec<Ancestor>, // in root-first order
-    pub timestamp: Timestamp,
+    pub key: EventKey,
+    pub timestamp: PreciseTimestamp,
     pub content: Value,
     pub namespace: Option<String>,
     pub function: Option<String>,
@@ -474,14 +540,15 @@ pub struct ComposedEvent {
     pub attributes: Vec<Attribute>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Span {
     pub kind: SourceKind,
     pub resource_key: ResourceKey,
     pub id: FullSpanId,
-    pub created_at: Timestamp,
-    pub closed_at: Option<Timestamp>,
-    pub busy: Option<u64>,
+    pub key: SpanKey,
+    pub created_at: PreciseTimestamp,
+    pub closed_at: Option<PreciseTimestamp>,
+    pub busy: Option<u64>, // in nanoseconds
     pub parent_id: Option<FullSpanId>,
     pub parent_key: Option<SpanKey>,
     pub links: Vec<(FullSpanId, BTreeMap<String, Value>)>,
@@ -498,13 +565,33 @@ pub struct Span {
 
 impl Span {
     pub fn key(&self) -> SpanKey {
-        self.created_at
+        self.key
     }
 
     // gets the duration of the span in microseconds if closed
     pub fn duration(&self) -> Option<u64> {
+        self.precise_duration().map(|duration| duration / 1000)
+    }
+
+    // gets the duration of the span in nanoseconds if closed
+    pub fn precise_duration(&self) -> Option<u64> {
         self.closed_at
-            .map(|closed_at| closed_at.get().saturating_sub(self.created_at.get()))
+            .map(|closed_at| closed_at.saturating_sub(self.created_at))
+    }
+
+    // gets the time the span was not entered in microseconds if closed and its
+    // busy time is known
+    pub fn idle(&self) -> Option<u64> {
+        let duration = self.precise_duration()?;
+        let busy = self.busy?;
+
+        Some(duration.saturating_sub(busy) / 1000)
+    }
+
+    // gets the microsecond timestamp the span was closed at, which is what is
+    // compared against query bounds
+    pub fn closed_at_micros(&self) -> Option<Timestamp> {
+        self.closed_at.map(to_micros)
     }
 }
 
@@ -513,9 +600,10 @@ pub struct ComposedSpan {
     pub kind: SourceKind,
     pub id: FullSpanId,
     pub ancestors: Vec<Ancestor>, // in root-first order
-    pub created_at: Timestamp,
-    pub closed_at: Option<Timestamp>,
-    pub busy: Option<u64>,
+    pub key: SpanKey,
+    pub created_at: PreciseTimestamp,
+    pub closed_at: Option<PreciseTimestamp>,
+    pub busy: Option<u64>, // in nanoseconds
     pub name: String,
     pub namespace: Option<String>,
     pub function: Option<String>,
@@ -525,6 +613,106 @@ pub struct ComposedSpan {
     pub attributes: Vec<Attribute>,
 }
 
+/// All the spans and events of a trace along with their tree structure.
+#[derive(Clone)]
+pub struct Trace {
+    pub root: TraceRoot,
+    /// The spans in depth-first order, with children in creation order.
+    pub spans: Vec<TraceSpan>,
+    /// The events in timestamp order.
+    pub events: Vec<TraceEvent>,
+}
+
+#[derive(Clone)]
+pub struct TraceSpan {
+    pub span: ComposedSpan,
+    /// The parent span, `None` if this is a root or if it is orphaned.
+    pub parent_key: Option<SpanKey>,
+    /// How many ancestors of this span are in the trace.
+    pub depth: usize,
+    /// Whether this span's parent has not arrived yet.
+    pub orphaned: bool,
+}
+
+#[derive(Clone)]
+pub struct TraceEvent {
+    pub event: ComposedEvent,
+    /// The parent span, `None` if this is a root or if it is orphaned.
+    pub parent_key: Option<SpanKey>,
+    /// How many ancestors of this event are in the trace.
+    pub depth: usize,
+    /// Whether this event's parent has not arrived yet.
+    pub orphaned: bool,
+}
+
+/// An aggregated call tree of spans, merged by the path of span names from
+/// the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlameGraph {
+    pub roots: Vec<FlameNode>,
+}
+
+impl FlameGraph {
+    /// Renders the graph in the "folded stacks" format where each line is a
+    /// `;`-separated path of names followed by the self-time in microseconds.
+    /// This is understood by tools like `inferno` and speedscope.
+    pub fn to_folded(&self) -> String {
+        fn write_node(node: &FlameNode, path: &mut String, folded: &mut String) {
+            let len = path.len();
+            if !path.is_empty() {
+                path.push(';');
+            }
+
+            // a `;` in a name would be mistaken for a separator
+            path.push_str(&node.name.replace(';', ":"));
+
+            if node.self_time > 0 {
+                folded.push_str(path);
+                folded.push(' ');
+                folded.push_str(&node.self_time.to_string());
+                folded.push('\n');
+            }
+
+            for child in &node.children {
+                write_node(child, path, folded);
+            }
+
+            path.truncate(len);
+        }
+
+        let mut folded = String::new();
+        for root in &self.roots {
+            write_node(root, &mut String::new(), &mut folded);
+        }
+
+        folded
+    }
+}
+
+/// The spans with the same path of names in a flame graph. All times are in
+/// microseconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlameNode {
+    pub name: String,
+    pub count: usize,
+    pub total_time: u64,
+    pub self_time: u64,
+    /// The children in name order.
+    pub children: Vec<FlameNode>,
+}
+
+/// A span on the critical path of a trace, i.e. the chain of spans that
+/// determined its end-to-end latency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CriticalPathSpan {
+    pub key: SpanKey,
+    pub name: String,
+    /// The time on the critical path spent in this span and not in any of its
+    /// children, in microseconds.
+    pub self_time: u64,
+    pub busy: Option<u64>, // in nanoseconds
+}
+
 #[derive(Clone, Serialize)]
 pub struct Ancestor {
     pub id: FullSpanId,
@@ -599,6 +787,39 @@ pub enum AttributeSource {
     Inherent,
 }
 
+/// The type of an attribute value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueKind {
+    Null,
+    F64,
+    I64,
+    U64,
+    I128,
+    U128,
+    Bool,
+    Str,
+    Bytes,
+    Array,
+    Object,
+}
+
+/// An attribute name and how many entities have it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeSummary {
+    pub name: String,
+    pub count: usize,
+    /// The types of values that were observed for this attribute.
+    pub kinds: Vec<ValueKind>,
+}
+
+/// A string value of an attribute and how many entities have it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeValueCount {
+    pub value: String,
+    pub count: usize,
+}
+
 pub struct DatasetStats {
     pub start: Option<Timestamp>,
     pub end: Option<Timestamp>,
@@ -606,19 +827,308 @@ pub struct DatasetStats {
     pub total_events: usize,
 }
 
+/// The entities within a slice of a histogram query, counted by level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramBucket {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    /// The counts indexed by `SimpleLevel`.
+    pub counts: [usize; 6],
+}
+
+/// A property that entities are grouped by in an aggregate query. This is
+/// parsed from `#property` or `@attribute` syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupKey {
+    Inherent(String),
+    Attribute(String),
+}
+
+impl FromStr for GroupKey {
+    type Err = InputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_at_checked(1) {
+            Some(("#", name)) if !name.is_empty() => Ok(GroupKey::Inherent(name.to_owned())),
+            Some(("@", name)) if !name.is_empty() => Ok(GroupKey::Attribute(name.to_owned())),
+            _ => Err(InputError::InvalidGroupKey),
+        }
+    }
+}
+
+/// The numeric property that an aggregate is computed over. Only `#duration`
+/// (in microseconds) and numeric attributes are supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AggregateField {
+    Duration,
+    Attribute(String),
+}
+
+impl FromStr for AggregateField {
+    type Err = InputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<GroupKey>() {
+            Ok(GroupKey::Inherent(name)) if name == "duration" => Ok(AggregateField::Duration),
+            Ok(GroupKey::Attribute(name)) => Ok(AggregateField::Attribute(name)),
+            _ => Err(InputError::InvalidAggregate),
+        }
+    }
+}
+
+/// A statistic computed for each group in an aggregate query. This is parsed
+/// from `count` or `<function>(<field>)` where the function is one of `min`,
+/// `max`, `sum`, `avg`, or a percentile like `p99`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregate {
+    Count,
+    Min(AggregateField),
+    Max(AggregateField),
+    Sum(AggregateField),
+    Avg(AggregateField),
+    Percentile(AggregateField, f64),
+}
+
+impl Aggregate {
+    pub fn field(&self) -> Option<&AggregateField> {
+        match self {
+            Aggregate::Count => None,
+            Aggregate::Min(field)
+            | Aggregate::Max(field)
+            | Aggregate::Sum(field)
+            | Aggregate::Avg(field)
+            | Aggregate::Percentile(field, _) => Some(field),
+        }
+    }
+}
+
+impl FromStr for Aggregate {
+    type Err = InputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "count" {
+            return Ok(Aggregate::Count);
+        }
+
+        let (function, field) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or(InputError::InvalidAggregate)?;
+
+        let field = field.trim().parse::<AggregateField>()?;
+
+        match function.trim() {
+            "min" => Ok(Aggregate::Min(field)),
+            "max" => Ok(Aggregate::Max(field)),
+            "sum" => Ok(Aggregate::Sum(field)),
+            "avg" => Ok(Aggregate::Avg(field)),
+            function => {
+                let percentile = function
+                    .strip_prefix('p')
+                    .and_then(|p| p.parse::<f64>().ok())
+                    .filter(|p| (0.0..=100.0).contains(p))
+                    .ok_or(InputError::InvalidAggregate)?;
+
+                Ok(Aggregate::Percentile(field, percentile))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AggregateQuery {
+    pub filter: Vec<FilterPredicate>,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub group_by: Vec<GroupKey>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+/// The result of an aggregate query for one distinct set of group values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateRow {
+    /// The value for each group key, `None` if the entities don't have it.
+    pub group: Vec<Option<String>>,
+    /// The result of each aggregate, `None` if there were no numeric values.
+    pub values: Vec<Option<f64>>,
+}
+
+/// What to group span latency statistics by.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyGroup {
+    Name,
+    Function,
+}
+
+/// The distribution of durations of a set of closed spans. All durations are
+/// in microseconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// The name or function of the spans, `None` if ungrouped or if the spans
+    /// have no function.
+    pub group: Option<String>,
+    pub count: usize,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+    pub histogram: Vec<LatencyBucket>,
+}
+
+/// The calls from spans of one resource into child spans of another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceDependency {
+    pub caller: ResourceKey,
+    pub callee: ResourceKey,
+    pub count: usize,
+    /// The number of calls where the child span has an error level or above.
+    /// OpenTelemetry spans with an error status are ingested at ERROR level,
+    /// so they are counted as well.
+    pub errors: usize,
+    /// The durations of the closed child spans, `None` if all are still open.
+    pub latency: Option<LatencyStats>,
+}
+
+/// The number of spans with a duration within `start..end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyBucket {
+    pub start: u64,
+    pub end: u64,
+    pub count: usize,
+}
+
 pub struct DeleteFilter {
     pub start: Timestamp,
     pub end: Timestamp,
     pub inside: bool,
+    /// Only spans matching these predicates are deleted. If neither this nor
+    /// `event_filter` is set, everything within the timeframe is deleted.
+    pub span_filter: Option<Vec<FilterPredicate>>,
+    /// Only events matching these predicates are deleted. If neither this nor
+    /// `span_filter` is set, everything within the timeframe is deleted.
+    pub event_filter: Option<Vec<FilterPredicate>>,
+    pub mode: DeleteMode,
     pub dry_run: bool,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeleteMode {
+    /// Deletes the whole trace of any matching span or event.
+    Traces,
+    /// Deletes only the matching spans and events, along with anything nested
+    /// within a matching span so that nothing is left orphaned.
+    Matching,
+}
+
 pub struct DeleteMetrics {
     pub spans: usize,
     pub span_events: usize,
     pub events: usize,
+    /// The tombstone holding the deleted entities, which can be passed to
+    /// `undo_delete` until it is purged. This is `None` for a dry run.
+    pub tombstone: Option<TombstoneKey>,
+}
+
+/// An entity that is protected from being deleted. Pinning a span protects the
+/// whole trace it belongs to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Pin {
+    Span(SpanKey),
+    Event(EventKey),
+}
+
+/// What an annotation is attached to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AnnotationTarget {
+    Span(SpanKey),
+    Event(EventKey),
+    /// A time range, inclusive on both ends.
+    Timespan(Timestamp, Timestamp),
+}
+
+/// A note attached to a span, event, or time range that is saved with the
+/// dataset. A bookmark is simply an annotation without any text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub key: AnnotationKey,
+    pub target: AnnotationTarget,
+    pub text: String,
+}
+
+pub struct NewAnnotation {
+    pub target: AnnotationTarget,
+    pub text: String,
+}
+
+/// The entities that were deleted together. They are kept in storage but not
+/// in the indexes until the tombstone is purged or undone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub key: TombstoneKey,
+    pub spans: Vec<SpanKey>,
+    pub span_events: Vec<SpanEventKey>,
+    pub events: Vec<EventKey>,
+    /// The resources that were left unused by this deletion. They are dropped
+    /// on purge if nothing else uses them by then.
+    pub resources: Vec<ResourceKey>,
+}
+
+pub struct CompactMetrics {
+    pub bytes_reclaimed: u64,
+}
+
+pub struct MergeMetrics {
+    pub resources: usize,
+    pub spans: usize,
+    pub span_events: usize,
+    pub events: usize,
+}
+
+/// The problems found when cross-checking a dataset. Each list holds the keys
+/// of the affected entities in order.
+pub struct IntegrityReport {
+    /// Spans whose `parent_key` doesn't refer to the span with their `parent_id`.
+    pub dangling_span_parents: Vec<SpanKey>,
+    /// Events whose `parent_key` doesn't refer to the span with their `parent_id`.
+    pub dangling_event_parents: Vec<EventKey>,
+    /// Span events for spans that don't exist.
+    pub dangling_span_events: Vec<SpanEventKey>,
+    /// Spans that are not linked to their parent even though it exists.
+    pub orphaned_spans: Vec<SpanKey>,
+    /// Events that are not linked to their parent even though it exists.
+    pub orphaned_events: Vec<EventKey>,
+    /// Span ids that are used by more than one span.
+    pub duplicate_span_ids: Vec<FullSpanId>,
+    /// Spans that are still open even though they can't be closed anymore,
+    /// either because their instance has disconnected or because the indexes
+    /// already consider them closed.
+    pub unclosed_spans: Vec<SpanKey>,
+    /// Spans that are only in the storage or only in the indexes.
+    pub mismatched_spans: Vec<SpanKey>,
+    /// Span events that are only in the storage or only in the indexes.
+    pub mismatched_span_events: Vec<SpanEventKey>,
+    /// Events that are only in the storage or only in the indexes.
+    pub mismatched_events: Vec<EventKey>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.dangling_span_parents.is_empty()
+            && self.dangling_event_parents.is_empty()
+            && self.dangling_span_events.is_empty()
+            && self.orphaned_spans.is_empty()
+            && self.orphaned_events.is_empty()
+            && self.duplicate_span_ids.is_empty()
+            && self.unclosed_spans.is_empty()
+            && self.mismatched_spans.is_empty()
+            && self.mismatched_span_events.is_empty()
+            && self.mismatched_events.is_empty()
+    }
 }
 
 pub struct EngineStatus {
     pub load: f64,
+    pub cache: Option<CacheStats>,
 }
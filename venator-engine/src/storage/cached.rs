@@ -1,31 +1,73 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::BTreeMap;
+use std::mem::size_of;
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use lru::LruCache;
 
-use crate::{Event, EventKey, FullSpanId, Resource, Span, SpanEvent, SpanKey, Timestamp, Value};
+use crate::{
+    Event, EventKey, FullSpanId, PreciseTimestamp, Resource, Span, SpanEvent, SpanEventKind,
+    SpanKey, Timestamp, Value,
+};
+
+use super::{
+    AnnotationStorage, IndexStorage, PartitionStorage, PinStorage, Storage, StorageError,
+    TombstoneStorage,
+};
+
+/// The number of entities of each kind that `CachedStorage` will hold. A
+/// capacity of zero disables caching for that kind.
+#[derive(Debug, Copy, Clone)]
+pub struct CacheCapacities {
+    pub resources: usize,
+    pub spans: usize,
+    pub span_events: usize,
+    pub events: usize,
+}
+
+impl CacheCapacities {
+    pub fn uniform(capacity: usize) -> CacheCapacities {
+        CacheCapacities {
+            resources: capacity,
+            spans: capacity,
+            span_events: capacity,
+            events: capacity,
+        }
+    }
+}
 
-use super::{IndexStorage, Storage, StorageError};
+/// Statistics on the effectiveness of a `CachedStorage` across all entities
+/// since it was created.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// A rough estimate of the number of bytes held by the cached entities.
+    pub memory_estimate: usize,
+}
 
 /// This storage wraps another storage implementation to keep some in memory.
 pub struct CachedStorage<S> {
-    resources: RefCell<LruCache<Timestamp, Arc<Resource>>>,
-    spans: RefCell<LruCache<Timestamp, Arc<Span>>>,
-    // span_events: RefCell<LruCache<Timestamp, Arc<SpanEvent>>>,
-    events: RefCell<LruCache<Timestamp, Arc<Event>>>,
+    resources: Cache<Resource>,
+    spans: Cache<Span>,
+    span_events: Cache<SpanEvent>,
+    events: Cache<Event>,
     inner: S,
 }
 
 impl<S> CachedStorage<S> {
     pub fn new(capacity: usize, storage: S) -> CachedStorage<S> {
-        let capacity = NonZeroUsize::new(capacity).unwrap();
+        CachedStorage::with_capacities(CacheCapacities::uniform(capacity), storage)
+    }
 
+    pub fn with_capacities(capacities: CacheCapacities, storage: S) -> CachedStorage<S> {
         CachedStorage {
-            resources: RefCell::new(LruCache::new(capacity)),
-            spans: RefCell::new(LruCache::new(capacity)),
-            events: RefCell::new(LruCache::new(capacity)),
+            resources: Cache::new(capacities.resources),
+            spans: Cache::new(capacities.spans),
+            span_events: Cache::new(capacities.span_events),
+            events: Cache::new(capacities.events),
             inner: storage,
         }
     }
@@ -36,40 +78,21 @@ where
     S: Storage,
 {
     fn get_resource(&self, at: Timestamp) -> Result<Arc<Resource>, StorageError> {
-        if let Some(resource) = self.resources.borrow_mut().get(&at) {
-            return Ok(resource.clone());
-        }
-
-        let resource = self.inner.get_resource(at)?;
-        self.resources.borrow_mut().put(at, resource.clone());
-
-        Ok(resource)
+        self.resources
+            .get_or_load(at, || self.inner.get_resource(at))
     }
 
     fn get_span(&self, at: Timestamp) -> Result<Arc<Span>, StorageError> {
-        if let Some(span) = self.spans.borrow_mut().get(&at) {
-            return Ok(span.clone());
-        }
-
-        let span = self.inner.get_span(at)?;
-        self.spans.borrow_mut().put(at, span.clone());
-
-        Ok(span)
+        self.spans.get_or_load(at, || self.inner.get_span(at))
     }
 
     fn get_span_event(&self, at: Timestamp) -> Result<Arc<SpanEvent>, StorageError> {
-        self.inner.get_span_event(at)
+        self.span_events
+            .get_or_load(at, || self.inner.get_span_event(at))
     }
 
     fn get_event(&self, at: Timestamp) -> Result<Arc<Event>, StorageError> {
-        if let Some(event) = self.events.borrow_mut().get(&at) {
-            return Ok(event.clone());
-        }
-
-        let event = self.inner.get_event(at)?;
-        self.events.borrow_mut().put(at, event.clone());
-
-        Ok(event)
+        self.events.get_or_load(at, || self.inner.get_event(at))
     }
 
     fn get_all_resources(
@@ -117,10 +140,10 @@ where
     fn update_span_closed(
         &mut self,
         at: Timestamp,
-        closed: Timestamp,
+        closed: PreciseTimestamp,
         busy: Option<u64>,
     ) -> Result<(), StorageError> {
-        self.spans.borrow_mut().pop(&at);
+        self.spans.pop(&at);
         self.inner.update_span_closed(at, closed, busy)
     }
 
@@ -129,7 +152,7 @@ where
         at: Timestamp,
         attributes: BTreeMap<String, Value>,
     ) -> Result<(), StorageError> {
-        self.spans.borrow_mut().pop(&at);
+        self.spans.pop(&at);
         self.inner.update_span_attributes(at, attributes)
     }
 
@@ -139,7 +162,7 @@ where
         link: FullSpanId,
         attributes: BTreeMap<String, Value>,
     ) -> Result<(), StorageError> {
-        self.spans.borrow_mut().pop(&at);
+        self.spans.pop(&at);
         self.inner.update_span_link(at, link, attributes)
     }
 
@@ -148,11 +171,9 @@ where
         parent_key: SpanKey,
         spans: &[SpanKey],
     ) -> Result<(), StorageError> {
-        let mut cached_spans = self.spans.borrow_mut();
         for span in spans {
-            cached_spans.pop(span);
+            self.spans.pop(span);
         }
-        drop(cached_spans);
         self.inner.update_span_parents(parent_key, spans)
     }
 
@@ -161,17 +182,15 @@ where
         parent_key: SpanKey,
         events: &[EventKey],
     ) -> Result<(), StorageError> {
-        let mut cached_events = self.events.borrow_mut();
         for event in events {
-            cached_events.pop(event);
+            self.events.pop(event);
         }
-        drop(cached_events);
         self.inner.update_event_parents(parent_key, events)
     }
 
     fn drop_resources(&mut self, resources: &[Timestamp]) -> Result<(), StorageError> {
         for c in resources {
-            self.resources.borrow_mut().pop(c);
+            self.resources.pop(c);
         }
 
         self.inner.drop_resources(resources)
@@ -179,31 +198,400 @@ where
 
     fn drop_spans(&mut self, spans: &[Timestamp]) -> Result<(), StorageError> {
         for s in spans {
-            self.spans.borrow_mut().pop(s);
+            self.spans.pop(s);
         }
 
         self.inner.drop_spans(spans)
     }
 
     fn drop_span_events(&mut self, span_events: &[Timestamp]) -> Result<(), StorageError> {
+        for s in span_events {
+            self.span_events.pop(s);
+        }
+
         self.inner.drop_span_events(span_events)
     }
 
     fn drop_events(&mut self, events: &[Timestamp]) -> Result<(), StorageError> {
         for s in events {
-            self.events.borrow_mut().pop(s);
+            self.events.pop(s);
         }
 
         self.inner.drop_events(events)
     }
 
-    #[allow(private_interfaces)]
+    fn compact(&mut self) -> Result<u64, StorageError> {
+        self.inner.compact()
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        let stats = [
+            self.resources.stats(),
+            self.spans.stats(),
+            self.span_events.stats(),
+            self.events.stats(),
+        ];
+
+        Some(
+            stats
+                .into_iter()
+                .fold(CacheStats::default(), |a, b| CacheStats {
+                    hits: a.hits + b.hits,
+                    misses: a.misses + b.misses,
+                    evictions: a.evictions + b.evictions,
+                    memory_estimate: a.memory_estimate + b.memory_estimate,
+                }),
+        )
+    }
+
     fn as_index_storage(&self) -> Option<&dyn IndexStorage> {
         self.inner.as_index_storage()
     }
 
-    #[allow(private_interfaces)]
     fn as_index_storage_mut(&mut self) -> Option<&mut dyn IndexStorage> {
         self.inner.as_index_storage_mut()
     }
+
+    fn as_tombstone_storage(&self) -> Option<&dyn TombstoneStorage> {
+        self.inner.as_tombstone_storage()
+    }
+
+    fn as_tombstone_storage_mut(&mut self) -> Option<&mut dyn TombstoneStorage> {
+        self.inner.as_tombstone_storage_mut()
+    }
+
+    fn as_pin_storage(&self) -> Option<&dyn PinStorage> {
+        self.inner.as_pin_storage()
+    }
+
+    fn as_pin_storage_mut(&mut self) -> Option<&mut dyn PinStorage> {
+        self.inner.as_pin_storage_mut()
+    }
+
+    fn as_annotation_storage(&self) -> Option<&dyn AnnotationStorage> {
+        self.inner.as_annotation_storage()
+    }
+
+    fn as_annotation_storage_mut(&mut self) -> Option<&mut dyn AnnotationStorage> {
+        self.inner.as_annotation_storage_mut()
+    }
+
+    fn as_partition_storage(&self) -> Option<&dyn PartitionStorage> {
+        self.inner.as_partition_storage()?;
+        Some(self)
+    }
+
+    fn as_partition_storage_mut(&mut self) -> Option<&mut dyn PartitionStorage> {
+        self.inner.as_partition_storage_mut()?;
+        Some(self)
+    }
+}
+
+// this is not forwarded directly like the other storage traits since the
+// cached entities from the dropped partitions must be removed as well
+impl<S> PartitionStorage for CachedStorage<S>
+where
+    S: Storage,
+{
+    fn drop_partitions_before(&mut self, at: Timestamp) -> Result<Option<Timestamp>, StorageError> {
+        let Some(inner) = self.inner.as_partition_storage_mut() else {
+            return Ok(None);
+        };
+
+        let cutoff = inner.drop_partitions_before(at)?;
+
+        if let Some(cutoff) = cutoff {
+            self.spans.pop_before(cutoff);
+            self.span_events.pop_before(cutoff);
+            self.events.pop_before(cutoff);
+        }
+
+        Ok(cutoff)
+    }
+}
+
+struct Cache<T> {
+    // this is `None` if the cache was configured with no capacity
+    entries: Option<RefCell<LruCache<Timestamp, Arc<T>>>>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+    evictions: Cell<u64>,
+    // this is kept up to date as entries are added and removed so that stats
+    // don't need to walk the whole cache
+    memory_estimate: Cell<usize>,
+}
+
+impl<T: EstimateSize> Cache<T> {
+    fn new(capacity: usize) -> Cache<T> {
+        Cache {
+            entries: NonZeroUsize::new(capacity).map(|c| RefCell::new(LruCache::new(c))),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+            evictions: Cell::new(0),
+            memory_estimate: Cell::new(0),
+        }
+    }
+
+    fn get_or_load(
+        &self,
+        at: Timestamp,
+        load: impl FnOnce() -> Result<Arc<T>, StorageError>,
+    ) -> Result<Arc<T>, StorageError> {
+        let Some(entries) = &self.entries else {
+            self.misses.set(self.misses.get() + 1);
+            return load();
+        };
+
+        if let Some(entry) = entries.borrow_mut().get(&at) {
+            self.hits.set(self.hits.get() + 1);
+            return Ok(entry.clone());
+        }
+
+        self.misses.set(self.misses.get() + 1);
+
+        let entry = load()?;
+        self.add_memory_estimate(&entry);
+        if let Some((evicted, evicted_entry)) = entries.borrow_mut().push(at, entry.clone()) {
+            self.remove_memory_estimate(&evicted_entry);
+            if evicted != at {
+                self.evictions.set(self.evictions.get() + 1);
+            }
+        }
+
+        Ok(entry)
+    }
+
+    fn pop(&self, at: &Timestamp) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+
+        if let Some(entry) = entries.borrow_mut().pop(at) {
+            self.remove_memory_estimate(&entry);
+        }
+    }
+
+    fn pop_before(&self, at: Timestamp) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+
+        let keys = entries
+            .borrow()
+            .iter()
+            .map(|(key, _)| *key)
+            .filter(|key| *key < at)
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            self.pop(&key);
+        }
+    }
+
+    fn add_memory_estimate(&self, entry: &T) {
+        let size = size_of::<Timestamp>() + entry.estimate_size();
+        self.memory_estimate.set(self.memory_estimate.get() + size);
+    }
+
+    fn remove_memory_estimate(&self, entry: &T) {
+        let size = size_of::<Timestamp>() + entry.estimate_size();
+        self.memory_estimate
+            .set(self.memory_estimate.get().saturating_sub(size));
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.get(),
+            misses: self.misses.get(),
+            evictions: self.evictions.get(),
+            memory_estimate: self.memory_estimate.get(),
+        }
+    }
+}
+
+/// Provides an approximation of the memory used by an entity, including its
+/// heap allocations.
+trait EstimateSize {
+    fn estimate_size(&self) -> usize;
+}
+
+impl EstimateSize for Resource {
+    fn estimate_size(&self) -> usize {
+        size_of::<Resource>() + estimate_attributes_size(&self.attributes)
+    }
+}
+
+impl EstimateSize for Span {
+    fn estimate_size(&self) -> usize {
+        size_of::<Span>()
+            + self.name.len()
+            + self.namespace.as_ref().map(String::len).unwrap_or_default()
+            + self.function.as_ref().map(String::len).unwrap_or_default()
+            + self.file_name.as_ref().map(String::len).unwrap_or_default()
+            + self
+                .links
+                .iter()
+                .map(|(_, attributes)| {
+                    size_of::<(FullSpanId, BTreeMap<String, Value>)>()
+                        + estimate_attributes_size(attributes)
+                })
+                .sum::<usize>()
+            + estimate_attributes_size(&self.instrumentation_attributes)
+            + estimate_attributes_size(&self.attributes)
+    }
+}
+
+impl EstimateSize for SpanEvent {
+    fn estimate_size(&self) -> usize {
+        let heap_size = match &self.kind {
+            SpanEventKind::Create(create) => {
+                create.name.len()
+                    + create
+                        .namespace
+                        .as_ref()
+                        .map(String::len)
+                        .unwrap_or_default()
+                    + create
+                        .function
+                        .as_ref()
+                        .map(String::len)
+                        .unwrap_or_default()
+                    + create
+                        .file_name
+                        .as_ref()
+                        .map(String::len)
+                        .unwrap_or_default()
+                    + estimate_attributes_size(&create.instrumentation_attributes)
+                    + estimate_attributes_size(&create.attributes)
+            }
+            SpanEventKind::Update(update) => estimate_attributes_size(&update.attributes),
+            SpanEventKind::Follows(_) => 0,
+            SpanEventKind::Enter(_) => 0,
+            SpanEventKind::Exit => 0,
+            SpanEventKind::Close(_) => 0,
+        };
+
+        size_of::<SpanEvent>() + heap_size
+    }
+}
+
+impl EstimateSize for Event {
+    fn estimate_size(&self) -> usize {
+        size_of::<Event>()
+            + estimate_value_heap_size(&self.content)
+            + self.namespace.as_ref().map(String::len).unwrap_or_default()
+            + self.function.as_ref().map(String::len).unwrap_or_default()
+            + self.file_name.as_ref().map(String::len).unwrap_or_default()
+            + estimate_attributes_size(&self.attributes)
+    }
+}
+
+fn estimate_attributes_size(attributes: &BTreeMap<String, Value>) -> usize {
+    attributes
+        .iter()
+        .map(|(key, value)| {
+            size_of::<String>() + key.len() + size_of::<Value>() + estimate_value_heap_size(value)
+        })
+        .sum()
+}
+
+fn estimate_value_heap_size(value: &Value) -> usize {
+    match value {
+        Value::Str(s) => s.len(),
+        Value::Bytes(b) => b.len(),
+        Value::Array(values) => values
+            .iter()
+            .map(|v| size_of::<Value>() + estimate_value_heap_size(v))
+            .sum(),
+        Value::Object(object) => estimate_attributes_size(object),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::TransientStorage;
+
+    use super::*;
+
+    #[test]
+    fn cache_stats_track_hits_misses_and_evictions() {
+        let mut storage = CachedStorage::new(1, TransientStorage::new());
+
+        for key in [1, 2] {
+            storage
+                .insert_resource(Resource {
+                    created_at: Timestamp::new(key).unwrap(),
+                    attributes: BTreeMap::new(),
+                })
+                .unwrap();
+        }
+
+        storage.get_resource(Timestamp::new(1).unwrap()).unwrap();
+        storage.get_resource(Timestamp::new(1).unwrap()).unwrap();
+        storage.get_resource(Timestamp::new(2).unwrap()).unwrap();
+
+        let stats = storage.cache_stats().unwrap();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(
+            stats.memory_estimate,
+            size_of::<Timestamp>() + size_of::<Resource>()
+        );
+    }
+
+    #[test]
+    fn cache_with_zero_capacity_is_disabled() {
+        let mut storage = CachedStorage::new(0, TransientStorage::new());
+
+        storage
+            .insert_resource(Resource {
+                created_at: Timestamp::new(1).unwrap(),
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        storage.get_resource(Timestamp::new(1).unwrap()).unwrap();
+        storage.get_resource(Timestamp::new(1).unwrap()).unwrap();
+
+        let stats = storage.cache_stats().unwrap();
+        assert_eq!(stats.hits, 0);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.memory_estimate, 0);
+    }
+
+    #[test]
+    fn cache_memory_estimate_follows_inserts_and_removals() {
+        let mut storage = CachedStorage::new(2, TransientStorage::new());
+
+        for key in [1, 2, 3] {
+            storage
+                .insert_resource(Resource {
+                    created_at: Timestamp::new(key).unwrap(),
+                    attributes: BTreeMap::new(),
+                })
+                .unwrap();
+        }
+
+        let entry_size = size_of::<Timestamp>() + size_of::<Resource>();
+
+        storage.get_resource(Timestamp::new(1).unwrap()).unwrap();
+        storage.get_resource(Timestamp::new(2).unwrap()).unwrap();
+        assert_eq!(
+            storage.cache_stats().unwrap().memory_estimate,
+            2 * entry_size
+        );
+
+        storage.get_resource(Timestamp::new(3).unwrap()).unwrap();
+        assert_eq!(
+            storage.cache_stats().unwrap().memory_estimate,
+            2 * entry_size
+        );
+
+        storage
+            .drop_resources(&[Timestamp::new(3).unwrap()])
+            .unwrap();
+        assert_eq!(storage.cache_stats().unwrap().memory_estimate, entry_size);
+    }
 }
@@ -1,18 +1,31 @@
 use std::collections::BTreeMap;
-use std::path::Path;
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use bincode::Options;
-use rusqlite::{params, Connection as DbConnection, Error as DbError, Params, Row};
+use rusqlite::types::Type;
+use rusqlite::{
+    params, Connection as DbConnection, Error as DbError, OpenFlags, Params, Row, ToSql,
+    Transaction,
+};
+use serde::de::DeserializeOwned;
 use tracing::instrument;
 
-use crate::index::{EventIndexes, SpanEventIndexes, SpanIndexes};
-use crate::models::{EventKey, Level, SourceKind, Value};
+use crate::models::{
+    Annotation, AnnotationKey, CloseSpanEvent, EnterSpanEvent, EventKey, FollowsSpanEvent, Level,
+    Pin, SourceKind, Tombstone, TombstoneKey, Value,
+};
 use crate::{
-    Event, FullSpanId, Resource, ResourceKey, Span, SpanEvent, SpanEventKind, SpanKey, Timestamp,
+    CreateSpanEvent, Event, FullSpanId, PreciseTimestamp, Resource, ResourceKey, Span, SpanEvent,
+    SpanEventKind, SpanKey, Timestamp, UpdateSpanEvent,
+};
+
+use super::{
+    AnnotationStorage, IndexSnapshot, IndexStorage, PinStorage, Storage, StorageError, StorageIter,
+    TombstoneStorage,
 };
 
-use super::{IndexStorage, Storage, StorageError, StorageIter};
+mod encoding;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum IndexState {
@@ -31,6 +44,10 @@ enum FileStorageError {
     Begin(DbError),
     Commit(DbError),
     Delete(DbError),
+    Vacuum(DbError),
+    Open(DbError),
+    Lock(std::io::Error),
+    Migrate(Box<FileStorageError>),
 }
 
 impl From<FileStorageError> for StorageError {
@@ -40,18 +57,53 @@ impl From<FileStorageError> for StorageError {
 }
 
 /// This storage holds all entities in an SQLite database at the provided path.
+///
+/// Only one writer may have a dataset open at a time, which is enforced by an
+/// exclusive lock on a `.lock` file next to the database that is removed when
+/// the writer is closed. A dataset can still be opened by any number of readers
+/// with `FileStorage::new_read_only`, which never modifies the file. Writers use
+/// SQLite's write-ahead log so readers only ever see committed data.
 pub struct FileStorage {
     connection: DbConnection,
     index_state: IndexState,
+    access: Access,
+}
+
+enum Access {
+    // the lock is not used directly but must be held for the lifetime of the
+    // storage
+    ReadWrite {
+        #[allow(unused)]
+        lock: File,
+        lock_path: PathBuf,
+    },
+    ReadOnly,
 }
 
 impl FileStorage {
-    pub fn new(path: &Path) -> FileStorage {
-        let connection = DbConnection::open(path).unwrap();
+    /// Opens the dataset for reading and writing, creating and migrating it if
+    /// necessary.
+    ///
+    /// This will fail if the dataset is already opened for writing by another
+    /// instance, or if it is not a dataset of a compatible version.
+    pub fn new(path: &Path) -> Result<FileStorage, StorageError> {
+        let lock_path = lock_path(path);
+        let lock = File::create(&lock_path).map_err(FileStorageError::Lock)?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(StorageError::Internal(
+                    "dataset is already opened for writing by another instance".to_owned(),
+                ));
+            }
+            Err(TryLockError::Error(err)) => return Err(FileStorageError::Lock(err).into()),
+        }
+
+        let mut connection = DbConnection::open(path).map_err(FileStorageError::Open)?;
 
         connection
-            .execute_batch(r#"PRAGMA synchronous = OFF; PRAGMA journal_mode = OFF;"#)
-            .unwrap();
+            .execute_batch(r#"PRAGMA synchronous = OFF; PRAGMA journal_mode = WAL;"#)
+            .map_err(FileStorageError::Open)?;
 
         let _ = connection.execute(
             r#"
@@ -66,7 +118,7 @@ impl FileStorage {
             (),
         );
 
-        let _ = connection.execute(r#"INSERT INTO meta VALUES (1, '0.5', 'STALE');"#, ());
+        let _ = connection.execute(r#"INSERT INTO meta VALUES (1, '0.7', 'STALE');"#, ());
 
         let (version, mut index_state): (String, String) = connection
             .query_row(
@@ -74,10 +126,17 @@ impl FileStorage {
                 (),
                 |row| row.get(0).and_then(|a| row.get(1).map(|b| (a, b))),
             )
-            .unwrap();
+            .map_err(FileStorageError::Query)?;
 
-        if version != "0.3" && version != "0.4" && version != "0.5" {
-            panic!("cannot load database with incompatible version");
+        if version != "0.3"
+            && version != "0.4"
+            && version != "0.5"
+            && version != "0.6"
+            && version != "0.7"
+        {
+            return Err(StorageError::Internal(
+                "cannot load database with incompatible version".to_owned(),
+            ));
         }
 
         if version == "0.3" || version == "0.4" {
@@ -90,7 +149,7 @@ impl FileStorage {
                     "UPDATE meta SET indexes = 'STALE', version = '0.5' WHERE id = 1",
                     (),
                 )
-                .unwrap();
+                .map_err(FileStorageError::Update)?;
         }
 
         let index_state = match &*index_state {
@@ -111,6 +170,8 @@ impl FileStorage {
             (),
         );
 
+        let _ = connection.execute(r#"INSERT INTO indexes VALUES ('version', x'');"#, ());
+
         let _ = connection.execute(r#"INSERT INTO indexes VALUES ('spans', x'');"#, ());
 
         let _ = connection.execute(r#"INSERT INTO indexes VALUES ('span_events', x'');"#, ());
@@ -121,7 +182,7 @@ impl FileStorage {
             r#"
             CREATE TABLE resources (
                 key        INT8 NOT NULL,
-                attributes TEXT NOT NULL,
+                attributes BLOB NOT NULL,
                 warnings   TEXT NOT NULL,
 
                 CONSTRAINT resources_pk PRIMARY KEY (key)
@@ -140,7 +201,7 @@ impl FileStorage {
                 busy             INT8,
                 parent_id        TEXT,
                 parent_key       INT8,
-                links            TEXT NOT NULL,
+                links            BLOB NOT NULL,
                 name             TEXT NOT NULL,
                 namespace        TEXT,
                 function         TEXT,
@@ -148,9 +209,10 @@ impl FileStorage {
                 file_name        TEXT,
                 file_line        INT,
                 file_column      INT,
-                instr_attributes TEXT NOT NULL,
-                attributes       TEXT NOT NULL,
+                instr_attributes BLOB NOT NULL,
+                attributes       BLOB NOT NULL,
                 warnings         TEXT NOT NULL,
+                created_at       INT8 NOT NULL,
 
                 CONSTRAINT spans_pk PRIMARY KEY (key)
             );"#,
@@ -160,11 +222,12 @@ impl FileStorage {
         let _ = connection.execute(
             r#"
             CREATE TABLE span_events (
-                key      INT8 NOT NULL,
-                span_key INT8 NOT NULL,
-                kind     TEXT NOT NULL,
-                data     TEXT,
-                warnings TEXT NOT NULL,
+                key       INT8 NOT NULL,
+                span_key  INT8 NOT NULL,
+                kind      TEXT NOT NULL,
+                data      BLOB,
+                warnings  TEXT NOT NULL,
+                timestamp INT8 NOT NULL,
 
                 CONSTRAINT span_events_pk PRIMARY KEY (key)
             );"#,
@@ -179,28 +242,139 @@ impl FileStorage {
                 resource_key INT8 NOT NULL,
                 parent_id    TEXT,
                 parent_key   INT8,
-                content      TEXT NOT NULL,
+                content      BLOB NOT NULL,
                 namespace    TEXT,
                 function     TEXT,
                 level        INT NOT NULL,
                 file_name    TEXT,
                 file_line    INT,
                 file_column  INT,
-                attributes   TEXT NOT NULL,
+                attributes   BLOB NOT NULL,
                 warnings     TEXT NOT NULL,
+                timestamp    INT8 NOT NULL,
 
                 CONSTRAINT events_pk PRIMARY KEY (key)
             );"#,
             (),
         );
 
-        FileStorage {
+        let _ = connection.execute(
+            r#"
+            CREATE TABLE tombstones (
+                key  INT8 NOT NULL,
+                data BLOB NOT NULL,
+
+                CONSTRAINT tombstones_pk PRIMARY KEY (key)
+            );"#,
+            (),
+        );
+
+        let _ = connection.execute(
+            r#"
+            CREATE TABLE pins (
+                kind INT NOT NULL,
+                key  INT8 NOT NULL,
+
+                CONSTRAINT pins_pk PRIMARY KEY (kind, key)
+            );"#,
+            (),
+        );
+
+        let _ = connection.execute(
+            r#"
+            CREATE TABLE annotations (
+                key  INT8 NOT NULL,
+                data BLOB NOT NULL,
+
+                CONSTRAINT annotations_pk PRIMARY KEY (key)
+            );"#,
+            (),
+        );
+
+        if version == "0.3" || version == "0.4" || version == "0.5" {
+            // migrating from 0.5 -> 0.6 requires re-encoding the JSON columns
+            // into their binary form, the indexes are unaffected
+
+            migrate_to_binary_encoding(&mut connection)
+                .map_err(|err| FileStorageError::Migrate(Box::new(err)))?;
+        }
+
+        if version == "0.3" || version == "0.4" || version == "0.5" || version == "0.6" {
+            // migrating from 0.6 -> 0.7 adds the precise timestamps, which are
+            // filled in from the keys since that is the best that is known
+
+            migrate_to_precise_timestamps(&mut connection)
+                .map_err(|err| FileStorageError::Migrate(Box::new(err)))?;
+        }
+
+        Ok(FileStorage {
             connection,
             index_state,
+            access: Access::ReadWrite { lock, lock_path },
+        })
+    }
+
+    /// Opens an existing dataset without ever modifying it. This is allowed
+    /// even if the dataset is opened for writing by another instance.
+    ///
+    /// This will fail if the dataset does not exist, is not a dataset, or is
+    /// from an older version that would need to be migrated.
+    pub fn new_read_only(path: &Path) -> Result<FileStorage, StorageError> {
+        let connection = DbConnection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(FileStorageError::Open)?;
+
+        let (version, index_state): (String, String) = connection
+            .query_row(
+                "SELECT version, indexes FROM meta WHERE id = 1",
+                (),
+                |row| row.get(0).and_then(|a| row.get(1).map(|b| (a, b))),
+            )
+            .map_err(|_| StorageError::Internal("file is not a dataset".to_owned()))?;
+
+        if version != "0.7" {
+            return Err(StorageError::Internal(
+                "cannot load database with incompatible version in read-only mode".to_owned(),
+            ));
+        }
+
+        let index_state = match &*index_state {
+            "STALE" => IndexState::Stale,
+            "FRESH" => IndexState::Fresh,
+            _ => IndexState::Stale,
+        };
+
+        Ok(FileStorage {
+            connection,
+            index_state,
+            access: Access::ReadOnly,
+        })
+    }
+
+    /// Returns whether the dataset at the path is currently opened for writing
+    /// by another instance.
+    pub fn is_locked(path: &Path) -> bool {
+        let Ok(lock) = File::open(lock_path(path)) else {
+            return false;
+        };
+
+        matches!(lock.try_lock_shared(), Err(TryLockError::WouldBlock))
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.access, Access::ReadOnly)
+    }
+
+    fn ensure_writable(&self) -> Result<(), StorageError> {
+        match self.access {
+            Access::ReadWrite { .. } => Ok(()),
+            Access::ReadOnly => Err(StorageError::ReadOnly),
         }
     }
 
-    fn invalidate_indexes(&mut self) {
+    pub(super) fn invalidate_indexes(&mut self) {
         if self.index_state == IndexState::Fresh {
             self.connection
                 .execute("UPDATE meta SET indexes = 'STALE' WHERE id = 1", ())
@@ -209,6 +383,68 @@ impl FileStorage {
             self.index_state = IndexState::Stale;
         }
     }
+
+    /// Returns the number of rows in an entity table (e.g. "spans").
+    pub(super) fn count_rows(&self, table: &str) -> Result<usize, StorageError> {
+        let count: i64 = self
+            .connection
+            .query_row(&format!("SELECT COUNT(*) FROM {table}"), (), |row| {
+                row.get(0)
+            })
+            .map_err(FileStorageError::Query)?;
+
+        Ok(count as usize)
+    }
+
+    /// Returns whether an entity table (e.g. "spans") has a row with the key.
+    pub(super) fn contains_row(&self, table: &str, at: Timestamp) -> Result<bool, StorageError> {
+        let mut stmt = self
+            .connection
+            .prepare_cached(&format!("SELECT 1 FROM {table} WHERE key = ?1"))
+            .map_err(FileStorageError::Prepare)?;
+
+        let exists = stmt.exists((at,)).map_err(FileStorageError::Query)?;
+
+        Ok(exists)
+    }
+
+    /// Deletes every row in an entity table (e.g. "spans"), which is much
+    /// faster than deleting them individually.
+    pub(super) fn clear_rows(&mut self, table: &str) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
+        self.invalidate_indexes();
+
+        self.connection
+            .execute(&format!("DELETE FROM {table}"), ())
+            .map_err(FileStorageError::Delete)?;
+
+        Ok(())
+    }
+
+    fn get_database_size(&self) -> Result<u64, FileStorageError> {
+        let page_count: i64 = self
+            .connection
+            .query_row("PRAGMA page_count", (), |row| row.get(0))
+            .map_err(FileStorageError::Query)?;
+
+        let page_size: i64 = self
+            .connection
+            .query_row("PRAGMA page_size", (), |row| row.get(0))
+            .map_err(FileStorageError::Query)?;
+
+        Ok(page_count as u64 * page_size as u64)
+    }
+}
+
+impl Drop for FileStorage {
+    fn drop(&mut self) {
+        // the lock file is removed while it is still held so that another
+        // writer can't have acquired it in the meantime
+        if let Access::ReadWrite { lock_path, .. } = &self.access {
+            let _ = std::fs::remove_file(lock_path);
+        }
+    }
 }
 
 impl Storage for FileStorage {
@@ -269,7 +505,7 @@ impl Storage for FileStorage {
     }
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    fn get_all_resources(&self) -> Result<StorageIter<Resource>, StorageError> {
+    fn get_all_resources(&self) -> Result<StorageIter<'_, Resource>, StorageError> {
         let mut stmt = self
             .connection
             .prepare_cached("SELECT * FROM resources ORDER BY key")
@@ -289,7 +525,7 @@ impl Storage for FileStorage {
     }
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    fn get_all_spans(&self) -> Result<StorageIter<Span>, StorageError> {
+    fn get_all_spans(&self) -> Result<StorageIter<'_, Span>, StorageError> {
         let mut stmt = self
             .connection
             .prepare_cached("SELECT * FROM spans ORDER BY key")
@@ -309,7 +545,7 @@ impl Storage for FileStorage {
     }
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    fn get_all_span_events(&self) -> Result<StorageIter<SpanEvent>, StorageError> {
+    fn get_all_span_events(&self) -> Result<StorageIter<'_, SpanEvent>, StorageError> {
         let mut stmt = self
             .connection
             .prepare_cached("SELECT * FROM span_events ORDER BY key")
@@ -329,7 +565,7 @@ impl Storage for FileStorage {
     }
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    fn get_all_events(&self) -> Result<StorageIter<Event>, StorageError> {
+    fn get_all_events(&self) -> Result<StorageIter<'_, Event>, StorageError> {
         let mut stmt = self
             .connection
             .prepare_cached("SELECT * FROM events ORDER BY key")
@@ -350,6 +586,8 @@ impl Storage for FileStorage {
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
     fn insert_resource(&mut self, resource: Resource) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         let mut stmt = self
             .connection
             .prepare_cached("INSERT INTO resources VALUES (?1, ?2, ?3)")
@@ -362,26 +600,28 @@ impl Storage for FileStorage {
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
     fn insert_span(&mut self, span: Span) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         self.invalidate_indexes();
 
         let mut stmt = self
             .connection
             .prepare_cached(
-                "INSERT INTO spans VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+                "INSERT INTO spans VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
             )
             .map_err(FileStorageError::Prepare)?;
 
         // have to inline it since I exceeded 16 elements
 
-        let key = span.created_at;
+        let key = span.key;
         let kind = span.kind as i32;
         let resource_key = span.resource_key;
         let id = span.id.to_string();
-        let closed_at = span.closed_at;
+        let closed_at = span.closed_at.map(|c| c as i64);
         let busy = span.busy.map(|b| b as i64);
         let parent_id = span.parent_id.map(|id| id.to_string());
         let parent_key = span.parent_key;
-        let links = serde_json::to_string(&span.links).unwrap();
+        let links = encoding::encode(&span.links);
         let name = span.name;
         let namespace = span.namespace;
         let function = span.function;
@@ -390,9 +630,10 @@ impl Storage for FileStorage {
         let file_line = span.file_line;
         let file_column = span.file_column;
         let instrumentation_attributes =
-            serde_json::to_string(&span.instrumentation_attributes).unwrap();
-        let attributes = serde_json::to_string(&span.attributes).unwrap();
+            encoding::encode_attributes(&span.instrumentation_attributes);
+        let attributes = encoding::encode_attributes(&span.attributes);
         let warnings = "[]";
+        let created_at = span.created_at as i64;
 
         stmt.execute(params![
             key,
@@ -414,6 +655,7 @@ impl Storage for FileStorage {
             instrumentation_attributes,
             attributes,
             warnings,
+            created_at,
         ])
         .map_err(FileStorageError::Insert)?;
         Ok(())
@@ -421,11 +663,13 @@ impl Storage for FileStorage {
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
     fn insert_span_event(&mut self, span_event: SpanEvent) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         self.invalidate_indexes();
 
         let mut stmt = self
             .connection
-            .prepare_cached("INSERT INTO span_events VALUES (?1, ?2, ?3, ?4, ?5)")
+            .prepare_cached("INSERT INTO span_events VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
             .map_err(FileStorageError::Prepare)?;
 
         stmt.execute(span_event_to_params(span_event))
@@ -435,12 +679,14 @@ impl Storage for FileStorage {
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
     fn insert_event(&mut self, event: Event) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         self.invalidate_indexes();
 
         let mut stmt = self
             .connection
             .prepare_cached(
-                "INSERT INTO events VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                "INSERT INTO events VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
             )
             .map_err(FileStorageError::Prepare)?;
 
@@ -454,9 +700,11 @@ impl Storage for FileStorage {
     fn update_span_closed(
         &mut self,
         at: Timestamp,
-        closed: Timestamp,
+        closed: PreciseTimestamp,
         busy: Option<u64>,
     ) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         self.invalidate_indexes();
 
         let mut stmt = self
@@ -464,7 +712,7 @@ impl Storage for FileStorage {
             .prepare_cached("UPDATE spans SET closed_at = ?2, busy = ?3 WHERE key = ?1")
             .map_err(FileStorageError::Prepare)?;
 
-        stmt.execute((at, closed, busy.map(|b| b as i64)))
+        stmt.execute((at, closed as i64, busy.map(|b| b as i64)))
             .map_err(FileStorageError::Update)?;
 
         Ok(())
@@ -476,6 +724,8 @@ impl Storage for FileStorage {
         at: Timestamp,
         attributes: BTreeMap<String, Value>,
     ) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         self.invalidate_indexes();
 
         let mut stmt = self
@@ -493,7 +743,7 @@ impl Storage for FileStorage {
             new_attributes.extend(attributes);
             new_attributes
         };
-        let attributes = serde_json::to_string(&attributes).unwrap();
+        let attributes = encoding::encode_attributes(&attributes);
 
         let mut stmt = self
             .connection
@@ -513,6 +763,8 @@ impl Storage for FileStorage {
         link: FullSpanId,
         attributes: BTreeMap<String, Value>,
     ) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         self.invalidate_indexes();
 
         let mut stmt = self
@@ -530,14 +782,14 @@ impl Storage for FileStorage {
             new_linkss.push((link, attributes));
             new_linkss
         };
-        let attributes = serde_json::to_string(&links).unwrap();
+        let links = encoding::encode(&links);
 
         let mut stmt = self
             .connection
-            .prepare_cached("UPDATE spans SET follows = ?2 WHERE key = ?1")
+            .prepare_cached("UPDATE spans SET links = ?2 WHERE key = ?1")
             .map_err(FileStorageError::Prepare)?;
 
-        stmt.execute((at, attributes))
+        stmt.execute((at, links))
             .map_err(FileStorageError::Update)?;
 
         Ok(())
@@ -549,6 +801,8 @@ impl Storage for FileStorage {
         parent_key: SpanKey,
         spans: &[SpanKey],
     ) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         self.invalidate_indexes();
 
         let tx = self
@@ -577,6 +831,8 @@ impl Storage for FileStorage {
         parent_key: SpanKey,
         events: &[EventKey],
     ) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         self.invalidate_indexes();
 
         let tx = self
@@ -601,6 +857,8 @@ impl Storage for FileStorage {
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
     fn drop_resources(&mut self, resources: &[Timestamp]) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         let tx = self
             .connection
             .transaction()
@@ -623,6 +881,8 @@ impl Storage for FileStorage {
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
     fn drop_spans(&mut self, spans: &[Timestamp]) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         let tx = self
             .connection
             .transaction()
@@ -645,6 +905,8 @@ impl Storage for FileStorage {
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
     fn drop_span_events(&mut self, span_events: &[Timestamp]) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         let tx = self
             .connection
             .transaction()
@@ -667,6 +929,8 @@ impl Storage for FileStorage {
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
     fn drop_events(&mut self, events: &[Timestamp]) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
         let tx = self
             .connection
             .transaction()
@@ -687,76 +951,93 @@ impl Storage for FileStorage {
         Ok(())
     }
 
-    #[allow(private_interfaces)]
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn compact(&mut self) -> Result<u64, StorageError> {
+        self.ensure_writable()?;
+
+        let size_before = self.get_database_size()?;
+
+        // the vacuumed pages are written to the write-ahead log, so it must be
+        // checkpointed for the database file itself to shrink
+        self.connection
+            .execute_batch("VACUUM; PRAGMA wal_checkpoint(TRUNCATE);")
+            .map_err(FileStorageError::Vacuum)?;
+
+        let size_after = self.get_database_size()?;
+
+        Ok(size_before.saturating_sub(size_after))
+    }
+
     fn as_index_storage(&self) -> Option<&dyn IndexStorage> {
         Some(self)
     }
 
-    #[allow(private_interfaces)]
     fn as_index_storage_mut(&mut self) -> Option<&mut dyn IndexStorage> {
+        if self.is_read_only() {
+            return None;
+        }
+
+        Some(self)
+    }
+
+    fn as_tombstone_storage(&self) -> Option<&dyn TombstoneStorage> {
+        Some(self)
+    }
+
+    fn as_tombstone_storage_mut(&mut self) -> Option<&mut dyn TombstoneStorage> {
+        Some(self)
+    }
+
+    fn as_pin_storage(&self) -> Option<&dyn PinStorage> {
+        Some(self)
+    }
+
+    fn as_pin_storage_mut(&mut self) -> Option<&mut dyn PinStorage> {
+        Some(self)
+    }
+
+    fn as_annotation_storage(&self) -> Option<&dyn AnnotationStorage> {
+        Some(self)
+    }
+
+    fn as_annotation_storage_mut(&mut self) -> Option<&mut dyn AnnotationStorage> {
         Some(self)
     }
 }
 
 impl IndexStorage for FileStorage {
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    fn get_indexes(
-        &self,
-    ) -> Result<Option<(SpanIndexes, SpanEventIndexes, EventIndexes)>, StorageError> {
-        use bincode::DefaultOptions;
-
+    fn get_indexes(&self) -> Result<Option<IndexSnapshot>, StorageError> {
         if self.index_state == IndexState::Stale {
             return Ok(None);
         }
 
-        let span_index_data: Vec<u8> = self
-            .connection
-            .query_row("SELECT data FROM indexes WHERE kind = 'spans'", (), |row| {
-                row.get(0)
-            })
-            .map_err(FileStorageError::Query)?;
-
-        let span_event_index_data: Vec<u8> = self
-            .connection
-            .query_row(
-                "SELECT data FROM indexes WHERE kind = 'span_events'",
-                (),
-                |row| row.get(0),
-            )
-            .map_err(FileStorageError::Query)?;
-
-        let event_index_data: Vec<u8> = self
-            .connection
-            .query_row(
-                "SELECT data FROM indexes WHERE kind = 'events'",
-                (),
-                |row| row.get(0),
-            )
-            .map_err(FileStorageError::Query)?;
-
-        let bincode_options = DefaultOptions::new().with_fixint_encoding();
+        let get_index_data = |kind: &str| -> Result<Vec<u8>, FileStorageError> {
+            self.connection
+                .query_row("SELECT data FROM indexes WHERE kind = ?1", (kind,), |row| {
+                    row.get(0)
+                })
+                .map_err(FileStorageError::Query)
+        };
 
-        let span_indexes = bincode_options.deserialize(&span_index_data).unwrap();
-        let span_event_indexes = bincode_options.deserialize(&span_event_index_data).unwrap();
-        let event_indexes = bincode_options.deserialize(&event_index_data).unwrap();
+        // indexes persisted before the format was versioned do not have this
+        // entry, but they are compatible with the first version
+        let version = match get_index_data("version")?.as_slice() {
+            [a, b, c, d] => u32::from_le_bytes([*a, *b, *c, *d]),
+            _ => 1,
+        };
 
-        Ok(Some((span_indexes, span_event_indexes, event_indexes)))
+        Ok(Some(IndexSnapshot {
+            version,
+            spans: get_index_data("spans")?,
+            span_events: get_index_data("span_events")?,
+            events: get_index_data("events")?,
+        }))
     }
 
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    fn update_indexes(
-        &mut self,
-        span_indexes: &SpanIndexes,
-        span_event_indexes: &SpanEventIndexes,
-        event_indexes: &EventIndexes,
-    ) -> Result<(), StorageError> {
-        use bincode::DefaultOptions;
-
-        let bincode_options = DefaultOptions::new().with_fixint_encoding();
-
-        let span_index_data = bincode_options.serialize(span_indexes).unwrap();
-        let span_event_index_data = bincode_options.serialize(span_event_indexes).unwrap();
-        let event_index_data = bincode_options.serialize(event_indexes).unwrap();
+    fn update_indexes(&mut self, snapshot: &IndexSnapshot) -> Result<(), StorageError> {
+        self.ensure_writable()?;
 
         let tx = self
             .connection
@@ -766,11 +1047,13 @@ impl IndexStorage for FileStorage {
         let mut stmt = tx
             .prepare("UPDATE indexes SET data = ?2 WHERE kind = ?1")
             .map_err(FileStorageError::Prepare)?;
-        stmt.execute(("spans", span_index_data))
+        stmt.execute(("version", snapshot.version.to_le_bytes()))
+            .map_err(FileStorageError::Update)?;
+        stmt.execute(("spans", &snapshot.spans))
             .map_err(FileStorageError::Update)?;
-        stmt.execute(("span_events", span_event_index_data))
+        stmt.execute(("span_events", &snapshot.span_events))
             .map_err(FileStorageError::Update)?;
-        stmt.execute(("events", event_index_data))
+        stmt.execute(("events", &snapshot.events))
             .map_err(FileStorageError::Update)?;
         drop(stmt);
 
@@ -783,9 +1066,431 @@ impl IndexStorage for FileStorage {
     }
 }
 
+impl TombstoneStorage for FileStorage {
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn get_tombstones(&self) -> Result<Vec<Tombstone>, StorageError> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT data FROM tombstones ORDER BY key")
+            .map_err(FileStorageError::Prepare)?;
+
+        let tombstones = stmt
+            .query_map((), |row| {
+                let data: Vec<u8> = row.get(0)?;
+                Ok(encoding::decode(&data).unwrap())
+            })
+            .map_err(FileStorageError::Query)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(FileStorageError::Row)?;
+
+        Ok(tombstones)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn insert_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
+        self.invalidate_indexes();
+
+        let mut stmt = self
+            .connection
+            .prepare_cached("INSERT INTO tombstones VALUES (?1, ?2)")
+            .map_err(FileStorageError::Prepare)?;
+
+        stmt.execute((tombstone.key, encoding::encode(tombstone)))
+            .map_err(FileStorageError::Insert)?;
+
+        Ok(())
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn drop_tombstone(&mut self, key: TombstoneKey) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
+        self.invalidate_indexes();
+
+        let mut stmt = self
+            .connection
+            .prepare_cached("DELETE FROM tombstones WHERE key = ?1")
+            .map_err(FileStorageError::Prepare)?;
+
+        stmt.execute((key,)).map_err(FileStorageError::Delete)?;
+
+        Ok(())
+    }
+}
+
+const PIN_KIND_SPAN: i64 = 0;
+const PIN_KIND_EVENT: i64 = 1;
+
+fn pin_to_row(pin: Pin) -> (i64, Timestamp) {
+    match pin {
+        Pin::Span(key) => (PIN_KIND_SPAN, key),
+        Pin::Event(key) => (PIN_KIND_EVENT, key),
+    }
+}
+
+impl PinStorage for FileStorage {
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn get_pins(&self) -> Result<Vec<Pin>, StorageError> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT kind, key FROM pins ORDER BY kind, key")
+            .map_err(FileStorageError::Prepare)?;
+
+        let pins = stmt
+            .query_map((), |row| {
+                let kind: i64 = row.get(0)?;
+                let key: Timestamp = row.get(1)?;
+                Ok((kind, key))
+            })
+            .map_err(FileStorageError::Query)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(FileStorageError::Row)?
+            .into_iter()
+            .filter_map(|(kind, key)| match kind {
+                PIN_KIND_SPAN => Some(Pin::Span(key)),
+                PIN_KIND_EVENT => Some(Pin::Event(key)),
+                _ => None,
+            })
+            .collect();
+
+        Ok(pins)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn insert_pin(&mut self, pin: Pin) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
+        let mut stmt = self
+            .connection
+            .prepare_cached("INSERT OR IGNORE INTO pins VALUES (?1, ?2)")
+            .map_err(FileStorageError::Prepare)?;
+
+        stmt.execute(pin_to_row(pin))
+            .map_err(FileStorageError::Insert)?;
+
+        Ok(())
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn drop_pin(&mut self, pin: Pin) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
+        let mut stmt = self
+            .connection
+            .prepare_cached("DELETE FROM pins WHERE kind = ?1 AND key = ?2")
+            .map_err(FileStorageError::Prepare)?;
+
+        stmt.execute(pin_to_row(pin))
+            .map_err(FileStorageError::Delete)?;
+
+        Ok(())
+    }
+}
+
+impl AnnotationStorage for FileStorage {
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn get_annotations(&self) -> Result<Vec<Annotation>, StorageError> {
+        let mut stmt = self
+            .connection
+            .prepare_cached("SELECT data FROM annotations ORDER BY key")
+            .map_err(FileStorageError::Prepare)?;
+
+        let annotations = stmt
+            .query_map((), |row| {
+                let data: Vec<u8> = row.get(0)?;
+                Ok(encoding::decode(&data).unwrap())
+            })
+            .map_err(FileStorageError::Query)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(FileStorageError::Row)?;
+
+        Ok(annotations)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn insert_annotation(&mut self, annotation: &Annotation) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
+        let mut stmt = self
+            .connection
+            .prepare_cached("INSERT INTO annotations VALUES (?1, ?2)")
+            .map_err(FileStorageError::Prepare)?;
+
+        stmt.execute((annotation.key, encoding::encode(annotation)))
+            .map_err(FileStorageError::Insert)?;
+
+        Ok(())
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn update_annotation(&mut self, annotation: &Annotation) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
+        let mut stmt = self
+            .connection
+            .prepare_cached("UPDATE annotations SET data = ?2 WHERE key = ?1")
+            .map_err(FileStorageError::Prepare)?;
+
+        let updated = stmt
+            .execute((annotation.key, encoding::encode(annotation)))
+            .map_err(FileStorageError::Update)?;
+
+        if updated == 0 {
+            return Err(StorageError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn drop_annotation(&mut self, key: AnnotationKey) -> Result<(), StorageError> {
+        self.ensure_writable()?;
+
+        let mut stmt = self
+            .connection
+            .prepare_cached("DELETE FROM annotations WHERE key = ?1")
+            .map_err(FileStorageError::Prepare)?;
+
+        stmt.execute((key,)).map_err(FileStorageError::Delete)?;
+
+        Ok(())
+    }
+}
+
+/// This re-encodes the JSON text columns used up to version 0.5 into the binary
+/// encoding used since version 0.6.
+fn migrate_to_binary_encoding(connection: &mut DbConnection) -> Result<(), FileStorageError> {
+    // a malformed row is reported like any other value that could not be read
+    // from the database, so the migration fails instead of panicking
+    fn from_json<T: DeserializeOwned>(row: &Row<'_>, idx: usize) -> Result<T, DbError> {
+        let json: String = row.get(idx)?;
+        serde_json::from_str(&json)
+            .map_err(|err| DbError::FromSqlConversionFailure(idx, Type::Text, Box::new(err)))
+    }
+
+    fn attributes_from_json(row: &Row<'_>, idx: usize) -> Result<Vec<u8>, DbError> {
+        let attributes: BTreeMap<String, Value> = from_json(row, idx)?;
+        Ok(encoding::encode_attributes(&attributes))
+    }
+
+    let tx = connection.transaction().map_err(FileStorageError::Begin)?;
+
+    migrate_columns(&tx, "resources", &["attributes"], &["attributes"], |row| {
+        Ok(vec![Some(attributes_from_json(row, 1)?)])
+    })?;
+
+    migrate_columns(
+        &tx,
+        "spans",
+        &["links", "instr_attributes", "attributes"],
+        &["links", "instr_attributes", "attributes"],
+        |row| {
+            let links: Vec<(FullSpanId, BTreeMap<String, Value>)> = from_json(row, 1)?;
+
+            Ok(vec![
+                Some(encoding::encode(&links)),
+                Some(attributes_from_json(row, 2)?),
+                Some(attributes_from_json(row, 3)?),
+            ])
+        },
+    )?;
+
+    migrate_columns(&tx, "span_events", &["kind", "data"], &["data"], |row| {
+        let kind: String = row.get(1)?;
+
+        let data = match kind.as_str() {
+            "create" => Some(encoding::encode(&from_json::<CreateSpanEvent>(row, 2)?)),
+            "update" => Some(encoding::encode(&from_json::<UpdateSpanEvent>(row, 2)?)),
+            "follows" => Some(encoding::encode(&from_json::<FollowsSpanEvent>(row, 2)?)),
+            "enter" => Some(encoding::encode(&from_json::<EnterSpanEvent>(row, 2)?)),
+            "exit" => None,
+            "close" => Some(encoding::encode(&from_json::<CloseSpanEvent>(row, 2)?)),
+            _ => {
+                return Err(DbError::FromSqlConversionFailure(
+                    1,
+                    Type::Text,
+                    format!("unknown span event kind {kind:?}").into(),
+                ))
+            }
+        };
+
+        Ok(vec![data])
+    })?;
+
+    migrate_columns(
+        &tx,
+        "events",
+        &["content", "attributes"],
+        &["content", "attributes"],
+        |row| {
+            let content: Value = from_json(row, 1)?;
+
+            Ok(vec![
+                Some(encoding::encode_content(&content)),
+                Some(attributes_from_json(row, 2)?),
+            ])
+        },
+    )?;
+
+    tx.execute("UPDATE meta SET version = '0.6' WHERE id = 1", ())
+        .map_err(FileStorageError::Update)?;
+
+    tx.commit().map_err(FileStorageError::Commit)?;
+
+    Ok(())
+}
+
+/// This adds the precise nanosecond timestamps introduced in version 0.7. They
+/// are derived from the microsecond keys (and existing microsecond values are
+/// scaled) since nothing more precise was recorded.
+fn migrate_to_precise_timestamps(connection: &mut DbConnection) -> Result<(), FileStorageError> {
+    let tx = connection.transaction().map_err(FileStorageError::Begin)?;
+
+    // tables that didn't exist before opening were already created with the
+    // new columns, so only the older tables are altered
+    let columns = [
+        ("spans", "created_at", "UPDATE spans SET created_at = key * 1000, closed_at = closed_at * 1000, busy = busy * 1000"),
+        ("span_events", "timestamp", "UPDATE span_events SET timestamp = key * 1000"),
+        ("events", "timestamp", "UPDATE events SET timestamp = key * 1000"),
+    ];
+
+    for (table, column, update) in columns {
+        let exists: bool = tx
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+                (table, column),
+                |row| row.get(0),
+            )
+            .map_err(FileStorageError::Query)?;
+
+        if exists {
+            continue;
+        }
+
+        tx.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} INT8 NOT NULL DEFAULT 0"),
+            (),
+        )
+        .map_err(FileStorageError::Update)?;
+        tx.execute(update, ()).map_err(FileStorageError::Update)?;
+    }
+
+    let mut select = tx
+        .prepare("SELECT key, data FROM span_events WHERE kind = 'close'")
+        .map_err(FileStorageError::Prepare)?;
+
+    let closes = select
+        .query_map((), |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Option<Vec<u8>>>(1)?))
+        })
+        .map_err(FileStorageError::Query)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(FileStorageError::Row)?;
+
+    drop(select);
+
+    let mut update = tx
+        .prepare("UPDATE span_events SET data = ?2 WHERE key = ?1")
+        .map_err(FileStorageError::Prepare)?;
+
+    for (key, data) in closes {
+        let Some(data) = data else {
+            continue;
+        };
+
+        let close: CloseSpanEvent = encoding::decode(&data).unwrap();
+        let close = CloseSpanEvent {
+            busy: close.busy.map(|busy| busy * 1000),
+        };
+
+        update
+            .execute((key, encoding::encode(&close)))
+            .map_err(FileStorageError::Update)?;
+    }
+
+    drop(update);
+
+    tx.execute("UPDATE meta SET version = '0.7' WHERE id = 1", ())
+        .map_err(FileStorageError::Update)?;
+
+    tx.commit().map_err(FileStorageError::Commit)?;
+
+    Ok(())
+}
+
+/// This walks through a table in batches and replaces the `update_columns`
+/// with the values produced by `convert`. The row passed to `convert` has the
+/// key in the first position followed by the `select_columns`.
+fn migrate_columns<F>(
+    tx: &Transaction<'_>,
+    table: &str,
+    select_columns: &[&str],
+    update_columns: &[&str],
+    convert: F,
+) -> Result<(), FileStorageError>
+where
+    F: Fn(&Row<'_>) -> Result<Vec<Option<Vec<u8>>>, DbError>,
+{
+    const BATCH_SIZE: usize = 1000;
+
+    let mut select = tx
+        .prepare(&format!(
+            "SELECT key, {} FROM {table} WHERE key > ?1 ORDER BY key LIMIT {BATCH_SIZE}",
+            select_columns.join(", "),
+        ))
+        .map_err(FileStorageError::Prepare)?;
+
+    let mut update = tx
+        .prepare(&format!(
+            "UPDATE {table} SET {} WHERE key = ?1",
+            update_columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| format!("{column} = ?{}", i + 2))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ))
+        .map_err(FileStorageError::Prepare)?;
+
+    let mut last_key: i64 = 0;
+    loop {
+        let rows = select
+            .query_map((last_key,), |row| {
+                Ok((row.get::<_, i64>(0)?, convert(row)?))
+            })
+            .map_err(FileStorageError::Query)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(FileStorageError::Row)?;
+
+        for (key, values) in &rows {
+            let mut params: Vec<&dyn ToSql> = vec![key];
+            params.extend(values.iter().map(|v| v as &dyn ToSql));
+
+            update
+                .execute(params.as_slice())
+                .map_err(FileStorageError::Update)?;
+        }
+
+        match rows.last() {
+            Some((key, _)) if rows.len() == BATCH_SIZE => last_key = *key,
+            _ => break,
+        }
+    }
+
+    Ok(())
+}
+
+pub(super) fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
 fn resource_to_params(resource: Resource) -> impl Params {
     let key = resource.key();
-    let attributes = serde_json::to_string(&resource.attributes).unwrap();
+    let attributes = encoding::encode_attributes(&resource.attributes);
     let warnings = "[]";
 
     (key, attributes, warnings)
@@ -793,8 +1498,8 @@ fn resource_to_params(resource: Resource) -> impl Params {
 
 fn resource_from_row(row: &Row<'_>) -> Result<Resource, DbError> {
     let key: i64 = row.get(0)?;
-    let attributes: String = row.get(1)?;
-    let attributes = serde_json::from_str(&attributes).unwrap();
+    let attributes: Vec<u8> = row.get(1)?;
+    let attributes = encoding::decode_attributes(&attributes).unwrap();
     // let warnings = row.get(2)?;
 
     Ok(Resource {
@@ -808,12 +1513,12 @@ fn span_from_row(row: &Row<'_>) -> Result<Span, DbError> {
     let kind: i32 = row.get(1)?;
     let resource_key = row.get(2)?;
     let id: String = row.get(3)?;
-    let closed_at = row.get(4)?;
+    let closed_at: Option<i64> = row.get(4)?;
     let busy: Option<i64> = row.get(5)?;
     let parent_id: Option<String> = row.get(6)?;
     let parent_key = row.get(7)?;
-    let links: String = row.get(8)?;
-    let links = serde_json::from_str(&links).unwrap();
+    let links: Vec<u8> = row.get(8)?;
+    let links = encoding::decode(&links).unwrap();
     let name = row.get(9)?;
     let namespace = row.get(10)?;
     let function = row.get(11)?;
@@ -821,18 +1526,21 @@ fn span_from_row(row: &Row<'_>) -> Result<Span, DbError> {
     let file_name = row.get(13)?;
     let file_line = row.get(14)?;
     let file_column = row.get(15)?;
-    let instrumentation_attributes: String = row.get(16)?;
-    let instrumentation_attributes = serde_json::from_str(&instrumentation_attributes).unwrap();
-    let attributes: String = row.get(17)?;
-    let attributes = serde_json::from_str(&attributes).unwrap();
+    let instrumentation_attributes: Vec<u8> = row.get(16)?;
+    let instrumentation_attributes =
+        encoding::decode_attributes(&instrumentation_attributes).unwrap();
+    let attributes: Vec<u8> = row.get(17)?;
+    let attributes = encoding::decode_attributes(&attributes).unwrap();
     // let warnings = row.get(18)?;
+    let created_at: i64 = row.get(19)?;
 
     Ok(Span {
         kind: SourceKind::try_from(kind).unwrap(),
-        created_at: key,
+        key,
+        created_at: created_at as u64,
         resource_key,
         id: id.parse().unwrap(),
-        closed_at,
+        closed_at: closed_at.map(|c| c as u64),
         busy: busy.map(|b| b as u64),
         parent_id: parent_id.map(|id| id.parse().unwrap()),
         parent_key,
@@ -850,59 +1558,61 @@ fn span_from_row(row: &Row<'_>) -> Result<Span, DbError> {
 }
 
 fn span_event_to_params(span_event: SpanEvent) -> impl Params {
+    let timestamp = span_event.timestamp as i64;
+
     match span_event.kind {
         SpanEventKind::Create(create_span_event) => {
-            let key = span_event.timestamp;
+            let key = span_event.key;
             let span_key = span_event.span_key;
             let kind = "create";
-            let data = serde_json::to_string(&create_span_event).unwrap();
+            let data = encoding::encode(&create_span_event);
             let warnings = "[]";
 
-            (key, span_key, kind, Some(data), warnings)
+            (key, span_key, kind, Some(data), warnings, timestamp)
         }
         SpanEventKind::Update(update_span_event) => {
-            let key = span_event.timestamp;
+            let key = span_event.key;
             let span_key = span_event.span_key;
             let kind = "update";
-            let data = serde_json::to_string(&update_span_event).unwrap();
+            let data = encoding::encode(&update_span_event);
             let warnings = "[]";
 
-            (key, span_key, kind, Some(data), warnings)
+            (key, span_key, kind, Some(data), warnings, timestamp)
         }
         SpanEventKind::Follows(follows_span_event) => {
-            let key = span_event.timestamp;
+            let key = span_event.key;
             let span_key = span_event.span_key;
             let kind = "follows";
-            let data = serde_json::to_string(&follows_span_event).unwrap();
+            let data = encoding::encode(&follows_span_event);
             let warnings = "[]";
 
-            (key, span_key, kind, Some(data), warnings)
+            (key, span_key, kind, Some(data), warnings, timestamp)
         }
         SpanEventKind::Enter(enter_span_event) => {
-            let key = span_event.timestamp;
+            let key = span_event.key;
             let span_key = span_event.span_key;
             let kind = "enter";
-            let data = serde_json::to_string(&enter_span_event).unwrap();
+            let data = encoding::encode(&enter_span_event);
             let warnings = "[]";
 
-            (key, span_key, kind, Some(data), warnings)
+            (key, span_key, kind, Some(data), warnings, timestamp)
         }
         SpanEventKind::Exit => {
-            let key = span_event.timestamp;
+            let key = span_event.key;
             let span_key = span_event.span_key;
             let kind = "exit";
             let warnings = "[]";
 
-            (key, span_key, kind, None, warnings)
+            (key, span_key, kind, None::<Vec<u8>>, warnings, timestamp)
         }
         SpanEventKind::Close(close_span_event) => {
-            let key = span_event.timestamp;
+            let key = span_event.key;
             let span_key = span_event.span_key;
             let kind = "close";
-            let data = serde_json::to_string(&close_span_event).unwrap();
+            let data = encoding::encode(&close_span_event);
             let warnings = "[]";
 
-            (key, span_key, kind, Some(data), warnings)
+            (key, span_key, kind, Some(data), warnings, timestamp)
         }
     }
 }
@@ -911,51 +1621,59 @@ fn span_event_from_row(row: &Row<'_>) -> Result<SpanEvent, DbError> {
     let key = row.get(0)?;
     let span_key = row.get(1)?;
     let kind: String = row.get(2)?;
-    let data: Option<String> = row.get(3)?;
+    let data: Option<Vec<u8>> = row.get(3)?;
     // let warnings = row.get(4)?;
+    let timestamp: i64 = row.get(5)?;
+    let timestamp = timestamp as u64;
 
     match kind.as_str() {
         "create" => {
-            let create_span_event = serde_json::from_str(&data.unwrap()).unwrap();
+            let create_span_event = encoding::decode(&data.unwrap()).unwrap();
             Ok(SpanEvent {
-                timestamp: key,
+                key,
+                timestamp,
                 span_key,
                 kind: SpanEventKind::Create(create_span_event),
             })
         }
         "update" => {
-            let update_span_event = serde_json::from_str(&data.unwrap()).unwrap();
+            let update_span_event = encoding::decode(&data.unwrap()).unwrap();
             Ok(SpanEvent {
-                timestamp: key,
+                key,
+                timestamp,
                 span_key,
                 kind: SpanEventKind::Update(update_span_event),
             })
         }
         "follows" => {
-            let follows_span_event = serde_json::from_str(&data.unwrap()).unwrap();
+            let follows_span_event = encoding::decode(&data.unwrap()).unwrap();
             Ok(SpanEvent {
-                timestamp: key,
+                key,
+                timestamp,
                 span_key,
                 kind: SpanEventKind::Follows(follows_span_event),
             })
         }
         "enter" => {
-            let enter_span_event = serde_json::from_str(&data.unwrap()).unwrap();
+            let enter_span_event = encoding::decode(&data.unwrap()).unwrap();
             Ok(SpanEvent {
-                timestamp: key,
+                key,
+                timestamp,
                 span_key,
                 kind: SpanEventKind::Enter(enter_span_event),
             })
         }
         "exit" => Ok(SpanEvent {
-            timestamp: key,
+            key,
+            timestamp,
             span_key,
             kind: SpanEventKind::Exit,
         }),
         "close" => {
-            let close_span_event = serde_json::from_str(&data.unwrap()).unwrap();
+            let close_span_event = encoding::decode(&data.unwrap()).unwrap();
             Ok(SpanEvent {
-                timestamp: key,
+                key,
+                timestamp,
                 span_key,
                 kind: SpanEventKind::Close(close_span_event),
             })
@@ -966,22 +1684,23 @@ fn span_event_from_row(row: &Row<'_>) -> Result<SpanEvent, DbError> {
 
 #[rustfmt::skip]
 fn event_to_params(event: Event) -> impl Params {
-    let key = event.timestamp;
+    let key = event.key;
     let kind = event.kind as i32;
     let resource_key = event.resource_key;
     let parent_id = event.parent_id.map(|id| id.to_string());
     let parent_key = event.parent_key;
-    let content = serde_json::to_string(&event.content).unwrap();
+    let content = encoding::encode_content(&event.content);
     let namespace = event.namespace;
     let function = event.function;
     let level = event.level.to_db();
     let file_name = event.file_name;
     let file_line = event.file_line;
     let file_column = event.file_column;
-    let attributes = serde_json::to_string(&event.attributes).unwrap();
+    let attributes = encoding::encode_attributes(&event.attributes);
     let warnings = "[]";
+    let timestamp = event.timestamp as i64;
 
-    (key, kind, resource_key, parent_id, parent_key, content, namespace, function, level, file_name, file_line, file_column, attributes, warnings)
+    (key, kind, resource_key, parent_id, parent_key, content, namespace, function, level, file_name, file_line, file_column, attributes, warnings, timestamp)
 }
 
 fn event_from_row(row: &Row<'_>) -> Result<Event, DbError> {
@@ -990,21 +1709,23 @@ fn event_from_row(row: &Row<'_>) -> Result<Event, DbError> {
     let resource_key = row.get(2)?;
     let parent_id: Option<String> = row.get(3)?;
     let parent_key = row.get(4)?;
-    let content: String = row.get(5)?;
-    let content = serde_json::from_str(&content).unwrap();
+    let content: Vec<u8> = row.get(5)?;
+    let content = encoding::decode_content(&content).unwrap();
     let namespace = row.get(6)?;
     let function = row.get(7)?;
     let level: i32 = row.get(8)?;
     let file_name = row.get(9)?;
     let file_line = row.get(10)?;
     let file_column = row.get(11)?;
-    let attributes: String = row.get(12)?;
-    let attributes = serde_json::from_str(&attributes).unwrap();
+    let attributes: Vec<u8> = row.get(12)?;
+    let attributes = encoding::decode_attributes(&attributes).unwrap();
     // let warnings = row.get(13)?;
+    let timestamp: i64 = row.get(14)?;
 
     Ok(Event {
         kind: SourceKind::try_from(kind).unwrap(),
-        timestamp: key,
+        key,
+        timestamp: timestamp as u64,
         resource_key,
         parent_id: parent_id.map(|id| id.parse().unwrap()),
         parent_key,
@@ -1018,3 +1739,354 @@ fn event_from_row(row: &Row<'_>) -> Result<Event, DbError> {
         attributes,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::engine::SyncEngine;
+    use crate::{NewEvent, NewResource};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("venator-{name}-{}.vena.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn indexes_are_persisted_with_format_version() {
+        let path = temp_path("indexes");
+
+        let mut engine = SyncEngine::new(FileStorage::new(&path).unwrap()).unwrap();
+        let resource_key = engine
+            .insert_resource(NewResource {
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+        engine
+            .insert_event(NewEvent {
+                kind: SourceKind::Tracing,
+                resource_key,
+                timestamp: 2000 * 1000,
+                span_id: None,
+                content: Value::Str("event".to_owned()),
+                namespace: None,
+                function: None,
+                level: Level::Info,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+        engine.shutdown().unwrap();
+        drop(engine);
+
+        let storage = FileStorage::new(&path).unwrap();
+        let snapshot = storage.get_indexes().unwrap().unwrap();
+        assert_eq!(snapshot.version, IndexSnapshot::FORMAT_VERSION);
+
+        let engine = SyncEngine::new(storage).unwrap();
+        assert_eq!(engine.query_stats().total_events, 1);
+
+        drop(engine);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn read_only_allowed_while_locked_by_writer() {
+        let path = temp_path("read-only");
+
+        let mut writer = FileStorage::new(&path).unwrap();
+        writer
+            .insert_resource(Resource {
+                created_at: Timestamp::new(1).unwrap(),
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        assert!(FileStorage::is_locked(&path));
+        assert!(FileStorage::new(&path).is_err());
+
+        let contents = std::fs::read(&path).unwrap();
+        let mut reader = FileStorage::new_read_only(&path).unwrap();
+        assert!(reader.get_resource(Timestamp::new(1).unwrap()).is_ok());
+        assert!(matches!(
+            reader.drop_resources(&[Timestamp::new(1).unwrap()]),
+            Err(StorageError::ReadOnly)
+        ));
+        assert!(reader.as_index_storage_mut().is_none());
+        drop(reader);
+        assert_eq!(std::fs::read(&path).unwrap(), contents);
+
+        drop(writer);
+        assert!(!FileStorage::is_locked(&path));
+        assert!(!lock_path(&path).exists());
+
+        let reader = FileStorage::new_read_only(&path).unwrap();
+        assert!(reader.get_resource(Timestamp::new(1).unwrap()).is_ok());
+        drop(reader);
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn opening_a_non_dataset_is_an_error() {
+        let path = temp_path("not-a-dataset");
+        std::fs::write(&path, "not a dataset").unwrap();
+
+        assert!(FileStorage::new_read_only(&path).is_err());
+        assert!(FileStorage::new(&path).is_err());
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn migrates_json_columns_to_binary() {
+        let path = temp_path("migrate");
+
+        let connection = DbConnection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                r#"
+                CREATE TABLE meta (id INT NOT NULL, version TEXT NOT NULL, indexes TEXT NOT NULL);
+                INSERT INTO meta VALUES (1, '0.5', 'STALE');
+                CREATE TABLE resources (key INT8 NOT NULL, attributes TEXT NOT NULL, warnings TEXT NOT NULL);
+                INSERT INTO resources VALUES (1, '{"service.name":{"Str":"test"}}', '[]');
+                CREATE TABLE events (
+                    key INT8 NOT NULL, kind INT NOT NULL, resource_key INT8 NOT NULL,
+                    parent_id TEXT, parent_key INT8, content TEXT NOT NULL,
+                    namespace TEXT, function TEXT, level INT NOT NULL, file_name TEXT,
+                    file_line INT, file_column INT, attributes TEXT NOT NULL, warnings TEXT NOT NULL
+                );
+                INSERT INTO events VALUES (2, 0, 1, NULL, NULL, '{"Str":"hello"}', NULL, NULL, 9, NULL, NULL, NULL, '{"a":{"I64":5}}', '[]');
+                "#,
+            )
+            .unwrap();
+        drop(connection);
+
+        let storage = FileStorage::new(&path).unwrap();
+
+        let resource = storage.get_resource(Timestamp::new(1).unwrap()).unwrap();
+        assert_eq!(
+            resource.attributes.get("service.name"),
+            Some(&Value::Str("test".to_owned()))
+        );
+
+        let event = storage.get_event(Timestamp::new(2).unwrap()).unwrap();
+        assert_eq!(event.content, Value::Str("hello".to_owned()));
+        assert_eq!(event.attributes.get("a"), Some(&Value::I64(5)));
+        assert_eq!(event.timestamp, 2000);
+
+        let version: String = storage
+            .connection
+            .query_row("SELECT version FROM meta WHERE id = 1", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(version, "0.7");
+
+        drop(storage);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn migrating_a_malformed_row_is_an_error() {
+        let path = temp_path("migrate-malformed");
+
+        let connection = DbConnection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                r#"
+                CREATE TABLE meta (id INT NOT NULL, version TEXT NOT NULL, indexes TEXT NOT NULL);
+                INSERT INTO meta VALUES (1, '0.5', 'STALE');
+                CREATE TABLE resources (key INT8 NOT NULL, attributes TEXT NOT NULL, warnings TEXT NOT NULL);
+                INSERT INTO resources VALUES (1, '{"service.name":', '[]');
+                "#,
+            )
+            .unwrap();
+        drop(connection);
+
+        let err = FileStorage::new(&path).err().unwrap();
+        assert!(
+            matches!(err, StorageError::Internal(message) if message.starts_with("Migrate(Row("))
+        );
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn migrates_microsecond_timestamps_to_precise_timestamps() {
+        let path = temp_path("migrate-precise");
+
+        let mut storage = FileStorage::new(&path).unwrap();
+        let resource_key = Timestamp::new(1).unwrap();
+        let span_key = Timestamp::new(5).unwrap();
+        let close_key = Timestamp::new(9).unwrap();
+        let event_key = Timestamp::new(7).unwrap();
+        storage
+            .insert_resource(Resource {
+                created_at: resource_key,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+        storage
+            .insert_span(Span {
+                kind: SourceKind::Tracing,
+                resource_key,
+                id: FullSpanId::Tracing(1, 1),
+                key: span_key,
+                created_at: 0,
+                closed_at: None,
+                busy: None,
+                parent_id: None,
+                parent_key: None,
+                links: Vec::new(),
+                name: "span".to_owned(),
+                namespace: None,
+                function: None,
+                level: Level::Info,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                instrumentation_attributes: BTreeMap::new(),
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+        storage.update_span_closed(span_key, 9, Some(3)).unwrap();
+        storage
+            .insert_span_event(SpanEvent {
+                key: close_key,
+                timestamp: 0,
+                span_key,
+                kind: SpanEventKind::Close(CloseSpanEvent { busy: Some(3) }),
+            })
+            .unwrap();
+        storage
+            .insert_event(Event {
+                kind: SourceKind::Tracing,
+                resource_key,
+                key: event_key,
+                timestamp: 0,
+                parent_id: None,
+                parent_key: Some(span_key),
+                content: Value::Str("hello".to_owned()),
+                namespace: None,
+                function: None,
+                level: Level::Info,
+                file_name: None,
+                file_line: None,
+                file_column: None,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        // rewind the dataset to the 0.6 format, where everything was recorded
+        // in microseconds and the keys were the only creation timestamps
+        storage
+            .connection
+            .execute_batch(
+                r#"
+                ALTER TABLE spans DROP COLUMN created_at;
+                ALTER TABLE span_events DROP COLUMN timestamp;
+                ALTER TABLE events DROP COLUMN timestamp;
+                UPDATE meta SET version = '0.6' WHERE id = 1;
+                "#,
+            )
+            .unwrap();
+        drop(storage);
+
+        let storage = FileStorage::new(&path).unwrap();
+
+        let span = storage.get_span(span_key).unwrap();
+        assert_eq!(span.created_at, 5000);
+        assert_eq!(span.closed_at, Some(9000));
+        assert_eq!(span.busy, Some(3000));
+
+        let close = storage.get_span_event(close_key).unwrap();
+        assert_eq!(close.timestamp, 9000);
+        assert!(matches!(
+            close.kind,
+            SpanEventKind::Close(CloseSpanEvent { busy: Some(3000) })
+        ));
+
+        let event = storage.get_event(event_key).unwrap();
+        assert_eq!(event.timestamp, 7000);
+        assert_eq!(event.content, Value::Str("hello".to_owned()));
+
+        drop(storage);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+
+    #[test]
+    fn compaction_reclaims_space_and_keeps_remaining_data() {
+        let path = temp_path("compact");
+
+        let mut storage = FileStorage::new(&path).unwrap();
+        let resource_key = Timestamp::new(1).unwrap();
+        storage
+            .insert_resource(Resource {
+                created_at: resource_key,
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+
+        let event_keys = (1..=1000)
+            .map(|i| Timestamp::new(1000 + i).unwrap())
+            .collect::<Vec<_>>();
+
+        for key in &event_keys {
+            storage
+                .insert_event(Event {
+                    kind: SourceKind::Tracing,
+                    resource_key,
+                    key: *key,
+                    timestamp: key.get() * 1000,
+                    parent_id: None,
+                    parent_key: None,
+                    content: Value::Str(format!("event {key} {}", "x".repeat(1000))),
+                    namespace: None,
+                    function: None,
+                    level: Level::Info,
+                    file_name: None,
+                    file_line: None,
+                    file_column: None,
+                    attributes: BTreeMap::new(),
+                })
+                .unwrap();
+        }
+
+        let (dropped_keys, kept_keys) = event_keys.split_at(990);
+        storage.drop_events(dropped_keys).unwrap();
+
+        let size_before = std::fs::metadata(&path).unwrap().len();
+        let bytes_reclaimed = storage.compact().unwrap();
+        let size_after = std::fs::metadata(&path).unwrap().len();
+
+        assert!(bytes_reclaimed > 0);
+        assert!(size_after < size_before);
+
+        assert!(storage.get_event(dropped_keys[0]).is_err());
+        for key in kept_keys {
+            let event = storage.get_event(*key).unwrap();
+            assert_eq!(
+                event.content,
+                Value::Str(format!("event {key} {}", "x".repeat(1000)))
+            );
+        }
+        assert_eq!(storage.get_all_events().unwrap().count(), kept_keys.len());
+
+        drop(storage);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(lock_path(&path));
+    }
+}
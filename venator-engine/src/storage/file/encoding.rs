@@ -0,0 +1,250 @@
+//! The binary encoding used for attributes and other structured columns.
+//!
+//! Attribute maps and values are encoded with bincode after deduplicating their
+//! strings into a table so that repeated keys and values (very common in nested
+//! objects and arrays) are only stored once. Any other structured data is
+//! encoded with plain bincode.
+//!
+//! Every encoded blob is prefixed with a single byte describing how the rest is
+//! stored. Payloads larger than `COMPRESSION_THRESHOLD` are compressed with LZ4
+//! if doing so actually makes them smaller.
+
+use std::collections::{BTreeMap, HashMap};
+
+use bincode::{DefaultOptions, Options};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::models::Value;
+
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+const FORMAT_RAW: u8 = 0;
+const FORMAT_LZ4: u8 = 1;
+
+#[allow(unused)]
+#[derive(Debug)]
+pub(super) enum EncodingError {
+    Bincode(bincode::Error),
+    Decompress(lz4_flex::block::DecompressError),
+    UnknownFormat(u8),
+    InvalidString(u32),
+    Empty,
+}
+
+#[derive(Serialize, Deserialize)]
+enum EncodedValue {
+    Null,
+    F64(f64),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    Bool(bool),
+    Str(u32),
+    Bytes(Vec<u8>),
+    Array(Vec<EncodedValue>),
+    Object(Vec<(u32, EncodedValue)>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedAttributes {
+    strings: Vec<String>,
+    attributes: Vec<(u32, EncodedValue)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedContent {
+    strings: Vec<String>,
+    value: EncodedValue,
+}
+
+#[derive(Default)]
+struct StringTable<'a> {
+    strings: Vec<&'a str>,
+    lookup: HashMap<&'a str, u32>,
+}
+
+impl<'a> StringTable<'a> {
+    fn intern(&mut self, s: &'a str) -> u32 {
+        *self.lookup.entry(s).or_insert_with(|| {
+            self.strings.push(s);
+            (self.strings.len() - 1) as u32
+        })
+    }
+
+    fn encode_value(&mut self, value: &'a Value) -> EncodedValue {
+        match value {
+            Value::Null => EncodedValue::Null,
+            Value::F64(v) => EncodedValue::F64(*v),
+            Value::I64(v) => EncodedValue::I64(*v),
+            Value::U64(v) => EncodedValue::U64(*v),
+            Value::I128(v) => EncodedValue::I128(*v),
+            Value::U128(v) => EncodedValue::U128(*v),
+            Value::Bool(v) => EncodedValue::Bool(*v),
+            Value::Str(v) => EncodedValue::Str(self.intern(v)),
+            Value::Bytes(v) => EncodedValue::Bytes(v.clone()),
+            Value::Array(values) => {
+                EncodedValue::Array(values.iter().map(|v| self.encode_value(v)).collect())
+            }
+            Value::Object(object) => EncodedValue::Object(self.encode_map(object)),
+        }
+    }
+
+    fn encode_map(&mut self, map: &'a BTreeMap<String, Value>) -> Vec<(u32, EncodedValue)> {
+        map.iter()
+            .map(|(k, v)| (self.intern(k), self.encode_value(v)))
+            .collect()
+    }
+
+    fn into_strings(self) -> Vec<String> {
+        self.strings.into_iter().map(str::to_owned).collect()
+    }
+}
+
+fn decode_string(strings: &[String], idx: u32) -> Result<String, EncodingError> {
+    strings
+        .get(idx as usize)
+        .cloned()
+        .ok_or(EncodingError::InvalidString(idx))
+}
+
+fn decode_value(strings: &[String], value: EncodedValue) -> Result<Value, EncodingError> {
+    Ok(match value {
+        EncodedValue::Null => Value::Null,
+        EncodedValue::F64(v) => Value::F64(v),
+        EncodedValue::I64(v) => Value::I64(v),
+        EncodedValue::U64(v) => Value::U64(v),
+        EncodedValue::I128(v) => Value::I128(v),
+        EncodedValue::U128(v) => Value::U128(v),
+        EncodedValue::Bool(v) => Value::Bool(v),
+        EncodedValue::Str(idx) => Value::Str(decode_string(strings, idx)?),
+        EncodedValue::Bytes(v) => Value::Bytes(v),
+        EncodedValue::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|v| decode_value(strings, v))
+                .collect::<Result<_, _>>()?,
+        ),
+        EncodedValue::Object(object) => Value::Object(decode_map(strings, object)?),
+    })
+}
+
+fn decode_map(
+    strings: &[String],
+    map: Vec<(u32, EncodedValue)>,
+) -> Result<BTreeMap<String, Value>, EncodingError> {
+    map.into_iter()
+        .map(|(k, v)| Ok((decode_string(strings, k)?, decode_value(strings, v)?)))
+        .collect()
+}
+
+pub(super) fn encode_attributes(attributes: &BTreeMap<String, Value>) -> Vec<u8> {
+    let mut table = StringTable::default();
+    let attributes = table.encode_map(attributes);
+    let strings = table.into_strings();
+
+    encode(&EncodedAttributes {
+        strings,
+        attributes,
+    })
+}
+
+pub(super) fn decode_attributes(data: &[u8]) -> Result<BTreeMap<String, Value>, EncodingError> {
+    let encoded: EncodedAttributes = decode(data)?;
+
+    decode_map(&encoded.strings, encoded.attributes)
+}
+
+pub(super) fn encode_content(content: &Value) -> Vec<u8> {
+    let mut table = StringTable::default();
+    let value = table.encode_value(content);
+    let strings = table.into_strings();
+
+    encode(&EncodedContent { strings, value })
+}
+
+pub(super) fn decode_content(data: &[u8]) -> Result<Value, EncodingError> {
+    let encoded: EncodedContent = decode(data)?;
+
+    decode_value(&encoded.strings, encoded.value)
+}
+
+pub(super) fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    let payload = DefaultOptions::new()
+        .serialize(value)
+        .expect("serializing into memory should not fail");
+
+    if payload.len() > COMPRESSION_THRESHOLD {
+        let compressed = lz4_flex::compress_prepend_size(&payload);
+        if compressed.len() < payload.len() {
+            let mut data = Vec::with_capacity(compressed.len() + 1);
+            data.push(FORMAT_LZ4);
+            data.extend(compressed);
+            return data;
+        }
+    }
+
+    let mut data = Vec::with_capacity(payload.len() + 1);
+    data.push(FORMAT_RAW);
+    data.extend(payload);
+    data
+}
+
+pub(super) fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, EncodingError> {
+    let (format, payload) = data.split_first().ok_or(EncodingError::Empty)?;
+
+    match *format {
+        FORMAT_RAW => DefaultOptions::new()
+            .deserialize(payload)
+            .map_err(EncodingError::Bincode),
+        FORMAT_LZ4 => {
+            let payload =
+                lz4_flex::decompress_size_prepended(payload).map_err(EncodingError::Decompress)?;
+
+            DefaultOptions::new()
+                .deserialize(&payload)
+                .map_err(EncodingError::Bincode)
+        }
+        format => Err(EncodingError::UnknownFormat(format)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_roundtrip_with_repeated_strings() {
+        let attributes = BTreeMap::from_iter([
+            ("a".to_owned(), Value::Str("repeated".to_owned())),
+            ("b".to_owned(), Value::Str("repeated".to_owned())),
+            ("c".to_owned(), Value::I64(-5)),
+            (
+                "d".to_owned(),
+                Value::Array(vec![
+                    Value::Str("a".to_owned()),
+                    Value::Object(BTreeMap::from_iter([(
+                        "repeated".to_owned(),
+                        Value::Bool(true),
+                    )])),
+                ]),
+            ),
+        ]);
+
+        let data = encode_attributes(&attributes);
+
+        assert_eq!(decode_attributes(&data).unwrap(), attributes);
+    }
+
+    #[test]
+    fn large_content_is_compressed() {
+        let content = Value::Str("abcd".repeat(1000));
+
+        let data = encode_content(&content);
+
+        assert_eq!(data[0], FORMAT_LZ4);
+        assert!(data.len() < 4000);
+        assert_eq!(decode_content(&data).unwrap(), content);
+    }
+}
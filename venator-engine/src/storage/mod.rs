@@ -2,36 +2,58 @@
 //!
 //! There are two primary storage implementations: `TransientStorage` that holds
 //! all data in memory and `FileStorage` which persists the data in an SQLite
-//! database file. Though when using file storage, it is best to wrap it in a
-//! `CachedStorage` layer since the engine does not cache data itself and new
-//! lookups are often temporally related.
+//! database file. There is also `SegmentedStorage` which persists the data in
+//! time-partitioned append-only files and is better suited for write-heavy
+//! workloads, and `PartitionedStorage` which spreads an SQLite dataset across
+//! one database per time partition so old data can be dropped cheaply. Though
+//! when using file storage, it is best to wrap it in a `CachedStorage` layer
+//! since the engine does not cache data itself and new lookups are often
+//! temporally related.
 //!
-//! Custom implemenations can be created and used with the engine with the only
-//! caveat that index persistence is only supported by `FileStorage` - any other
-//! implementation must rebuild indexes based on `get_all_*` calls on startup.
+//! Custom implemenations can be created and used with the engine. By default the
+//! engine will rebuild its indexes based on `get_all_*` calls on startup, but an
+//! implementation can opt into persisting them by implementing `IndexStorage`
+//! and returning it from `Storage::as_index_storage(_mut)`. Similarly deleted
+//! entities are only restorable within the engine's lifetime unless the storage
+//! implements `TombstoneStorage`, pins are only kept if it implements
+//! `PinStorage`, and annotations are only kept if it implements
+//! `AnnotationStorage`. A storage that can drop old data in bulk can implement
+//! `PartitionStorage` to support time-based retention.
 
 use std::collections::BTreeMap;
 use std::error::Error as StdError;
 use std::fmt::{Display, Error as FmtError, Formatter};
 use std::sync::Arc;
 
+use bincode::{DefaultOptions, Options};
+
 mod cached;
 #[cfg(feature = "persist")]
 mod file;
+#[cfg(feature = "persist")]
+mod partitioned;
+mod segmented;
 mod transient;
 
 use crate::index::{EventIndexes, SpanEventIndexes, SpanIndexes};
-use crate::models::{Event, EventKey, Resource, Span, SpanEvent, SpanKey, Timestamp, Value};
+use crate::models::{
+    Annotation, AnnotationKey, Event, EventKey, Pin, PreciseTimestamp, Resource, Span, SpanEvent,
+    SpanKey, Timestamp, Tombstone, TombstoneKey, Value,
+};
 use crate::FullSpanId;
 
-pub use cached::CachedStorage;
+pub use cached::{CacheCapacities, CacheStats, CachedStorage};
 #[cfg(feature = "persist")]
 pub use file::FileStorage;
+#[cfg(feature = "persist")]
+pub use partitioned::PartitionedStorage;
+pub use segmented::SegmentedStorage;
 pub use transient::TransientStorage;
 
 #[derive(Debug, Clone)]
 pub enum StorageError {
     NotFound,
+    ReadOnly,
     Internal(String),
 }
 
@@ -39,6 +61,7 @@ impl Display for StorageError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
         match self {
             StorageError::NotFound => write!(f, "not found"),
+            StorageError::ReadOnly => write!(f, "storage is read-only"),
             StorageError::Internal(s) => write!(f, "internal: {s}"),
         }
     }
@@ -73,7 +96,7 @@ pub trait Storage {
     fn update_span_closed(
         &mut self,
         at: Timestamp,
-        closed: Timestamp,
+        closed: PreciseTimestamp,
         busy: Option<u64>,
     ) -> Result<(), StorageError>;
     fn update_span_attributes(
@@ -103,27 +126,192 @@ pub trait Storage {
     fn drop_span_events(&mut self, span_events: &[Timestamp]) -> Result<(), StorageError>;
     fn drop_events(&mut self, events: &[Timestamp]) -> Result<(), StorageError>;
 
-    #[doc(hidden)]
-    #[allow(private_interfaces)]
+    /// Reclaims space left behind by dropped entities and returns the number
+    /// of bytes freed. Implementations that have nothing to reclaim can rely
+    /// on the default which does nothing.
+    fn compact(&mut self) -> Result<u64, StorageError> {
+        Ok(0)
+    }
+
+    /// Returns statistics on any caching done by this storage, or `None` if it
+    /// does not cache.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Returns this storage as an `IndexStorage` if it supports persisting
+    /// indexes.
     fn as_index_storage(&self) -> Option<&dyn IndexStorage> {
         None
     }
 
-    #[doc(hidden)]
-    #[allow(private_interfaces)]
+    /// Returns this storage as an `IndexStorage` if it supports persisting
+    /// indexes.
     fn as_index_storage_mut(&mut self) -> Option<&mut dyn IndexStorage> {
         None
     }
+
+    /// Returns this storage as a `TombstoneStorage` if it supports persisting
+    /// tombstones.
+    fn as_tombstone_storage(&self) -> Option<&dyn TombstoneStorage> {
+        None
+    }
+
+    /// Returns this storage as a `TombstoneStorage` if it supports persisting
+    /// tombstones.
+    fn as_tombstone_storage_mut(&mut self) -> Option<&mut dyn TombstoneStorage> {
+        None
+    }
+
+    /// Returns this storage as a `PinStorage` if it supports persisting pins.
+    fn as_pin_storage(&self) -> Option<&dyn PinStorage> {
+        None
+    }
+
+    /// Returns this storage as a `PinStorage` if it supports persisting pins.
+    fn as_pin_storage_mut(&mut self) -> Option<&mut dyn PinStorage> {
+        None
+    }
+
+    /// Returns this storage as an `AnnotationStorage` if it supports persisting
+    /// annotations.
+    fn as_annotation_storage(&self) -> Option<&dyn AnnotationStorage> {
+        None
+    }
+
+    /// Returns this storage as an `AnnotationStorage` if it supports persisting
+    /// annotations.
+    fn as_annotation_storage_mut(&mut self) -> Option<&mut dyn AnnotationStorage> {
+        None
+    }
+
+    /// Returns this storage as a `PartitionStorage` if it supports dropping
+    /// whole partitions.
+    fn as_partition_storage(&self) -> Option<&dyn PartitionStorage> {
+        None
+    }
+
+    /// Returns this storage as a `PartitionStorage` if it supports dropping
+    /// whole partitions.
+    fn as_partition_storage_mut(&mut self) -> Option<&mut dyn PartitionStorage> {
+        None
+    }
 }
 
-pub(crate) trait IndexStorage {
-    fn get_indexes(
-        &self,
-    ) -> Result<Option<(SpanIndexes, SpanEventIndexes, EventIndexes)>, StorageError>;
-    fn update_indexes(
-        &mut self,
+/// This allows a storage to persist the engine's indexes so they do not need
+/// to be rebuilt on startup.
+///
+/// The engine will call `update_indexes` on shutdown (and on compaction) and
+/// `get_indexes` on startup. An implementation should return `None` from
+/// `get_indexes` if any entities were inserted, updated, or dropped since the
+/// indexes were last updated since they would then be stale.
+pub trait IndexStorage {
+    fn get_indexes(&self) -> Result<Option<IndexSnapshot>, StorageError>;
+    fn update_indexes(&mut self, snapshot: &IndexSnapshot) -> Result<(), StorageError>;
+}
+
+/// This allows a storage to persist the tombstones of deleted entities so that
+/// they stay deleted (and can still be restored) after a restart.
+///
+/// The entities referenced by a tombstone are not dropped until it is purged,
+/// so `get_all_*` should still return them. Inserting or dropping a tombstone
+/// should be treated like any other change for the purposes of `IndexStorage`.
+pub trait TombstoneStorage {
+    fn get_tombstones(&self) -> Result<Vec<Tombstone>, StorageError>;
+    fn insert_tombstone(&mut self, tombstone: &Tombstone) -> Result<(), StorageError>;
+    fn drop_tombstone(&mut self, key: TombstoneKey) -> Result<(), StorageError>;
+}
+
+/// This allows a storage to persist which entities are pinned so they stay
+/// protected from deletion after a restart.
+///
+/// Pins are not part of the persisted indexes, so changing them does not make
+/// the indexes stale.
+pub trait PinStorage {
+    fn get_pins(&self) -> Result<Vec<Pin>, StorageError>;
+    fn insert_pin(&mut self, pin: Pin) -> Result<(), StorageError>;
+    fn drop_pin(&mut self, pin: Pin) -> Result<(), StorageError>;
+}
+
+/// This allows a storage to persist annotations alongside the dataset.
+///
+/// Annotations are not part of the persisted indexes, so changing them does
+/// not make the indexes stale. `update_annotation` replaces the annotation with
+/// the same key.
+pub trait AnnotationStorage {
+    fn get_annotations(&self) -> Result<Vec<Annotation>, StorageError>;
+    fn insert_annotation(&mut self, annotation: &Annotation) -> Result<(), StorageError>;
+    fn update_annotation(&mut self, annotation: &Annotation) -> Result<(), StorageError>;
+    fn drop_annotation(&mut self, key: AnnotationKey) -> Result<(), StorageError>;
+}
+
+/// This allows a storage that holds entities in time partitions to drop whole
+/// partitions at once for retention.
+///
+/// `drop_partitions_before` should remove every partition that ends at or
+/// before `at` and return the key that all remaining spans, span events, and
+/// events are at or after (or `None` if nothing was dropped) so the engine can
+/// update its indexes. Resources are not dropped by this.
+pub trait PartitionStorage {
+    fn drop_partitions_before(&mut self, at: Timestamp) -> Result<Option<Timestamp>, StorageError>;
+}
+
+/// A serialized snapshot of the engine's span, span event, and event indexes.
+///
+/// The contents are opaque and should be stored and returned as-is. The format
+/// `version` is recorded alongside so that the engine can reject snapshots that
+/// were created by an incompatible version and rebuild them instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexSnapshot {
+    pub version: u32,
+    pub spans: Vec<u8>,
+    pub span_events: Vec<u8>,
+    pub events: Vec<u8>,
+}
+
+impl IndexSnapshot {
+    /// The current version of the index format. This will change whenever the
+    /// serialized form of any of the indexes changes.
+    pub const FORMAT_VERSION: u32 = 4;
+
+    pub(crate) fn new(
         span_indexes: &SpanIndexes,
         span_event_indexes: &SpanEventIndexes,
         event_indexes: &EventIndexes,
-    ) -> Result<(), StorageError>;
+    ) -> IndexSnapshot {
+        let bincode_options = DefaultOptions::new().with_fixint_encoding();
+
+        IndexSnapshot {
+            version: Self::FORMAT_VERSION,
+            spans: bincode_options.serialize(span_indexes).unwrap(),
+            span_events: bincode_options.serialize(span_event_indexes).unwrap(),
+            events: bincode_options.serialize(event_indexes).unwrap(),
+        }
+    }
+
+    pub(crate) fn decode(
+        &self,
+    ) -> Result<(SpanIndexes, SpanEventIndexes, EventIndexes), StorageError> {
+        if self.version != Self::FORMAT_VERSION {
+            return Err(StorageError::Internal(format!(
+                "incompatible index format version {}",
+                self.version
+            )));
+        }
+
+        let bincode_options = DefaultOptions::new().with_fixint_encoding();
+        let decode_err = |err: bincode::Error| StorageError::Internal(err.to_string());
+
+        let span_indexes = bincode_options
+            .deserialize(&self.spans)
+            .map_err(decode_err)?;
+        let span_event_indexes = bincode_options
+            .deserialize(&self.span_events)
+            .map_err(decode_err)?;
+        let event_indexes = bincode_options
+            .deserialize(&self.events)
+            .map_err(decode_err)?;
+
+        Ok((span_indexes, span_event_indexes, event_indexes))
+    }
 }
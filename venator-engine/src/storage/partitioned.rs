@@ -0,0 +1,721 @@
+use std::cell::{RefCell, RefMut};
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use lru::LruCache;
+use tracing::instrument;
+
+use crate::models::{EventKey, PreciseTimestamp, Value};
+use crate::{Event, FullSpanId, Resource, Span, SpanEvent, SpanKey, Timestamp};
+
+use super::{
+    AnnotationStorage, FileStorage, IndexStorage, PartitionStorage, PinStorage, Storage,
+    StorageError, StorageIter, TombstoneStorage,
+};
+
+const RESOURCES_FILE_NAME: &str = "resources.vena.db";
+const PARTITION_EXTENSION: &str = ".vena.db";
+const PARTITION_DURATION_FILE_NAME: &str = "partition-duration";
+const MAX_ATTACHED_PARTITIONS: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EntityKind {
+    Span,
+    SpanEvent,
+    Event,
+}
+
+impl EntityKind {
+    fn table(self) -> &'static str {
+        match self {
+            EntityKind::Span => "spans",
+            EntityKind::SpanEvent => "span_events",
+            EntityKind::Event => "events",
+        }
+    }
+}
+
+/// This storage holds entities in multiple SQLite databases within the provided
+/// directory.
+///
+/// Spans, span events, and events are partitioned by their timestamp into
+/// separate database files that each cover a fixed duration (e.g. a day).
+/// Partitions are only opened once they are needed, so a dataset spanning a
+/// long time does not need to open everything when its indexes are persisted,
+/// and only the most recently used ones are kept open.
+///
+/// Once all the entities within a partition are dropped, its file is removed
+/// entirely. Old partitions can also be removed directly with
+/// `drop_partitions_before` so retention is as fast as deleting a file.
+/// Resources and the persisted indexes are kept in their own database that is
+/// never removed.
+pub struct PartitionedStorage {
+    path: PathBuf,
+    partition_duration: u64,
+    base: FileStorage,
+    partitions: BTreeSet<u64>,
+    attached: RefCell<LruCache<u64, FileStorage>>,
+}
+
+impl PartitionedStorage {
+    /// Opens the dataset directory for reading and writing, creating it if
+    /// necessary.
+    ///
+    /// The partition duration is saved with the dataset when it is created.
+    /// Reopening it with a different duration uses the saved one instead since
+    /// the existing partitions would otherwise be misread.
+    ///
+    /// This will fail if the dataset is already opened for writing by another
+    /// instance or if the path is not a directory.
+    pub fn new(
+        path: &Path,
+        partition_duration: Duration,
+    ) -> Result<PartitionedStorage, StorageError> {
+        if path.exists() && !path.is_dir() {
+            return Err(StorageError::Internal(
+                "partitioned dataset path is not a directory".to_owned(),
+            ));
+        }
+
+        std::fs::create_dir_all(path).map_err(io_error)?;
+
+        let base = FileStorage::new(&path.join(RESOURCES_FILE_NAME))?;
+
+        let requested_duration = u64::try_from(partition_duration.as_micros())
+            .unwrap_or(u64::MAX)
+            .max(1);
+
+        let duration_path = path.join(PARTITION_DURATION_FILE_NAME);
+        let partition_duration = match std::fs::read_to_string(&duration_path) {
+            Ok(stored) => {
+                let stored_duration = stored
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|duration| *duration > 0)
+                    .ok_or_else(|| {
+                        StorageError::Internal("invalid stored partition duration".to_owned())
+                    })?;
+
+                if stored_duration != requested_duration {
+                    tracing::warn!(
+                        stored_duration,
+                        requested_duration,
+                        "dataset was created with a different partition duration, using the stored one"
+                    );
+                }
+
+                stored_duration
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                std::fs::write(&duration_path, requested_duration.to_string()).map_err(io_error)?;
+
+                requested_duration
+            }
+            Err(err) => return Err(io_error(err)),
+        };
+
+        let mut partitions = BTreeSet::new();
+        for entry in std::fs::read_dir(path).map_err(io_error)? {
+            let file_name = entry.map_err(io_error)?.file_name();
+            let file_name = file_name.to_string_lossy();
+
+            let Some(start) = file_name
+                .strip_suffix(PARTITION_EXTENSION)
+                .and_then(|name| name.parse::<u64>().ok())
+            else {
+                continue;
+            };
+
+            partitions.insert(start);
+        }
+
+        Ok(PartitionedStorage {
+            path: path.to_owned(),
+            partition_duration,
+            base,
+            partitions,
+            attached: RefCell::new(LruCache::new(
+                NonZeroUsize::new(MAX_ATTACHED_PARTITIONS).unwrap(),
+            )),
+        })
+    }
+
+    /// Returns the start of each partition that currently exists (in
+    /// microseconds since the Unix epoch).
+    pub fn partitions(&self) -> Vec<u64> {
+        self.partitions.iter().copied().collect()
+    }
+
+    fn partition_start(&self, at: Timestamp) -> u64 {
+        at.get() - at.get() % self.partition_duration
+    }
+
+    fn partition_path(&self, start: u64) -> PathBuf {
+        self.path.join(format!("{start:020}{PARTITION_EXTENSION}"))
+    }
+
+    fn attach(&self, start: u64) -> Result<RefMut<'_, FileStorage>, StorageError> {
+        let mut attached = self.attached.borrow_mut();
+        if !attached.contains(&start) {
+            // this closes the least recently used partition if too many are
+            // open, so iterating over all of them doesn't keep them all open
+            attached.push(start, FileStorage::new(&self.partition_path(start))?);
+        }
+
+        Ok(RefMut::map(attached, |attached| {
+            attached.get_mut(&start).unwrap()
+        }))
+    }
+
+    fn attach_existing(
+        &self,
+        at: Timestamp,
+    ) -> Result<Option<RefMut<'_, FileStorage>>, StorageError> {
+        let start = self.partition_start(at);
+
+        if !self.partitions.contains(&start) {
+            return Ok(None);
+        }
+
+        self.attach(start).map(Some)
+    }
+
+    fn attach_or_create(&mut self, at: Timestamp) -> Result<RefMut<'_, FileStorage>, StorageError> {
+        // writes to any partition make the indexes persisted alongside the
+        // resources out-of-date
+        self.base.invalidate_indexes();
+
+        let start = self.partition_start(at);
+        self.partitions.insert(start);
+        self.attach(start)
+    }
+
+    fn get_all<T: 'static>(
+        &self,
+        get: fn(&FileStorage) -> Result<StorageIter<'_, T>, StorageError>,
+    ) -> Result<StorageIter<'_, T>, StorageError> {
+        // partitions are only read once the iterator reaches them, so at most
+        // one partition's entities are held in memory at a time
+        let entities = self.partitions.iter().flat_map(move |start| {
+            let partition = match self.attach(*start) {
+                Ok(partition) => partition,
+                Err(err) => return vec![Err(err)],
+            };
+
+            let entities: Vec<_> = match get(&partition) {
+                Ok(entities) => entities.collect(),
+                Err(err) => vec![Err(err)],
+            };
+
+            entities
+        });
+
+        Ok(Box::new(entities))
+    }
+
+    fn group_by_partition(&self, keys: &[Timestamp]) -> BTreeMap<u64, Vec<Timestamp>> {
+        let mut keys_by_partition: BTreeMap<u64, Vec<Timestamp>> = BTreeMap::new();
+        for at in keys {
+            keys_by_partition
+                .entry(self.partition_start(*at))
+                .or_default()
+                .push(*at);
+        }
+
+        keys_by_partition.retain(|start, _| self.partitions.contains(start));
+        keys_by_partition
+    }
+
+    fn drop_entities(&mut self, kind: EntityKind, keys: &[Timestamp]) -> Result<(), StorageError> {
+        self.base.invalidate_indexes();
+
+        for (start, keys) in self.group_by_partition(keys) {
+            let mut partition = self.attach(start)?;
+
+            let mut contained_keys = Vec::with_capacity(keys.len());
+            for at in keys {
+                if partition.contains_row(kind.table(), at)? {
+                    contained_keys.push(at);
+                }
+            }
+
+            let mut keys = contained_keys;
+            keys.sort();
+            keys.dedup();
+
+            if keys.len() == partition.count_rows(kind.table())? {
+                // everything left in the partition is being dropped, so the table
+                // is cleared rather than deleting each row
+                partition.clear_rows(kind.table())?;
+            } else {
+                match kind {
+                    EntityKind::Span => partition.drop_spans(&keys)?,
+                    EntityKind::SpanEvent => partition.drop_span_events(&keys)?,
+                    EntityKind::Event => partition.drop_events(&keys)?,
+                }
+            }
+
+            let mut is_empty = true;
+            for kind in [EntityKind::Span, EntityKind::SpanEvent, EntityKind::Event] {
+                is_empty &= partition.count_rows(kind.table())? == 0;
+            }
+
+            drop(partition);
+
+            if is_empty {
+                self.remove_partition(start)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_partition(&mut self, start: u64) -> Result<(), StorageError> {
+        // the connection must be closed (which also removes its lock) before
+        // removing the file
+        self.attached.get_mut().pop(&start);
+        self.partitions.remove(&start);
+
+        let path = self.partition_path(start);
+        std::fs::remove_file(&path).map_err(io_error)?;
+
+        Ok(())
+    }
+}
+
+fn io_error(err: std::io::Error) -> StorageError {
+    StorageError::Internal(format!("{err:?}"))
+}
+
+impl Storage for PartitionedStorage {
+    fn get_resource(&self, at: Timestamp) -> Result<Arc<Resource>, StorageError> {
+        self.base.get_resource(at)
+    }
+
+    fn get_span(&self, at: Timestamp) -> Result<Arc<Span>, StorageError> {
+        match self.attach_existing(at)? {
+            Some(partition) => partition.get_span(at),
+            None => Err(StorageError::NotFound),
+        }
+    }
+
+    fn get_span_event(&self, at: Timestamp) -> Result<Arc<SpanEvent>, StorageError> {
+        match self.attach_existing(at)? {
+            Some(partition) => partition.get_span_event(at),
+            None => Err(StorageError::NotFound),
+        }
+    }
+
+    fn get_event(&self, at: Timestamp) -> Result<Arc<Event>, StorageError> {
+        match self.attach_existing(at)? {
+            Some(partition) => partition.get_event(at),
+            None => Err(StorageError::NotFound),
+        }
+    }
+
+    fn get_all_resources(&self) -> Result<StorageIter<'_, Resource>, StorageError> {
+        self.base.get_all_resources()
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn get_all_spans(&self) -> Result<StorageIter<'_, Span>, StorageError> {
+        self.get_all(FileStorage::get_all_spans)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn get_all_span_events(&self) -> Result<StorageIter<'_, SpanEvent>, StorageError> {
+        self.get_all(FileStorage::get_all_span_events)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn get_all_events(&self) -> Result<StorageIter<'_, Event>, StorageError> {
+        self.get_all(FileStorage::get_all_events)
+    }
+
+    fn insert_resource(&mut self, resource: Resource) -> Result<(), StorageError> {
+        self.base.insert_resource(resource)
+    }
+
+    fn insert_span(&mut self, span: Span) -> Result<(), StorageError> {
+        self.attach_or_create(span.key)?.insert_span(span)
+    }
+
+    fn insert_span_event(&mut self, span_event: SpanEvent) -> Result<(), StorageError> {
+        self.attach_or_create(span_event.key)?
+            .insert_span_event(span_event)
+    }
+
+    fn insert_event(&mut self, event: Event) -> Result<(), StorageError> {
+        self.attach_or_create(event.key)?.insert_event(event)
+    }
+
+    fn update_span_closed(
+        &mut self,
+        at: Timestamp,
+        closed: PreciseTimestamp,
+        busy: Option<u64>,
+    ) -> Result<(), StorageError> {
+        self.base.invalidate_indexes();
+
+        match self.attach_existing(at)? {
+            Some(mut partition) => partition.update_span_closed(at, closed, busy),
+            None => Ok(()),
+        }
+    }
+
+    fn update_span_attributes(
+        &mut self,
+        at: Timestamp,
+        attributes: BTreeMap<String, Value>,
+    ) -> Result<(), StorageError> {
+        self.base.invalidate_indexes();
+
+        match self.attach_existing(at)? {
+            Some(mut partition) => partition.update_span_attributes(at, attributes),
+            None => Ok(()),
+        }
+    }
+
+    fn update_span_link(
+        &mut self,
+        at: Timestamp,
+        link: FullSpanId,
+        attributes: BTreeMap<String, Value>,
+    ) -> Result<(), StorageError> {
+        self.base.invalidate_indexes();
+
+        match self.attach_existing(at)? {
+            Some(mut partition) => partition.update_span_link(at, link, attributes),
+            None => Ok(()),
+        }
+    }
+
+    fn update_span_parents(
+        &mut self,
+        parent_key: SpanKey,
+        spans: &[SpanKey],
+    ) -> Result<(), StorageError> {
+        self.base.invalidate_indexes();
+
+        for (start, spans) in self.group_by_partition(spans) {
+            self.attach(start)?
+                .update_span_parents(parent_key, &spans)?;
+        }
+
+        Ok(())
+    }
+
+    fn update_event_parents(
+        &mut self,
+        parent_key: SpanKey,
+        events: &[EventKey],
+    ) -> Result<(), StorageError> {
+        self.base.invalidate_indexes();
+
+        for (start, events) in self.group_by_partition(events) {
+            self.attach(start)?
+                .update_event_parents(parent_key, &events)?;
+        }
+
+        Ok(())
+    }
+
+    fn drop_resources(&mut self, resources: &[Timestamp]) -> Result<(), StorageError> {
+        self.base.drop_resources(resources)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn drop_spans(&mut self, spans: &[Timestamp]) -> Result<(), StorageError> {
+        self.drop_entities(EntityKind::Span, spans)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn drop_span_events(&mut self, span_events: &[Timestamp]) -> Result<(), StorageError> {
+        self.drop_entities(EntityKind::SpanEvent, span_events)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn drop_events(&mut self, events: &[Timestamp]) -> Result<(), StorageError> {
+        self.drop_entities(EntityKind::Event, events)
+    }
+
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn compact(&mut self) -> Result<u64, StorageError> {
+        let mut reclaimed = self.base.compact()?;
+
+        for start in self.partitions.clone() {
+            reclaimed += self.attach(start)?.compact()?;
+        }
+
+        Ok(reclaimed)
+    }
+
+    fn as_index_storage(&self) -> Option<&dyn IndexStorage> {
+        self.base.as_index_storage()
+    }
+
+    fn as_index_storage_mut(&mut self) -> Option<&mut dyn IndexStorage> {
+        self.base.as_index_storage_mut()
+    }
+
+    fn as_tombstone_storage(&self) -> Option<&dyn TombstoneStorage> {
+        self.base.as_tombstone_storage()
+    }
+
+    fn as_tombstone_storage_mut(&mut self) -> Option<&mut dyn TombstoneStorage> {
+        self.base.as_tombstone_storage_mut()
+    }
+
+    fn as_pin_storage(&self) -> Option<&dyn PinStorage> {
+        self.base.as_pin_storage()
+    }
+
+    fn as_pin_storage_mut(&mut self) -> Option<&mut dyn PinStorage> {
+        self.base.as_pin_storage_mut()
+    }
+
+    fn as_annotation_storage(&self) -> Option<&dyn AnnotationStorage> {
+        self.base.as_annotation_storage()
+    }
+
+    fn as_annotation_storage_mut(&mut self) -> Option<&mut dyn AnnotationStorage> {
+        self.base.as_annotation_storage_mut()
+    }
+
+    fn as_partition_storage(&self) -> Option<&dyn PartitionStorage> {
+        Some(self)
+    }
+
+    fn as_partition_storage_mut(&mut self) -> Option<&mut dyn PartitionStorage> {
+        Some(self)
+    }
+}
+
+impl PartitionStorage for PartitionedStorage {
+    #[instrument(level = tracing::Level::TRACE, skip_all)]
+    fn drop_partitions_before(&mut self, at: Timestamp) -> Result<Option<Timestamp>, StorageError> {
+        let expired = self
+            .partitions
+            .iter()
+            .copied()
+            .take_while(|start| start.saturating_add(self.partition_duration) <= at.get())
+            .collect::<Vec<_>>();
+
+        let Some(last) = expired.last().copied() else {
+            return Ok(None);
+        };
+
+        self.base.invalidate_indexes();
+
+        for start in expired {
+            self.remove_partition(start)?;
+        }
+
+        Ok(Timestamp::new(last.saturating_add(self.partition_duration)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::SyncEngine;
+    use crate::models::{Level, SourceKind};
+    use crate::storage::CachedStorage;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("venator-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    fn event(timestamp: u64) -> Event {
+        Event {
+            kind: SourceKind::Tracing,
+            resource_key: Timestamp::new(1).unwrap(),
+            key: Timestamp::new(timestamp).unwrap(),
+            timestamp: timestamp * 1000,
+            parent_id: None,
+            parent_key: None,
+            content: Value::Str(format!("event {timestamp}")),
+            namespace: None,
+            function: None,
+            level: Level::Info,
+            file_name: None,
+            file_line: None,
+            file_column: None,
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn partitions_are_opened_on_demand_and_removed_when_emptied() {
+        let path = temp_path("partitioned");
+        let partition_duration = Duration::from_micros(100);
+
+        let mut storage = PartitionedStorage::new(&path, partition_duration).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        storage.insert_event(event(150)).unwrap();
+        storage.insert_event(event(160)).unwrap();
+        drop(storage);
+
+        let storage = PartitionedStorage::new(&path, partition_duration).unwrap();
+        assert_eq!(storage.partitions().len(), 2);
+        assert!(storage.attached.borrow().is_empty());
+
+        let event = storage.get_event(Timestamp::new(150).unwrap()).unwrap();
+        assert_eq!(event.content, Value::Str("event 150".to_owned()));
+        assert_eq!(storage.attached.borrow().len(), 1);
+        assert!(matches!(
+            storage.get_event(Timestamp::new(250).unwrap()),
+            Err(StorageError::NotFound)
+        ));
+
+        let mut storage = storage;
+        storage
+            .drop_events(&[Timestamp::new(150).unwrap()])
+            .unwrap();
+        assert_eq!(storage.partitions().len(), 2);
+
+        storage
+            .drop_events(&[Timestamp::new(50).unwrap(), Timestamp::new(160).unwrap()])
+            .unwrap();
+        assert!(storage.partitions().is_empty());
+        assert_eq!(storage.get_all_events().unwrap().count(), 0);
+        drop(storage);
+
+        let storage = PartitionedStorage::new(&path, partition_duration).unwrap();
+        assert!(storage.partitions().is_empty());
+        drop(storage);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn old_partitions_are_dropped_whole() {
+        let path = temp_path("partitioned-retention");
+        let mut storage = PartitionedStorage::new(&path, Duration::from_micros(100)).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        storage.insert_event(event(150)).unwrap();
+        storage.insert_event(event(250)).unwrap();
+
+        // the partition containing the cutoff is kept
+        let cutoff = storage
+            .drop_partitions_before(Timestamp::new(260).unwrap())
+            .unwrap();
+        assert_eq!(cutoff, Timestamp::new(200));
+        assert_eq!(storage.partitions(), vec![200]);
+        assert!(!storage.partition_path(0).exists());
+        assert!(!storage.partition_path(100).exists());
+
+        let cutoff = storage
+            .drop_partitions_before(Timestamp::new(260).unwrap())
+            .unwrap();
+        assert_eq!(cutoff, None);
+        assert_eq!(storage.get_all_events().unwrap().count(), 1);
+        drop(storage);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn engine_drops_keys_of_dropped_partitions() {
+        let path = temp_path("partitioned-engine-retention");
+        let mut storage = PartitionedStorage::new(&path, Duration::from_micros(100)).unwrap();
+        storage
+            .insert_resource(Resource {
+                created_at: Timestamp::new(1).unwrap(),
+                attributes: BTreeMap::new(),
+            })
+            .unwrap();
+        storage.insert_event(event(50)).unwrap();
+        storage.insert_event(event(60)).unwrap();
+        storage.insert_event(event(150)).unwrap();
+
+        let mut engine = SyncEngine::new(CachedStorage::new(10, storage)).unwrap();
+        assert_eq!(engine.query_stats().total_events, 3);
+
+        let metrics = engine
+            .drop_partitions_before(Timestamp::new(100).unwrap())
+            .unwrap();
+        assert_eq!(metrics.events, 2);
+        assert_eq!(engine.query_stats().total_events, 1);
+        drop(engine);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn iterating_keeps_a_bounded_number_of_partitions_open() {
+        let path = temp_path("partitioned-attached");
+        let mut storage = PartitionedStorage::new(&path, Duration::from_micros(100)).unwrap();
+        for partition in 0..MAX_ATTACHED_PARTITIONS as u64 * 2 {
+            storage.insert_event(event(partition * 100 + 50)).unwrap();
+        }
+
+        assert_eq!(
+            storage.get_all_events().unwrap().count(),
+            MAX_ATTACHED_PARTITIONS * 2
+        );
+        assert_eq!(storage.attached.borrow().len(), MAX_ATTACHED_PARTITIONS);
+        drop(storage);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn dropping_keys_outside_a_partition_keeps_the_rest() {
+        let path = temp_path("partitioned-drop-missing");
+        let mut storage = PartitionedStorage::new(&path, Duration::from_micros(100)).unwrap();
+        storage.insert_event(event(150)).unwrap();
+        storage.insert_event(event(160)).unwrap();
+        storage.insert_event(event(170)).unwrap();
+
+        // as many keys as there are rows, but only one of them exists
+        storage
+            .drop_events(&[
+                Timestamp::new(150).unwrap(),
+                Timestamp::new(151).unwrap(),
+                Timestamp::new(152).unwrap(),
+            ])
+            .unwrap();
+
+        assert_eq!(storage.get_all_events().unwrap().count(), 2);
+        assert!(storage.get_event(Timestamp::new(160).unwrap()).is_ok());
+        drop(storage);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn reopening_uses_the_stored_partition_duration() {
+        let path = temp_path("partitioned-duration");
+        let mut storage = PartitionedStorage::new(&path, Duration::from_micros(100)).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        storage.insert_event(event(150)).unwrap();
+        drop(storage);
+
+        let storage = PartitionedStorage::new(&path, Duration::from_micros(1000)).unwrap();
+        assert_eq!(storage.partitions(), vec![0, 100]);
+        let event = storage.get_event(Timestamp::new(150).unwrap()).unwrap();
+        assert_eq!(event.content, Value::Str("event 150".to_owned()));
+        assert_eq!(storage.get_all_events().unwrap().count(), 2);
+        drop(storage);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn opening_a_file_as_a_partitioned_dataset_is_an_error() {
+        let path = temp_path("partitioned-file");
+        std::fs::write(&path, b"not a directory").unwrap();
+
+        assert!(PartitionedStorage::new(&path, Duration::from_micros(100)).is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
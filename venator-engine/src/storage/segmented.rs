@@ -0,0 +1,1074 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bincode::{DefaultOptions, Options};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{EventKey, PreciseTimestamp, Value};
+use crate::{Event, FullSpanId, Resource, Span, SpanEvent, SpanKey, Timestamp};
+
+use super::{Storage, StorageError, StorageIter};
+
+const HEADER_LEN: usize = 13;
+const GENERATION_LEN: u64 = 8;
+const SEGMENT_DURATION_FILE_NAME: &str = "segment-duration";
+
+#[allow(unused)]
+#[derive(Debug)]
+enum SegmentedStorageError {
+    Io(IoError),
+    Encode(bincode::Error),
+    Decode(bincode::Error),
+    UnknownEntityKind(u8),
+}
+
+impl From<SegmentedStorageError> for StorageError {
+    fn from(value: SegmentedStorageError) -> Self {
+        StorageError::Internal(format!("{value:?}"))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum EntityKind {
+    Resource = 0,
+    Span = 1,
+    SpanEvent = 2,
+    Event = 3,
+}
+
+impl TryFrom<u8> for EntityKind {
+    type Error = SegmentedStorageError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(EntityKind::Resource),
+            1 => Ok(EntityKind::Span),
+            2 => Ok(EntityKind::SpanEvent),
+            3 => Ok(EntityKind::Event),
+            _ => Err(SegmentedStorageError::UnknownEntityKind(value)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum Mutation {
+    SpanClosed(PreciseTimestamp, Option<u64>),
+    SpanAttributes(BTreeMap<String, Value>),
+    SpanLink(FullSpanId, BTreeMap<String, Value>),
+    SpanParent(SpanKey),
+    EventParent(SpanKey),
+    Dropped,
+}
+
+/// A mutation as it is written to the log. It records the generation of the
+/// data file it applies to and the length of that file when it was made so it
+/// can be replayed in order with the entities that were appended around it.
+#[derive(Serialize, Deserialize)]
+struct LoggedMutation<M> {
+    generation: u64,
+    position: u64,
+    mutation: M,
+}
+
+/// This storage holds all entities in append-only segment files within the
+/// provided directory.
+///
+/// Spans, span events, and events are partitioned by their timestamp into
+/// segments of a fixed duration. Each segment consists of a data file that
+/// entities are appended to and a mutation log that records updates and drops,
+/// which are applied when entities are read. The location of each entity is
+/// kept in a per-segment key index that is rebuilt from the files on startup.
+///
+/// Once all the entities within a segment are dropped, its files are removed
+/// entirely so retention does not require rewriting any data. Resources are
+/// kept in their own segment that is never removed.
+pub struct SegmentedStorage {
+    path: PathBuf,
+    segment_duration: u64,
+    resources: Segment,
+    segments: BTreeMap<u64, Segment>,
+}
+
+impl SegmentedStorage {
+    /// Opens the storage in the directory, creating it if necessary. This will
+    /// fail if the directory can't be read or a segment is corrupted.
+    ///
+    /// The segment duration is saved with the dataset when it is created.
+    /// Reopening it with a different duration uses the saved one instead since
+    /// the existing segments would otherwise be misread.
+    pub fn new(path: &Path, segment_duration: Duration) -> Result<SegmentedStorage, StorageError> {
+        std::fs::create_dir_all(path).map_err(SegmentedStorageError::Io)?;
+
+        let requested_duration = u64::try_from(segment_duration.as_micros())
+            .unwrap_or(u64::MAX)
+            .max(1);
+
+        let duration_path = path.join(SEGMENT_DURATION_FILE_NAME);
+        let segment_duration = match std::fs::read_to_string(&duration_path) {
+            Ok(stored) => {
+                let stored_duration = stored
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|duration| *duration > 0)
+                    .ok_or_else(|| {
+                        StorageError::Internal("invalid stored segment duration".to_owned())
+                    })?;
+
+                if stored_duration != requested_duration {
+                    tracing::warn!(
+                        stored_duration,
+                        requested_duration,
+                        "dataset was created with a different segment duration, using the stored one"
+                    );
+                }
+
+                stored_duration
+            }
+            Err(err) if err.kind() == IoErrorKind::NotFound => {
+                std::fs::write(&duration_path, requested_duration.to_string())
+                    .map_err(SegmentedStorageError::Io)?;
+
+                requested_duration
+            }
+            Err(err) => return Err(SegmentedStorageError::Io(err).into()),
+        };
+
+        let resources = Segment::open(path, "resources")?;
+
+        let mut segments = BTreeMap::new();
+        for entry in std::fs::read_dir(path).map_err(SegmentedStorageError::Io)? {
+            let file_name = entry.map_err(SegmentedStorageError::Io)?.file_name();
+            let file_name = file_name.to_string_lossy();
+
+            let Some(start) = file_name
+                .strip_suffix(".seg")
+                .and_then(|name| name.parse::<u64>().ok())
+            else {
+                continue;
+            };
+
+            let segment = Segment::open(path, &segment_name(start))?;
+            segments.insert(start, segment);
+        }
+
+        Ok(SegmentedStorage {
+            path: path.to_owned(),
+            segment_duration,
+            resources,
+            segments,
+        })
+    }
+
+    fn segment_start(&self, at: Timestamp) -> u64 {
+        at.get() - at.get() % self.segment_duration
+    }
+
+    fn segment(&self, at: Timestamp) -> Option<&Segment> {
+        self.segments.get(&self.segment_start(at))
+    }
+
+    fn segment_mut(&mut self, at: Timestamp) -> Option<&mut Segment> {
+        let start = self.segment_start(at);
+        self.segments.get_mut(&start)
+    }
+
+    fn segment_or_create(&mut self, at: Timestamp) -> Result<&mut Segment, SegmentedStorageError> {
+        let start = self.segment_start(at);
+
+        if !self.segments.contains_key(&start) {
+            let segment = Segment::open(&self.path, &segment_name(start))?;
+            self.segments.insert(start, segment);
+        }
+
+        Ok(self.segments.get_mut(&start).unwrap())
+    }
+
+    fn get_all<T: DeserializeOwned + 'static>(
+        &self,
+        kind: EntityKind,
+        apply: fn(&mut T, &Mutation),
+    ) -> StorageIter<'_, T> {
+        Box::new(self.segments.values().flat_map(move |segment| {
+            segment.keys(kind).map(move |at| {
+                segment
+                    .read(kind, at, apply)
+                    .map(Arc::new)
+                    .map_err(StorageError::from)
+            })
+        }))
+    }
+
+    fn mutate(
+        &mut self,
+        kind: EntityKind,
+        at: Timestamp,
+        mutation: Mutation,
+    ) -> Result<(), StorageError> {
+        if let Some(segment) = self.segment_mut(at) {
+            if segment.contains(kind, at) {
+                segment.append_mutation(kind, at, mutation)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn drop_entities(&mut self, kind: EntityKind, keys: &[Timestamp]) -> Result<(), StorageError> {
+        let mut keys_by_segment: BTreeMap<u64, Vec<Timestamp>> = BTreeMap::new();
+        for at in keys {
+            keys_by_segment
+                .entry(self.segment_start(*at))
+                .or_default()
+                .push(*at);
+        }
+
+        for (start, keys) in keys_by_segment {
+            let Some(segment) = self.segments.get_mut(&start) else {
+                continue;
+            };
+
+            let mut keys = keys;
+            keys.retain(|at| segment.contains(kind, *at));
+            keys.sort();
+            keys.dedup();
+
+            if keys.len() == segment.len() {
+                // everything left in the segment is being dropped, so rather
+                // than recording each drop the whole segment is removed
+                let segment = self.segments.remove(&start).unwrap();
+                segment.remove()?;
+            } else {
+                for at in keys {
+                    segment.append_mutation(kind, at, Mutation::Dropped)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for SegmentedStorage {
+    fn get_resource(&self, at: Timestamp) -> Result<Arc<Resource>, StorageError> {
+        if !self.resources.contains(EntityKind::Resource, at) {
+            return Err(StorageError::NotFound);
+        }
+
+        let resource = self
+            .resources
+            .read(EntityKind::Resource, at, apply_resource_mutation)?;
+
+        Ok(Arc::new(resource))
+    }
+
+    fn get_span(&self, at: Timestamp) -> Result<Arc<Span>, StorageError> {
+        match self.segment(at) {
+            Some(segment) if segment.contains(EntityKind::Span, at) => {
+                let span = segment.read(EntityKind::Span, at, apply_span_mutation)?;
+                Ok(Arc::new(span))
+            }
+            _ => Err(StorageError::NotFound),
+        }
+    }
+
+    fn get_span_event(&self, at: Timestamp) -> Result<Arc<SpanEvent>, StorageError> {
+        match self.segment(at) {
+            Some(segment) if segment.contains(EntityKind::SpanEvent, at) => {
+                let span_event =
+                    segment.read(EntityKind::SpanEvent, at, apply_span_event_mutation)?;
+                Ok(Arc::new(span_event))
+            }
+            _ => Err(StorageError::NotFound),
+        }
+    }
+
+    fn get_event(&self, at: Timestamp) -> Result<Arc<Event>, StorageError> {
+        match self.segment(at) {
+            Some(segment) if segment.contains(EntityKind::Event, at) => {
+                let event = segment.read(EntityKind::Event, at, apply_event_mutation)?;
+                Ok(Arc::new(event))
+            }
+            _ => Err(StorageError::NotFound),
+        }
+    }
+
+    fn get_all_resources(&self) -> Result<StorageIter<'_, Resource>, StorageError> {
+        let resources = &self.resources;
+
+        Ok(Box::new(resources.keys(EntityKind::Resource).map(
+            move |at| {
+                resources
+                    .read(EntityKind::Resource, at, apply_resource_mutation)
+                    .map(Arc::new)
+                    .map_err(StorageError::from)
+            },
+        )))
+    }
+
+    fn get_all_spans(&self) -> Result<StorageIter<'_, Span>, StorageError> {
+        Ok(self.get_all(EntityKind::Span, apply_span_mutation))
+    }
+
+    fn get_all_span_events(&self) -> Result<StorageIter<'_, SpanEvent>, StorageError> {
+        Ok(self.get_all(EntityKind::SpanEvent, apply_span_event_mutation))
+    }
+
+    fn get_all_events(&self) -> Result<StorageIter<'_, Event>, StorageError> {
+        Ok(self.get_all(EntityKind::Event, apply_event_mutation))
+    }
+
+    fn insert_resource(&mut self, resource: Resource) -> Result<(), StorageError> {
+        self.resources
+            .append(EntityKind::Resource, resource.key(), &resource)?;
+        Ok(())
+    }
+
+    fn insert_span(&mut self, span: Span) -> Result<(), StorageError> {
+        let at = span.key;
+        self.segment_or_create(at)?
+            .append(EntityKind::Span, at, &span)?;
+        Ok(())
+    }
+
+    fn insert_span_event(&mut self, span_event: SpanEvent) -> Result<(), StorageError> {
+        let at = span_event.key;
+        self.segment_or_create(at)?
+            .append(EntityKind::SpanEvent, at, &span_event)?;
+        Ok(())
+    }
+
+    fn insert_event(&mut self, event: Event) -> Result<(), StorageError> {
+        let at = event.key;
+        self.segment_or_create(at)?
+            .append(EntityKind::Event, at, &event)?;
+        Ok(())
+    }
+
+    fn update_span_closed(
+        &mut self,
+        at: Timestamp,
+        closed: PreciseTimestamp,
+        busy: Option<u64>,
+    ) -> Result<(), StorageError> {
+        self.mutate(EntityKind::Span, at, Mutation::SpanClosed(closed, busy))
+    }
+
+    fn update_span_attributes(
+        &mut self,
+        at: Timestamp,
+        attributes: BTreeMap<String, Value>,
+    ) -> Result<(), StorageError> {
+        self.mutate(EntityKind::Span, at, Mutation::SpanAttributes(attributes))
+    }
+
+    fn update_span_link(
+        &mut self,
+        at: Timestamp,
+        link: FullSpanId,
+        attributes: BTreeMap<String, Value>,
+    ) -> Result<(), StorageError> {
+        self.mutate(EntityKind::Span, at, Mutation::SpanLink(link, attributes))
+    }
+
+    fn update_span_parents(
+        &mut self,
+        parent_key: SpanKey,
+        spans: &[SpanKey],
+    ) -> Result<(), StorageError> {
+        for span in spans {
+            self.mutate(EntityKind::Span, *span, Mutation::SpanParent(parent_key))?;
+        }
+
+        Ok(())
+    }
+
+    fn update_event_parents(
+        &mut self,
+        parent_key: SpanKey,
+        events: &[EventKey],
+    ) -> Result<(), StorageError> {
+        for event in events {
+            self.mutate(EntityKind::Event, *event, Mutation::EventParent(parent_key))?;
+        }
+
+        Ok(())
+    }
+
+    fn drop_resources(&mut self, resources: &[Timestamp]) -> Result<(), StorageError> {
+        for at in resources {
+            if self.resources.contains(EntityKind::Resource, *at) {
+                self.resources
+                    .append_mutation(EntityKind::Resource, *at, Mutation::Dropped)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn drop_spans(&mut self, spans: &[Timestamp]) -> Result<(), StorageError> {
+        self.drop_entities(EntityKind::Span, spans)
+    }
+
+    fn drop_span_events(&mut self, span_events: &[Timestamp]) -> Result<(), StorageError> {
+        self.drop_entities(EntityKind::SpanEvent, span_events)
+    }
+
+    fn drop_events(&mut self, events: &[Timestamp]) -> Result<(), StorageError> {
+        self.drop_entities(EntityKind::Event, events)
+    }
+
+    fn compact(&mut self) -> Result<u64, StorageError> {
+        let mut bytes_reclaimed = self.resources.compact()?;
+        for segment in self.segments.values_mut() {
+            bytes_reclaimed += segment.compact()?;
+        }
+
+        Ok(bytes_reclaimed)
+    }
+}
+
+/// A single partition of the storage, made up of a data file and mutation log.
+///
+/// Both files are sequences of records, each with a header of the entity kind
+/// (1 byte), the entity key (8 bytes), and the payload length (4 bytes)
+/// followed by the bincode-encoded payload. The data file starts with its
+/// generation (8 bytes) which is incremented each time it is compacted.
+///
+/// Once the mutation log grows larger than the data file, the segment is
+/// compacted by rewriting the data with the mutations applied.
+struct Segment {
+    data_path: PathBuf,
+    mutations_path: PathBuf,
+    data: RefCell<File>,
+    mutations: File,
+    generation: u64,
+    data_len: u64,
+    mutations_len: u64,
+    index: BTreeMap<(EntityKind, Timestamp), (u64, u32)>,
+    pending: HashMap<(EntityKind, Timestamp), Vec<Mutation>>,
+}
+
+impl Segment {
+    fn open(dir: &Path, name: &str) -> Result<Segment, SegmentedStorageError> {
+        let data_path = dir.join(format!("{name}.seg"));
+        let mutations_path = dir.join(format!("{name}.mut"));
+
+        let mut data = open_log(&data_path)?;
+        let mut mutations = open_log(&mutations_path)?;
+
+        let generation = read_generation(&mut data)?;
+
+        let mut records = Vec::new();
+        let data_len = scan_records(&mut data, GENERATION_LEN, |kind, at, offset, len, _| {
+            records.push((offset, kind, at, len));
+            Ok(())
+        })?;
+
+        let mut logged = Vec::new();
+        let mut stale = false;
+        let mut mutations_len = scan_records(&mut mutations, 0, |kind, at, _, _, payload| {
+            let record: LoggedMutation<Mutation> = decode(&payload()?)?;
+            if record.generation == generation {
+                logged.push((record.position, kind, at, record.mutation));
+            } else {
+                stale = true;
+            }
+            Ok(())
+        })?;
+
+        // the segment was compacted but the log wasn't emptied before shutting
+        // down, so its mutations are already applied in the data file
+        if stale {
+            tracing::warn!("discarding mutation log from before segment was compacted");
+            mutations.set_len(0).map_err(SegmentedStorageError::Io)?;
+            mutations_len = 0;
+            logged.clear();
+        }
+
+        let mut segment = Segment {
+            data_path,
+            mutations_path,
+            data: RefCell::new(data),
+            mutations,
+            generation,
+            data_len,
+            mutations_len,
+            index: BTreeMap::new(),
+            pending: HashMap::new(),
+        };
+
+        // a mutation only applies to the entities appended before it, so they
+        // are replayed in the order they were originally written
+        let mut logged = logged.into_iter().peekable();
+        for (offset, kind, at, len) in records {
+            while let Some((_, kind, at, mutation)) =
+                logged.next_if(|(position, ..)| *position <= offset)
+            {
+                segment.track_mutation(kind, at, mutation);
+            }
+
+            segment.track_entity(kind, at, offset, len);
+        }
+        for (_, kind, at, mutation) in logged {
+            segment.track_mutation(kind, at, mutation);
+        }
+
+        Ok(segment)
+    }
+
+    fn track_entity(&mut self, kind: EntityKind, at: Timestamp, offset: u64, len: u32) {
+        self.index.insert((kind, at), (offset, len));
+        self.pending.remove(&(kind, at));
+    }
+
+    fn track_mutation(&mut self, kind: EntityKind, at: Timestamp, mutation: Mutation) {
+        if let Mutation::Dropped = mutation {
+            self.index.remove(&(kind, at));
+            self.pending.remove(&(kind, at));
+        } else {
+            self.pending.entry((kind, at)).or_default().push(mutation);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn contains(&self, kind: EntityKind, at: Timestamp) -> bool {
+        self.index.contains_key(&(kind, at))
+    }
+
+    fn keys(&self, kind: EntityKind) -> impl Iterator<Item = Timestamp> + '_ {
+        self.index
+            .range((kind, Timestamp::MIN)..=(kind, Timestamp::MAX))
+            .map(|((_, at), _)| *at)
+    }
+
+    fn read<T: DeserializeOwned>(
+        &self,
+        kind: EntityKind,
+        at: Timestamp,
+        apply: fn(&mut T, &Mutation),
+    ) -> Result<T, SegmentedStorageError> {
+        let (offset, len) = self.index[&(kind, at)];
+
+        let mut payload = vec![0; len as usize];
+        let mut data = self.data.borrow_mut();
+        data.seek(SeekFrom::Start(offset + HEADER_LEN as u64))
+            .map_err(SegmentedStorageError::Io)?;
+        data.read_exact(&mut payload)
+            .map_err(SegmentedStorageError::Io)?;
+        drop(data);
+
+        let mut entity: T = decode(&payload)?;
+        for mutation in self.pending.get(&(kind, at)).into_iter().flatten() {
+            apply(&mut entity, mutation);
+        }
+
+        Ok(entity)
+    }
+
+    fn append<T: Serialize>(
+        &mut self,
+        kind: EntityKind,
+        at: Timestamp,
+        entity: &T,
+    ) -> Result<(), SegmentedStorageError> {
+        let record = encode_record(kind, at, entity)?;
+
+        self.data
+            .get_mut()
+            .write_all(&record)
+            .map_err(SegmentedStorageError::Io)?;
+
+        let len = (record.len() - HEADER_LEN) as u32;
+        self.track_entity(kind, at, self.data_len, len);
+        self.data_len += record.len() as u64;
+
+        Ok(())
+    }
+
+    fn append_mutation(
+        &mut self,
+        kind: EntityKind,
+        at: Timestamp,
+        mutation: Mutation,
+    ) -> Result<(), SegmentedStorageError> {
+        let logged = LoggedMutation {
+            generation: self.generation,
+            position: self.data_len,
+            mutation: &mutation,
+        };
+        let record = encode_record(kind, at, &logged)?;
+
+        self.mutations
+            .write_all(&record)
+            .map_err(SegmentedStorageError::Io)?;
+        self.mutations_len += record.len() as u64;
+
+        self.track_mutation(kind, at, mutation);
+
+        if self.mutations_len > self.data_len {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the data file with all pending mutations applied and dropped
+    /// entities removed, and then empties the mutation log. Returns the number
+    /// of bytes reclaimed.
+    ///
+    /// The new data file has the next generation, so if the log can't be
+    /// emptied after replacing it, its mutations are ignored when reopened.
+    fn compact(&mut self) -> Result<u64, SegmentedStorageError> {
+        if self.mutations_len == 0 {
+            return Ok(0);
+        }
+
+        let len_before = self.data_len + self.mutations_len;
+
+        let compacted_path = self.data_path.with_extension("seg.tmp");
+        let mut compacted = File::create(&compacted_path).map_err(SegmentedStorageError::Io)?;
+
+        let generation = self.generation + 1;
+        compacted
+            .write_all(&generation.to_le_bytes())
+            .map_err(SegmentedStorageError::Io)?;
+
+        let mut index = BTreeMap::new();
+        let mut data_len = GENERATION_LEN;
+        for &(kind, at) in self.index.keys() {
+            let record = match kind {
+                EntityKind::Resource => {
+                    encode_record(kind, at, &self.read(kind, at, apply_resource_mutation)?)?
+                }
+                EntityKind::Span => {
+                    encode_record(kind, at, &self.read(kind, at, apply_span_mutation)?)?
+                }
+                EntityKind::SpanEvent => {
+                    encode_record(kind, at, &self.read(kind, at, apply_span_event_mutation)?)?
+                }
+                EntityKind::Event => {
+                    encode_record(kind, at, &self.read(kind, at, apply_event_mutation)?)?
+                }
+            };
+
+            compacted
+                .write_all(&record)
+                .map_err(SegmentedStorageError::Io)?;
+
+            let len = (record.len() - HEADER_LEN) as u32;
+            index.insert((kind, at), (data_len, len));
+            data_len += record.len() as u64;
+        }
+
+        compacted.sync_all().map_err(SegmentedStorageError::Io)?;
+        drop(compacted);
+
+        // the mutations are already applied in the new data file, so the log
+        // is emptied only after it has replaced the old one
+        std::fs::rename(&compacted_path, &self.data_path).map_err(SegmentedStorageError::Io)?;
+        *self.data.get_mut() = open_log(&self.data_path)?;
+        self.mutations
+            .set_len(0)
+            .map_err(SegmentedStorageError::Io)?;
+
+        self.generation = generation;
+        self.data_len = data_len;
+        self.mutations_len = 0;
+        self.index = index;
+        self.pending.clear();
+
+        Ok(len_before.saturating_sub(data_len))
+    }
+
+    fn remove(self) -> Result<(), SegmentedStorageError> {
+        let Segment {
+            data_path,
+            mutations_path,
+            data,
+            mutations,
+            ..
+        } = self;
+
+        drop(data);
+        drop(mutations);
+
+        std::fs::remove_file(data_path).map_err(SegmentedStorageError::Io)?;
+        std::fs::remove_file(mutations_path).map_err(SegmentedStorageError::Io)?;
+
+        Ok(())
+    }
+}
+
+fn segment_name(start: u64) -> String {
+    format!("{start:020}")
+}
+
+fn open_log(path: &Path) -> Result<File, SegmentedStorageError> {
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(SegmentedStorageError::Io)
+}
+
+/// This reads the generation from the start of a data file, writing the
+/// initial one if the file is new (or was left incomplete when created).
+fn read_generation(file: &mut File) -> Result<u64, SegmentedStorageError> {
+    let file_len = file.metadata().map_err(SegmentedStorageError::Io)?.len();
+
+    if file_len < GENERATION_LEN {
+        file.set_len(0).map_err(SegmentedStorageError::Io)?;
+        file.write_all(&0u64.to_le_bytes())
+            .map_err(SegmentedStorageError::Io)?;
+        return Ok(0);
+    }
+
+    let mut generation = [0; GENERATION_LEN as usize];
+    file.seek(SeekFrom::Start(0))
+        .map_err(SegmentedStorageError::Io)?;
+    file.read_exact(&mut generation)
+        .map_err(SegmentedStorageError::Io)?;
+
+    Ok(u64::from_le_bytes(generation))
+}
+
+/// This reads through all the records in the file after `start`, calling `f`
+/// with the kind, key, offset, payload length, and a way to read the payload of
+/// each. If the file ends with an incomplete record (from an unclean shutdown)
+/// it will be truncated. Returns the length of the valid portion of the file.
+fn scan_records<F>(file: &mut File, start: u64, mut f: F) -> Result<u64, SegmentedStorageError>
+where
+    F: FnMut(
+        EntityKind,
+        Timestamp,
+        u64,
+        u32,
+        &mut dyn FnMut() -> Result<Vec<u8>, SegmentedStorageError>,
+    ) -> Result<(), SegmentedStorageError>,
+{
+    let file_len = file.metadata().map_err(SegmentedStorageError::Io)?.len();
+
+    file.seek(SeekFrom::Start(start))
+        .map_err(SegmentedStorageError::Io)?;
+
+    let mut offset = start;
+    let mut header = [0; HEADER_LEN];
+    loop {
+        match file.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == IoErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(SegmentedStorageError::Io(err)),
+        }
+
+        let kind = EntityKind::try_from(header[0])?;
+        let key = u64::from_le_bytes(header[1..9].try_into().unwrap());
+        let len = u32::from_le_bytes(header[9..13].try_into().unwrap());
+
+        let record_end = offset + HEADER_LEN as u64 + len as u64;
+        let Some(at) = Timestamp::new(key).filter(|_| record_end <= file_len) else {
+            break;
+        };
+
+        let mut payload_read = false;
+        let mut read_payload = || {
+            payload_read = true;
+            let mut payload = vec![0; len as usize];
+            file.read_exact(&mut payload)
+                .map_err(SegmentedStorageError::Io)?;
+            Ok(payload)
+        };
+
+        f(kind, at, offset, len, &mut read_payload)?;
+
+        if !payload_read {
+            file.seek(SeekFrom::Start(record_end))
+                .map_err(SegmentedStorageError::Io)?;
+        }
+
+        offset = record_end;
+    }
+
+    if offset < file_len {
+        tracing::warn!("truncating incomplete record from segment file");
+        file.set_len(offset).map_err(SegmentedStorageError::Io)?;
+    }
+
+    Ok(offset)
+}
+
+fn encode_record<T: Serialize>(
+    kind: EntityKind,
+    at: Timestamp,
+    value: &T,
+) -> Result<Vec<u8>, SegmentedStorageError> {
+    let payload = DefaultOptions::new()
+        .serialize(value)
+        .map_err(SegmentedStorageError::Encode)?;
+
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.push(kind as u8);
+    record.extend(at.get().to_le_bytes());
+    record.extend((payload.len() as u32).to_le_bytes());
+    record.extend(payload);
+
+    Ok(record)
+}
+
+fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, SegmentedStorageError> {
+    DefaultOptions::new()
+        .deserialize(payload)
+        .map_err(SegmentedStorageError::Decode)
+}
+
+fn apply_resource_mutation(_resource: &mut Resource, _mutation: &Mutation) {}
+
+fn apply_span_mutation(span: &mut Span, mutation: &Mutation) {
+    match mutation {
+        Mutation::SpanClosed(closed_at, busy) => {
+            span.closed_at = Some(*closed_at);
+            span.busy = *busy;
+        }
+        Mutation::SpanAttributes(attributes) => {
+            span.attributes.extend(attributes.clone());
+        }
+        Mutation::SpanLink(link, attributes) => {
+            span.links.push((*link, attributes.clone()));
+        }
+        Mutation::SpanParent(parent_key) => {
+            span.parent_key = Some(*parent_key);
+        }
+        Mutation::EventParent(_) | Mutation::Dropped => {}
+    }
+}
+
+fn apply_span_event_mutation(_span_event: &mut SpanEvent, _mutation: &Mutation) {}
+
+fn apply_event_mutation(event: &mut Event, mutation: &Mutation) {
+    if let Mutation::EventParent(parent_key) = mutation {
+        event.parent_key = Some(*parent_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Level, SourceKind};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("venator-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    fn event(timestamp: u64) -> Event {
+        Event {
+            kind: SourceKind::Tracing,
+            resource_key: Timestamp::new(1).unwrap(),
+            key: Timestamp::new(timestamp).unwrap(),
+            timestamp: timestamp * 1000,
+            parent_id: None,
+            parent_key: None,
+            content: Value::Str(format!("event {timestamp}")),
+            namespace: None,
+            function: None,
+            level: Level::Info,
+            file_name: None,
+            file_line: None,
+            file_column: None,
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn entities_and_mutations_survive_reopening() {
+        let path = temp_path("segmented-reopen");
+        let segment_duration = Duration::from_micros(100);
+
+        let mut storage = SegmentedStorage::new(&path, segment_duration).unwrap();
+        storage.insert_event(event(150)).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        storage.insert_event(event(120)).unwrap();
+        storage
+            .update_event_parents(Timestamp::new(10).unwrap(), &[Timestamp::new(120).unwrap()])
+            .unwrap();
+        storage
+            .drop_events(&[Timestamp::new(150).unwrap()])
+            .unwrap();
+        drop(storage);
+
+        let storage = SegmentedStorage::new(&path, segment_duration).unwrap();
+        let events = storage
+            .get_all_events()
+            .unwrap()
+            .map(|e| e.unwrap().key.get())
+            .collect::<Vec<_>>();
+        assert_eq!(events, [50, 120]);
+
+        let event = storage.get_event(Timestamp::new(120).unwrap()).unwrap();
+        assert_eq!(event.parent_key, Timestamp::new(10));
+        assert!(storage.get_event(Timestamp::new(150).unwrap()).is_err());
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn reinserted_entities_survive_reopening() {
+        let path = temp_path("segmented-reinsert");
+        let segment_duration = Duration::from_micros(100);
+
+        let mut storage = SegmentedStorage::new(&path, segment_duration).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        storage.insert_event(event(60)).unwrap();
+        storage
+            .update_event_parents(Timestamp::new(10).unwrap(), &[Timestamp::new(50).unwrap()])
+            .unwrap();
+        storage.drop_events(&[Timestamp::new(50).unwrap()]).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        drop(storage);
+
+        let storage = SegmentedStorage::new(&path, segment_duration).unwrap();
+        let event = storage.get_event(Timestamp::new(50).unwrap()).unwrap();
+        assert_eq!(event.parent_key, None);
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn mutation_log_from_before_compaction_is_ignored() {
+        let path = temp_path("segmented-stale-log");
+        let segment_duration = Duration::from_micros(100);
+        let mutations_path = path.join(format!("{}.mut", segment_name(0)));
+
+        let mut storage = SegmentedStorage::new(&path, segment_duration).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        storage.drop_events(&[Timestamp::new(50).unwrap()]).unwrap();
+        storage.insert_event(event(50)).unwrap();
+
+        // simulate shutting down after the data file was replaced but before
+        // the log was emptied
+        let stale_log = std::fs::read(&mutations_path).unwrap();
+        storage.compact().unwrap();
+        drop(storage);
+        std::fs::write(&mutations_path, stale_log).unwrap();
+
+        let storage = SegmentedStorage::new(&path, segment_duration).unwrap();
+        assert!(storage.get_event(Timestamp::new(50).unwrap()).is_ok());
+        assert_eq!(std::fs::metadata(&mutations_path).unwrap().len(), 0);
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn reopening_uses_stored_segment_duration() {
+        let path = temp_path("segmented-duration");
+
+        let mut storage = SegmentedStorage::new(&path, Duration::from_micros(100)).unwrap();
+        storage.insert_event(event(150)).unwrap();
+        drop(storage);
+
+        let storage = SegmentedStorage::new(&path, Duration::from_micros(1000)).unwrap();
+        assert_eq!(storage.segment_duration, 100);
+        assert!(storage.get_event(Timestamp::new(150).unwrap()).is_ok());
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn dropping_all_entities_removes_segment() {
+        let path = temp_path("segmented-retention");
+
+        let mut storage = SegmentedStorage::new(&path, Duration::from_micros(100)).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        storage.insert_event(event(60)).unwrap();
+        storage.insert_event(event(150)).unwrap();
+        storage
+            .drop_events(&[Timestamp::new(50).unwrap(), Timestamp::new(60).unwrap()])
+            .unwrap();
+
+        assert!(!path.join(format!("{}.seg", segment_name(0))).exists());
+        assert!(path.join(format!("{}.seg", segment_name(100))).exists());
+        assert_eq!(storage.segments.len(), 1);
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn corrupt_segment_is_an_error() {
+        let path = temp_path("segmented-corrupt");
+
+        let mut storage = SegmentedStorage::new(&path, Duration::from_micros(100)).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        drop(storage);
+
+        let data_path = path.join(format!("{}.seg", segment_name(0)));
+        let mut data = std::fs::read(&data_path).unwrap();
+        data[GENERATION_LEN as usize] = 42;
+        std::fs::write(&data_path, data).unwrap();
+
+        assert!(SegmentedStorage::new(&path, Duration::from_micros(100)).is_err());
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn compaction_applies_and_clears_mutation_log() {
+        let path = temp_path("segmented-compact");
+        let segment_duration = Duration::from_micros(100);
+        let mutations_path = path.join(format!("{}.mut", segment_name(0)));
+
+        let mut storage = SegmentedStorage::new(&path, segment_duration).unwrap();
+        storage.insert_event(event(50)).unwrap();
+        storage.insert_event(event(60)).unwrap();
+        storage
+            .update_event_parents(Timestamp::new(10).unwrap(), &[Timestamp::new(50).unwrap()])
+            .unwrap();
+        storage.drop_events(&[Timestamp::new(60).unwrap()]).unwrap();
+
+        assert!(storage.compact().unwrap() > 0);
+        assert_eq!(std::fs::metadata(&mutations_path).unwrap().len(), 0);
+        drop(storage);
+
+        let mut storage = SegmentedStorage::new(&path, segment_duration).unwrap();
+        let event = storage.get_event(Timestamp::new(50).unwrap()).unwrap();
+        assert_eq!(event.parent_key, Timestamp::new(10));
+        assert!(storage.get_event(Timestamp::new(60).unwrap()).is_err());
+
+        // the log is compacted on its own once it outgrows the data
+        for parent in 1..100 {
+            storage
+                .update_event_parents(
+                    Timestamp::new(parent).unwrap(),
+                    &[Timestamp::new(50).unwrap()],
+                )
+                .unwrap();
+        }
+
+        let data_path = path.join(format!("{}.seg", segment_name(0)));
+        assert!(
+            std::fs::metadata(&mutations_path).unwrap().len()
+                <= std::fs::metadata(&data_path).unwrap().len()
+        );
+        let event = storage.get_event(Timestamp::new(50).unwrap()).unwrap();
+        assert_eq!(event.parent_key, Timestamp::new(99));
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -1,8 +1,8 @@
 use std::collections::BTreeMap;
 use std::sync::Arc;
 
-use super::{Storage, StorageError};
-use crate::models::{EventKey, Value};
+use super::{AnnotationStorage, Storage, StorageError};
+use crate::models::{Annotation, AnnotationKey, EventKey, PreciseTimestamp, Value};
 use crate::{Event, FullSpanId, Resource, Span, SpanEvent, SpanKey, Timestamp};
 
 /// This storage just holds all entities in memory.
@@ -11,6 +11,7 @@ pub struct TransientStorage {
     spans: BTreeMap<Timestamp, Arc<Span>>,
     span_events: BTreeMap<Timestamp, Arc<SpanEvent>>,
     events: BTreeMap<Timestamp, Arc<Event>>,
+    annotations: BTreeMap<AnnotationKey, Annotation>,
 }
 
 impl TransientStorage {
@@ -20,6 +21,7 @@ impl TransientStorage {
             spans: BTreeMap::new(),
             span_events: BTreeMap::new(),
             events: BTreeMap::new(),
+            annotations: BTreeMap::new(),
         }
     }
 }
@@ -86,19 +88,19 @@ impl Storage for TransientStorage {
     }
 
     fn insert_span(&mut self, span: Span) -> Result<(), StorageError> {
-        let at = span.created_at;
+        let at = span.key;
         self.spans.insert(at, Arc::new(span));
         Ok(())
     }
 
     fn insert_span_event(&mut self, span_event: SpanEvent) -> Result<(), StorageError> {
-        let at = span_event.timestamp;
+        let at = span_event.key;
         self.span_events.insert(at, Arc::new(span_event));
         Ok(())
     }
 
     fn insert_event(&mut self, event: Event) -> Result<(), StorageError> {
-        let at = event.timestamp;
+        let at = event.key;
         self.events.insert(at, Arc::new(event));
         Ok(())
     }
@@ -106,7 +108,7 @@ impl Storage for TransientStorage {
     fn update_span_closed(
         &mut self,
         at: Timestamp,
-        closed_at: Timestamp,
+        closed_at: PreciseTimestamp,
         busy: Option<u64>,
     ) -> Result<(), StorageError> {
         if let Some(span) = self.spans.get(&at) {
@@ -211,4 +213,41 @@ impl Storage for TransientStorage {
 
         Ok(())
     }
+
+    fn as_annotation_storage(&self) -> Option<&dyn AnnotationStorage> {
+        Some(self)
+    }
+
+    fn as_annotation_storage_mut(&mut self) -> Option<&mut dyn AnnotationStorage> {
+        Some(self)
+    }
+}
+
+impl AnnotationStorage for TransientStorage {
+    fn get_annotations(&self) -> Result<Vec<Annotation>, StorageError> {
+        Ok(self.annotations.values().cloned().collect())
+    }
+
+    fn insert_annotation(&mut self, annotation: &Annotation) -> Result<(), StorageError> {
+        self.annotations.insert(annotation.key, annotation.clone());
+
+        Ok(())
+    }
+
+    fn update_annotation(&mut self, annotation: &Annotation) -> Result<(), StorageError> {
+        let existing = self
+            .annotations
+            .get_mut(&annotation.key)
+            .ok_or(StorageError::NotFound)?;
+
+        *existing = annotation.clone();
+
+        Ok(())
+    }
+
+    fn drop_annotation(&mut self, key: AnnotationKey) -> Result<(), StorageError> {
+        self.annotations.remove(&key);
+
+        Ok(())
+    }
 }
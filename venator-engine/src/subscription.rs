@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
 use crate::context::{EventContext, SpanContext};
@@ -91,9 +93,13 @@ impl SpanSubscription {
     }
 
     /// This should be called when a span is created or was impacted by a change
-    /// in a parent span.
-    pub(crate) fn on_span<S: Storage>(&mut self, span: &SpanContext<'_, S>) {
-        if self.filter.matches(span) {
+    /// in a parent span. The `children` index is needed to match on self time.
+    pub(crate) fn on_span<S: Storage>(
+        &mut self,
+        span: &SpanContext<'_, S>,
+        children: &HashMap<SpanKey, Vec<SpanKey>>,
+    ) {
+        if self.filter.matches(span, children) {
             let idx = self.cache.upper_bound_via_expansion(&span.key());
             if idx == 0 || self.cache[idx - 1] != span.key() {
                 // the span was not visible by this subscription before